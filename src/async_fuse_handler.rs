@@ -0,0 +1,1379 @@
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::fuse_handler::FuseHandler;
+use crate::types::*;
+
+/// The `AsyncFuseHandler` trait is the asynchronous counterpart of [`FuseHandler`], available with the `async` feature.
+///
+/// Every filesystem operation returns a future, which is driven by the tokio runtime owned by the mount.
+/// This allows handlers backed by network or other IO-bound resources to await their requests
+/// without blocking a worker for each round-trip.
+///
+/// Because async functions in traits are not object safe yet, this trait relies on the `async_trait` macro,
+/// which is re-exported by this crate. Implementations must be annotated with `#[async_trait]` as well.
+///
+/// # Usage
+///
+/// Composition works exactly as with `FuseHandler`: unprovided methods are delegated to the handler returned by `get_inner()`.
+///
+/// ```rust, no_run
+/// use easy_fuser::prelude::*;
+/// use easy_fuser::templates::DefaultFuseHandler;
+/// use easy_fuser::async_trait;
+/// use std::ffi::OsStr;
+/// use std::path::PathBuf;
+///
+/// struct MyRemoteFs {
+///     inner: Box<dyn AsyncFuseHandler<PathBuf>>,
+/// }
+///
+/// impl MyRemoteFs {
+///     pub fn new() -> Self {
+///         MyRemoteFs { inner: Box::new(DefaultFuseHandler::new()) }
+///     }
+/// }
+///
+/// #[async_trait]
+/// impl AsyncFuseHandler<PathBuf> for MyRemoteFs {
+///     fn get_inner(&self) -> &dyn AsyncFuseHandler<PathBuf> {
+///         self.inner.as_ref()
+///     }
+///
+///     async fn lookup(&self, req: &RequestInfo, parent_id: PathBuf, name: &OsStr) -> FuseResult<FileAttribute> {
+///         // Await a remote call here
+///         // ...
+///
+///         // Delegate to inner handler for standard behavior
+///         self.inner.lookup(req, parent_id, name).await
+///     }
+/// }
+/// ```
+///
+/// # Synchronous handlers
+///
+/// Every type implementing `FuseHandler` also implements `AsyncFuseHandler`, each operation running
+/// to completion on the runtime worker that polls it. This allows mounting the provided templates
+/// (such as `MirrorFs` or `DefaultFuseHandler`) directly, or using them as inner handlers of an async one.
+/// Long blocking operations should however be implemented asynchronously to avoid starving the runtime.
+///
+/// # Multithreading, Safety, Traits & Lifetime
+///
+/// This trait requires `Send + Sync` and `'static` lifetime, and all the returned futures must be `Send`.
+#[async_trait]
+pub trait AsyncFuseHandler<TId: FileIdType>: Send + Sync + 'static {
+    /// Delegate unprovided methods to another FuseHandler, enabling composition
+    fn get_inner(&self) -> &dyn AsyncFuseHandler<TId>;
+
+    /// Provide a default Time-To-Live for file metadata
+    ///
+    /// Can be overriden for each FileAttributes returned.
+    fn get_default_ttl(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    /// Provide a default Time-To-Live for the names of the entries, cached in the dentries of the kernel
+    ///
+    /// See `FuseHandler::get_default_entry_ttl`.
+    fn get_default_entry_ttl(&self) -> Duration {
+        self.get_default_ttl()
+    }
+
+    /// Provide a default Time-To-Live for the attributes of the files, eg: their size
+    ///
+    /// See `FuseHandler::get_default_attr_ttl`.
+    fn get_default_attr_ttl(&self) -> Duration {
+        self.get_default_ttl()
+    }
+
+    /// Provide a Time-To-Live for the missing entries, cached by the kernel as negative entries
+    ///
+    /// See `FuseHandler::get_negative_ttl`.
+    fn get_negative_ttl(&self) -> Option<Duration> {
+        self.get_inner().get_negative_ttl()
+    }
+
+    /// Kind of locks handled by the filesystem instead of the kernel, queried during `init`
+    ///
+    /// See `FuseHandler::remote_locks`.
+    fn remote_locks(&self) -> Option<LockKind> {
+        self.get_inner().remote_locks()
+    }
+
+    /// Whether `write_buf` uses the payload of the writes without converting it to a `Vec`
+    ///
+    /// See `FuseHandler::pooled_writes`.
+    fn pooled_writes(&self) -> bool {
+        self.get_inner().pooled_writes()
+    }
+
+    /// Whether this handler is a `DefaultFuseHandler`, ending the chain of inner handlers
+    ///
+    /// See `FuseHandler::is_default_handler`.
+    fn is_default_handler(&self) -> bool {
+        false
+    }
+
+    /// Initialize the filesystem and configure kernel connection
+    ///
+    /// The `notifier` can be cloned and kept to invalidate kernel caches when the filesystem
+    /// content changes outside of FUSE operations.
+    async fn init(
+        &self,
+        req: &RequestInfo,
+        config: &mut KernelConfig,
+        notifier: Notifier<TId>,
+    ) -> FuseResult<()> {
+        self.get_inner().init(req, config, notifier).await
+    }
+
+    /// Perform cleanup operations on filesystem exit
+    async fn destroy(&self) {
+        self.get_inner().destroy().await;
+    }
+
+    /// Check file access permissions
+    ///
+    /// This method is called for the access() system call. If the 'default_permissions'
+    /// mount option is given, this method is not called. This method is not called
+    /// under Linux kernel versions 2.4.x
+    async fn access(&self, req: &RequestInfo, file_id: TId, mask: AccessMask) -> FuseResult<()> {
+        self.get_inner().access(req, file_id, mask).await
+    }
+
+    /// Map block index within file to block index within device
+    ///
+    /// Note: This makes sense only for block device backed filesystems mounted
+    /// with the 'blkdev' option
+    async fn bmap(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        blocksize: u32,
+        idx: u64,
+    ) -> FuseResult<u64> {
+        self.get_inner().bmap(req, file_id, blocksize, idx).await
+    }
+
+    /// Copy the specified range from the source inode to the destination inode
+    async fn copy_file_range(
+        &self,
+        req: &RequestInfo,
+        file_in: TId,
+        file_handle_in: BorrowedFileHandle<'_>,
+        offset_in: i64,
+        file_out: TId,
+        file_handle_out: BorrowedFileHandle<'_>,
+        offset_out: i64,
+        len: u64,
+        flags: u32, // Not implemented yet in standard
+    ) -> FuseResult<u32> {
+        self.get_inner()
+            .copy_file_range(
+                req,
+                file_in,
+                file_handle_in,
+                offset_in,
+                file_out,
+                file_handle_out,
+                offset_out,
+                len,
+                flags,
+            )
+            .await
+    }
+
+    /// Create and open a file
+    ///
+    /// If the file does not exist, first create it with the specified mode, and then
+    /// open it. Open flags (with the exception of O_NOCTTY) are available in flags.
+    /// If this method is not implemented or under Linux kernel versions earlier than
+    /// 2.6.15, the mknod() and open() methods will be called instead.
+    async fn create(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, TId::Metadata, FUSEOpenResponseFlags)> {
+        self.get_inner()
+            .create(req, parent_id, name, mode, umask, flags)
+            .await
+    }
+
+    /// Preallocate or deallocate space to a file
+    async fn fallocate(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        offset: i64,
+        length: i64,
+        mode: FallocateFlags,
+    ) -> FuseResult<()> {
+        self.get_inner()
+            .fallocate(req, file_id, file_handle, offset, length, mode)
+            .await
+    }
+
+    /// Flush cached data for an open file
+    ///
+    /// Called on each close() of the opened file. Not guaranteed to be called after writes or at all.
+    /// Used for returning write errors or removing file locks.
+    async fn flush(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        lock_owner: u64,
+    ) -> FuseResult<()> {
+        self.get_inner()
+            .flush(req, file_id, file_handle, lock_owner)
+            .await
+    }
+
+    /// Release references to an inode, if the nlookup count reaches zero (to substract from the number of lookups).
+    async fn forget(&self, req: &RequestInfo, file_id: TId, nlookup: u64) {
+        self.get_inner().forget(req, file_id, nlookup).await;
+    }
+
+    /// Synchronize file contents
+    ///
+    /// If datasync is true, only flush user data, not metadata.
+    async fn fsync(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        datasync: bool,
+    ) -> FuseResult<()> {
+        self.get_inner()
+            .fsync(req, file_id, file_handle, datasync)
+            .await
+    }
+
+    /// Synchronize directory contents
+    ///
+    /// If the datasync parameter is true, then only the directory contents should
+    /// be flushed, not the metadata. The file_handle will contain the value set
+    /// by the opendir method, or will be undefined if the opendir method didn't
+    /// set any value.
+    async fn fsyncdir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        datasync: bool,
+    ) -> FuseResult<()> {
+        self.get_inner()
+            .fsyncdir(req, file_id, file_handle, datasync)
+            .await
+    }
+
+    /// Modify file attributes
+    async fn getattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: Option<BorrowedFileHandle<'_>>,
+    ) -> FuseResult<FileAttribute> {
+        self.get_inner().getattr(req, file_id, file_handle).await
+    }
+
+    /// Test for a POSIX file lock.
+    async fn getlk(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        lock_owner: u64,
+        lock_info: LockInfo,
+    ) -> FuseResult<LockInfo> {
+        self.get_inner()
+            .getlk(req, file_id, file_handle, lock_owner, lock_info)
+            .await
+    }
+
+    /// Get an extended attribute
+    async fn getxattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        name: &OsStr,
+        size: u32,
+    ) -> FuseResult<Vec<u8>> {
+        self.get_inner().getxattr(req, file_id, name, size).await
+    }
+
+    /// control device
+    async fn ioctl(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        flags: IOCtlFlags,
+        cmd: u32,
+        in_data: Vec<u8>,
+        out_size: u32,
+    ) -> FuseResult<(i32, Vec<u8>)> {
+        self.get_inner()
+            .ioctl(req, file_id, file_handle, flags, cmd, in_data, out_size)
+            .await
+    }
+
+    /// Create a hard link.
+    async fn link(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        newparent: TId,
+        newname: &OsStr,
+    ) -> FuseResult<TId::Metadata> {
+        self.get_inner()
+            .link(req, file_id, newparent, newname)
+            .await
+    }
+
+    /// List extended attribute names
+    async fn listxattr(&self, req: &RequestInfo, file_id: TId, size: u32) -> FuseResult<Vec<u8>> {
+        self.get_inner().listxattr(req, file_id, size).await
+    }
+
+    /// Retrieve file attributes for a directory entry by name and increment the lookup count associated with the inode.
+    async fn lookup(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+    ) -> FuseResult<TId::Metadata> {
+        self.get_inner().lookup(req, parent_id, name).await
+    }
+
+    /// Reposition read/write file offset
+    ///
+    /// `seek` can also ask for the next data or hole of a sparse file (`SEEK_DATA` and `SEEK_HOLE`), in which
+    /// case the offset of the data or hole found is returned.
+    async fn lseek(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: LseekFrom,
+    ) -> FuseResult<i64> {
+        self.get_inner()
+            .lseek(req, file_id, file_handle, seek)
+            .await
+    }
+
+    /// Create a new directory
+    async fn mkdir(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+    ) -> FuseResult<TId::Metadata> {
+        self.get_inner()
+            .mkdir(req, parent_id, name, mode, umask)
+            .await
+    }
+
+    /// Create a new file node (regular file, device, FIFO, socket, etc)
+    async fn mknod(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: DeviceType,
+    ) -> FuseResult<TId::Metadata> {
+        self.get_inner()
+            .mknod(req, parent_id, name, mode, umask, rdev)
+            .await
+    }
+
+    /// Open a file and return a file handle.
+    ///
+    /// Open flags (with the exception of O_CREAT, O_EXCL, O_NOCTTY and O_TRUNC) are available in flags. You may store an arbitrary file handle (pointer, index, etc) in file_handle response, and use this in other all other file operations (read, write, flush, release, fsync). Filesystem may also implement stateless file I/O and not store anything in fh. There are also some flags (direct_io, keep_cache) which the filesystem may set, to change the way the file is opened. See fuse_file_info structure in <fuse_common.h> for more details.
+    async fn open(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        self.get_inner().open(req, file_id, flags).await
+    }
+
+    /// Open a directory
+    ///
+    /// Allows storing a file handle for use in subsequent directory operations.
+    async fn opendir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        self.get_inner().opendir(req, file_id, flags).await
+    }
+
+    /// Poll a file for I/O readiness
+    ///
+    /// Returns the subset of the requested `events` which are ready. If `flags` contains
+    /// `FUSEPollFlags::SCHEDULE_NOTIFY` and no requested event is ready yet, the handler should keep
+    /// `poll_handle` and call `PollHandle::notify` once one of them becomes ready. Only the latest
+    /// handle received for a given file handle needs to be notified.
+    ///
+    /// If this method is not implemented, the kernel considers the file always ready.
+    async fn poll(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        poll_handle: PollHandle,
+        events: PollEvents,
+        flags: FUSEPollFlags,
+    ) -> FuseResult<PollEvents> {
+        self.get_inner()
+            .poll(req, file_id, file_handle, poll_handle, events, flags)
+            .await
+    }
+
+    /// Read data from a file
+    ///
+    /// Read should send exactly the number of bytes requested except on EOF or error, otherwise the rest of the data will be substituted with zeroes. An exception to this is when the file has been opened in ‘direct_io’ mode, in which case the return value of the read system call will reflect the return value of this operation. fh will contain the value set by the open method, or will be undefined if the open method didn’t set any value.
+    ///
+    /// flags: these are the file flags, such as O_SYNC. Only supported with ABI >= 7.9 lock_owner: only supported with ABI >= 7.9
+    async fn read(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<Vec<u8>> {
+        self.get_inner()
+            .read(req, file_id, file_handle, seek, size, flags, lock_owner)
+            .await
+    }
+
+    /// Read data from a file, replying from a buffer which does not have to be owned
+    ///
+    /// See `FuseHandler::read_buf`. Default implementation delegates to the inner handler, or is an adapter on top
+    /// of `read` when the inner handler is a `DefaultFuseHandler`.
+    async fn read_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<ReadData> {
+        if !self.get_inner().is_default_handler() {
+            return self
+                .get_inner()
+                .read_buf(req, file_id, file_handle, seek, size, flags, lock_owner)
+                .await;
+        }
+        self.read(req, file_id, file_handle, seek, size, flags, lock_owner)
+            .await
+            .map(ReadData::from)
+    }
+
+    /// Read directory contents
+    ///
+    /// Returns a list of directory entries with minimal metadata.
+    ///
+    /// Important: The returned file names (OsString) must not contain any slashes ('/').
+    /// Including slashes in the file names will result in undefined behavior.
+    async fn readdir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+    ) -> FuseResult<Vec<(OsString, TId::MinimalMetadata)>> {
+        self.get_inner().readdir(req, file_id, file_handle).await
+    }
+
+    /// Read directory contents with full file attributes
+    ///
+    /// Default implementation combines readdir and lookup operations.
+    ///
+    /// Important: The returned file names (OsString) must not contain any slashes ('/').
+    /// Including slashes in the file names will result in undefined behavior.
+    async fn readdirplus(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+    ) -> FuseResult<Vec<(OsString, TId::Metadata)>> {
+        let readdir_result = self.readdir(req, file_id.clone(), file_handle).await?;
+        let mut result = Vec::with_capacity(readdir_result.len());
+        for (name, _) in readdir_result.into_iter() {
+            let metadata = self.lookup(req, file_id.clone(), &name).await?;
+            result.push((name, metadata));
+        }
+        Ok(result)
+    }
+
+    /// Read directory contents lazily, starting after the first `offset` entries
+    ///
+    /// This is the streaming variant of `readdir`: the returned iterator is only pulled as far as
+    /// the kernel buffer allows and kept alive between successive reads of the same directory handle,
+    /// until `releasedir`, which avoids listing very large directories at once.
+    ///
+    /// `offset` is the number of entries already returned for this directory stream. It is zero on the first read,
+    /// but may be non-zero if the kernel resumes a listing whose iterator is no longer available (eg: after a seekdir).
+    ///
+    /// Default implementation delegates to the inner handler, or is an adapter on top of `readdir` skipping the
+    /// first `offset` entries when the inner handler is a `DefaultFuseHandler`, see `FuseHandler::readdir_iter`.
+    ///
+    /// Important: The returned file names (OsString) must not contain any slashes ('/').
+    /// Including slashes in the file names will result in undefined behavior.
+    async fn readdir_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::MinimalMetadata>> {
+        if !self.get_inner().is_default_handler() {
+            return self
+                .get_inner()
+                .readdir_iter(req, file_id, file_handle, offset)
+                .await;
+        }
+        let entries = self.readdir(req, file_id, file_handle).await?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
+    /// Read directory contents with full file attributes lazily, starting after the first `offset` entries
+    ///
+    /// This is the streaming variant of `readdirplus`, see `readdir_iter` for more details.
+    ///
+    /// Default implementation delegates to the inner handler, or is an adapter on top of `readdirplus` skipping the
+    /// first `offset` entries when the inner handler is a `DefaultFuseHandler`, see `FuseHandler::readdirplus_iter`.
+    /// Handlers implementing `readdir_iter` should also implement this method to avoid listing the whole directory.
+    async fn readdirplus_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::Metadata>> {
+        if !self.get_inner().is_default_handler() {
+            return self
+                .get_inner()
+                .readdirplus_iter(req, file_id, file_handle, offset)
+                .await;
+        }
+        let entries = self.readdirplus(req, file_id, file_handle).await?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
+    /// Read the target of a symbolic link
+    async fn readlink(&self, req: &RequestInfo, file_id: TId) -> FuseResult<Vec<u8>> {
+        self.get_inner().readlink(req, file_id).await
+    }
+
+    /// Release an open file
+    ///
+    /// Called when all file descriptors are closed and all memory mappings are unmapped.
+    /// Guaranteed to be called once for every open() call.
+    async fn release(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: OwnedFileHandle,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+        flush: bool,
+    ) -> FuseResult<()> {
+        self.get_inner()
+            .release(req, file_id, file_handle, flags, lock_owner, flush)
+            .await
+    }
+
+    /// Release an open directory
+    ///
+    /// This method is called exactly once for every successful opendir operation.
+    /// The file_handle parameter will contain the value set by the opendir method,
+    /// or will be undefined if the opendir method didn't set any value.
+    async fn releasedir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: OwnedFileHandle,
+        flags: OpenFlags,
+    ) -> FuseResult<()> {
+        self.get_inner()
+            .releasedir(req, file_id, file_handle, flags)
+            .await
+    }
+
+    /// Remove an extended attribute.
+    async fn removexattr(&self, req: &RequestInfo, file_id: TId, name: &OsStr) -> FuseResult<()> {
+        self.get_inner().removexattr(req, file_id, name).await
+    }
+
+    /// Rename a file or directory
+    async fn rename(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        newparent: TId,
+        newname: &OsStr,
+        flags: RenameFlags,
+    ) -> FuseResult<()> {
+        self.get_inner()
+            .rename(req, parent_id, name, newparent, newname, flags)
+            .await
+    }
+
+    /// Remove a directory
+    async fn rmdir(&self, req: &RequestInfo, parent_id: TId, name: &OsStr) -> FuseResult<()> {
+        self.get_inner().rmdir(req, parent_id, name).await
+    }
+
+    /// Set file attributes.
+    async fn setattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        attrs: SetAttrRequest<'_>,
+    ) -> FuseResult<FileAttribute> {
+        self.get_inner().setattr(req, file_id, attrs).await
+    }
+
+    /// Acquire, modify or release a POSIX file lock
+    ///
+    /// For POSIX threads (NPTL) there's a 1-1 relation between pid and owner, but
+    /// otherwise this is not always the case. For checking lock ownership, 'fi->owner'
+    /// must be used. The l_pid field in 'struct flock' should only be used to fill
+    /// in this field in getlk().
+    async fn setlk(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        lock_owner: u64,
+        lock_info: LockInfo,
+        sleep: bool,
+    ) -> FuseResult<()> {
+        self.get_inner()
+            .setlk(req, file_id, file_handle, lock_owner, lock_info, sleep)
+            .await
+    }
+
+    /// Set an extended attribute
+    async fn setxattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: FUSESetXAttrFlags,
+        position: u32,
+    ) -> FuseResult<()> {
+        self.get_inner()
+            .setxattr(req, file_id, name, value, flags, position)
+            .await
+    }
+
+    /// Get file system statistics
+    async fn statfs(&self, req: &RequestInfo, file_id: TId) -> FuseResult<StatFs> {
+        self.get_inner().statfs(req, file_id).await
+    }
+
+    /// Create a symbolic link.
+    async fn symlink(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        link_name: &OsStr,
+        target: &Path,
+    ) -> FuseResult<TId::Metadata> {
+        self.get_inner()
+            .symlink(req, parent_id, link_name, target)
+            .await
+    }
+
+    /// Write data to a file
+    ///
+    /// Write should return exactly the number of bytes requested except on error. An exception to this is when the file has been opened in ‘direct_io’ mode, in which case the return value of the write system call will reflect the return value of this operation. fh will contain the value set by the open method, or will be undefined if the open method didn’t set any value.
+    ///
+    /// write_flags: will contain FUSE_WRITE_CACHE, if this write is from the page cache. If set, the pid, uid, gid, and fh may not match the value that would have been sent if write cachin is disabled flags: these are the file flags, such as O_SYNC. Only supported with ABI >= 7.9 lock_owner: only supported with ABI >= 7.9
+    async fn write(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: SeekFrom,
+        data: Vec<u8>,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        self.get_inner()
+            .write(
+                req,
+                file_id,
+                file_handle,
+                seek,
+                data,
+                write_flags,
+                flags,
+                lock_owner,
+            )
+            .await
+    }
+
+    /// Write data to a file from a borrowed buffer
+    ///
+    /// See `FuseHandler::write_buf`. Default implementation delegates to the inner handler, or is an adapter on top
+    /// of `write` when the inner handler is a `DefaultFuseHandler`.
+    async fn write_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: SeekFrom,
+        data: WriteData<'_>,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        if !self.get_inner().is_default_handler() {
+            return self
+                .get_inner()
+                .write_buf(
+                    req,
+                    file_id,
+                    file_handle,
+                    seek,
+                    data,
+                    write_flags,
+                    flags,
+                    lock_owner,
+                )
+                .await;
+        }
+        self.write(
+            req,
+            file_id,
+            file_handle,
+            seek,
+            data.into_vec(),
+            write_flags,
+            flags,
+            lock_owner,
+        )
+        .await
+    }
+
+    /// Remove a file
+    async fn unlink(&self, req: &RequestInfo, parent_id: TId, name: &OsStr) -> FuseResult<()> {
+        self.get_inner().unlink(req, parent_id, name).await
+    }
+}
+
+#[async_trait]
+impl<TId: FileIdType, T: FuseHandler<TId>> AsyncFuseHandler<TId> for T {
+    fn get_inner(&self) -> &dyn AsyncFuseHandler<TId> {
+        // Every method is forwarded to the synchronous implementation,
+        // so the adapted handler acts as its own inner handler.
+        self
+    }
+
+    fn get_default_ttl(&self) -> Duration {
+        FuseHandler::get_default_ttl(self)
+    }
+
+    fn get_default_entry_ttl(&self) -> Duration {
+        FuseHandler::get_default_entry_ttl(self)
+    }
+
+    fn get_default_attr_ttl(&self) -> Duration {
+        FuseHandler::get_default_attr_ttl(self)
+    }
+
+    fn get_negative_ttl(&self) -> Option<Duration> {
+        FuseHandler::get_negative_ttl(self)
+    }
+
+    fn remote_locks(&self) -> Option<LockKind> {
+        FuseHandler::remote_locks(self)
+    }
+
+    fn pooled_writes(&self) -> bool {
+        FuseHandler::pooled_writes(self)
+    }
+
+    fn is_default_handler(&self) -> bool {
+        FuseHandler::is_default_handler(self)
+    }
+
+    async fn init(
+        &self,
+        req: &RequestInfo,
+        config: &mut KernelConfig,
+        notifier: Notifier<TId>,
+    ) -> FuseResult<()> {
+        FuseHandler::init(self, req, config, notifier)
+    }
+
+    async fn destroy(&self) {
+        FuseHandler::destroy(self)
+    }
+
+    async fn access(&self, req: &RequestInfo, file_id: TId, mask: AccessMask) -> FuseResult<()> {
+        FuseHandler::access(self, req, file_id, mask)
+    }
+
+    async fn bmap(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        blocksize: u32,
+        idx: u64,
+    ) -> FuseResult<u64> {
+        FuseHandler::bmap(self, req, file_id, blocksize, idx)
+    }
+
+    async fn copy_file_range(
+        &self,
+        req: &RequestInfo,
+        file_in: TId,
+        file_handle_in: BorrowedFileHandle<'_>,
+        offset_in: i64,
+        file_out: TId,
+        file_handle_out: BorrowedFileHandle<'_>,
+        offset_out: i64,
+        len: u64,
+        flags: u32, // Not implemented yet in standard
+    ) -> FuseResult<u32> {
+        FuseHandler::copy_file_range(
+            self,
+            req,
+            file_in,
+            file_handle_in,
+            offset_in,
+            file_out,
+            file_handle_out,
+            offset_out,
+            len,
+            flags,
+        )
+    }
+
+    async fn create(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, TId::Metadata, FUSEOpenResponseFlags)> {
+        FuseHandler::create(self, req, parent_id, name, mode, umask, flags)
+    }
+
+    async fn fallocate(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        offset: i64,
+        length: i64,
+        mode: FallocateFlags,
+    ) -> FuseResult<()> {
+        FuseHandler::fallocate(self, req, file_id, file_handle, offset, length, mode)
+    }
+
+    async fn flush(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        lock_owner: u64,
+    ) -> FuseResult<()> {
+        FuseHandler::flush(self, req, file_id, file_handle, lock_owner)
+    }
+
+    async fn forget(&self, req: &RequestInfo, file_id: TId, nlookup: u64) {
+        FuseHandler::forget(self, req, file_id, nlookup)
+    }
+
+    async fn fsync(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        datasync: bool,
+    ) -> FuseResult<()> {
+        FuseHandler::fsync(self, req, file_id, file_handle, datasync)
+    }
+
+    async fn fsyncdir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        datasync: bool,
+    ) -> FuseResult<()> {
+        FuseHandler::fsyncdir(self, req, file_id, file_handle, datasync)
+    }
+
+    async fn getattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: Option<BorrowedFileHandle<'_>>,
+    ) -> FuseResult<FileAttribute> {
+        FuseHandler::getattr(self, req, file_id, file_handle)
+    }
+
+    async fn getlk(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        lock_owner: u64,
+        lock_info: LockInfo,
+    ) -> FuseResult<LockInfo> {
+        FuseHandler::getlk(self, req, file_id, file_handle, lock_owner, lock_info)
+    }
+
+    async fn getxattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        name: &OsStr,
+        size: u32,
+    ) -> FuseResult<Vec<u8>> {
+        FuseHandler::getxattr(self, req, file_id, name, size)
+    }
+
+    async fn ioctl(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        flags: IOCtlFlags,
+        cmd: u32,
+        in_data: Vec<u8>,
+        out_size: u32,
+    ) -> FuseResult<(i32, Vec<u8>)> {
+        FuseHandler::ioctl(
+            self,
+            req,
+            file_id,
+            file_handle,
+            flags,
+            cmd,
+            in_data,
+            out_size,
+        )
+    }
+
+    async fn link(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        newparent: TId,
+        newname: &OsStr,
+    ) -> FuseResult<TId::Metadata> {
+        FuseHandler::link(self, req, file_id, newparent, newname)
+    }
+
+    async fn listxattr(&self, req: &RequestInfo, file_id: TId, size: u32) -> FuseResult<Vec<u8>> {
+        FuseHandler::listxattr(self, req, file_id, size)
+    }
+
+    async fn lookup(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+    ) -> FuseResult<TId::Metadata> {
+        FuseHandler::lookup(self, req, parent_id, name)
+    }
+
+    async fn lseek(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: LseekFrom,
+    ) -> FuseResult<i64> {
+        FuseHandler::lseek(self, req, file_id, file_handle, seek)
+    }
+
+    async fn mkdir(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+    ) -> FuseResult<TId::Metadata> {
+        FuseHandler::mkdir(self, req, parent_id, name, mode, umask)
+    }
+
+    async fn mknod(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: DeviceType,
+    ) -> FuseResult<TId::Metadata> {
+        FuseHandler::mknod(self, req, parent_id, name, mode, umask, rdev)
+    }
+
+    async fn open(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        FuseHandler::open(self, req, file_id, flags)
+    }
+
+    async fn opendir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        FuseHandler::opendir(self, req, file_id, flags)
+    }
+
+    async fn poll(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        poll_handle: PollHandle,
+        events: PollEvents,
+        flags: FUSEPollFlags,
+    ) -> FuseResult<PollEvents> {
+        FuseHandler::poll(self, req, file_id, file_handle, poll_handle, events, flags)
+    }
+
+    async fn read(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<Vec<u8>> {
+        FuseHandler::read(
+            self,
+            req,
+            file_id,
+            file_handle,
+            seek,
+            size,
+            flags,
+            lock_owner,
+        )
+    }
+
+    async fn read_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<ReadData> {
+        FuseHandler::read_buf(
+            self,
+            req,
+            file_id,
+            file_handle,
+            seek,
+            size,
+            flags,
+            lock_owner,
+        )
+    }
+
+    async fn readdir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+    ) -> FuseResult<Vec<(OsString, TId::MinimalMetadata)>> {
+        FuseHandler::readdir(self, req, file_id, file_handle)
+    }
+
+    async fn readdirplus(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+    ) -> FuseResult<Vec<(OsString, TId::Metadata)>> {
+        FuseHandler::readdirplus(self, req, file_id, file_handle)
+    }
+
+    async fn readdir_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::MinimalMetadata>> {
+        FuseHandler::readdir_iter(self, req, file_id, file_handle, offset)
+    }
+
+    async fn readdirplus_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::Metadata>> {
+        FuseHandler::readdirplus_iter(self, req, file_id, file_handle, offset)
+    }
+
+    async fn readlink(&self, req: &RequestInfo, file_id: TId) -> FuseResult<Vec<u8>> {
+        FuseHandler::readlink(self, req, file_id)
+    }
+
+    async fn release(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: OwnedFileHandle,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+        flush: bool,
+    ) -> FuseResult<()> {
+        FuseHandler::release(self, req, file_id, file_handle, flags, lock_owner, flush)
+    }
+
+    async fn releasedir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: OwnedFileHandle,
+        flags: OpenFlags,
+    ) -> FuseResult<()> {
+        FuseHandler::releasedir(self, req, file_id, file_handle, flags)
+    }
+
+    async fn removexattr(&self, req: &RequestInfo, file_id: TId, name: &OsStr) -> FuseResult<()> {
+        FuseHandler::removexattr(self, req, file_id, name)
+    }
+
+    async fn rename(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        newparent: TId,
+        newname: &OsStr,
+        flags: RenameFlags,
+    ) -> FuseResult<()> {
+        FuseHandler::rename(self, req, parent_id, name, newparent, newname, flags)
+    }
+
+    async fn rmdir(&self, req: &RequestInfo, parent_id: TId, name: &OsStr) -> FuseResult<()> {
+        FuseHandler::rmdir(self, req, parent_id, name)
+    }
+
+    async fn setattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        attrs: SetAttrRequest<'_>,
+    ) -> FuseResult<FileAttribute> {
+        FuseHandler::setattr(self, req, file_id, attrs)
+    }
+
+    async fn setlk(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        lock_owner: u64,
+        lock_info: LockInfo,
+        sleep: bool,
+    ) -> FuseResult<()> {
+        FuseHandler::setlk(
+            self,
+            req,
+            file_id,
+            file_handle,
+            lock_owner,
+            lock_info,
+            sleep,
+        )
+    }
+
+    async fn setxattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: FUSESetXAttrFlags,
+        position: u32,
+    ) -> FuseResult<()> {
+        FuseHandler::setxattr(self, req, file_id, name, value, flags, position)
+    }
+
+    async fn statfs(&self, req: &RequestInfo, file_id: TId) -> FuseResult<StatFs> {
+        FuseHandler::statfs(self, req, file_id)
+    }
+
+    async fn symlink(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        link_name: &OsStr,
+        target: &Path,
+    ) -> FuseResult<TId::Metadata> {
+        FuseHandler::symlink(self, req, parent_id, link_name, target)
+    }
+
+    async fn write(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: SeekFrom,
+        data: Vec<u8>,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        FuseHandler::write(
+            self,
+            req,
+            file_id,
+            file_handle,
+            seek,
+            data,
+            write_flags,
+            flags,
+            lock_owner,
+        )
+    }
+
+    async fn write_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle<'_>,
+        seek: SeekFrom,
+        data: WriteData<'_>,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        FuseHandler::write_buf(
+            self,
+            req,
+            file_id,
+            file_handle,
+            seek,
+            data,
+            write_flags,
+            flags,
+            lock_owner,
+        )
+    }
+
+    async fn unlink(&self, req: &RequestInfo, parent_id: TId, name: &OsStr) -> FuseResult<()> {
+        FuseHandler::unlink(self, req, parent_id, name)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::templates::memory_fs::MemoryFs;
+    use crate::testing::TestSession;
+
+    /// Awaits a timer before each lookup, delegating everything to a synchronous `MemoryFs`
+    struct SlowFs {
+        inner: MemoryFs,
+        lookups: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl AsyncFuseHandler<Inode> for SlowFs {
+        fn get_inner(&self) -> &dyn AsyncFuseHandler<Inode> {
+            &self.inner
+        }
+
+        async fn lookup(
+            &self,
+            req: &RequestInfo,
+            parent_id: Inode,
+            name: &OsStr,
+        ) -> FuseResult<(Inode, FileAttribute)> {
+            // Timers only work when the future is driven by the runtime of the mount
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            AsyncFuseHandler::lookup(&self.inner, req, parent_id, name).await
+        }
+    }
+
+    #[test]
+    fn test_async_handler() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let session = TestSession::new::<Inode, _>(SlowFs {
+            inner: MemoryFs::new(),
+            lookups: lookups.clone(),
+        })
+        .unwrap();
+        let dir = session
+            .mkdir(TestSession::ROOT_INO, OsStr::new("dir"), 0o755, 0)
+            .unwrap();
+        let (file, fh, _) = session
+            .create(dir.ino, OsStr::new("file"), 0o644, 0, OpenFlags::READ_WRITE)
+            .unwrap();
+        session
+            .write(file.ino, fh, 0, b"async", OpenFlags::READ_WRITE)
+            .unwrap();
+        session
+            .release(file.ino, fh, OpenFlags::READ_WRITE)
+            .unwrap();
+
+        let entry = session.lookup_path(Path::new("dir/file")).unwrap();
+        assert_eq!((entry.ino, entry.attr.size), (file.ino, 5));
+        assert_eq!(
+            session
+                .lookup(dir.ino, OsStr::new("missing"))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::FileNotFound
+        );
+        assert_eq!(lookups.load(Ordering::SeqCst), 3);
+    }
+}
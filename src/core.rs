@@ -4,5 +4,9 @@ mod inode_mapping;
 mod macros;
 mod thread_mode;
 
-pub(crate) use fuse_driver_types::FuseDriver;
-pub(crate) use inode_mapping::{InodeResolvable, ROOT_INO};
+pub(crate) use fuse_driver_types::{DriverSettings, FuseDriver, FuseDriverHandler};
+pub(crate) use inode_mapping::ROOT_INO;
+pub use inode_mapping::{
+    ComponentsResolver, EvictionPolicy, FileIdResolver, InodeResolvable, InodeResolver,
+    PathResolver, ResolverStats, DEFAULT_MAX_RESOLVER_ENTRIES,
+};
@@ -1,7 +1,7 @@
 use std::{
     ffi::OsStr,
     path::Path,
-    time::{Instant, SystemTime},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
 use libc::c_int;
@@ -10,30 +10,93 @@ use log::{error, info, warn};
 use fuser::{
     self, KernelConfig, ReplyAttr, ReplyBmap, ReplyCreate, ReplyData, ReplyDirectory,
     ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyIoctl, ReplyLock, ReplyLseek, ReplyOpen,
-    ReplyStatfs, ReplyWrite, ReplyXattr, Request, TimeOrNow,
+    ReplyPoll, ReplyStatfs, ReplyWrite, ReplyXattr, Request, TimeOrNow,
 };
 
 use super::{
-    fuse_driver_types::{execute_task, FuseDriver},
+    fuse_driver_types::{
+        await_handler, block_on_handler, execute_task, DirCursor, FuseDriver, FuseDriverHandler,
+    },
     inode_mapping::FileIdResolver,
     macros::*,
     thread_mode::*,
 };
-use crate::{fuse_handler::FuseHandler, types::*};
+use crate::types::*;
 
 fn get_random_generation() -> u64 {
     Instant::now().elapsed().as_nanos() as u64
 }
 
+/// Attributes of a negative entry, of which the kernel only considers the inode number 0
+fn negative_entry_attr() -> fuser::FileAttr {
+    fuser::FileAttr {
+        ino: 0,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: FileKind::RegularFile,
+        perm: 0,
+        nlink: 0,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 0,
+        flags: 0,
+    }
+}
+
 impl<TId, THandler> fuser::Filesystem for FuseDriver<TId, THandler>
 where
     TId: FileIdType,
-    THandler: FuseHandler<TId>,
+    THandler: FuseDriverHandler<TId>,
 {
     fn init(&mut self, req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
         let req = RequestInfo::from(req);
-        match self.get_handler().init(&req, config) {
-            Ok(()) => Ok(()),
+        let notifier = self.get_notifier();
+        // Settings of the mount are applied first, so that the handler can still adjust them
+        if let Some(max_write) = self.settings.max_write {
+            if let Err(nearest) = config.set_max_write(max_write) {
+                warn!(
+                    "max_write {} is not supported, nearest value is {}",
+                    max_write, nearest
+                );
+            }
+        }
+        if let Some(max_readahead) = self.settings.max_readahead {
+            if let Err(nearest) = config.set_max_readahead(max_readahead) {
+                warn!(
+                    "max_readahead {} is not supported, nearest value is {}",
+                    max_readahead, nearest
+                );
+            }
+        }
+        if let Err(unsupported) = config.add_capabilities(self.settings.capabilities) {
+            warn!(
+                "capabilities {:#x} are not supported by the kernel",
+                unsupported
+            );
+            // fuser rejects the whole set, request the supported ones only
+            let _ = config.add_capabilities(self.settings.capabilities & !unsupported);
+        }
+        match block_on_handler!(self, self.get_handler().init(&req, config, notifier)) {
+            Ok(()) => {
+                if let Some(kind) = self.get_handler().remote_locks() {
+                    let capability = match kind {
+                        LockKind::Posix => fuser::consts::FUSE_POSIX_LOCKS,
+                        LockKind::Flock => fuser::consts::FUSE_FLOCK_LOCKS,
+                    };
+                    if config.add_capabilities(capability).is_err() {
+                        warn!(
+                            "{:?} locks are not supported by the kernel, they are kept local",
+                            kind
+                        );
+                    }
+                }
+                Ok(())
+            }
             Err(e) => {
                 warn!("[{}] init {:?}", e, req);
                 Err(e.raw_error())
@@ -42,7 +105,7 @@ where
     }
 
     fn destroy(&mut self) {
-        self.get_handler().destroy();
+        block_on_handler!(self, self.get_handler().destroy());
     }
 
     fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
@@ -50,11 +113,11 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.access(
+            match await_handler!(handler.access(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, access),
                 AccessMask::from_bits_retain(mask),
-            ) {
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("access: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -69,7 +132,12 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.bmap(&req, resolver.resolve_id(ino), blocksize, idx) {
+            match await_handler!(handler.bmap(
+                &req,
+                resolve_id!(resolver, &req, ino, reply, bmap),
+                blocksize,
+                idx
+            )) {
                 Ok(block) => reply.bmap(block),
                 Err(e) => {
                     warn!("bmap: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -96,17 +164,17 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.copy_file_range(
+            match await_handler!(handler.copy_file_range(
                 &req,
-                resolver.resolve_id(ino_in),
+                resolve_id!(resolver, &req, ino_in, reply, copy_file_range),
                 unsafe { BorrowedFileHandle::from_raw(fh_in) },
                 offset_in,
-                resolver.resolve_id(ino_out),
+                resolve_id!(resolver, &req, ino_out, reply, copy_file_range),
                 unsafe { BorrowedFileHandle::from_raw(fh_out) },
                 offset_out,
                 len,
                 flags,
-            ) {
+            )) {
                 Ok(bytes_written) => reply.written(bytes_written),
                 Err(e) => {
                     warn!("copy_file_range: ino {:x?}, [{}], {:?}", ino_in, e, req);
@@ -129,25 +197,35 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
+        let settings = self.settings;
         let name = name.to_owned();
         execute_task!(self, {
-            match handler.create(
+            match await_handler!(handler.create(
                 &req,
-                resolver.resolve_id(parent),
+                resolve_id!(resolver, &req, parent, reply, create),
                 &name,
                 mode,
                 umask,
                 OpenFlags::from_bits_retain(flags),
-            ) {
+            )) {
                 Ok((file_handle, metadata, response_flags)) => {
-                    let default_ttl = handler.get_default_ttl();
+                    let default_ttl = settings.entry_ttl(|| handler.get_default_entry_ttl());
                     let (id, file_attr) = TId::extract_metadata(metadata);
-                    let ino = resolver.lookup(parent, &name, id, true);
+                    let ino = match resolver.lookup(parent, &name, id, true) {
+                        Ok(ino) => ino,
+                        Err(e) => {
+                            log_resolver_error!(resolver, &req, parent, e, create);
+                            reply.error(e.raw_error());
+                            return;
+                        }
+                    };
                     let (fuse_attr, ttl, generation) = file_attr.to_fuse(ino);
                     reply.created(
                         &ttl.unwrap_or(default_ttl),
                         &fuse_attr,
-                        generation.unwrap_or(get_random_generation()),
+                        generation
+                            .or_else(|| resolver.generation(ino))
+                            .unwrap_or_else(get_random_generation),
                         file_handle.as_raw(),
                         response_flags.bits(),
                     );
@@ -174,14 +252,14 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.fallocate(
+            match await_handler!(handler.fallocate(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, fallocate),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
                 offset,
                 length,
                 FallocateFlags::from_bits_retain(mode),
-            ) {
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("fallocate: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -196,12 +274,12 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.flush(
+            match await_handler!(handler.flush(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, flush),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
                 lock_owner,
-            ) {
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("flush: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -215,8 +293,17 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
-        handler.forget(&req, resolver.resolve_id(ino), nlookup);
-        resolver.forget(ino, nlookup);
+        let file_id = match resolver.resolve_id(ino) {
+            Ok(file_id) => file_id,
+            Err(e) => {
+                log_resolver_error!(resolver, &req, ino, e, forget);
+                return;
+            }
+        };
+        block_on_handler!(self, handler.forget(&req, file_id, nlookup));
+        if let Err(e) = resolver.forget(ino, nlookup) {
+            log_resolver_error!(resolver, &req, ino, e, forget);
+        }
     }
 
     fn fsync(&mut self, req: &Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
@@ -224,12 +311,12 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.fsync(
+            match await_handler!(handler.fsync(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, fsync),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
                 datasync,
-            ) {
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("fsync: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -244,12 +331,12 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.fsyncdir(
+            match await_handler!(handler.fsyncdir(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, fsyncdir),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
                 datasync,
-            ) {
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("fsyncdir: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -263,17 +350,19 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
+        let settings = self.settings;
         execute_task!(self, {
             handle_fuse_reply_attr!(
                 handler,
                 resolver,
+                settings,
                 &req,
                 ino,
                 reply,
                 getattr,
                 (
                     &req,
-                    resolver.resolve_id(ino),
+                    resolve_id!(resolver, &req, ino, reply, getattr),
                     fh.map(|fh| unsafe { BorrowedFileHandle::from_raw(fh) })
                 )
             );
@@ -295,20 +384,22 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
+        let kind = handler.remote_locks().unwrap_or_default();
         execute_task!(self, {
             let lock_info = LockInfo {
                 start,
                 end,
                 lock_type: LockType::from_bits_retain(typ),
                 pid,
+                kind,
             };
-            match handler.getlk(
+            match await_handler!(handler.getlk(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, getlk),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
                 lock_owner,
                 lock_info,
-            ) {
+            )) {
                 Ok(lock_info) => reply.locked(
                     lock_info.start,
                     lock_info.end,
@@ -329,7 +420,12 @@ where
         let resolver = self.get_resolver();
         let name = name.to_owned();
         execute_task!(self, {
-            match handler.getxattr(&req, resolver.resolve_id(ino), &name, size) {
+            match await_handler!(handler.getxattr(
+                &req,
+                resolve_id!(resolver, &req, ino, reply, getxattr),
+                &name,
+                size
+            )) {
                 Ok(xattr_data) => {
                     if size == 0 {
                         reply.size(xattr_data.len() as u32);
@@ -363,15 +459,15 @@ where
         let resolver = self.get_resolver();
         let in_data = in_data.to_owned();
         execute_task!(self, {
-            match handler.ioctl(
+            match await_handler!(handler.ioctl(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, ioctl),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
                 IOCtlFlags::from_bits_retain(flags),
                 cmd,
                 in_data,
                 out_size,
-            ) {
+            )) {
                 Ok((result, data)) => reply.ioctl(result, &data),
                 Err(e) => {
                     warn!("ioctl: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -392,11 +488,13 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
+        let settings = self.settings;
         let newname = newname.to_owned();
         execute_task!(self, {
             handle_fuse_reply_entry!(
                 handler,
                 resolver,
+                settings,
                 &req,
                 newparent,
                 &newname,
@@ -404,10 +502,11 @@ where
                 link,
                 (
                     &req,
-                    resolver.resolve_id(ino),
-                    resolver.resolve_id(newparent),
+                    resolve_id!(resolver, &req, ino, reply, link),
+                    resolve_id!(resolver, &req, newparent, reply, link),
                     &newname
-                )
+                ),
+                linking ino
             );
         });
     }
@@ -417,7 +516,11 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.listxattr(&req, resolver.resolve_id(ino), size) {
+            match await_handler!(handler.listxattr(
+                &req,
+                resolve_id!(resolver, &req, ino, reply, listxattr),
+                size
+            )) {
                 Ok(xattr_data) => {
                     if size == 0 {
                         reply.size(xattr_data.len() as u32);
@@ -439,17 +542,23 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
+        let settings = self.settings;
         let name = name.to_owned();
         execute_task!(self, {
             handle_fuse_reply_entry!(
                 handler,
                 resolver,
+                settings,
                 &req,
                 parent,
                 &name,
                 reply,
                 lookup,
-                (&req, resolver.resolve_id(parent), &name)
+                (
+                    &req,
+                    resolve_id!(resolver, &req, parent, reply, lookup),
+                    &name
+                )
             );
         });
     }
@@ -464,15 +573,23 @@ where
         reply: ReplyLseek,
     ) {
         let req = RequestInfo::from(req);
+        let Some(seek) = lseek_from_raw(whence, offset) else {
+            warn!(
+                "lseek: ino {:x?}, invalid whence {} or offset {}, {:?}",
+                ino, whence, offset, req
+            );
+            reply.error(libc::EINVAL);
+            return;
+        };
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.lseek(
+            match await_handler!(handler.lseek(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, lseek),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
-                seek_from_raw(Some(whence), offset),
-            ) {
+                seek,
+            )) {
                 Ok(new_offset) => reply.offset(new_offset),
                 Err(e) => {
                     warn!("lseek: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -494,17 +611,25 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
+        let settings = self.settings;
         let name = name.to_owned();
         execute_task!(self, {
             handle_fuse_reply_entry!(
                 handler,
                 resolver,
+                settings,
                 &req,
                 parent,
                 &name,
                 reply,
                 mkdir,
-                (&req, resolver.resolve_id(parent), &name, mode, umask)
+                (
+                    &req,
+                    resolve_id!(resolver, &req, parent, reply, mkdir),
+                    &name,
+                    mode,
+                    umask
+                )
             );
         });
     }
@@ -522,11 +647,13 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
+        let settings = self.settings;
         let name = name.to_owned();
         execute_task!(self, {
             handle_fuse_reply_entry!(
                 handler,
                 resolver,
+                settings,
                 &req,
                 parent,
                 &name,
@@ -534,7 +661,7 @@ where
                 mknod,
                 (
                     &req,
-                    resolver.resolve_id(parent),
+                    resolve_id!(resolver, &req, parent, reply, mknod),
                     &name,
                     mode,
                     umask,
@@ -549,11 +676,11 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.open(
+            match await_handler!(handler.open(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, open),
                 OpenFlags::from_bits_retain(_flags),
-            ) {
+            )) {
                 Ok((file_handle, response_flags)) => {
                     reply.opened(file_handle.as_raw(), response_flags.bits())
                 }
@@ -570,11 +697,11 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.opendir(
+            match await_handler!(handler.opendir(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, opendir),
                 OpenFlags::from_bits_retain(_flags),
-            ) {
+            )) {
                 Ok((file_handle, response_flags)) => {
                     reply.opened(file_handle.as_raw(), response_flags.bits())
                 }
@@ -586,6 +713,37 @@ where
         });
     }
 
+    fn poll(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        ph: PollHandle,
+        events: u32,
+        flags: u32,
+        reply: ReplyPoll,
+    ) {
+        let req = RequestInfo::from(req);
+        let handler = self.get_handler();
+        let resolver = self.get_resolver();
+        execute_task!(self, {
+            match await_handler!(handler.poll(
+                &req,
+                resolve_id!(resolver, &req, ino, reply, poll),
+                unsafe { BorrowedFileHandle::from_raw(fh) },
+                ph,
+                PollEvents::from_bits_retain(events),
+                FUSEPollFlags::from_bits_retain(flags),
+            )) {
+                Ok(ready_events) => reply.poll(ready_events.bits()),
+                Err(e) => {
+                    warn!("poll: ino {:x?}, [{}], {:?}", ino, e, req);
+                    reply.error(e.raw_error())
+                }
+            };
+        });
+    }
+
     fn read(
         &mut self,
         req: &Request,
@@ -601,16 +759,16 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.read(
+            match await_handler!(handler.read_buf(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, read),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
                 seek_from_raw(None, offset),
                 size,
                 FUSEOpenFlags::from_bits_retain(flags),
                 lock_owner,
-            ) {
-                Ok(data_reply) => reply.data(&data_reply),
+            )) {
+                Ok(data_reply) => reply.data(data_reply.as_slice()),
                 Err(e) => {
                     warn!("read: ino {:x?}, [{}], {:?}", ino, e, req);
                     reply.error(e.raw_error())
@@ -634,7 +792,7 @@ where
             fh,
             offset,
             reply,
-            readdir,
+            readdir_iter,
             get_dirmap_iter,
             ReplyDirectory
         );
@@ -655,7 +813,7 @@ where
             fh,
             offset,
             reply,
-            readdirplus,
+            readdirplus_iter,
             get_dirmapplus_iter,
             ReplyDirectoryPlus
         );
@@ -666,7 +824,9 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.readlink(&req, resolver.resolve_id(ino)) {
+            match await_handler!(
+                handler.readlink(&req, resolve_id!(resolver, &req, ino, reply, readlink))
+            ) {
                 Ok(link) => reply.data(&link),
                 Err(e) => {
                     warn!("[{}] readlink, ino: {:x?}, {:?}", ino, e, req);
@@ -690,14 +850,14 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.release(
+            match await_handler!(handler.release(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, release),
                 unsafe { OwnedFileHandle::from_raw(fh) },
                 OpenFlags::from_bits_retain(_flags),
                 _lock_owner,
                 _flush,
-            ) {
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("release: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -709,15 +869,20 @@ where
 
     fn releasedir(&mut self, req: &Request, ino: u64, fh: u64, flags: i32, reply: ReplyEmpty) {
         let req = RequestInfo::from(req);
+        // Directory streams of this handle end with it, even when they were not read to the end
+        self.get_dirmap_iter().safe_borrow_mut().remove(&(ino, fh));
+        self.get_dirmapplus_iter()
+            .safe_borrow_mut()
+            .remove(&(ino, fh));
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.releasedir(
+            match await_handler!(handler.releasedir(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, releasedir),
                 unsafe { OwnedFileHandle::from_raw(fh) },
                 OpenFlags::from_bits_retain(flags),
-            ) {
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("releasedir: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -733,7 +898,11 @@ where
         let resolver = self.get_resolver();
         let name = name.to_owned();
         execute_task!(self, {
-            match handler.removexattr(&req, resolver.resolve_id(ino), &name) {
+            match await_handler!(handler.removexattr(
+                &req,
+                resolve_id!(resolver, &req, ino, reply, removexattr),
+                &name
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("removexattr: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -759,16 +928,20 @@ where
         let name = name.to_owned();
         let newname = newname.to_owned();
         execute_task!(self, {
-            match handler.rename(
+            match await_handler!(handler.rename(
                 &req,
-                resolver.resolve_id(parent),
+                resolve_id!(resolver, &req, parent, reply, rename),
                 &name,
-                resolver.resolve_id(newparent),
+                resolve_id!(resolver, &req, newparent, reply, rename),
                 &newname,
                 RenameFlags::from_bits_retain(flags),
-            ) {
+            )) {
                 Ok(()) => {
-                    resolver.rename(parent, &name, newparent, &newname);
+                    // The file was renamed by the handler, so the rename succeeded even if the
+                    // resolver did not know the entry
+                    if let Err(e) = resolver.rename(parent, &name, newparent, &newname) {
+                        log_resolver_error!(resolver, &req, parent, e, rename);
+                    }
                     reply.ok()
                 }
                 Err(e) => {
@@ -785,8 +958,17 @@ where
         let resolver = self.get_resolver();
         let name = name.to_owned();
         execute_task!(self, {
-            match handler.rmdir(&req, resolver.resolve_id(parent), &name) {
-                Ok(()) => reply.ok(),
+            match await_handler!(handler.rmdir(
+                &req,
+                resolve_id!(resolver, &req, parent, reply, rmdir),
+                &name
+            )) {
+                Ok(()) => {
+                    if let Err(e) = resolver.unlink(parent, &name) {
+                        log_resolver_error!(resolver, &req, parent, e, rmdir);
+                    }
+                    reply.ok()
+                }
                 Err(e) => {
                     warn!("[{}] rmdir: parent_ino: {:x?}, {:?}", parent, e, req);
                     reply.error(e.raw_error())
@@ -816,6 +998,7 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
+        let settings = self.settings;
         let attrs = SetAttrRequest {
             mode,
             uid,
@@ -834,11 +1017,16 @@ where
             handle_fuse_reply_attr!(
                 handler,
                 resolver,
+                settings,
                 &req,
                 ino,
                 reply,
                 setattr,
-                (&req, resolver.resolve_id(ino), attrs)
+                (
+                    &req,
+                    resolve_id!(resolver, &req, ino, reply, setattr),
+                    attrs
+                )
             );
         });
     }
@@ -859,21 +1047,25 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
-        execute_task!(self, {
+        let kind = handler.remote_locks().unwrap_or_default();
+        // A request with `sleep` set waits for the release of a conflicting lock: waiting in the thread pool
+        // could take all its threads, leaving none to handle the unlock
+        execute_task!(self, blocking: sleep, {
             let lock_info = LockInfo {
                 start,
                 end,
                 lock_type: LockType::from_bits_retain(typ),
                 pid,
+                kind,
             };
-            match handler.setlk(
+            match await_handler!(handler.setlk(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, setlk),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
                 lock_owner,
                 lock_info,
                 sleep,
-            ) {
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("setlk: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -899,14 +1091,14 @@ where
         let name = name.to_owned();
         let value = value.to_owned();
         execute_task!(self, {
-            match handler.setxattr(
+            match await_handler!(handler.setxattr(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, setxattr),
                 &name,
                 value,
                 FUSESetXAttrFlags::from_bits_retain(flags),
                 position,
-            ) {
+            )) {
                 Ok(()) => reply.ok(),
                 Err(e) => {
                     warn!("setxattr: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -921,7 +1113,9 @@ where
         let handler = self.get_handler();
         let resolver = self.get_resolver();
         execute_task!(self, {
-            match handler.statfs(&req, resolver.resolve_id(ino)) {
+            match await_handler!(
+                handler.statfs(&req, resolve_id!(resolver, &req, ino, reply, statfs))
+            ) {
                 Ok(statfs) => reply.statfs(
                     statfs.total_blocks,
                     statfs.free_blocks,
@@ -951,18 +1145,25 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
+        let settings = self.settings;
         let link_name = link_name.to_owned();
         let target = target.to_owned();
         execute_task!(self, {
             handle_fuse_reply_entry!(
                 handler,
                 resolver,
+                settings,
                 &req,
                 parent,
                 &link_name,
                 reply,
                 symlink,
-                (&req, resolver.resolve_id(parent), &link_name, &target)
+                (
+                    &req,
+                    resolve_id!(resolver, &req, parent, reply, symlink),
+                    &link_name,
+                    &target
+                )
             );
         });
     }
@@ -982,18 +1183,30 @@ where
         let req = RequestInfo::from(req);
         let handler = self.get_handler();
         let resolver = self.get_resolver();
-        let data = data.to_owned();
+        // The payload is only borrowed for the duration of this call: when the request is handled by
+        // another thread, it is copied into a pooled buffer if the handler writes from it, otherwise
+        // into the `Vec` taken by `write`, as a pooled buffer handed to `write` would never come back
+        #[cfg(not(feature = "serial"))]
+        let data = if handler.pooled_writes() {
+            let mut buffer = BufferPool::global().get(data.len());
+            buffer.extend_from_slice(data);
+            WriteData::Pooled(buffer)
+        } else {
+            WriteData::Owned(data.to_vec())
+        };
+        #[cfg(feature = "serial")]
+        let data = WriteData::Borrowed(data);
         execute_task!(self, {
-            match handler.write(
+            match await_handler!(handler.write_buf(
                 &req,
-                resolver.resolve_id(ino),
+                resolve_id!(resolver, &req, ino, reply, write),
                 unsafe { BorrowedFileHandle::from_raw(fh) },
                 seek_from_raw(None, offset),
                 data,
                 FUSEWriteFlags::from_bits_retain(write_flags),
                 OpenFlags::from_bits_retain(flags),
                 lock_owner,
-            ) {
+            )) {
                 Ok(bytes_written) => reply.written(bytes_written),
                 Err(e) => {
                     warn!("write: ino {:x?}, [{}], {:?}", ino, e, req);
@@ -1009,8 +1222,17 @@ where
         let resolver = self.get_resolver();
         let name = name.to_owned();
         execute_task!(self, {
-            match handler.unlink(&req, resolver.resolve_id(parent), &name) {
-                Ok(()) => reply.ok(),
+            match await_handler!(handler.unlink(
+                &req,
+                resolve_id!(resolver, &req, parent, reply, unlink),
+                &name
+            )) {
+                Ok(()) => {
+                    if let Err(e) = resolver.unlink(parent, &name) {
+                        log_resolver_error!(resolver, &req, parent, e, unlink);
+                    }
+                    reply.ok()
+                }
                 Err(e) => {
                     warn!("[{}] unlink: parent_ino: {:x?}, {:?}", parent, e, req);
                     reply.error(e.raw_error())
@@ -3,13 +3,79 @@
 use std::{
     collections::{HashMap, VecDeque},
     ffi::{OsStr, OsString},
+    sync::Arc,
+    time::Duration,
 };
 
 use super::inode_mapping::FileIdResolver;
-use crate::fuse_handler::FuseHandler;
 use crate::types::*;
 
-type DirIter<TAttr> = HashMap<(u64, i64), VecDeque<(OsString, u64, TAttr)>>;
+#[cfg(not(feature = "async"))]
+pub(crate) use crate::fuse_handler::FuseHandler as FuseDriverHandler;
+
+#[cfg(feature = "async")]
+pub(crate) use crate::async_fuse_handler::AsyncFuseHandler as FuseDriverHandler;
+
+/// State of a directory stream between two reads of the kernel
+pub(crate) struct DirCursor<TMetadata, TAttr> {
+    /// Offset of the last entry returned, from which the next read resumes
+    pub offset: i64,
+    /// Entry already resolved that did not fit in the previous reply
+    pub pending: Option<(OsString, u64, TAttr)>,
+    pub entries: ReaddirIter<TMetadata>,
+}
+
+impl<TMetadata, TAttr> DirCursor<TMetadata, TAttr> {
+    pub fn new(entries: ReaddirIter<TMetadata>, offset: i64) -> Self {
+        DirCursor {
+            offset,
+            pending: None,
+            entries,
+        }
+    }
+}
+
+/// Directory streams keyed by the inode and the file handle of the open directory, removed by `releasedir`
+type DirIter<TMetadata, TAttr> = HashMap<(u64, u64), DirCursor<TMetadata, TAttr>>;
+
+/// Settings of the driver given by the `MountBuilder`, which take precedence over the handler
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct DriverSettings {
+    /// Replaces `get_default_entry_ttl` of the handler
+    pub entry_ttl: Option<Duration>,
+    /// Replaces `get_default_attr_ttl` of the handler
+    pub attr_ttl: Option<Duration>,
+    /// Replaces `get_negative_ttl` of the handler
+    pub negative_ttl: Option<Duration>,
+    pub max_write: Option<u32>,
+    pub max_readahead: Option<u32>,
+    /// Capabilities requested from the kernel during `init`, see `FUSECapabilities`
+    pub capabilities: u64,
+}
+
+impl DriverSettings {
+    /// Default TTL of the entries returned without their own TTL
+    pub fn entry_ttl(&self, handler_ttl: impl FnOnce() -> Duration) -> Duration {
+        self.entry_ttl.unwrap_or_else(handler_ttl)
+    }
+
+    /// Default TTL of the attributes returned without their own TTL
+    pub fn attr_ttl(&self, handler_ttl: impl FnOnce() -> Duration) -> Duration {
+        self.attr_ttl.unwrap_or_else(handler_ttl)
+    }
+
+    /// TTL of the negative entry replied for a missing file, or None to reply with the error
+    pub fn negative_ttl(
+        &self,
+        error: &PosixError,
+        handler_ttl: impl FnOnce() -> Option<Duration>,
+    ) -> Option<Duration> {
+        error
+            .negative_ttl()
+            .or(self.negative_ttl)
+            .or_else(handler_ttl)
+    }
+}
 
 #[cfg(feature = "serial")]
 mod serial {
@@ -20,26 +86,39 @@ mod serial {
     pub(crate) struct FuseDriver<TId, THandler>
     where
         TId: FileIdType,
-        THandler: FuseHandler<TId>,
+        THandler: FuseDriverHandler<TId>,
     {
         handler: THandler,
-        resolver: TId::Resolver,
-        dirmap_iter: RefCell<DirIter<FileKind>>,
-        dirmapplus_iter: RefCell<DirIter<FileAttribute>>,
+        resolver: Arc<TId::Resolver>,
+        notifier: Notifier<TId>,
+        dirmap_iter: RefCell<DirIter<TId::MinimalMetadata, FileKind>>,
+        dirmapplus_iter: RefCell<DirIter<TId::Metadata, FileAttribute>>,
+        pub settings: DriverSettings,
     }
 
     impl<TId, THandler> FuseDriver<TId, THandler>
     where
         TId: FileIdType,
-        THandler: FuseHandler<TId>,
+        THandler: FuseDriverHandler<TId>,
     {
         /// num_thread is ignored in serial mode, it is kept for consistency with other modes
-        pub fn new(handler: THandler, _num_threads: usize) -> FuseDriver<TId, THandler> {
+        pub fn new(handler: THandler, num_threads: usize) -> FuseDriver<TId, THandler> {
+            Self::with_resolver(handler, TId::create_resolver(), num_threads)
+        }
+
+        pub fn with_resolver(
+            handler: THandler,
+            resolver: TId::Resolver,
+            _num_threads: usize,
+        ) -> FuseDriver<TId, THandler> {
+            let resolver = Arc::new(resolver);
             FuseDriver {
                 handler,
-                resolver: TId::Resolver::new(),
+                notifier: Notifier::new(resolver.clone()),
+                resolver,
                 dirmap_iter: RefCell::new(HashMap::new()),
                 dirmapplus_iter: RefCell::new(HashMap::new()),
+                settings: DriverSettings::default(),
             }
         }
 
@@ -51,19 +130,27 @@ mod serial {
             &self.resolver
         }
 
-        pub fn get_dirmap_iter(&self) -> &RefCell<DirIter<FileKind>> {
+        pub fn get_notifier(&self) -> Notifier<TId> {
+            self.notifier.clone()
+        }
+
+        pub fn get_dirmap_iter(&self) -> &RefCell<DirIter<TId::MinimalMetadata, FileKind>> {
             &self.dirmap_iter
         }
 
-        pub fn get_dirmapplus_iter(&self) -> &RefCell<DirIter<FileAttribute>> {
+        pub fn get_dirmapplus_iter(&self) -> &RefCell<DirIter<TId::Metadata, FileAttribute>> {
             &self.dirmapplus_iter
         }
     }
 
+    /// Runs the task on the thread of the session, even if it may block
     macro_rules! execute_task {
         ($self:expr, $block:block) => {
             $block
         };
+        ($self:expr, blocking: $blocking:expr, $block:block) => {
+            $block
+        };
     }
 
     pub(crate) use execute_task;
@@ -73,8 +160,6 @@ mod serial {
 mod parallel {
     use super::*;
 
-    use std::sync::Arc;
-
     use threadpool::ThreadPool;
 
     #[cfg(feature = "deadlock_detection")]
@@ -85,29 +170,42 @@ mod parallel {
     pub(crate) struct FuseDriver<TId, THandler>
     where
         TId: FileIdType,
-        THandler: FuseHandler<TId>,
+        THandler: FuseDriverHandler<TId>,
     {
         handler: Arc<THandler>,
         resolver: Arc<TId::Resolver>,
-        dirmap_iter: Arc<Mutex<DirIter<FileKind>>>,
-        dirmapplus_iter: Arc<Mutex<DirIter<FileAttribute>>>,
+        notifier: Notifier<TId>,
+        dirmap_iter: Arc<Mutex<DirIter<TId::MinimalMetadata, FileKind>>>,
+        dirmapplus_iter: Arc<Mutex<DirIter<TId::Metadata, FileAttribute>>>,
         pub threadpool: ThreadPool,
+        pub settings: DriverSettings,
     }
 
     impl<TId, THandler> FuseDriver<TId, THandler>
     where
         TId: FileIdType,
-        THandler: FuseHandler<TId>,
+        THandler: FuseDriverHandler<TId>,
     {
         pub fn new(handler: THandler, num_threads: usize) -> FuseDriver<TId, THandler> {
+            Self::with_resolver(handler, TId::create_resolver(), num_threads)
+        }
+
+        pub fn with_resolver(
+            handler: THandler,
+            resolver: TId::Resolver,
+            num_threads: usize,
+        ) -> FuseDriver<TId, THandler> {
             #[cfg(feature = "deadlock_detection")]
             spawn_deadlock_checker();
+            let resolver = Arc::new(resolver);
             FuseDriver {
                 handler: Arc::new(handler),
-                resolver: Arc::new(TId::create_resolver()),
+                notifier: Notifier::new(resolver.clone()),
+                resolver,
                 dirmap_iter: Arc::new(Mutex::new(HashMap::new())),
                 dirmapplus_iter: Arc::new(Mutex::new(HashMap::new())),
                 threadpool: ThreadPool::new(num_threads),
+                settings: DriverSettings::default(),
             }
         }
 
@@ -119,19 +217,31 @@ mod parallel {
             self.resolver.clone()
         }
 
-        pub fn get_dirmap_iter(&self) -> Arc<Mutex<DirIter<FileKind>>> {
+        pub fn get_notifier(&self) -> Notifier<TId> {
+            self.notifier.clone()
+        }
+
+        pub fn get_dirmap_iter(&self) -> Arc<Mutex<DirIter<TId::MinimalMetadata, FileKind>>> {
             self.dirmap_iter.clone()
         }
 
-        pub fn get_dirmapplus_iter(&self) -> Arc<Mutex<DirIter<FileAttribute>>> {
+        pub fn get_dirmapplus_iter(&self) -> Arc<Mutex<DirIter<TId::Metadata, FileAttribute>>> {
             self.dirmapplus_iter.clone()
         }
     }
 
+    /// Runs the task in the thread pool, or in a dedicated thread if it may block until another request is handled
     macro_rules! execute_task {
         ($self:expr, $block:block) => {
             $self.threadpool.execute(move || $block);
         };
+        ($self:expr, blocking: $blocking:expr, $block:block) => {
+            if $blocking {
+                std::thread::spawn(move || $block);
+            } else {
+                $self.threadpool.execute(move || $block);
+            }
+        };
     }
 
     pub(crate) use execute_task;
@@ -141,36 +251,51 @@ mod parallel {
 mod async_task {
     use super::*;
 
-    use std::sync::Arc;
-    use tokio::runtime::Runtime;
-    use tokio::sync::Mutex;
+    use std::sync::Mutex;
+    use tokio::runtime::{Builder, Runtime};
 
     pub(crate) struct FuseDriver<TId, THandler>
     where
         TId: FileIdType,
-        THandler: FuseHandler<TId>,
+        THandler: FuseDriverHandler<TId>,
     {
         handler: Arc<THandler>,
         resolver: Arc<TId::Resolver>,
-        dirmap_iter: Arc<Mutex<DirIter<FileKind>>>,
-        dirmapplus_iter: Arc<Mutex<DirIter<FileAttribute>>>,
+        notifier: Notifier<TId>,
+        dirmap_iter: Arc<Mutex<DirIter<TId::MinimalMetadata, FileKind>>>,
+        dirmapplus_iter: Arc<Mutex<DirIter<TId::Metadata, FileAttribute>>>,
         pub runtime: Runtime,
+        pub settings: DriverSettings,
     }
 
     impl<TId, THandler> FuseDriver<TId, THandler>
     where
         TId: FileIdType,
-        THandler: FuseHandler<TId>,
+        THandler: FuseDriverHandler<TId>,
     {
-        pub fn new(handler: THandler, _num_threads: usize) -> FuseDriver<TId, THandler> {
-            #[cfg(feature = "deadlock_detection")]
-            spawn_deadlock_checker();
+        /// num_threads is the number of worker threads of the tokio runtime
+        pub fn new(handler: THandler, num_threads: usize) -> FuseDriver<TId, THandler> {
+            Self::with_resolver(handler, TId::create_resolver(), num_threads)
+        }
+
+        pub fn with_resolver(
+            handler: THandler,
+            resolver: TId::Resolver,
+            num_threads: usize,
+        ) -> FuseDriver<TId, THandler> {
+            let resolver = Arc::new(resolver);
             FuseDriver {
                 handler: Arc::new(handler),
-                resolver: Arc::new(TId::create_resolver()),
+                notifier: Notifier::new(resolver.clone()),
+                resolver,
                 dirmap_iter: Arc::new(Mutex::new(HashMap::new())),
                 dirmapplus_iter: Arc::new(Mutex::new(HashMap::new())),
-                runtime: Runtime::new().unwrap(),
+                runtime: Builder::new_multi_thread()
+                    .worker_threads(num_threads)
+                    .enable_all()
+                    .build()
+                    .expect("Failed to build the tokio runtime"),
+                settings: DriverSettings::default(),
             }
         }
 
@@ -182,24 +307,74 @@ mod async_task {
             self.resolver.clone()
         }
 
-        pub fn get_dirmap_iter(&self) -> Arc<Mutex<DirIter<FileKind>>> {
+        pub fn get_notifier(&self) -> Notifier<TId> {
+            self.notifier.clone()
+        }
+
+        pub fn get_dirmap_iter(&self) -> Arc<Mutex<DirIter<TId::MinimalMetadata, FileKind>>> {
             self.dirmap_iter.clone()
         }
 
-        pub fn get_dirmapplus_iter(&self) -> Arc<Mutex<DirIter<FileAttribute>>> {
+        pub fn get_dirmapplus_iter(&self) -> Arc<Mutex<DirIter<TId::Metadata, FileAttribute>>> {
             self.dirmapplus_iter.clone()
         }
     }
 
+    /// Spawns the task on the runtime, or on a blocking thread if it may block until another request is handled
     macro_rules! execute_task {
         ($self:expr, $block:block) => {
             $self.runtime.spawn(async move { $block });
         };
+        ($self:expr, blocking: $blocking:expr, $block:block) => {
+            if $blocking {
+                $self.runtime.spawn_blocking(move || {
+                    tokio::runtime::Handle::current().block_on(async move { $block })
+                });
+            } else {
+                $self.runtime.spawn(async move { $block });
+            }
+        };
     }
 
+    /// Awaits the future returned by an `AsyncFuseHandler` method inside a task
+    macro_rules! await_handler {
+        ($call:expr) => {
+            $call.await
+        };
+    }
+
+    /// Drives an `AsyncFuseHandler` method to completion outside of a task
+    macro_rules! block_on_handler {
+        ($self:expr, $call:expr) => {
+            $self.runtime.block_on($call)
+        };
+    }
+
+    pub(crate) use await_handler;
+    pub(crate) use block_on_handler;
     pub(crate) use execute_task;
 }
 
+#[cfg(not(feature = "async"))]
+mod sync_handler {
+    /// Synchronous handlers return their result directly
+    macro_rules! await_handler {
+        ($call:expr) => {
+            $call
+        };
+    }
+
+    /// Synchronous handlers return their result directly
+    macro_rules! block_on_handler {
+        ($self:expr, $call:expr) => {
+            $call
+        };
+    }
+
+    pub(crate) use await_handler;
+    pub(crate) use block_on_handler;
+}
+
 #[cfg(feature = "deadlock_detection")]
 fn spawn_deadlock_checker() {
     use log::{error, info};
@@ -234,3 +409,6 @@ pub use parallel::*;
 
 #[cfg(feature = "async")]
 pub use async_task::*;
+
+#[cfg(not(feature = "async"))]
+pub use sync_handler::*;
@@ -1,21 +1,35 @@
 use std::{
+    collections::VecDeque,
     ffi::{OsStr, OsString},
     path::PathBuf,
     sync::atomic::Ordering,
+    time::{Duration, Instant},
 };
 
-use std::sync::{atomic::AtomicU64, RwLock};
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize},
+    Arc, Mutex, RwLock,
+};
 
 use crate::inode_mapper::*;
+use crate::inode_store::InodeStore;
 use crate::types::*;
 
 pub(crate) const ROOT_INO: u64 = 1;
 
-/// Trait to allow a FileIdType to be mapped to use a converter
+/// Associates a [`FileIdType`] with the [`FileIdResolver`] translating it from and to inode numbers.
+///
+/// The driver creates the resolver with `create_resolver` when the filesystem is mounted.
+/// See [`FileIdType`] for an example of a custom file id type.
 pub trait InodeResolvable {
     type Resolver: FileIdResolver<ResolvedType = Self>;
 
     fn create_resolver() -> Self::Resolver;
+
+    /// Creates a resolver keeping the inode numbers in `store`, returns None if the type does not support it
+    fn create_resolver_with_inode_store(_store: InodeStore) -> Option<Self::Resolver> {
+        None
+    }
 }
 
 impl InodeResolvable for PathBuf {
@@ -24,6 +38,10 @@ impl InodeResolvable for PathBuf {
     fn create_resolver() -> Self::Resolver {
         PathResolver::new()
     }
+
+    fn create_resolver_with_inode_store(store: InodeStore) -> Option<Self::Resolver> {
+        Some(PathResolver::with_inode_store(store))
+    }
 }
 
 impl InodeResolvable for Inode {
@@ -40,32 +58,98 @@ impl InodeResolvable for Vec<OsString> {
     fn create_resolver() -> Self::Resolver {
         ComponentsResolver::new()
     }
+
+    fn create_resolver_with_inode_store(store: InodeStore) -> Option<Self::Resolver> {
+        Some(ComponentsResolver::with_inode_store(store))
+    }
 }
 
-/// FileIdResolver
-/// FileIdResolver handles its data behind Locks if needed and should not be nested inside a Mutex
+/// Translates the inode numbers used by the kernel to the file ids given to the handler, and back.
+///
+/// The kernel identifies files by inode numbers. Each time a handler returns a new entry (from `lookup`, `create`,
+/// `mkdir`, `readdir`...), the driver calls [`lookup`](FileIdResolver::lookup) with the parent inode, the name of the
+/// entry and the id part of its metadata ([`FileIdType::_Id`]), and replies with the inode number returned. Requests
+/// on that inode are then given to the handler with the id returned by [`resolve_id`](FileIdResolver::resolve_id).
+///
+/// # Contract
+///
+/// - The root directory has the inode number 1 (`ROOT_INODE`), and must resolve to the id for which
+///   [`FileIdType::is_filesystem_root`] returns true.
+/// - An inode number must keep resolving to the same file as long as the kernel holds a reference to it, that is until
+///   the lookups counted with `increment` are balanced by [`forget`](FileIdResolver::forget).
+/// - Two entries known at the same time must not share an inode number, unless they are the same file.
+///
+/// The methods are called concurrently from several threads (except with the `serial` feature), the resolver must
+/// handle its data behind locks if needed and should not be nested inside a Mutex.
+///
+/// # Errors
+///
+/// The kernel should only send inode numbers known by the resolver, but a resolver must not panic if it does not:
+/// an unknown inode should be reported with `ErrorKind::StaleFileHandle` (`ESTALE`), and an unknown entry with
+/// `ErrorKind::FileNotFound` (`ENOENT`). The driver replies to the request with the error instead of calling the
+/// handler, see the `resolver_debug` feature to log those errors with their full context.
+///
+/// # Provided resolvers
+///
+/// - [`InodeResolver`]: the id is the inode number, returned as is.
+/// - [`ComponentsResolver`]: attributes inode numbers to the path of each entry, stored as components in reverse order.
+///   Files hard-linked through the filesystem keep a single inode number, resolved to one of their paths.
+/// - [`PathResolver`]: same as `ComponentsResolver`, with paths.
 pub trait FileIdResolver: Send + Sync + 'static {
     type ResolvedType: FileIdType;
 
     fn new() -> Self;
-    fn resolve_id(&self, ino: u64) -> Self::ResolvedType;
+    /// Returns the id of the file with the inode number `ino`.
+    ///
+    /// The kernel only sends inode numbers previously returned by `lookup` and not forgotten since.
+    fn resolve_id(&self, ino: u64) -> FuseResult<Self::ResolvedType>;
+    /// Reverse of resolve_id, returns None if the id has no inode known by the kernel
+    ///
+    /// Resolvers which don't keep track of the references of the kernel may return the inode number anyway.
+    fn find_ino(&self, id: &Self::ResolvedType) -> Option<u64>;
+    /// Returns the inode number of the entry `child` of `parent`, whose id part is `id`.
+    ///
+    /// If `increment` is true, the kernel keeps a reference to the inode, which is released by `forget`.
     fn lookup(
         &self,
         parent: u64,
         child: &OsStr,
         id: <Self::ResolvedType as FileIdType>::_Id,
         increment: bool,
-    ) -> u64;
+    ) -> FuseResult<u64>;
+    /// Same as `lookup`, for several children of `parent`
     fn add_children(
         &self,
         parent: u64,
         children: Vec<(OsString, <Self::ResolvedType as FileIdType>::_Id)>,
         increment: bool,
-    ) -> Vec<(OsString, u64)>;
-    fn forget(&self, ino: u64, nlookup: u64);
-    fn rename(&self, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr);
+    ) -> FuseResult<Vec<(OsString, u64)>>;
+    /// Releases `nlookup` references of the kernel to `ino`, the inode can be discarded when none is left
+    fn forget(&self, ino: u64, nlookup: u64) -> FuseResult<()>;
+    /// Called after a successful rename, for resolvers mapping inodes to names
+    fn rename(&self, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr) -> FuseResult<()>;
+    /// Called after a successful link, before looking up the new entry: `newname` in `newparent` becomes another name
+    /// of `ino`, for resolvers mapping inodes to names
+    fn link(&self, _ino: u64, _newparent: u64, _newname: &OsStr) -> FuseResult<()> {
+        Ok(())
+    }
+    /// Called after a successful unlink or rmdir, for resolvers mapping inodes to names
+    fn unlink(&self, _parent: u64, _name: &OsStr) -> FuseResult<()> {
+        Ok(())
+    }
+    /// Generation number of the inode `ino`, None to let the driver choose one
+    ///
+    /// An inode number given to another file must come with another generation number.
+    fn generation(&self, _ino: u64) -> Option<u64> {
+        None
+    }
+    /// Size of the resolver, for resolvers keeping track of the inodes
+    fn stats(&self) -> ResolverStats {
+        ResolverStats::default()
+    }
 }
 
+/// Resolver of [`Inode`] ids, which are the inode numbers themselves
 pub struct InodeResolver {}
 
 impl FileIdResolver for InodeResolver {
@@ -75,12 +159,17 @@ impl FileIdResolver for InodeResolver {
         Self {}
     }
 
-    fn resolve_id(&self, ino: u64) -> Self::ResolvedType {
-        Inode::from(ino)
+    fn resolve_id(&self, ino: u64) -> FuseResult<Self::ResolvedType> {
+        Ok(Inode::from(ino))
+    }
+
+    /// Returns the inode number as is, the references of the kernel are not tracked
+    fn find_ino(&self, id: &Inode) -> Option<u64> {
+        Some(id.clone().into())
     }
 
-    fn lookup(&self, _parent: u64, _child: &OsStr, id: Inode, _increment: bool) -> u64 {
-        id.into()
+    fn lookup(&self, _parent: u64, _child: &OsStr, id: Inode, _increment: bool) -> FuseResult<u64> {
+        Ok(id.into())
     }
 
     // Do nothing, user should provide its own inode
@@ -89,62 +178,310 @@ impl FileIdResolver for InodeResolver {
         _parent: u64,
         children: Vec<(OsString, Inode)>,
         _increment: bool,
-    ) -> Vec<(OsString, u64)> {
-        children
+    ) -> FuseResult<Vec<(OsString, u64)>> {
+        Ok(children
             .into_iter()
             .map(|(name, inode)| (name, u64::from(inode)))
-            .collect()
+            .collect())
+    }
+
+    fn forget(&self, _ino: u64, _nlookup: u64) -> FuseResult<()> {
+        Ok(())
+    }
+
+    fn rename(
+        &self,
+        _parent: u64,
+        _name: &OsStr,
+        _newparent: u64,
+        _newname: &OsStr,
+    ) -> FuseResult<()> {
+        Ok(())
+    }
+}
+
+/// Default maximum number of entries of the [`ComponentsResolver`] and [`PathResolver`]
+pub const DEFAULT_MAX_RESOLVER_ENTRIES: usize = 1 << 17;
+
+/// Controls when a [`ComponentsResolver`] or [`PathResolver`] discards the entries not referenced by the kernel.
+///
+/// Entries are referenced by the kernel from the lookup returning them until they are forgotten. Entries listed by
+/// `readdir` are never referenced, and referenced entries become unreferenced once forgotten: such entries are kept
+/// so that the file keeps its inode number, but can be evicted to bound the memory used by the resolver. Only entries
+/// without children are evicted, directories are evicted once their children are. Unreferenced entries are queued in
+/// their order of use, so that an eviction only visits the entries it discards rather than the whole resolver.
+///
+/// An evicted entry gets a new inode number when looked up again, unless the resolver uses an [`InodeStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvictionPolicy {
+    /// Maximum number of entries. When exceeded, the least recently used unreferenced entries are evicted until
+    /// a quarter of the capacity is free again.
+    pub max_entries: Option<usize>,
+    /// Interval at which every unreferenced entry is evicted, checked when new entries are added
+    pub interval: Option<Duration>,
+}
+
+impl EvictionPolicy {
+    /// Never evicts entries, the resolver only shrinks when entries are forgotten
+    pub fn disabled() -> Self {
+        EvictionPolicy {
+            max_entries: None,
+            interval: None,
+        }
+    }
+}
+
+impl Default for EvictionPolicy {
+    /// Keeps at most [`DEFAULT_MAX_RESOLVER_ENTRIES`] entries
+    fn default() -> Self {
+        EvictionPolicy {
+            max_entries: Some(DEFAULT_MAX_RESOLVER_ENTRIES),
+            interval: None,
+        }
     }
+}
+
+/// Size of a resolver, see [`FileIdResolver::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResolverStats {
+    /// Number of inodes known by the resolver, including the root
+    pub entries: usize,
+    /// Number of inodes referenced by the kernel, including the root
+    pub referenced_entries: usize,
+    /// Number of inodes evicted since the resolver was created
+    pub evicted_entries: u64,
+}
 
-    fn forget(&self, _ino: u64, _nlookup: u64) {}
+/// Lookup count and last access of an inode of a [`ComponentsResolver`]
+struct ResolverEntry {
+    nlookup: AtomicU64,
+    /// Tick of the latest record of the entry in the queue of unreferenced entries
+    last_used: AtomicU64,
+}
+
+impl ResolverEntry {
+    fn new(nlookup: u64, now: u64) -> Self {
+        ResolverEntry {
+            nlookup: AtomicU64::new(nlookup),
+            last_used: AtomicU64::new(now),
+        }
+    }
+}
 
-    fn rename(&self, _parent: u64, _name: &OsStr, _newparent: u64, _newname: &OsStr) {}
+fn unknown_inode(ino: u64) -> PosixError {
+    ErrorKind::StaleFileHandle.to_error(format!("Unknown inode {:x}", ino))
 }
 
+/// Resolver of `Vec<OsString>` ids, attributing an inode number to each path looked up
+///
+/// Entries unreferenced by the kernel are evicted according to an [`EvictionPolicy`].
 pub struct ComponentsResolver {
-    mapper: RwLock<InodeMapper<AtomicU64>>,
+    mapper: RwLock<InodeMapper<ResolverEntry>>,
+    store: Option<Arc<Mutex<InodeStore>>>,
+    policy: EvictionPolicy,
+    /// Logical clock ordering the accesses to the entries
+    clock: AtomicU64,
+    /// Number of entries above which the next eviction happens
+    eviction_threshold: AtomicUsize,
+    /// Unreferenced entries from the least to the most recently used, records older than the
+    /// last use of their entry, or of entries referenced since, are skipped by the eviction
+    unreferenced: Mutex<VecDeque<(u64, Inode)>>,
+    last_sweep: Mutex<Instant>,
+    evicted: AtomicU64,
+}
+
+impl ComponentsResolver {
+    fn with_mapper(
+        mapper: InodeMapper<ResolverEntry>,
+        store: Option<Arc<Mutex<InodeStore>>>,
+    ) -> Self {
+        ComponentsResolver {
+            mapper: RwLock::new(mapper),
+            store,
+            policy: EvictionPolicy::default(),
+            clock: AtomicU64::new(0),
+            eviction_threshold: AtomicUsize::new(DEFAULT_MAX_RESOLVER_ENTRIES),
+            unreferenced: Mutex::new(VecDeque::new()),
+            last_sweep: Mutex::new(Instant::now()),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates a resolver attributing the inode numbers saved in `store`
+    pub fn with_inode_store(store: InodeStore) -> Self {
+        let store = Arc::new(Mutex::new(store));
+        let mut mapper = InodeMapper::new(ResolverEntry::new(0, 0));
+        let allocator_store = store.clone();
+        mapper.set_inode_allocator(move |parent, name| {
+            Inode::from(
+                allocator_store
+                    .lock()
+                    .unwrap()
+                    .get_or_insert(u64::from(parent.clone()), name),
+            )
+        });
+        Self::with_mapper(mapper, Some(store))
+    }
+
+    /// Replaces the eviction policy of the resolver, [`EvictionPolicy::default`] if not called
+    pub fn with_eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.policy = policy;
+        self.eviction_threshold
+            .store(policy.max_entries.unwrap_or(usize::MAX), Ordering::Relaxed);
+        self
+    }
+
+    /// Evicts every unreferenced entry, regardless of the eviction policy
+    pub fn evict_unreferenced(&self) -> usize {
+        let mut mapper = self.mapper.write().expect("Failed to acquire write lock");
+        self.evict(&mut mapper, 0)
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Queues `inode` as the most recently used candidate of the eviction, if not referenced by the kernel
+    fn mark_unreferenced(&self, mapper: &InodeMapper<ResolverEntry>, inode: &Inode) {
+        let Some(info) = mapper.get(inode) else {
+            return;
+        };
+        let mut unreferenced = self.unreferenced.lock().unwrap();
+        if info.data.nlookup.load(Ordering::SeqCst) != 0 {
+            return;
+        }
+        let now = self.tick();
+        info.data.last_used.store(now, Ordering::Relaxed);
+        unreferenced.push_back((now, inode.clone()));
+        // Drop the stale records left by entries used again or referenced since they were queued
+        if unreferenced.len() > 2 * mapper.len() {
+            unreferenced.retain(|(tick, inode)| {
+                mapper.get(inode).is_some_and(|info| {
+                    info.data.nlookup.load(Ordering::Relaxed) == 0
+                        && info.data.last_used.load(Ordering::Relaxed) == *tick
+                })
+            });
+        }
+    }
+
+    /// Evicts entries if required by the eviction policy, called after adding entries
+    fn maybe_evict(&self, mapper: &mut InodeMapper<ResolverEntry>) {
+        if let Some(interval) = self.policy.interval {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if last_sweep.elapsed() >= interval {
+                *last_sweep = Instant::now();
+                drop(last_sweep);
+                self.evict(mapper, 0);
+            }
+        }
+        if let Some(max_entries) = self.policy.max_entries {
+            if mapper.len() > self.eviction_threshold.load(Ordering::Relaxed) {
+                self.evict(mapper, max_entries - max_entries / 4);
+                // Entries still referenced can't be evicted, wait for the resolver to grow
+                // before trying again rather than scanning it on every insertion
+                let remaining = mapper.len();
+                self.eviction_threshold.store(
+                    max_entries.max(remaining.saturating_add(max_entries / 4)),
+                    Ordering::Relaxed,
+                );
+            }
+        }
+    }
+
+    /// Evicts the least recently used unreferenced entries until at most `target` are left, returns the number evicted
+    fn evict(&self, mapper: &mut InodeMapper<ResolverEntry>, target: usize) -> usize {
+        let root = mapper.get_root_inode();
+        let mut unreferenced = self.unreferenced.lock().unwrap();
+        let mut evicted = 0;
+        // Directories are requeued until their children are evicted, stop once every record was skipped
+        let mut skipped = 0;
+        while mapper.len() > target && skipped < unreferenced.len() {
+            let Some((tick, inode)) = unreferenced.pop_front() else {
+                break;
+            };
+            let Some(info) = mapper.get(&inode) else {
+                continue;
+            };
+            if inode == root
+                || info.data.nlookup.load(Ordering::Relaxed) != 0
+                || info.data.last_used.load(Ordering::Relaxed) != tick
+            {
+                continue;
+            }
+            if mapper.has_children(&inode) {
+                unreferenced.push_back((tick, inode));
+                skipped += 1;
+                continue;
+            }
+            mapper.remove(&inode);
+            evicted += 1;
+            skipped = 0;
+        }
+        self.evicted.fetch_add(evicted as u64, Ordering::Relaxed);
+        evicted
+    }
 }
 
 impl FileIdResolver for ComponentsResolver {
     type ResolvedType = Vec<OsString>;
 
     fn new() -> Self {
-        ComponentsResolver {
-            mapper: RwLock::new(InodeMapper::new(AtomicU64::new(0))),
-        }
+        Self::with_mapper(InodeMapper::new(ResolverEntry::new(0, 0)), None)
     }
 
-    fn resolve_id(&self, ino: u64) -> Self::ResolvedType {
-        self.mapper
-            .read()
-            .unwrap()
-            .resolve(&Inode::from(ino))
-            .expect("Failed to resolve inode")
+    fn resolve_id(&self, ino: u64) -> FuseResult<Self::ResolvedType> {
+        let mapper = self.mapper.read().unwrap();
+        let inode = Inode::from(ino);
+        Ok(mapper
+            .resolve(&inode)
+            .ok_or_else(|| unknown_inode(ino))?
             .iter()
             .map(|inode_info| (**inode_info.name).clone())
-            .collect()
+            .collect())
     }
 
-    fn lookup(&self, parent: u64, child: &OsStr, _id: (), increment: bool) -> u64 {
+    fn find_ino(&self, id: &Vec<OsString>) -> Option<u64> {
+        let mapper = self.mapper.read().unwrap();
+        let mut inode = mapper.get_root_inode();
+        // Components are stored from the leaf to the root
+        for name in id.iter().rev() {
+            inode = mapper.lookup(&inode, name)?.inode.clone();
+        }
+        // Entries only listed by readdir, or forgotten since, are unknown to the kernel
+        if !id.is_empty() && mapper.get(&inode)?.data.nlookup.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        Some(u64::from(inode))
+    }
+
+    fn lookup(&self, parent: u64, child: &OsStr, _id: (), increment: bool) -> FuseResult<u64> {
+        let parent_ino = parent;
         let parent = Inode::from(parent);
+        let now = self.tick();
         {
             // Optimistically assume the child exists
-            if let Some(lookup_result) = self.mapper.read().unwrap().lookup(&parent, child) {
+            let mapper = self.mapper.read().unwrap();
+            if let Some(lookup_result) = mapper.lookup(&parent, child) {
                 if increment {
-                    lookup_result.data.fetch_add(1, Ordering::SeqCst);
+                    lookup_result.data.nlookup.fetch_add(1, Ordering::SeqCst);
+                } else {
+                    self.mark_unreferenced(&mapper, lookup_result.inode);
                 }
-                return u64::from(lookup_result.inode.clone());
+                return Ok(u64::from(lookup_result.inode.clone()));
             }
         }
-        u64::from(
-            self.mapper
-                .write()
-                .expect("Failed to acquire write lock")
-                .insert_child(&parent, child.to_os_string(), |_| {
-                    AtomicU64::new(if increment { 1 } else { 0 })
-                })
-                .expect("Failed to insert child"),
-        )
+        let mut mapper = self.mapper.write().expect("Failed to acquire write lock");
+        let inode = mapper
+            .insert_child(&parent, child.to_os_string(), |value_creator| {
+                let count = value_creator
+                    .existing_data
+                    .map_or(0, |entry| entry.nlookup.load(Ordering::Relaxed));
+                ResolverEntry::new(if increment { count + 1 } else { count }, now)
+            })
+            .map_err(|_| unknown_inode(parent_ino))?;
+        self.mark_unreferenced(&mapper, &inode);
+        self.maybe_evict(&mut mapper);
+        Ok(u64::from(inode))
     }
 
     fn add_children(
@@ -152,72 +489,184 @@ impl FileIdResolver for ComponentsResolver {
         parent: u64,
         children: Vec<(OsString, ())>,
         increment: bool,
-    ) -> Vec<(OsString, u64)> {
+    ) -> FuseResult<Vec<(OsString, u64)>> {
+        let now = self.tick();
         let children_with_creator: Vec<_> = children
             .iter()
             .map(|(name, _)| {
                 (
                     name.clone(),
-                    |value_creator: ValueCreatorParams<AtomicU64>| match value_creator.existing_data
-                    {
-                        Some(nlookup) => {
-                            let count = nlookup.load(Ordering::Relaxed);
-                            AtomicU64::new(if increment { count + 1 } else { count })
-                        }
-                        None => AtomicU64::new(if increment { 1 } else { 0 }),
+                    |value_creator: ValueCreatorParams<ResolverEntry>| {
+                        let count = value_creator
+                            .existing_data
+                            .map_or(0, |entry| entry.nlookup.load(Ordering::Relaxed));
+                        ResolverEntry::new(if increment { count + 1 } else { count }, now)
                     },
                 )
             })
             .collect();
 
         let parent_inode = Inode::from(parent);
-        let inserted_children = self
-            .mapper
-            .write()
-            .expect("Failed to acquire write lock")
+        let mut mapper = self.mapper.write().expect("Failed to acquire write lock");
+        let inserted_children = mapper
             .insert_children(&parent_inode, children_with_creator)
-            .expect("Failed to insert children");
+            .map_err(|_| unknown_inode(parent))?;
+        for inode in inserted_children.iter() {
+            self.mark_unreferenced(&mapper, inode);
+        }
+        self.maybe_evict(&mut mapper);
 
-        inserted_children
+        Ok(inserted_children
             .into_iter()
             .zip(children)
             .map(|(inode, (name, _))| (name, u64::from(inode)))
-            .collect()
+            .collect())
     }
 
-    fn forget(&self, ino: u64, nlookup: u64) {
-        let inode = Inode::from(ino);
-        {
-            // Optimistically assume we don't have to remove yet
-            let guard = self.mapper.read().expect("Failed to acquire read lock");
-            let inode_info = guard.get(&inode).expect("Failed to find inode");
-            if inode_info.data.fetch_sub(nlookup, Ordering::SeqCst) > 0 {
-                return;
-            }
+    fn forget(&self, ino: u64, nlookup: u64) -> FuseResult<()> {
+        // Forgotten entries are kept until evicted, so that the file keeps its inode number
+        let guard = self.mapper.read().expect("Failed to acquire read lock");
+        let inode_info = guard
+            .get(&Inode::from(ino))
+            .ok_or_else(|| unknown_inode(ino))?;
+        let count = inode_info
+            .data
+            .nlookup
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                Some(count.saturating_sub(nlookup))
+            })
+            .unwrap_or_default();
+        if count < nlookup {
+            return Err(ErrorKind::InvalidArgument.to_error(format!(
+                "Forget of {} lookups of inode {:x}, only {} known",
+                nlookup, ino, count
+            )));
+        }
+        if count == nlookup {
+            self.mark_unreferenced(&guard, &Inode::from(ino));
         }
-        self.mapper.write().unwrap().remove(&inode).unwrap();
+        Ok(())
     }
 
-    fn rename(&self, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr) {
+    fn rename(&self, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr) -> FuseResult<()> {
         let parent_inode = Inode::from(parent);
         let newparent_inode = Inode::from(newparent);
-        self.mapper
-            .write()
-            .expect("Failed to acquire write lock")
+        let mut mapper = self.mapper.write().expect("Failed to acquire write lock");
+        mapper
             .rename(
                 &parent_inode,
                 name,
                 &newparent_inode,
                 newname.to_os_string(),
             )
-            .expect("Failed to rename inode");
+            .map_err(|e| match e {
+                RenameError::NotFound => ErrorKind::FileNotFound
+                    .to_error(format!("Unknown entry {:?} of inode {:x}", name, parent)),
+                RenameError::ParentNotFound => unknown_inode(parent),
+                RenameError::NewParentNotFound => unknown_inode(newparent),
+            })?;
+        if let Some(store) = &self.store {
+            store
+                .lock()
+                .unwrap()
+                .rename(parent, name, newparent, newname);
+        }
+        Ok(())
+    }
+
+    fn link(&self, ino: u64, newparent: u64, newname: &OsStr) -> FuseResult<()> {
+        let mut mapper = self.mapper.write().expect("Failed to acquire write lock");
+        mapper
+            .insert_link(
+                &Inode::from(ino),
+                &Inode::from(newparent),
+                newname.to_os_string(),
+            )
+            .map_err(|e| match e {
+                LinkError::NotFound => unknown_inode(ino),
+                LinkError::NewParentNotFound => unknown_inode(newparent),
+            })?;
+        if let Some(store) = &self.store {
+            store.lock().unwrap().link(ino, newparent, newname);
+        }
+        Ok(())
+    }
+
+    fn unlink(&self, parent: u64, name: &OsStr) -> FuseResult<()> {
+        let mut mapper = self.mapper.write().expect("Failed to acquire write lock");
+        match mapper.unlink(&Inode::from(parent), name) {
+            Ok(_) => {
+                if let Some(store) = &self.store {
+                    store.lock().unwrap().remove(parent, name);
+                }
+                Ok(())
+            }
+            // The last name of a file is kept until its inode is evicted, the kernel may still use it
+            Err(UnlinkError::LastName) => {
+                // A file created again with this name gets the same inode number, with a new generation
+                if let (Some(store), Some(child)) =
+                    (&self.store, mapper.lookup(&Inode::from(parent), name))
+                {
+                    store.lock().unwrap().retire(u64::from(child.inode.clone()));
+                }
+                Ok(())
+            }
+            Err(UnlinkError::NotFound) => Err(ErrorKind::FileNotFound
+                .to_error(format!("Unknown entry {:?} of inode {:x}", name, parent))),
+        }
+    }
+
+    fn generation(&self, ino: u64) -> Option<u64> {
+        self.store
+            .as_ref()
+            .map(|store| store.lock().unwrap().inode_generation(ino))
+    }
+
+    fn stats(&self) -> ResolverStats {
+        let mapper = self.mapper.read().expect("Failed to acquire read lock");
+        let root = mapper.get_root_inode();
+        ResolverStats {
+            entries: mapper.len(),
+            // The root is always referenced, without being looked up
+            referenced_entries: mapper
+                .iter()
+                .filter(|(inode, info)| {
+                    **inode == root || info.data.nlookup.load(Ordering::Relaxed) > 0
+                })
+                .count(),
+            evicted_entries: self.evicted.load(Ordering::Relaxed),
+        }
     }
 }
 
+/// Resolver of `PathBuf` ids, attributing an inode number to each path looked up
+///
+/// Entries unreferenced by the kernel are evicted according to an [`EvictionPolicy`].
 pub struct PathResolver {
     resolver: ComponentsResolver,
 }
 
+impl PathResolver {
+    /// Creates a resolver attributing the inode numbers saved in `store`
+    pub fn with_inode_store(store: InodeStore) -> Self {
+        PathResolver {
+            resolver: ComponentsResolver::with_inode_store(store),
+        }
+    }
+
+    /// Replaces the eviction policy of the resolver, [`EvictionPolicy::default`] if not called
+    pub fn with_eviction_policy(self, policy: EvictionPolicy) -> Self {
+        PathResolver {
+            resolver: self.resolver.with_eviction_policy(policy),
+        }
+    }
+
+    /// Evicts every unreferenced entry, regardless of the eviction policy
+    pub fn evict_unreferenced(&self) -> usize {
+        self.resolver.evict_unreferenced()
+    }
+}
+
 impl FileIdResolver for PathResolver {
     type ResolvedType = PathBuf;
 
@@ -227,12 +676,18 @@ impl FileIdResolver for PathResolver {
         }
     }
 
-    fn resolve_id(&self, ino: u64) -> Self::ResolvedType {
-        self.resolver
-            .resolve_id(ino)
+    fn resolve_id(&self, ino: u64) -> FuseResult<Self::ResolvedType> {
+        Ok(self
+            .resolver
+            .resolve_id(ino)?
             .iter()
             .rev()
-            .collect::<PathBuf>()
+            .collect::<PathBuf>())
+    }
+
+    fn find_ino(&self, id: &PathBuf) -> Option<u64> {
+        let components = id.iter().rev().map(|name| name.to_os_string()).collect();
+        self.resolver.find_ino(&components)
     }
 
     fn lookup(
@@ -241,7 +696,7 @@ impl FileIdResolver for PathResolver {
         child: &OsStr,
         id: <Self::ResolvedType as FileIdType>::_Id,
         increment: bool,
-    ) -> u64 {
+    ) -> FuseResult<u64> {
         self.resolver.lookup(parent, child, id, increment)
     }
 
@@ -250,16 +705,32 @@ impl FileIdResolver for PathResolver {
         parent: u64,
         children: Vec<(OsString, <Self::ResolvedType as FileIdType>::_Id)>,
         increment: bool,
-    ) -> Vec<(OsString, u64)> {
+    ) -> FuseResult<Vec<(OsString, u64)>> {
         self.resolver.add_children(parent, children, increment)
     }
 
-    fn forget(&self, ino: u64, nlookup: u64) {
-        self.resolver.forget(ino, nlookup);
+    fn forget(&self, ino: u64, nlookup: u64) -> FuseResult<()> {
+        self.resolver.forget(ino, nlookup)
+    }
+
+    fn rename(&self, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr) -> FuseResult<()> {
+        self.resolver.rename(parent, name, newparent, newname)
+    }
+
+    fn link(&self, ino: u64, newparent: u64, newname: &OsStr) -> FuseResult<()> {
+        self.resolver.link(ino, newparent, newname)
     }
 
-    fn rename(&self, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr) {
-        self.resolver.rename(parent, name, newparent, newname);
+    fn unlink(&self, parent: u64, name: &OsStr) -> FuseResult<()> {
+        self.resolver.unlink(parent, name)
+    }
+
+    fn generation(&self, ino: u64) -> Option<u64> {
+        self.resolver.generation(ino)
+    }
+
+    fn stats(&self) -> ResolverStats {
+        self.resolver.stats()
     }
 }
 
@@ -275,8 +746,10 @@ mod tests {
 
         // Test lookup and resolve_id
         let parent_ino = ROOT_INODE.into();
-        let child_ino = resolver.lookup(parent_ino, OsStr::new("child"), (), true);
-        let resolved_path = resolver.resolve_id(child_ino);
+        let child_ino = resolver
+            .lookup(parent_ino, OsStr::new("child"), (), true)
+            .unwrap();
+        let resolved_path = resolver.resolve_id(child_ino).unwrap();
 
         assert_eq!(resolved_path, vec![OsString::from("child")]);
 
@@ -285,22 +758,26 @@ mod tests {
             (OsString::from("grandchild1"), ()),
             (OsString::from("grandchild2"), ()),
         ];
-        let added_children = resolver.add_children(child_ino, grandchildren, true);
+        let added_children = resolver
+            .add_children(child_ino, grandchildren, true)
+            .unwrap();
 
         assert_eq!(added_children.len(), 2);
 
         // Test forget
-        resolver.forget(child_ino, 1);
+        resolver.forget(child_ino, 1).unwrap();
 
         // Test rename
-        resolver.rename(
-            parent_ino,
-            OsStr::new("child"),
-            parent_ino,
-            OsStr::new("renamed_child"),
-        );
+        resolver
+            .rename(
+                parent_ino,
+                OsStr::new("child"),
+                parent_ino,
+                OsStr::new("renamed_child"),
+            )
+            .unwrap();
 
-        let renamed_path = resolver.resolve_id(child_ino);
+        let renamed_path = resolver.resolve_id(child_ino).unwrap();
         assert_eq!(renamed_path, vec![OsString::from("renamed_child")]);
     }
 
@@ -310,16 +787,22 @@ mod tests {
 
         // Test lookup and resolve_id for root
         let root_ino = ROOT_INODE.into();
-        let root_path = resolver.resolve_id(root_ino);
+        let root_path = resolver.resolve_id(root_ino).unwrap();
         assert_eq!(root_path, PathBuf::from(""));
 
         // Create a nested structure: /dir1/dir2/file.txt
-        let dir1_ino = resolver.lookup(root_ino, OsStr::new("dir1"), (), true);
-        let dir2_ino = resolver.lookup(dir1_ino, OsStr::new("dir2"), (), true);
-        let file_ino = resolver.lookup(dir2_ino, OsStr::new("file.txt"), (), true);
+        let dir1_ino = resolver
+            .lookup(root_ino, OsStr::new("dir1"), (), true)
+            .unwrap();
+        let dir2_ino = resolver
+            .lookup(dir1_ino, OsStr::new("dir2"), (), true)
+            .unwrap();
+        let file_ino = resolver
+            .lookup(dir2_ino, OsStr::new("file.txt"), (), true)
+            .unwrap();
 
         // Test resolve_id for nested structure
-        let file_path = resolver.resolve_id(file_ino);
+        let file_path = resolver.resolve_id(file_ino).unwrap();
         assert_eq!(file_path, PathBuf::from("dir1/dir2/file.txt"));
 
         // Test add_children
@@ -327,12 +810,14 @@ mod tests {
             (OsString::from("child1.txt"), ()),
             (OsString::from("child2.txt"), ()),
         ];
-        let added_children = resolver.add_children(dir2_ino, dir2_children, true);
+        let added_children = resolver
+            .add_children(dir2_ino, dir2_children, true)
+            .unwrap();
         assert_eq!(added_children.len(), 2);
 
         // Verify added children
         for (name, ino) in added_children {
-            let child_path = resolver.resolve_id(ino);
+            let child_path = resolver.resolve_id(ino).unwrap();
             assert_eq!(
                 child_path,
                 PathBuf::from(format!("dir1/dir2/{}", name.to_str().unwrap()))
@@ -340,38 +825,307 @@ mod tests {
         }
 
         // Test forget
-        resolver.forget(file_ino, 1);
+        resolver.forget(file_ino, 1).unwrap();
 
         // Test rename within the same directory
-        resolver.rename(
-            dir2_ino,
-            OsStr::new("file.txt"),
-            dir2_ino,
-            OsStr::new("renamed_file.txt"),
-        );
+        resolver
+            .rename(
+                dir2_ino,
+                OsStr::new("file.txt"),
+                dir2_ino,
+                OsStr::new("renamed_file.txt"),
+            )
+            .unwrap();
 
-        let renamed_file_path = resolver.resolve_id(file_ino);
+        let renamed_file_path = resolver.resolve_id(file_ino).unwrap();
         assert_eq!(
             renamed_file_path,
             PathBuf::from("dir1/dir2/renamed_file.txt")
         );
 
         // Test rename to a different directory
-        let dir3_ino = resolver.lookup(root_ino, OsStr::new("dir3"), (), true);
-        resolver.rename(
-            dir2_ino,
-            OsStr::new("renamed_file.txt"),
-            dir3_ino,
-            OsStr::new("moved_file.txt"),
-        );
+        let dir3_ino = resolver
+            .lookup(root_ino, OsStr::new("dir3"), (), true)
+            .unwrap();
+        resolver
+            .rename(
+                dir2_ino,
+                OsStr::new("renamed_file.txt"),
+                dir3_ino,
+                OsStr::new("moved_file.txt"),
+            )
+            .unwrap();
 
-        let moved_file_path = resolver.resolve_id(file_ino);
+        let moved_file_path = resolver.resolve_id(file_ino).unwrap();
         assert_eq!(moved_file_path, PathBuf::from("dir3/moved_file.txt"));
 
         // Test lookup for non-existent file
-        let non_existent_ino = resolver.lookup(root_ino, OsStr::new("non_existent"), (), false);
+        let non_existent_ino = resolver
+            .lookup(root_ino, OsStr::new("non_existent"), (), false)
+            .unwrap();
         assert_ne!(non_existent_ino, 0);
-        let non_existent_path = resolver.resolve_id(non_existent_ino);
+        let non_existent_path = resolver.resolve_id(non_existent_ino).unwrap();
         assert_eq!(non_existent_path, PathBuf::from("non_existent"));
     }
+
+    #[test]
+    fn test_find_ino() {
+        let resolver = PathResolver::new();
+        let root_ino: u64 = ROOT_INODE.into();
+        assert_eq!(resolver.find_ino(&PathBuf::from("")), Some(root_ino));
+
+        let dir_ino = resolver
+            .lookup(root_ino, OsStr::new("dir"), (), true)
+            .unwrap();
+        let file_ino = resolver
+            .lookup(dir_ino, OsStr::new("file.txt"), (), true)
+            .unwrap();
+        assert_eq!(resolver.find_ino(&PathBuf::from("dir")), Some(dir_ino));
+        assert_eq!(
+            resolver.find_ino(&PathBuf::from("dir/file.txt")),
+            Some(file_ino)
+        );
+        assert_eq!(resolver.find_ino(&PathBuf::from("dir/unknown")), None);
+
+        let components_resolver = ComponentsResolver::new();
+        let dir_ino = components_resolver
+            .lookup(root_ino, OsStr::new("dir"), (), true)
+            .unwrap();
+        let file_ino = components_resolver
+            .lookup(dir_ino, OsStr::new("file.txt"), (), true)
+            .unwrap();
+        assert_eq!(
+            components_resolver.find_ino(&vec![OsString::from("file.txt"), OsString::from("dir")]),
+            Some(file_ino)
+        );
+    }
+
+    #[test]
+    fn test_eviction_of_unreferenced_entries() {
+        let resolver = PathResolver::new().with_eviction_policy(EvictionPolicy {
+            max_entries: Some(8),
+            interval: None,
+        });
+        let root_ino: u64 = ROOT_INODE.into();
+        let dir_ino = resolver
+            .lookup(root_ino, OsStr::new("dir"), (), true)
+            .unwrap();
+        let file_ino = resolver
+            .lookup(dir_ino, OsStr::new("file"), (), true)
+            .unwrap();
+
+        // Entries listed by readdir are not referenced by the kernel
+        for i in 0..20 {
+            resolver
+                .lookup(dir_ino, OsStr::new(&format!("listed{}", i)), (), false)
+                .unwrap();
+        }
+        let stats = resolver.stats();
+        assert!(stats.entries <= 8);
+        assert_eq!(stats.referenced_entries, 3);
+        assert_eq!(stats.evicted_entries, 20 + 3 - stats.entries as u64);
+        // The most recently used entries are kept
+        let is_mapped = |name: &str| {
+            let mapper = resolver.resolver.mapper.read().unwrap();
+            mapper
+                .lookup(&Inode::from(dir_ino), OsStr::new(name))
+                .is_some()
+        };
+        assert!(is_mapped("listed19"));
+        assert!(!is_mapped("listed0"));
+        // Listed entries are not known by the kernel
+        assert_eq!(resolver.find_ino(&PathBuf::from("dir/listed19")), None);
+        assert_eq!(
+            resolver.resolve_id(file_ino).unwrap(),
+            PathBuf::from("dir/file")
+        );
+
+        // Forgotten entries are kept until evicted, directories once their children are
+        resolver.forget(file_ino, 1).unwrap();
+        resolver.forget(dir_ino, 1).unwrap();
+        assert_eq!(resolver.stats().referenced_entries, 1);
+        assert_eq!(
+            resolver.resolve_id(file_ino).unwrap(),
+            PathBuf::from("dir/file")
+        );
+        resolver.evict_unreferenced();
+        assert_eq!(resolver.stats().entries, 1);
+        assert_eq!(resolver.find_ino(&PathBuf::from("dir")), None);
+    }
+
+    #[test]
+    fn test_periodic_eviction() {
+        let resolver = ComponentsResolver::new().with_eviction_policy(EvictionPolicy {
+            max_entries: None,
+            interval: Some(Duration::ZERO),
+        });
+        let root_ino: u64 = ROOT_INODE.into();
+        let kept_ino = resolver
+            .lookup(root_ino, OsStr::new("kept"), (), true)
+            .unwrap();
+        resolver
+            .add_children(
+                root_ino,
+                vec![(OsString::from("a"), ()), (OsString::from("b"), ())],
+                false,
+            )
+            .unwrap();
+        // Every unreferenced entry is evicted on each insertion
+        assert_eq!(resolver.stats().entries, 2);
+        assert_eq!(resolver.stats().evicted_entries, 2);
+        assert_eq!(
+            resolver.resolve_id(kept_ino).unwrap(),
+            vec![OsString::from("kept")]
+        );
+
+        let resolver = ComponentsResolver::new().with_eviction_policy(EvictionPolicy::disabled());
+        for i in 0..100 {
+            resolver
+                .lookup(root_ino, OsStr::new(&i.to_string()), (), false)
+                .unwrap();
+        }
+        assert_eq!(resolver.stats().entries, 101);
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        let resolver = ComponentsResolver::new().with_eviction_policy(EvictionPolicy {
+            max_entries: Some(8),
+            interval: None,
+        });
+        let root_ino: u64 = ROOT_INODE.into();
+        for name in ["a", "b", "c", "d", "e"] {
+            resolver
+                .lookup(root_ino, OsStr::new(name), (), false)
+                .unwrap();
+        }
+        // Listing "b" again makes it the most recently used, "a" is unreferenced once forgotten
+        let a_ino = resolver
+            .lookup(root_ino, OsStr::new("a"), (), true)
+            .unwrap();
+        resolver
+            .lookup(root_ino, OsStr::new("b"), (), false)
+            .unwrap();
+        resolver.forget(a_ino, 1).unwrap();
+        for name in ["f", "g", "h"] {
+            resolver
+                .lookup(root_ino, OsStr::new(name), (), false)
+                .unwrap();
+        }
+        let is_mapped = |name: &str| {
+            let mapper = resolver.mapper.read().unwrap();
+            mapper
+                .lookup(&Inode::from(root_ino), OsStr::new(name))
+                .is_some()
+        };
+        assert_eq!(resolver.stats().entries, 6);
+        for name in ["a", "b", "f", "g", "h"] {
+            assert!(is_mapped(name), "{} was evicted", name);
+        }
+        for name in ["c", "d", "e"] {
+            assert!(!is_mapped(name), "{} was kept", name);
+        }
+
+        // Listing the same entries again doesn't grow the queue of unreferenced entries
+        for _ in 0..100 {
+            resolver
+                .lookup(root_ino, OsStr::new("f"), (), false)
+                .unwrap();
+        }
+        assert!(resolver.unreferenced.lock().unwrap().len() <= 2 * resolver.stats().entries);
+    }
+
+    #[test]
+    fn test_resolver_errors() {
+        let resolver = PathResolver::new();
+        let root_ino: u64 = ROOT_INODE.into();
+        let file_ino = resolver
+            .lookup(root_ino, OsStr::new("file"), (), true)
+            .unwrap();
+
+        let error = resolver.resolve_id(0xdead).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::StaleFileHandle);
+        let error = resolver
+            .lookup(0xdead, OsStr::new("file"), (), true)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::StaleFileHandle);
+        let error = resolver
+            .rename(
+                root_ino,
+                OsStr::new("unknown"),
+                root_ino,
+                OsStr::new("file"),
+            )
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::FileNotFound);
+        assert!(resolver.forget(0xdead, 1).is_err());
+        // Forgetting more lookups than known releases every reference
+        assert!(resolver.forget(file_ino, 2).is_err());
+        assert_eq!(resolver.stats().referenced_entries, 1);
+        assert_eq!(
+            resolver.resolve_id(file_ino).unwrap(),
+            PathBuf::from("file")
+        );
+    }
+
+    #[test]
+    fn test_hard_links() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store_path = dir.path().join("inodes");
+        let root_ino: u64 = ROOT_INODE.into();
+        let resolver =
+            PathBuf::create_resolver_with_inode_store(InodeStore::open(&store_path).unwrap())
+                .unwrap();
+        let dir_ino = resolver
+            .lookup(root_ino, OsStr::new("dir"), (), true)
+            .unwrap();
+        let file_ino = resolver
+            .lookup(root_ino, OsStr::new("file"), (), true)
+            .unwrap();
+
+        resolver
+            .link(file_ino, dir_ino, OsStr::new("link"))
+            .unwrap();
+        assert_eq!(
+            resolver
+                .lookup(dir_ino, OsStr::new("link"), (), true)
+                .unwrap(),
+            file_ino
+        );
+        assert_eq!(
+            resolver.find_ino(&PathBuf::from("dir/link")),
+            Some(file_ino)
+        );
+        assert_eq!(resolver.stats().entries, 3);
+
+        // The file is resolved to its remaining name once unlinked
+        resolver.unlink(root_ino, OsStr::new("file")).unwrap();
+        assert_eq!(
+            resolver.resolve_id(file_ino).unwrap(),
+            PathBuf::from("dir/link")
+        );
+        assert_eq!(resolver.find_ino(&PathBuf::from("file")), None);
+        resolver
+            .link(file_ino, root_ino, OsStr::new("file"))
+            .unwrap();
+        drop(resolver);
+
+        // Links are kept by the inode store
+        let resolver =
+            PathBuf::create_resolver_with_inode_store(InodeStore::open(&store_path).unwrap())
+                .unwrap();
+        let file = resolver
+            .lookup(root_ino, OsStr::new("file"), (), true)
+            .unwrap();
+        let dir_ino = resolver
+            .lookup(root_ino, OsStr::new("dir"), (), true)
+            .unwrap();
+        let link = resolver
+            .lookup(dir_ino, OsStr::new("link"), (), true)
+            .unwrap();
+        assert_eq!(file, file_ino);
+        assert_eq!(link, file_ino);
+        assert_eq!(resolver.stats().entries, 3);
+        assert_eq!(resolver.stats().referenced_entries, 3);
+    }
 }
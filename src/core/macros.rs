@@ -1,6 +1,47 @@
+/// Logs an error returned by the resolver.
+///
+/// With the `resolver_debug` feature, the error is logged as an inconsistency along with the request,
+/// the statistics of the resolver and a backtrace.
+macro_rules! log_resolver_error {
+    ($resolver:expr, $req:expr, $ino:expr, $error:expr, $function:ident) => {
+        #[cfg(not(feature = "resolver_debug"))]
+        warn!(
+            "{}: ino {:x?}, resolver error [{}], {:?}",
+            stringify!($function),
+            $ino,
+            $error,
+            $req
+        );
+        #[cfg(feature = "resolver_debug")]
+        error!(
+            "{}: ino {:x?}, resolver inconsistency [{}], {:?}, {:?}\n{}",
+            stringify!($function),
+            $ino,
+            $error,
+            $req,
+            $resolver.stats(),
+            std::backtrace::Backtrace::force_capture()
+        );
+    };
+}
+
+/// Resolves the file id of an inode, replying with the error of the resolver and returning from the task on failure
+macro_rules! resolve_id {
+    ($resolver:expr, $req:expr, $ino:expr, $reply:expr, $function:ident) => {
+        match $resolver.resolve_id($ino) {
+            Ok(file_id) => file_id,
+            Err(e) => {
+                log_resolver_error!($resolver, $req, $ino, e, $function);
+                $reply.error(e.raw_error());
+                return;
+            }
+        }
+    };
+}
+
 macro_rules! handle_fuse_reply_entry {
-    ($handler:expr, $resolver:expr, $req:expr, $parent:expr, $name:expr, $reply:expr,
-    $function:ident, ($($args:expr),*)) => {
+    ($handler:expr, $resolver:expr, $settings:expr, $req:expr, $parent:expr, $name:expr, $reply:expr,
+    $function:ident, ($($args:expr),*) $(, linking $ino:expr)?) => {
         macro_rules! if_lookup {
             (lookup, $choice1:tt, $choice2:tt) => {
                 $choice1
@@ -11,16 +52,31 @@ macro_rules! handle_fuse_reply_entry {
         }
 
         let handler = $handler;
-        match handler.$function($($args),*) {
+        match await_handler!(handler.$function($($args),*)) {
             Ok(metadata) => {
-                let default_ttl = handler.get_default_ttl();
+                let default_ttl = $settings.entry_ttl(|| handler.get_default_entry_ttl());
                 let (id, file_attr) = TId::extract_metadata(metadata);
-                let ino = $resolver.lookup($parent, $name, id, true);
+                $(
+                    // The new entry is another name of the linked inode
+                    if let Err(e) = $resolver.link($ino, $parent, $name) {
+                        log_resolver_error!($resolver, $req, $ino, e, $function);
+                    }
+                )?
+                let ino = match $resolver.lookup($parent, $name, id, true) {
+                    Ok(ino) => ino,
+                    Err(e) => {
+                        log_resolver_error!($resolver, $req, $parent, e, $function);
+                        $reply.error(e.raw_error());
+                        return;
+                    }
+                };
                 let (fuse_attr, ttl, generation) = file_attr.to_fuse(ino);
                 $reply.entry(
                     &ttl.unwrap_or(default_ttl),
                     &fuse_attr,
-                    generation.unwrap_or(get_random_generation()),
+                    generation
+                        .or_else(|| $resolver.generation(ino))
+                        .unwrap_or_else(get_random_generation),
                 );
             }
             Err(e) => {
@@ -29,6 +85,11 @@ macro_rules! handle_fuse_reply_entry {
                         // Lookup is preemptivly done in normal situations, we don't need to log an error
                         // eg: before creating a file
                         info!("{}: parent_ino {:x?}, [{}], {:?}", stringify!($function), $parent, e, $req);
+                        if let Some(ttl) = $settings.negative_ttl(&e, || handler.get_negative_ttl()) {
+                            // The kernel caches the absence of the entry until the TTL expires
+                            $reply.entry(&ttl, &negative_entry_attr(), 0);
+                            return;
+                        }
                     } else {
                         warn!("{}: parent_ino {:x?}, [{}], {:?}", stringify!($function), $parent, e, $req);
                     };
@@ -42,11 +103,11 @@ macro_rules! handle_fuse_reply_entry {
 }
 
 macro_rules! handle_fuse_reply_attr {
-    ($handler:expr, $resolve:expr, $req:expr, $ino:expr, $reply:expr,
+    ($handler:expr, $resolve:expr, $settings:expr, $req:expr, $ino:expr, $reply:expr,
         $function:ident, ($($args:expr),*)) => {
-        match $handler.$function($($args),*) {
+        match await_handler!($handler.$function($($args),*)) {
             Ok(file_attr) => {
-                let default_ttl = $handler.get_default_ttl();
+                let default_ttl = $settings.attr_ttl(|| $handler.get_default_attr_ttl());
                 let (fuse_attr, ttl, _) = file_attr.to_fuse($ino);
                 $reply.attr(&ttl.unwrap_or(default_ttl), &fuse_attr);
             }
@@ -58,37 +119,42 @@ macro_rules! handle_fuse_reply_attr {
     };
 }
 
-/// Handles directory read operations for FUSE filesystem.//+
-/////+
-/// This macro implements the logic for reading directory contents, supporting both//+
-/// regular directory reads (`readdir`) and extended directory reads (`readdirplus`).//+
-/////+
-/// # Parameters//+
-/////+
-/// * `$self`: The current filesystem instance.//+
-/// * `$req`: The FUSE request object.//+
-/// * `$ino`: The inode number of the directory being read.//+
-/// * `$fh`: The file handle of the open directory.//+
-/// * `$offset`: The offset from which to start reading directory entries.//+
-/// * `$reply`: The FUSE reply object to send the response.//+
-/// * `$handler_method`: The method to call on the handler to retrieve directory entries.//+
-/// * `$unpack_method`: The method to unpack metadata for each directory entry.//+
-/// * `$get_iter_method`: The method to retrieve the directory iterator.//+
-/// * `$reply_type`: The type of reply (readdir or readdirplus).//+
-/////+
-/// # Returns//+
-/////+
-/// This macro doesn't return a value directly, but it populates the `$reply` object//+
-/// with directory entries or an error code.//
+/// Handles directory read operations for FUSE filesystem.
+///
+/// This macro implements the logic for reading directory contents, supporting both
+/// regular directory reads (`readdir`) and extended directory reads (`readdirplus`).
+///
+/// Entries are pulled lazily from the iterator returned by the handler, and only as far as the
+/// reply buffer allows. The remaining iterator is saved as a [`DirCursor`] keyed by the inode and
+/// the file handle of the directory, so that the next read of this handle resumes where the previous
+/// one stopped. A read at another offset (eg: after a rewinddir) starts a new iterator, and the cursor
+/// is dropped by `releasedir`.
+///
+/// # Parameters
+///
+/// * `$self`: The current filesystem instance.
+/// * `$req`: The FUSE request object.
+/// * `$ino`: The inode number of the directory being read.
+/// * `$fh`: The file handle of the open directory.
+/// * `$offset`: The offset from which to start reading directory entries.
+/// * `$reply`: The FUSE reply object to send the response.
+/// * `$handler_method`: The method to call on the handler to retrieve directory entries.
+/// * `$get_iter_method`: The method to retrieve the directory iterator.
+/// * `$reply_type`: The type of reply (readdir or readdirplus).
+///
+/// # Returns
+///
+/// This macro doesn't return a value directly, but it populates the `$reply` object
+/// with directory entries or an error code.
 macro_rules! handle_dir_read {
     ($self:expr, $req:expr, $ino:expr, $fh:expr, $offset:expr, $reply:expr,
     $handler_method:ident, $get_iter_method:ident, $reply_type:ty) => {{
         // Inner macro to handle readdir vs readdirplus differences
         macro_rules! if_readdir {
-            (readdir, $choice1:tt, $choice2:tt) => {
+            (readdir_iter, $choice1:tt, $choice2:tt) => {
                 $choice1
             };
-            (readdirplus, $choice1:tt, $choice2:tt) => {
+            (readdirplus_iter, $choice1:tt, $choice2:tt) => {
                 $choice2
             };
         }
@@ -96,6 +162,9 @@ macro_rules! handle_dir_read {
         let req_info = RequestInfo::from($req);
         let handler = $self.get_handler();
         let resolver = $self.get_resolver();
+        // Only used by readdirplus, for the TTL of the entries
+        #[allow(unused_variables)]
+        let settings = $self.settings;
         let dirmap_iter = $self.$get_iter_method();
 
         execute_task!($self, {
@@ -106,100 +175,94 @@ macro_rules! handle_dir_read {
                 return;
             }
 
-            // ### Initialize directory iterator
-            let mut dir_iter = match $offset {
-                // First read: fetch children from handler
-                0 => match handler.$handler_method(&req_info, resolver.resolve_id($ino), unsafe {
-                    BorrowedFileHandle::from_raw($fh)
-                }) {
-                    Ok(children) => {
-                        // Unpack and process children
-                        let (child_list, attr_list): (Vec<_>, Vec<_>) = children
-                            .into_iter()
-                            .map(|item| {
-                                let (child_id, child_attr) = if_readdir!(
-                                    $handler_method,
-                                    { TId::extract_minimal_metadata(item.1) },
-                                    { TId::extract_metadata(item.1) }
-                                );
-                                ((item.0, child_id), child_attr)
-                            })
-                            .unzip();
-
-                        // Add children to resolver and create iterator
-                        resolver
-                            .add_children(
-                                $ino,
-                                child_list,
-                                if_readdir!($handler_method, false, true),
-                            )
-                            .into_iter()
-                            .zip(attr_list.into_iter())
-                            .map(|((file_name, file_ino), file_attr)| {
-                                (file_name, file_ino, file_attr)
-                            })
-                            .collect()
-                    }
+            // ### Initialize directory cursor
+            // Resume the cursor of this handle if it stopped at $offset, otherwise ask the handler for the entries after $offset
+            let saved_cursor = { dirmap_iter.safe_borrow_mut().remove(&($ino, $fh)) };
+            let mut cursor = match saved_cursor.filter(|cursor| cursor.offset == $offset) {
+                Some(cursor) => cursor,
+                None => match await_handler!(handler.$handler_method(
+                    &req_info,
+                    resolve_id!(resolver, &req_info, $ino, $reply, $handler_method),
+                    unsafe { BorrowedFileHandle::from_raw($fh) },
+                    $offset
+                )) {
+                    Ok(entries) => DirCursor::new(entries, $offset),
                     Err(e) => {
                         warn!("readdir {:?}: {:?}", req_info, e);
                         $reply.error(e.raw_error());
                         return;
                     }
                 },
-                // Subsequent reads: retrieve saved iterator
-                _ => match { dirmap_iter.safe_borrow_mut().remove(&($ino, $offset)) } {
-                    Some(dirmap_iter) => dirmap_iter,
-                    None => {
-                        // Case when fuse tries to read again after the final item
-                        $reply.ok();
-                        return;
-                    }
-                },
             };
 
             let mut new_offset = $offset;
 
-            // ### Process directory entries
-            if_readdir!(
-                $handler_method,
-                {
-                    // readdir: Add entries until buffer is full
-                    while let Some((name, ino, kind)) = dir_iter.pop_front() {
-                        if $reply.add(ino, new_offset, kind, &name) {
-                            dir_iter.push_front((name, ino, kind));
-                            dirmap_iter
-                                .safe_borrow_mut()
-                                .insert(($ino, new_offset - 1), dir_iter);
+            // ### Process directory entries until the buffer is full
+            loop {
+                let (name, ino, file_attr) = match cursor.pending.take() {
+                    Some(entry) => entry,
+                    None => match cursor.entries.next() {
+                        Some(Ok((name, metadata))) => {
+                            let (child_id, file_attr) = if_readdir!(
+                                $handler_method,
+                                { TId::extract_minimal_metadata(metadata) },
+                                { TId::extract_metadata(metadata) }
+                            );
+                            let ino = match resolver.lookup(
+                                $ino,
+                                &name,
+                                child_id,
+                                if_readdir!($handler_method, false, true),
+                            ) {
+                                Ok(ino) => ino,
+                                Err(e) => {
+                                    log_resolver_error!(resolver, &req_info, $ino, e, $handler_method);
+                                    $reply.error(e.raw_error());
+                                    return;
+                                }
+                            };
+                            (name, ino, file_attr)
+                        }
+                        Some(Err(e)) => {
+                            warn!("readdir {:?}: {:?}", req_info, e);
+                            $reply.error(e.raw_error());
+                            return;
+                        }
+                        None => {
+                            // Keep the exhausted cursor so that the final read of the kernel
+                            // returns an empty reply instead of listing the directory again
+                            cursor.offset = new_offset;
+                            dirmap_iter.safe_borrow_mut().insert(($ino, $fh), cursor);
                             break;
                         }
-                        new_offset += 1;
-                    }
-                    $reply.ok();
-                },
-                {
-                    // readdirplus: Add entries with extended attributes
-                    let default_ttl = handler.get_default_ttl();
-                    while let Some((name, ino, file_attr)) = dir_iter.pop_front() {
+                    },
+                };
+                let is_full = if_readdir!(
+                    $handler_method,
+                    { $reply.add(ino, new_offset + 1, file_attr, &name) },
+                    {
                         let (fuse_attr, ttl, generation) = file_attr.clone().to_fuse(ino);
-                        if $reply.add(
+                        $reply.add(
                             ino,
-                            new_offset,
+                            new_offset + 1,
                             &name,
-                            &ttl.unwrap_or(default_ttl),
+                            &ttl.unwrap_or_else(|| settings.entry_ttl(|| handler.get_default_entry_ttl())),
                             &fuse_attr,
-                            generation.unwrap_or(get_random_generation()),
-                        ) {
-                            dir_iter.push_front((name, ino, file_attr.clone()));
-                            dirmap_iter
-                                .safe_borrow_mut()
-                                .insert((ino, new_offset - 1), dir_iter);
-                            break;
-                        }
-                        new_offset += 1;
+                            generation
+                                .or_else(|| resolver.generation(ino))
+                                .unwrap_or_else(get_random_generation),
+                        )
                     }
-                    $reply.ok();
+                );
+                if is_full {
+                    cursor.offset = new_offset;
+                    cursor.pending = Some((name, ino, file_attr));
+                    dirmap_iter.safe_borrow_mut().insert(($ino, $fh), cursor);
+                    break;
                 }
-            );
+                new_offset += 1;
+            }
+            $reply.ok();
         });
     }};
 }
@@ -207,3 +270,5 @@ macro_rules! handle_dir_read {
 pub(super) use handle_dir_read;
 pub(super) use handle_fuse_reply_attr;
 pub(super) use handle_fuse_reply_entry;
+pub(super) use log_resolver_error;
+pub(super) use resolve_id;
@@ -15,7 +15,10 @@ mod safe_borrowable_impl {
     use std::cell::{RefCell, RefMut};
 
     impl<T> SafeBorrowable for RefCell<T> {
-        type Guard<'a> = RefMut<'a, T> where Self: 'a;
+        type Guard<'a>
+            = RefMut<'a, T>
+        where
+            Self: 'a;
 
         fn safe_borrow_mut(&self) -> Self::Guard<'_> {
             self.borrow_mut()
@@ -23,14 +26,21 @@ mod safe_borrowable_impl {
     }
 }
 
-#[cfg(all(feature = "parallel", not(feature = "deadlock_detection")))]
+// The async mode also relies on a blocking mutex, because guards are never held across an await point
+#[cfg(all(
+    any(feature = "parallel", feature = "async"),
+    not(feature = "deadlock_detection")
+))]
 mod safe_borrowable_impl {
     use super::*;
 
     use std::sync::{Mutex, MutexGuard};
 
     impl<T> SafeBorrowable for Mutex<T> {
-        type Guard<'a> = MutexGuard<'a, T> where Self: 'a;
+        type Guard<'a>
+            = MutexGuard<'a, T>
+        where
+            Self: 'a;
 
         fn safe_borrow_mut(&self) -> Self::Guard<'_> {
             self.lock().unwrap()
@@ -45,25 +55,13 @@ mod safe_borrowable_impl {
     use parking_lot::{Mutex, MutexGuard};
 
     impl<T> SafeBorrowable for Mutex<T> {
-        type Guard<'a> = MutexGuard<'a, T> where Self: 'a;
+        type Guard<'a>
+            = MutexGuard<'a, T>
+        where
+            Self: 'a;
 
         fn safe_borrow_mut(&self) -> Self::Guard<'_> {
             self.lock()
         }
     }
 }
-
-#[cfg(any(feature = "async"))]
-mod safe_borrowable_impl {
-    use super::*;
-
-    use tokio::sync::{Mutex, MutexGuard};
-
-    impl<T> SafeBorrowable for Mutex<T> {
-        type Guard<'a> = MutexGuard<'a, T> where Self: 'a;
-
-        async fn safe_borrow_mut(&self) -> Self::Guard<'_> {
-            self.lock().await
-        }
-    }
-}
@@ -91,9 +91,10 @@ use std::ffi::{OsStr, OsString};
 use std::path::Path;
 use std::time::Duration;
 
+use crate::middleware::{Layered, Middleware};
 use crate::types::*;
 
-mod private {
+pub(crate) mod private {
     #[cfg(not(feature = "serial"))]
     pub trait OptionalSendSync: Sync + Send {}
     #[cfg(not(feature = "serial"))]
@@ -111,14 +112,94 @@ pub trait FuseHandler<TId: FileIdType>: OptionalSendSync + 'static {
 
     /// Provide a default Time-To-Live for file metadata
     ///
-    /// Can be overriden for each FileAttributes returned.
+    /// Can be overriden for each FileAttributes returned. Used by default for both the names of the entries
+    /// and their attributes, see `get_default_entry_ttl` and `get_default_attr_ttl` to tell them apart.
     fn get_default_ttl(&self) -> Duration {
         Duration::from_secs(1)
     }
 
+    /// Provide a default Time-To-Live for the names of the entries, cached in the dentries of the kernel
+    ///
+    /// Can be overriden with the `ttl` of each FileAttribute returned. Defaults to `get_default_ttl`.
+    ///
+    /// Note: fuser replies lookups, creations and readdirplus with a single TTL. The attributes returned in these
+    /// replies are cached by the kernel for the entry TTL, not for `get_default_attr_ttl`.
+    fn get_default_entry_ttl(&self) -> Duration {
+        self.get_default_ttl()
+    }
+
+    /// Provide a default Time-To-Live for the attributes of the files, eg: their size
+    ///
+    /// Only used by the replies of getattr and setattr, and can be overriden with the `ttl` of each FileAttribute
+    /// returned. Defaults to `get_default_ttl`.
+    fn get_default_attr_ttl(&self) -> Duration {
+        self.get_default_ttl()
+    }
+
+    /// Provide a Time-To-Live for the missing entries, cached by the kernel as negative entries
+    ///
+    /// When `lookup` fails with `FileNotFound`, the kernel caches the absence of the entry for this TTL instead
+    /// of asking again at each access, eg: for compilers probing include paths. With `None`, lookups are
+    /// replied with the error and never cached. Can be overriden for each lookup with `PosixError::with_negative_ttl`.
+    ///
+    /// Entries created through the filesystem replace the negative entry, but entries created outside of it
+    /// stay hidden until the TTL expires, unless invalidated with `Notifier::inval_entry`.
+    fn get_negative_ttl(&self) -> Option<Duration> {
+        self.get_inner().get_negative_ttl()
+    }
+
+    /// Kind of locks handled by the filesystem instead of the kernel, queried during `init`
+    ///
+    /// With `None`, the kernel keeps every lock local to the mount and `getlk`/`setlk` are never called.
+    /// Otherwise, the matching capability is requested from the kernel, and locks of that kind are
+    /// forwarded to `getlk`/`setlk` with the `kind` of their `LockInfo` set accordingly.
+    ///
+    /// Only one kind can be forwarded: fuser does not tell `flock` requests apart from POSIX ones.
+    fn remote_locks(&self) -> Option<LockKind> {
+        self.get_inner().remote_locks()
+    }
+
+    /// Whether `write_buf` uses the payload of the writes without converting it to a `Vec`
+    ///
+    /// When a write is handled by another thread than the one which received it, its payload is copied into a
+    /// pooled buffer if this returns true, otherwise into a new `Vec` which the default `write_buf` hands as is
+    /// to `write`. Handlers overriding `write_buf` should return true, the buffers are then reused across writes.
+    fn pooled_writes(&self) -> bool {
+        self.get_inner().pooled_writes()
+    }
+
+    /// Whether this handler is a `DefaultFuseHandler`, ending the chain of inner handlers
+    ///
+    /// The defaults of `read_buf`, `write_buf`, `readdir_iter` and `readdirplus_iter` delegate to the same method
+    /// of the inner handler, so that wrappers keep its zero-copy and streaming implementations. When the inner
+    /// handler is a `DefaultFuseHandler`, they are adapters on top of `read`, `write`, `readdir` and `readdirplus`
+    /// of this handler instead. Not delegated to the inner handler.
+    fn is_default_handler(&self) -> bool {
+        false
+    }
+
+    /// Wrap this handler with a middleware, see the `middleware` module for more details
+    ///
+    /// Layers can be stacked, the last one added being the outermost.
+    fn layer<TMiddleware>(self, middleware: TMiddleware) -> Layered<Self, TMiddleware>
+    where
+        Self: Sized,
+        TMiddleware: Middleware<TId>,
+    {
+        Layered::new(self, middleware)
+    }
+
     /// Initialize the filesystem and configure kernel connection
-    fn init(&self, req: &RequestInfo, config: &mut KernelConfig) -> FuseResult<()> {
-        self.get_inner().init(req, config)
+    ///
+    /// The `notifier` can be cloned and kept to invalidate kernel caches when the filesystem
+    /// content changes outside of FUSE operations.
+    fn init(
+        &self,
+        req: &RequestInfo,
+        config: &mut KernelConfig,
+        notifier: Notifier<TId>,
+    ) -> FuseResult<()> {
+        self.get_inner().init(req, config, notifier)
     }
 
     /// Perform cleanup operations on filesystem exit
@@ -323,12 +404,15 @@ pub trait FuseHandler<TId: FileIdType>: OptionalSendSync + 'static {
     }
 
     /// Reposition read/write file offset
+    ///
+    /// `seek` can also ask for the next data or hole of a sparse file (`SEEK_DATA` and `SEEK_HOLE`), in which
+    /// case the offset of the data or hole found is returned.
     fn lseek(
         &self,
         req: &RequestInfo,
         file_id: TId,
         file_handle: BorrowedFileHandle,
-        seek: SeekFrom,
+        seek: LseekFrom,
     ) -> FuseResult<i64> {
         self.get_inner().lseek(req, file_id, file_handle, seek)
     }
@@ -383,6 +467,27 @@ pub trait FuseHandler<TId: FileIdType>: OptionalSendSync + 'static {
         self.get_inner().opendir(req, file_id, flags)
     }
 
+    /// Poll a file for I/O readiness
+    ///
+    /// Returns the subset of the requested `events` which are ready. If `flags` contains
+    /// `FUSEPollFlags::SCHEDULE_NOTIFY` and no requested event is ready yet, the handler should keep
+    /// `poll_handle` and call `PollHandle::notify` once one of them becomes ready. Only the latest
+    /// handle received for a given file handle needs to be notified.
+    ///
+    /// If this method is not implemented, the kernel considers the file always ready.
+    fn poll(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        poll_handle: PollHandle,
+        events: PollEvents,
+        flags: FUSEPollFlags,
+    ) -> FuseResult<PollEvents> {
+        self.get_inner()
+            .poll(req, file_id, file_handle, poll_handle, events, flags)
+    }
+
     /// Read data from a file
     ///
     /// Read should send exactly the number of bytes requested except on EOF or error, otherwise the rest of the data will be substituted with zeroes. An exception to this is when the file has been opened in ‘direct_io’ mode, in which case the return value of the read system call will reflect the return value of this operation. fh will contain the value set by the open method, or will be undefined if the open method didn’t set any value.
@@ -402,6 +507,39 @@ pub trait FuseHandler<TId: FileIdType>: OptionalSendSync + 'static {
             .read(req, file_id, file_handle, seek, size, flags, lock_owner)
     }
 
+    /// Read data from a file, replying from a buffer which does not have to be owned
+    ///
+    /// This is the variant of `read` called by the driver: the returned `ReadData` can be a pooled buffer,
+    /// a range of a buffer shared with the handler or static data, which saves an allocation and a copy per read.
+    ///
+    /// Default implementation delegates to the inner handler, or is an adapter on top of `read` when the inner
+    /// handler is a `DefaultFuseHandler` (see `is_default_handler`). Handlers overriding `read` on top of another
+    /// inner handler should override this method as well.
+    fn read_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<ReadData> {
+        if !self.get_inner().is_default_handler() {
+            return self.get_inner().read_buf(
+                req,
+                file_id,
+                file_handle,
+                seek,
+                size,
+                flags,
+                lock_owner,
+            );
+        }
+        self.read(req, file_id, file_handle, seek, size, flags, lock_owner)
+            .map(ReadData::from)
+    }
+
     /// Read directory contents
     ///
     /// Returns a list of directory entries with minimal metadata.
@@ -438,6 +576,61 @@ pub trait FuseHandler<TId: FileIdType>: OptionalSendSync + 'static {
         Ok(result)
     }
 
+    /// Read directory contents lazily, starting after the first `offset` entries
+    ///
+    /// This is the streaming variant of `readdir`: the returned iterator is only pulled as far as
+    /// the kernel buffer allows and kept alive between successive reads of the same directory handle,
+    /// until `releasedir`, which avoids listing very large directories at once.
+    ///
+    /// `offset` is the number of entries already returned for this directory stream. It is zero on the first read,
+    /// but may be non-zero if the kernel resumes a listing whose iterator is no longer available (eg: after a seekdir).
+    ///
+    /// Default implementation delegates to the inner handler, or is an adapter on top of `readdir` skipping the
+    /// first `offset` entries when the inner handler is a `DefaultFuseHandler` (see `is_default_handler`).
+    /// Handlers overriding `readdir` on top of another inner handler should override this method as well.
+    ///
+    /// Important: The returned file names (OsString) must not contain any slashes ('/').
+    /// Including slashes in the file names will result in undefined behavior.
+    fn readdir_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::MinimalMetadata>> {
+        if !self.get_inner().is_default_handler() {
+            return self
+                .get_inner()
+                .readdir_iter(req, file_id, file_handle, offset);
+        }
+        let entries = self.readdir(req, file_id, file_handle)?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
+    /// Read directory contents with full file attributes lazily, starting after the first `offset` entries
+    ///
+    /// This is the streaming variant of `readdirplus`, see `readdir_iter` for more details.
+    ///
+    /// Default implementation delegates to the inner handler, or is an adapter on top of `readdirplus` skipping the
+    /// first `offset` entries when the inner handler is a `DefaultFuseHandler` (see `is_default_handler`).
+    /// Handlers implementing `readdir_iter` should also implement this method to avoid listing the whole directory,
+    /// and handlers overriding `readdir` or `readdirplus` on top of another inner handler should override it as well.
+    fn readdirplus_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::Metadata>> {
+        if !self.get_inner().is_default_handler() {
+            return self
+                .get_inner()
+                .readdirplus_iter(req, file_id, file_handle, offset);
+        }
+        let entries = self.readdirplus(req, file_id, file_handle)?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
     /// Read the target of a symbolic link
     fn readlink(&self, req: &RequestInfo, file_id: TId) -> FuseResult<Vec<u8>> {
         self.get_inner().readlink(req, file_id)
@@ -587,6 +780,50 @@ pub trait FuseHandler<TId: FileIdType>: OptionalSendSync + 'static {
         )
     }
 
+    /// Write data to a file from a borrowed buffer
+    ///
+    /// This is the variant of `write` called by the driver: `data` is the payload of the request, or a copy of it
+    /// when the request is handled by another thread, taken from a pool if `pooled_writes` returns true, which saves
+    /// an allocation per write.
+    ///
+    /// Default implementation delegates to the inner handler, or is an adapter on top of `write` when the inner
+    /// handler is a `DefaultFuseHandler` (see `is_default_handler`), the payload then being only copied if it was
+    /// borrowed. Handlers overriding `write` on top of another inner handler should override this method as well.
+    fn write_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        data: WriteData,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        if !self.get_inner().is_default_handler() {
+            return self.get_inner().write_buf(
+                req,
+                file_id,
+                file_handle,
+                seek,
+                data,
+                write_flags,
+                flags,
+                lock_owner,
+            );
+        }
+        self.write(
+            req,
+            file_id,
+            file_handle,
+            seek,
+            data.into_vec(),
+            write_flags,
+            flags,
+            lock_owner,
+        )
+    }
+
     /// Remove a file
     fn unlink(&self, req: &RequestInfo, parent_id: TId, name: &OsStr) -> FuseResult<()> {
         self.get_inner().unlink(req, parent_id, name)
@@ -18,13 +18,20 @@ use super::{Inode, ROOT_INODE};
 ///
 /// # Note
 /// - T is the type of data associated with each inode.
-/// - Maintains a next_inode counter for generating unique inode values.
+/// - Maintains a next_inode counter for generating unique inode values, unless an allocator is set
+///   with `set_inode_allocator`.
+/// - An inode may have several names (hard links), added with `insert_link`. Its first name is the one
+///   used by `get` and `resolve`, the others are only known to `lookup` and `get_names`.
 pub struct InodeMapper<T> {
     data: InodeData<T>,
     root_inode: Inode,
     next_inode: Inode,
+    inode_allocator: Option<Box<InodeAllocator>>,
 }
 
+/// Attributes the inode of a new child from its parent inode and its name
+pub type InodeAllocator = dyn FnMut(&Inode, &OsStr) -> Inode + Send + Sync;
+
 struct InodeData<T> {
     inodes: HashMap<Inode, InodeValue<T>>,
     children: HashMap<Inode, HashMap<OsStringWrapper, Inode>>,
@@ -34,6 +41,8 @@ struct InodeData<T> {
 struct InodeValue<T> {
     parent: Inode,
     name: OsStringWrapper,
+    /// Other names of the inode, as (parent, name)
+    links: Vec<(Inode, OsStringWrapper)>,
     data: T,
 }
 
@@ -74,6 +83,18 @@ pub enum RenameError {
     NewParentNotFound,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkError {
+    NotFound,
+    NewParentNotFound,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UnlinkError {
+    NotFound,
+    LastName,
+}
+
 /// A wrapper around `Arc<OsString>` for efficient storage and comparison in hash maps.
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 struct OsStringWrapper(Arc<OsString>);
@@ -109,12 +130,14 @@ impl<T: Send + Sync + 'static> InodeMapper<T> {
             },
             root_inode: ROOT_INODE.clone(),
             next_inode: ROOT_INODE.add_one(),
+            inode_allocator: None,
         };
         result.data.inodes.insert(
             ROOT_INODE.clone(),
             InodeValue {
                 parent: ROOT_INODE.clone(),
                 name: OsStringWrapper(Arc::new(OsString::from(""))),
+                links: Vec::new(),
                 data,
             },
         );
@@ -125,6 +148,16 @@ impl<T: Send + Sync + 'static> InodeMapper<T> {
         self.root_inode.clone()
     }
 
+    /// Replaces the next_inode counter by `allocator` to attribute the inodes of new children.
+    ///
+    /// The allocator must return an inode which is not used by another entry of the mapper.
+    pub fn set_inode_allocator<F>(&mut self, allocator: F)
+    where
+        F: FnMut(&Inode, &OsStr) -> Inode + Send + Sync + 'static,
+    {
+        self.inode_allocator = Some(Box::new(allocator));
+    }
+
     /// A private method that inserts a child inode into the InodeMapper, even if the parent doesn't exist.
     ///
     /// This function creates a new inode or updates an existing one, associating it with the given parent and child name. It uses a value_creator function to generate or update the data associated with the inode.
@@ -134,6 +167,7 @@ impl<T: Send + Sync + 'static> InodeMapper<T> {
     /// # Behavior:
     /// - If the child doesn't exist, a new inode is created with a unique ID.
     /// - If the child already exists, its data is updated using the value_creator function.
+    /// - If the allocator returns an inode which already exists, the child becomes another name of that inode.
     /// - The value_creator function is called with the inode, parent, child name, and existing data (if any) as arguments.
     ///
     /// Caveat: This method may create orphaned inodes if used with non-existent parents. Use with caution.
@@ -158,16 +192,22 @@ impl<T: Send + Sync + 'static> InodeMapper<T> {
             .entry(child.clone())
             .or_insert_with(|| {
                 is_new = true;
-                self.next_inode.clone()
+                match self.inode_allocator.as_mut() {
+                    Some(allocator) => allocator(parent, child.as_ref()),
+                    None => self.next_inode.clone(),
+                }
             })
             .clone();
-        if is_new {
+        if is_new && self.inode_allocator.is_none() {
             self.next_inode = inode.add_one();
+        }
+        if is_new && !self.data.inodes.contains_key(&inode) {
             self.data.inodes.insert(
                 inode.clone(),
                 InodeValue {
                     parent: parent.clone(),
                     name: child.clone(),
+                    links: Vec::new(),
                     data: value_creator(ValueCreatorParams {
                         parent: &parent,
                         new_inode: &inode,
@@ -178,6 +218,9 @@ impl<T: Send + Sync + 'static> InodeMapper<T> {
             );
         } else {
             let inode_value = &mut self.data.inodes.get_mut(&inode).unwrap();
+            if is_new {
+                inode_value.links.push((parent.clone(), child.clone()));
+            }
             inode_value.data = value_creator(ValueCreatorParams {
                 parent: &parent,
                 new_inode: &inode,
@@ -391,6 +434,35 @@ impl<T: Send + Sync + 'static> InodeMapper<T> {
             })
     }
 
+    /// Returns the number of inodes, including the root
+    pub fn len(&self) -> usize {
+        self.data.inodes.len()
+    }
+
+    /// Always false, as the root inode is never removed
+    pub fn is_empty(&self) -> bool {
+        self.data.inodes.is_empty()
+    }
+
+    /// Iterates over every inode, including the root, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&Inode, InodeInfo<'_, T>)> {
+        self.data.inodes.iter().map(|(inode, inode_value)| {
+            (
+                inode,
+                InodeInfo {
+                    parent: &inode_value.parent,
+                    name: inode_value.name.as_ref(),
+                    data: &inode_value.data,
+                },
+            )
+        })
+    }
+
+    /// Returns true if the inode has at least one child
+    pub fn has_children(&self, inode: &Inode) -> bool {
+        self.data.children.contains_key(inode)
+    }
+
     // Retrieves all children of a given parent inode.
     ///
     /// # Note
@@ -426,6 +498,10 @@ impl<T: Send + Sync + 'static> InodeMapper<T> {
     }
 
     /// Renames a child inode from one parent to another
+    ///
+    /// The entry previously named `newname` in `newparent` is replaced: its inode is removed and returned,
+    /// unless it has other names, in which case only the replaced name is removed. Renaming a name to another
+    /// name of the same inode does nothing.
     pub fn rename(
         &mut self,
         parent: &Inode,
@@ -442,50 +518,176 @@ impl<T: Send + Sync + 'static> InodeMapper<T> {
         if !self.data.inodes.contains_key(newparent) {
             return Err(RenameError::NewParentNotFound);
         }
-
-        // Remove the child from the old parent
-        let mut is_parent_empty = false;
         let child_inode = self
             .data
             .children
-            .get_mut(parent)
-            .ok_or(RenameError::NotFound)
-            .and_then(|parent_children| {
-                let child_inode = parent_children
-                    .remove(oldname)
-                    .ok_or(RenameError::NotFound)?;
-                if parent_children.is_empty() {
-                    is_parent_empty = true;
-                }
-                Ok(child_inode)
-            })?;
+            .get(parent)
+            .and_then(|parent_children| parent_children.get(oldname))
+            .cloned()
+            .ok_or(RenameError::NotFound)?;
 
-        // Remove the old parent if it's now empty
-        if is_parent_empty {
-            self.data.children.remove(parent);
+        // Remove the entry replaced by the child
+        let mut replaced = None;
+        let existing_inode = self
+            .data
+            .children
+            .get(newparent)
+            .and_then(|children| children.get(newname.0.as_os_str()))
+            .cloned();
+        if let Some(old_inode) = existing_inode {
+            if old_inode == child_inode {
+                return Ok(None);
+            }
+            if self.get_names(&old_inode).len() > 1 {
+                self.detach_name(&old_inode, newparent, newname.0.as_os_str());
+            } else {
+                replaced = self
+                    .remove(&old_inode)
+                    .map(|data| (old_inode.clone(), data));
+            }
+        }
+
+        // Remove the child from the old parent, and the old parent if it's now empty
+        if let Some(parent_children) = self.data.children.get_mut(parent) {
+            parent_children.remove(oldname);
+            if parent_children.is_empty() {
+                self.data.children.remove(parent);
+            }
         }
 
-        // Update the inode value with the new parent and name
-        self.data.inodes.get_mut(&child_inode).map(|inode_value| {
-            inode_value.parent = newparent.clone();
-            inode_value.name = newname.clone();
-        });
+        // Update the name of the inode with the new parent and name
+        if let Some(inode_value) = self.data.inodes.get_mut(&child_inode) {
+            if inode_value.parent == *parent && inode_value.name.0.as_os_str() == oldname {
+                inode_value.parent = newparent.clone();
+                inode_value.name = newname.clone();
+            } else if let Some(link) =
+                inode_value
+                    .links
+                    .iter_mut()
+                    .find(|(link_parent, link_name)| {
+                        link_parent == parent && link_name.0.as_os_str() == oldname
+                    })
+            {
+                *link = (newparent.clone(), newname.clone());
+            }
+        }
 
         // Insert the child into the new parent's children map
-        if let Some(old_inode) = self
+        self.data
+            .children
+            .entry(newparent.clone())
+            .or_default()
+            .insert(newname, child_inode);
+        Ok(replaced)
+    }
+
+    /// Returns every name of an inode as (parent, name), starting with the name returned by `get`
+    pub fn get_names(&self, inode: &Inode) -> Vec<(&Inode, &Arc<OsString>)> {
+        self.data
+            .inodes
+            .get(inode)
+            .map(|inode_value| {
+                std::iter::once((&inode_value.parent, inode_value.name.as_ref()))
+                    .chain(
+                        inode_value
+                            .links
+                            .iter()
+                            .map(|(parent, name)| (parent, name.as_ref())),
+                    )
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Adds the name `newname` in `newparent` to an existing inode, as a hard link does
+    ///
+    /// An entry previously named `newname` in `newparent` is replaced like with `rename`.
+    pub fn insert_link(
+        &mut self,
+        inode: &Inode,
+        newparent: &Inode,
+        newname: OsString,
+    ) -> Result<Option<(Inode, T)>, LinkError> {
+        if !self.data.inodes.contains_key(inode) {
+            return Err(LinkError::NotFound);
+        }
+        if !self.data.inodes.contains_key(newparent) {
+            return Err(LinkError::NewParentNotFound);
+        }
+        let newname = OsStringWrapper(Arc::new(newname));
+
+        let mut replaced = None;
+        let existing_inode = self
             .data
             .children
-            .get_mut(newparent)
-            .and_then(|children| children.insert(newname, child_inode))
-        {
-            let InodeValue {
-                parent: _,
-                name: _,
-                data,
-            } = self.data.inodes.remove(&old_inode).unwrap();
-            Ok(Some((old_inode, data)))
-        } else {
-            Ok(None)
+            .get(newparent)
+            .and_then(|children| children.get(newname.0.as_os_str()))
+            .cloned();
+        if let Some(old_inode) = existing_inode {
+            if old_inode == *inode {
+                return Ok(None);
+            }
+            if self.get_names(&old_inode).len() > 1 {
+                self.detach_name(&old_inode, newparent, newname.0.as_os_str());
+            } else {
+                replaced = self
+                    .remove(&old_inode)
+                    .map(|data| (old_inode.clone(), data));
+            }
+        }
+
+        self.data
+            .inodes
+            .get_mut(inode)
+            .unwrap()
+            .links
+            .push((newparent.clone(), newname.clone()));
+        self.data
+            .children
+            .entry(newparent.clone())
+            .or_default()
+            .insert(newname, inode.clone());
+        Ok(replaced)
+    }
+
+    /// Removes the name `name` in `parent` of an inode having other names, returns the inode
+    ///
+    /// The last name of an inode is never removed, `remove` removes the inode itself.
+    pub fn unlink(&mut self, parent: &Inode, name: &OsStr) -> Result<Inode, UnlinkError> {
+        let inode = self
+            .data
+            .children
+            .get(parent)
+            .and_then(|children| children.get(name))
+            .cloned()
+            .ok_or(UnlinkError::NotFound)?;
+        if self.get_names(&inode).len() < 2 {
+            return Err(UnlinkError::LastName);
+        }
+        self.detach_name(&inode, parent, name);
+        Ok(inode)
+    }
+
+    /// Removes one of the names of an inode having several, the next name replaces the first one if removed
+    fn detach_name(&mut self, inode: &Inode, parent: &Inode, name: &OsStr) {
+        if let Some(parent_children) = self.data.children.get_mut(parent) {
+            parent_children.remove(name);
+            if parent_children.is_empty() {
+                self.data.children.remove(parent);
+            }
+        }
+        if let Some(inode_value) = self.data.inodes.get_mut(inode) {
+            if inode_value.parent == *parent && inode_value.name.0.as_os_str() == name {
+                if !inode_value.links.is_empty() {
+                    let (link_parent, link_name) = inode_value.links.remove(0);
+                    inode_value.parent = link_parent;
+                    inode_value.name = link_name;
+                }
+            } else {
+                inode_value.links.retain(|(link_parent, link_name)| {
+                    !(link_parent == parent && link_name.0.as_os_str() == name)
+                });
+            }
         }
     }
 
@@ -508,13 +710,17 @@ impl<T: Send + Sync + 'static> InodeMapper<T> {
             panic!("Cannot remove ROOT");
         }
         if let Some(inode_value) = self.data.inodes.remove(inode) {
-            // Remove this inode from its parent's children
-            if let Some(parent_children) = self.data.children.get_mut(&inode_value.parent) {
-                parent_children.remove(&inode_value.name);
-
-                // If the parent's children map is now empty, remove it from the children HashMap
-                if parent_children.is_empty() {
-                    self.data.children.remove(&inode_value.parent);
+            // Remove every name of this inode from its parents' children
+            let names = std::iter::once((inode_value.parent.clone(), inode_value.name.clone()))
+                .chain(inode_value.links.iter().cloned());
+            for (parent, name) in names {
+                if let Some(parent_children) = self.data.children.get_mut(&parent) {
+                    parent_children.remove(&name);
+
+                    // If the parent's children map is now empty, remove it from the children HashMap
+                    if parent_children.is_empty() {
+                        self.data.children.remove(&parent);
+                    }
                 }
             }
 
@@ -826,4 +1032,115 @@ mod tests {
         assert_eq!(mapper.get_children(&ROOT_INODE).len(), 0);
         assert!(mapper.get(&ROOT_INODE).is_some());
     }
+
+    #[test]
+    fn test_rename_to_parent_without_children() {
+        let mut mapper = InodeMapper::new(());
+        let root = mapper.get_root_inode();
+        let dir = mapper
+            .insert_child(&root, OsString::from("dir"), |_| ())
+            .unwrap();
+        let file = mapper
+            .insert_child(&root, OsString::from("file"), |_| ())
+            .unwrap();
+
+        mapper
+            .rename(&root, OsStr::new("file"), &dir, OsString::from("moved"))
+            .unwrap();
+        assert_eq!(
+            mapper.lookup(&dir, OsStr::new("moved")).unwrap().inode,
+            &file
+        );
+        assert_eq!(mapper.resolve(&file).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_links() {
+        let mut mapper = InodeMapper::new(0);
+        let root = mapper.get_root_inode();
+        let dir = mapper
+            .insert_child(&root, OsString::from("dir"), |_| 0)
+            .unwrap();
+        let file = mapper
+            .insert_child(&root, OsString::from("file"), |_| 1)
+            .unwrap();
+        let other = mapper
+            .insert_child(&dir, OsString::from("other"), |_| 2)
+            .unwrap();
+
+        // A link replaces the entry with the same name
+        assert_eq!(
+            mapper.insert_link(&file, &dir, OsString::from("other")),
+            Ok(Some((other.clone(), 2)))
+        );
+        assert_eq!(
+            mapper.insert_link(&Inode::from(999), &dir, OsString::from("link")),
+            Err(LinkError::NotFound)
+        );
+        assert_eq!(
+            mapper.lookup(&dir, OsStr::new("other")).unwrap().inode,
+            &file
+        );
+        assert_eq!(mapper.get_names(&file).len(), 2);
+
+        // Renaming a name to another name of the same inode does nothing
+        assert_eq!(
+            mapper.rename(&dir, OsStr::new("other"), &root, OsString::from("file")),
+            Ok(None)
+        );
+        assert_eq!(mapper.get_names(&file).len(), 2);
+
+        // Each name can be renamed
+        mapper
+            .rename(&dir, OsStr::new("other"), &dir, OsString::from("renamed"))
+            .unwrap();
+        assert!(mapper.lookup(&dir, OsStr::new("other")).is_none());
+        assert_eq!(
+            mapper.lookup(&dir, OsStr::new("renamed")).unwrap().inode,
+            &file
+        );
+
+        // Removing the first name makes the next one the path of the inode
+        assert_eq!(mapper.unlink(&root, OsStr::new("file")), Ok(file.clone()));
+        assert!(mapper.lookup(&root, OsStr::new("file")).is_none());
+        let path = mapper.resolve(&file).unwrap();
+        assert_eq!(**path[0].name, "renamed");
+        assert_eq!(path[1].name.as_os_str(), "dir");
+        assert_eq!(
+            mapper.unlink(&dir, OsStr::new("renamed")),
+            Err(UnlinkError::LastName)
+        );
+        assert_eq!(
+            mapper.unlink(&dir, OsStr::new("unknown")),
+            Err(UnlinkError::NotFound)
+        );
+
+        // Removing the inode removes all its names
+        mapper
+            .insert_link(&file, &root, OsString::from("link"))
+            .unwrap();
+        mapper.remove(&file);
+        assert!(mapper.lookup(&root, OsStr::new("link")).is_none());
+        assert!(mapper.lookup(&dir, OsStr::new("renamed")).is_none());
+        assert_eq!(mapper.len(), 2);
+    }
+
+    #[test]
+    fn test_allocator_returning_existing_inode() {
+        let mut mapper = InodeMapper::new(0);
+        let root = mapper.get_root_inode();
+        // Every name is attributed the same inode, as for hard links saved by an inode store
+        mapper.set_inode_allocator(|_, _| Inode::from(2));
+        let first = mapper
+            .insert_child(&root, OsString::from("first"), |_| 1)
+            .unwrap();
+        let second = mapper
+            .insert_child(&root, OsString::from("second"), |params| {
+                params.existing_data.unwrap() + 1
+            })
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(*mapper.get(&first).unwrap().data, 2);
+        assert_eq!(mapper.get_names(&first).len(), 2);
+    }
 }
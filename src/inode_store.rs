@@ -0,0 +1,535 @@
+//! Persistent storage of the inode numbers attributed to paths.
+//!
+//! By default, `PathBuf` and `Vec<OsString>` based filesystems attribute inode numbers in the order files are looked up,
+//! so the same file gets a different inode number after every remount. This breaks tools relying on inode numbers
+//! being stable, such as NFS re-exports, `find -inum`, hard-link detection of rsync or backup tools.
+//!
+//! An [`InodeStore`] records the inode number of every entry in a file, and reloads them when the filesystem is mounted
+//! again with [`MountBuilder::inode_store`](crate::MountBuilder::inode_store). Entries are identified by the inode number
+//! of their parent and their name, so renaming a directory keeps the inode numbers of everything below it. Hard links
+//! made through the filesystem are recorded as several entries with the same inode number.
+//!
+//! The store also keeps the generation number of the inodes, created with the store. A file deleted through the
+//! filesystem keeps its entry, and the generation of its inode is incremented: a file created again with the same
+//! name gets the inode number back with a new generation, so that the kernel (and NFS clients) tell it apart from
+//! the deleted one.
+//!
+//! # Format
+//!
+//! The file is a journal: a header followed by one record per new entry, rename, removed link or deleted file. Records
+//! are appended as they happen, and the journal is compacted each time the store is opened. A record truncated by a
+//! crash is ignored.
+//!
+//! The header holds the generation of the store and the next inode number to attribute, so that the numbers of
+//! inodes no entry refers to anymore (eg: the target of a rename) are not given again after a remount.
+//!
+//! # Caveats
+//!
+//! - Entries are never removed from the store, as path-based filesystems identify files by their path: a file created
+//!   with the name of a deleted file gets its inode number back, with a new generation. Only the names of a
+//!   hard-linked file are removed when unlinked, as long as the file keeps another name.
+//! - Files deleted outside of the filesystem (eg: in the source directory of a mirror) keep their generation.
+//! - The store must not be shared by two mounted filesystems.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::error;
+
+use crate::types::*;
+
+const MAGIC: &[u8; 8] = b"EFINODE2";
+const RECORD_INSERT: u8 = 1;
+const RECORD_RENAME: u8 = 2;
+const RECORD_REMOVE: u8 = 3;
+const RECORD_GENERATION: u8 = 4;
+
+/// Inode numbers of the entries of a filesystem, persisted in a file
+pub struct InodeStore {
+    path: PathBuf,
+    journal: File,
+    table: InodeTable,
+    generation: u64,
+}
+
+/// Entries of the store, rebuilt from the journal
+struct InodeTable {
+    entries: HashMap<(u64, OsString), u64>,
+    next_inode: u64,
+    /// Generation of the inodes whose file was deleted, the others have the generation of the store
+    generations: HashMap<u64, u64>,
+}
+
+impl InodeTable {
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Insert { parent, name, ino } => {
+                self.next_inode = self.next_inode.max(ino + 1);
+                self.entries.insert((parent, name), ino);
+            }
+            Record::Rename {
+                parent,
+                name,
+                newparent,
+                newname,
+            } => {
+                if let Some(ino) = self.entries.remove(&(parent, name)) {
+                    self.entries.insert((newparent, newname), ino);
+                }
+            }
+            Record::Remove { parent, name } => {
+                self.entries.remove(&(parent, name));
+            }
+            Record::Generation { ino, generation } => {
+                self.generations.insert(ino, generation);
+            }
+        }
+    }
+}
+
+enum Record {
+    Insert {
+        parent: u64,
+        name: OsString,
+        ino: u64,
+    },
+    Rename {
+        parent: u64,
+        name: OsString,
+        newparent: u64,
+        newname: OsString,
+    },
+    Remove {
+        parent: u64,
+        name: OsString,
+    },
+    Generation {
+        ino: u64,
+        generation: u64,
+    },
+}
+
+impl Record {
+    fn write_to(&self, buffer: &mut Vec<u8>) {
+        fn write_name(buffer: &mut Vec<u8>, name: &OsStr) {
+            buffer.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(name.as_bytes());
+        }
+        match self {
+            Record::Insert { parent, name, ino } => {
+                buffer.push(RECORD_INSERT);
+                buffer.extend_from_slice(&parent.to_le_bytes());
+                buffer.extend_from_slice(&ino.to_le_bytes());
+                write_name(buffer, name);
+            }
+            Record::Rename {
+                parent,
+                name,
+                newparent,
+                newname,
+            } => {
+                buffer.push(RECORD_RENAME);
+                buffer.extend_from_slice(&parent.to_le_bytes());
+                write_name(buffer, name);
+                buffer.extend_from_slice(&newparent.to_le_bytes());
+                write_name(buffer, newname);
+            }
+            Record::Remove { parent, name } => {
+                buffer.push(RECORD_REMOVE);
+                buffer.extend_from_slice(&parent.to_le_bytes());
+                write_name(buffer, name);
+            }
+            Record::Generation { ino, generation } => {
+                buffer.push(RECORD_GENERATION);
+                buffer.extend_from_slice(&ino.to_le_bytes());
+                buffer.extend_from_slice(&generation.to_le_bytes());
+            }
+        }
+    }
+
+    /// Reads the next record, returns None at the end of the journal or on a truncated record
+    fn read_from(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            Ok(u64::from_le_bytes(bytes))
+        }
+        fn read_name(reader: &mut impl Read) -> io::Result<OsString> {
+            let mut len = [0; 4];
+            reader.read_exact(&mut len)?;
+            let mut name = vec![0; u32::from_le_bytes(len) as usize];
+            reader.read_exact(&mut name)?;
+            Ok(OsString::from_vec(name))
+        }
+        fn read_insert(reader: &mut impl Read) -> io::Result<Record> {
+            let parent = read_u64(reader)?;
+            let ino = read_u64(reader)?;
+            let name = read_name(reader)?;
+            Ok(Record::Insert { parent, name, ino })
+        }
+        fn read_rename(reader: &mut impl Read) -> io::Result<Record> {
+            let parent = read_u64(reader)?;
+            let name = read_name(reader)?;
+            let newparent = read_u64(reader)?;
+            let newname = read_name(reader)?;
+            Ok(Record::Rename {
+                parent,
+                name,
+                newparent,
+                newname,
+            })
+        }
+        fn read_remove(reader: &mut impl Read) -> io::Result<Record> {
+            let parent = read_u64(reader)?;
+            let name = read_name(reader)?;
+            Ok(Record::Remove { parent, name })
+        }
+        fn read_generation(reader: &mut impl Read) -> io::Result<Record> {
+            let ino = read_u64(reader)?;
+            let generation = read_u64(reader)?;
+            Ok(Record::Generation { ino, generation })
+        }
+
+        let mut tag = [0; 1];
+        if reader.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let record = match tag[0] {
+            RECORD_INSERT => read_insert(reader),
+            RECORD_RENAME => read_rename(reader),
+            RECORD_REMOVE => read_remove(reader),
+            RECORD_GENERATION => read_generation(reader),
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid record type {} in inode store", tag),
+                ))
+            }
+        };
+        match record {
+            Ok(record) => Ok(Some(record)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl InodeStore {
+    /// Opens the store saved at `path`, or creates it if the file does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut table = InodeTable {
+            entries: HashMap::new(),
+            next_inode: u64::from(ROOT_INODE.add_one()),
+            generations: HashMap::new(),
+        };
+        let generation = match File::open(&path) {
+            Ok(file) => Self::load(&path, file, &mut table)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+            Err(e) => return Err(e),
+        };
+        let journal = Self::compact(&path, &table, generation)?;
+        Ok(Self {
+            path,
+            journal,
+            table,
+            generation,
+        })
+    }
+
+    /// Replays the journal into `table`, returns the generation of the store
+    fn load(path: &Path, file: File, table: &mut InodeTable) -> io::Result<u64> {
+        let mut reader = BufReader::new(file);
+        let mut header = [0; 24];
+        reader.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: not an inode store", path.display()),
+            ));
+        }
+        table.next_inode = u64::from_le_bytes(header[16..].try_into().unwrap());
+        while let Some(record) = Record::read_from(&mut reader)? {
+            table.apply(record);
+        }
+        Ok(u64::from_le_bytes(header[8..16].try_into().unwrap()))
+    }
+
+    /// Rewrites the journal with one record per entry, returns the journal opened for appending
+    fn compact(path: &Path, table: &InodeTable, generation: u64) -> io::Result<File> {
+        let mut temporary_path = path.as_os_str().to_os_string();
+        temporary_path.push(".tmp");
+        let temporary_path = PathBuf::from(temporary_path);
+        let mut writer = BufWriter::new(File::create(&temporary_path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&generation.to_le_bytes())?;
+        writer.write_all(&table.next_inode.to_le_bytes())?;
+        let mut buffer = Vec::new();
+        for ((parent, name), ino) in &table.entries {
+            buffer.clear();
+            Record::Insert {
+                parent: *parent,
+                name: name.clone(),
+                ino: *ino,
+            }
+            .write_to(&mut buffer);
+            writer.write_all(&buffer)?;
+        }
+        for (ino, generation) in &table.generations {
+            buffer.clear();
+            Record::Generation {
+                ino: *ino,
+                generation: *generation,
+            }
+            .write_to(&mut buffer);
+            writer.write_all(&buffer)?;
+        }
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&temporary_path, path)?;
+        OpenOptions::new().append(true).open(path)
+    }
+
+    fn append(&mut self, record: Record) {
+        let mut buffer = Vec::new();
+        record.write_to(&mut buffer);
+        if let Err(e) = self.journal.write_all(&buffer) {
+            error!("{}: failed to save inode store: {}", self.path.display(), e);
+        }
+        self.table.apply(record);
+    }
+
+    /// Path of the file of the store
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Generation number of the store, reported for the inodes whose file was never deleted, unchanged across remounts
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Generation number of the inode `ino`, incremented each time its file is deleted through the filesystem
+    pub fn inode_generation(&self, ino: u64) -> u64 {
+        self.table
+            .generations
+            .get(&ino)
+            .copied()
+            .unwrap_or(self.generation)
+    }
+
+    /// Number of entries in the store
+    pub fn len(&self) -> usize {
+        self.table.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.entries.is_empty()
+    }
+
+    /// Returns the inode number of the entry `name` in `parent`, if known by the store
+    pub fn get(&self, parent: u64, name: &OsStr) -> Option<u64> {
+        self.table
+            .entries
+            .get(&(parent, name.to_os_string()))
+            .copied()
+    }
+
+    /// Returns the inode number of the entry, attributing and saving a new one if the entry is unknown
+    pub(crate) fn get_or_insert(&mut self, parent: u64, name: &OsStr) -> u64 {
+        if let Some(ino) = self.get(parent, name) {
+            return ino;
+        }
+        let ino = self.table.next_inode;
+        self.append(Record::Insert {
+            parent,
+            name: name.to_os_string(),
+            ino,
+        });
+        ino
+    }
+
+    /// Moves an entry to its new name, replacing the entry previously using that name
+    pub(crate) fn rename(&mut self, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr) {
+        let ino = self.get(parent, name);
+        let replaced = self.get(newparent, newname);
+        if replaced.is_some() && replaced != ino {
+            // The inode of the replaced entry loses this name, its number is never given again
+            self.append(Record::Remove {
+                parent: newparent,
+                name: newname.to_os_string(),
+            });
+        }
+        if ino.is_none() {
+            return;
+        }
+        self.append(Record::Rename {
+            parent,
+            name: name.to_os_string(),
+            newparent,
+            newname: newname.to_os_string(),
+        });
+    }
+
+    /// Saves `newname` in `newparent` as another name of the inode `ino`
+    pub(crate) fn link(&mut self, ino: u64, newparent: u64, newname: &OsStr) {
+        self.append(Record::Insert {
+            parent: newparent,
+            name: newname.to_os_string(),
+            ino,
+        });
+    }
+
+    /// Records the deletion of the file of `ino`, the next file getting this inode number has a new generation
+    pub(crate) fn retire(&mut self, ino: u64) {
+        let generation = self.inode_generation(ino).wrapping_add(1);
+        self.append(Record::Generation { ino, generation });
+    }
+
+    /// Removes an entry, used for the names of hard-linked files
+    pub(crate) fn remove(&mut self, parent: u64, name: &OsStr) {
+        if self.get(parent, name).is_none() {
+            return;
+        }
+        self.append(Record::Remove {
+            parent,
+            name: name.to_os_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_inode_store_reload() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("inodes");
+        let root = u64::from(ROOT_INODE);
+
+        let mut store = InodeStore::open(&path).unwrap();
+        let generation = store.generation();
+        let dir_ino = store.get_or_insert(root, OsStr::new("dir"));
+        let file_ino = store.get_or_insert(dir_ino, OsStr::new("file"));
+        let other_ino = store.get_or_insert(root, OsStr::new("other"));
+        assert_eq!(store.get_or_insert(root, OsStr::new("dir")), dir_ino);
+        assert_ne!(dir_ino, file_ino);
+        store.rename(root, OsStr::new("dir"), root, OsStr::new("other"));
+        drop(store);
+
+        let mut store = InodeStore::open(&path).unwrap();
+        assert_eq!(store.generation(), generation);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(root, OsStr::new("dir")), None);
+        assert_eq!(store.get(root, OsStr::new("other")), Some(dir_ino));
+        assert_eq!(store.get(dir_ino, OsStr::new("file")), Some(file_ino));
+        // Inode numbers are never reused
+        let new_ino = store.get_or_insert(root, OsStr::new("new"));
+        assert!(new_ino > other_ino);
+
+        store.link(file_ino, root, OsStr::new("link"));
+        store.remove(dir_ino, OsStr::new("file"));
+        drop(store);
+        let store = InodeStore::open(&path).unwrap();
+        assert_eq!(store.get(root, OsStr::new("link")), Some(file_ino));
+        assert_eq!(store.get(dir_ino, OsStr::new("file")), None);
+    }
+
+    #[test]
+    fn test_inode_store_generations() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("inodes");
+        let root = u64::from(ROOT_INODE);
+
+        let mut store = InodeStore::open(&path).unwrap();
+        let generation = store.generation();
+        let ino = store.get_or_insert(root, OsStr::new("file"));
+        let kept_ino = store.get_or_insert(root, OsStr::new("kept"));
+        assert_eq!(store.inode_generation(ino), generation);
+        store.retire(ino);
+        assert_eq!(store.get_or_insert(root, OsStr::new("file")), ino);
+        let new_generation = store.inode_generation(ino);
+        assert_ne!(new_generation, generation);
+        drop(store);
+
+        // Generations are kept across compactions
+        let mut store = InodeStore::open(&path).unwrap();
+        assert_eq!(store.inode_generation(ino), new_generation);
+        assert_eq!(store.inode_generation(kept_ino), generation);
+        store.retire(ino);
+        assert_ne!(store.inode_generation(ino), new_generation);
+    }
+
+    #[test]
+    fn test_inode_store_rename_over() {
+        let dir = TempDir::new().unwrap();
+        let root = u64::from(ROOT_INODE);
+
+        for (from, to) in [("b", "a"), ("a", "b")] {
+            let path = dir.path().join(format!("inodes-{}", from));
+            let mut store = InodeStore::open(&path).unwrap();
+            let a = store.get_or_insert(root, OsStr::new("a"));
+            let b = store.get_or_insert(root, OsStr::new("b"));
+            let replaced = if to == "a" { a } else { b };
+            let renamed = if from == "a" { a } else { b };
+            store.rename(root, OsStr::new(from), root, OsStr::new(to));
+            assert_eq!(store.get(root, OsStr::new(to)), Some(renamed));
+            assert_eq!(store.len(), 1);
+            let replaced_generation = store.inode_generation(replaced);
+            drop(store);
+
+            // The number of the replaced inode is not given again after the journal is compacted, even if it
+            // was the highest one
+            drop(InodeStore::open(&path).unwrap());
+            let mut store = InodeStore::open(&path).unwrap();
+            let c = store.get_or_insert(root, OsStr::new("c"));
+            assert_ne!(
+                (c, store.inode_generation(c)),
+                (replaced, replaced_generation)
+            );
+            assert!(c > a.max(b));
+            drop(store);
+            let store = InodeStore::open(&path).unwrap();
+            assert_eq!(store.get(root, OsStr::new("c")), Some(c));
+            assert_eq!(store.get(root, OsStr::new(to)), Some(renamed));
+        }
+    }
+
+    #[test]
+    fn test_inode_store_truncated_journal() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("inodes");
+        let root = u64::from(ROOT_INODE);
+
+        let mut store = InodeStore::open(&path).unwrap();
+        let ino = store.get_or_insert(root, OsStr::new("file"));
+        store.get_or_insert(root, OsStr::new("truncated"));
+        drop(store);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let store = InodeStore::open(&path).unwrap();
+        assert_eq!(store.get(root, OsStr::new("file")), Some(ino));
+        assert_eq!(store.get(root, OsStr::new("truncated")), None);
+
+        fs::write(&path, b"not an inode store").unwrap();
+        assert!(InodeStore::open(&path).is_err());
+    }
+}
@@ -1,8 +1,5 @@
 #![doc = include_str!("../README.md")]
 
-#[cfg(feature = "async")]
-compile_error!("Feature 'async' is not yet implemented.");
-
 #[cfg(all(
     not(feature = "serial"),
     not(feature = "parallel"),
@@ -19,20 +16,36 @@ compile_error!("Feature 'parallel' cannot be used with feature serial or async")
 #[cfg(all(feature = "async", any(feature = "serial", feature = "parallel")))]
 compile_error!("Feature 'async' cannot be used with feature serial or parallel");
 
+#[cfg(feature = "async")]
+mod async_fuse_handler;
 mod core;
 mod fuse_handler;
 
 pub mod inode_mapper;
+pub mod inode_store;
+pub mod middleware;
+pub mod mount_builder;
 pub mod templates;
+#[cfg(target_os = "linux")]
+pub mod testing;
 pub mod types;
 pub mod unix_fs;
 
+#[cfg(feature = "async")]
+pub use async_fuse_handler::AsyncFuseHandler;
+#[cfg(feature = "async")]
+pub use async_trait::async_trait;
 pub use fuse_handler::FuseHandler;
 use fuser::{BackgroundSession, MountOption};
+pub use mount_builder::MountBuilder;
 
 pub mod prelude {
     //! Re-exports the necessary types and functions from the `easy_fuser` crate.
+    #[cfg(feature = "async")]
+    pub use super::async_fuse_handler::AsyncFuseHandler;
     pub use super::fuse_handler::FuseHandler;
+    pub use super::middleware::{Middleware, Operation, Outcome};
+    pub use super::mount_builder::MountBuilder;
     pub use super::types::*;
     pub use super::{mount, spawn_mount};
 
@@ -43,12 +56,10 @@ pub mod prelude {
 use std::io;
 use std::path::Path;
 
-use core::FuseDriver;
-use fuser::{mount2, spawn_mount2};
 use prelude::*;
 
 #[doc = include_str!("../docs/mount.md")]
-#[cfg(not(feature = "serial"))]
+#[cfg(feature = "parallel")]
 pub fn mount<T, FS, P>(
     filesystem: FS,
     mountpoint: P,
@@ -60,8 +71,10 @@ where
     FS: FuseHandler<T>,
     P: AsRef<Path>,
 {
-    let driver = FuseDriver::new(filesystem, num_threads);
-    mount2(driver, mountpoint, options)
+    MountBuilder::new(filesystem)
+        .options(options.iter().cloned())
+        .num_threads(num_threads)
+        .mount(mountpoint)
 }
 #[doc = include_str!("../docs/mount.md")]
 #[cfg(feature = "serial")]
@@ -71,13 +84,13 @@ where
     FS: FuseHandler<T>,
     P: AsRef<Path>,
 {
-    // num_thread argument will not be taken into account in this function due to feature serial
-    let driver = FuseDriver::new(filesystem, 1);
-    mount2(driver, mountpoint, options)
+    MountBuilder::new(filesystem)
+        .options(options.iter().cloned())
+        .mount(mountpoint)
 }
 
 #[doc = include_str!("../docs/spawn_mount.md")]
-#[cfg(not(feature = "serial"))]
+#[cfg(feature = "parallel")]
 pub fn spawn_mount<T, FS, P>(
     filesystem: FS,
     mountpoint: P,
@@ -89,8 +102,10 @@ where
     FS: FuseHandler<T> + Send,
     P: AsRef<Path>,
 {
-    let driver = FuseDriver::new(filesystem, num_threads);
-    spawn_mount2(driver, mountpoint, options)
+    MountBuilder::new(filesystem)
+        .options(options.iter().cloned())
+        .num_threads(num_threads)
+        .spawn(mountpoint)
 }
 
 #[doc = include_str!("../docs/spawn_mount.md")]
@@ -105,7 +120,45 @@ where
     FS: FuseHandler<T> + Send,
     P: AsRef<Path>,
 {
-    // num_thread argument will not be taken into account in this function due to feature serial
-    let driver = FuseDriver::new(filesystem, 1);
-    spawn_mount2(driver, mountpoint, options)
+    MountBuilder::new(filesystem)
+        .options(options.iter().cloned())
+        .spawn(mountpoint)
+}
+
+#[doc = include_str!("../docs/mount.md")]
+#[cfg(feature = "async")]
+pub fn mount<T, FS, P>(
+    filesystem: FS,
+    mountpoint: P,
+    options: &[MountOption],
+    num_threads: usize,
+) -> io::Result<()>
+where
+    T: FileIdType,
+    FS: AsyncFuseHandler<T>,
+    P: AsRef<Path>,
+{
+    MountBuilder::new(filesystem)
+        .options(options.iter().cloned())
+        .num_threads(num_threads)
+        .mount(mountpoint)
+}
+
+#[doc = include_str!("../docs/spawn_mount.md")]
+#[cfg(feature = "async")]
+pub fn spawn_mount<T, FS, P>(
+    filesystem: FS,
+    mountpoint: P,
+    options: &[MountOption],
+    num_threads: usize,
+) -> io::Result<BackgroundSession>
+where
+    T: FileIdType,
+    FS: AsyncFuseHandler<T>,
+    P: AsRef<Path>,
+{
+    MountBuilder::new(filesystem)
+        .options(options.iter().cloned())
+        .num_threads(num_threads)
+        .spawn(mountpoint)
 }
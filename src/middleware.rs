@@ -0,0 +1,1342 @@
+//! Composable middlewares for FUSE handlers.
+//!
+//! Wrapping a handler usually means writing a struct that implements every `FuseHandler` method
+//! it wants to intercept. This module provides a generic alternative for cross-cutting concerns
+//! (logging, metrics, access control...): a [`Middleware`] only implements two hooks, which are
+//! called around every operation of the wrapped handler.
+//!
+//! Middlewares are applied with [`FuseHandler::layer`], which returns a [`Layered`] handler.
+//! Layers can be stacked, the last one added being the outermost:
+//!
+//! ```text
+//! let fs = MirrorFs::new(source_path, DefaultFuseHandler::new())
+//!     .layer(AccessControl::new())
+//!     .layer(Logger::new());
+//! // Logger::before -> AccessControl::before -> MirrorFs -> AccessControl::after -> Logger::after
+//! ```
+//!
+//! ## Example
+//!
+//! ```rust, no_run
+//! use easy_fuser::prelude::*;
+//! use easy_fuser::templates::{DefaultFuseHandler, mirror_fs::*};
+//! use std::path::PathBuf;
+//!
+//! /// Reject writes and every operation creating a new entry, and log the failures of the wrapped handler
+//! struct NoNewData;
+//!
+//! impl<TId: FileIdType> Middleware<TId> for NoNewData {
+//!     fn before(&self, op: &Operation<'_, TId>) -> FuseResult<()> {
+//!         // "write" also matches the `write_buf` entry point called by the driver
+//!         match op.name {
+//!             "write" | "create" | "mkdir" | "mknod" | "symlink" | "link" => {
+//!                 Err(ErrorKind::PermissionDenied.to_error("No new data allowed"))
+//!             }
+//!             _ => Ok(()),
+//!         }
+//!     }
+//!
+//!     fn after(&self, op: &Operation<'_, TId>, outcome: &mut Outcome<'_>) {
+//!         if let Some(e) = outcome.error() {
+//!             eprintln!("{} failed after {:?}: {}", op.name, op.elapsed(), e);
+//!         }
+//!     }
+//! }
+//!
+//! let fs = MirrorFs::new(PathBuf::from("/tmp/source"), DefaultFuseHandler::new())
+//!     .layer(NoNewData);
+//! ```
+//!
+//! ## Limitations
+//!
+//! - `get_inner`, the `get_*_ttl` methods, `remote_locks` and `pooled_writes` are not intercepted.
+//! - Middlewares only apply to synchronous handlers implementing `FuseHandler`.
+
+use std::any::Any;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Debug;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::fuse_handler::private::OptionalSendSync;
+use crate::fuse_handler::FuseHandler;
+use crate::types::*;
+
+/// Hooks called around every operation of a handler wrapped with [`FuseHandler::layer`].
+///
+/// Both hooks have a default implementation which does nothing, so only the needed one must be implemented.
+pub trait Middleware<TId: FileIdType>: OptionalSendSync + 'static {
+    /// Called before the operation is forwarded to the wrapped handler
+    ///
+    /// Returning an error skips the wrapped handler, the error is then given to `after` as the outcome.
+    fn before(&self, op: &Operation<'_, TId>) -> FuseResult<()> {
+        let _ = op;
+        Ok(())
+    }
+
+    /// Called with the result of the operation, before it is returned to the kernel
+    ///
+    /// The result can be inspected and rewritten through `outcome`.
+    fn after(&self, op: &Operation<'_, TId>, outcome: &mut Outcome<'_>) {
+        let _ = (op, outcome);
+    }
+}
+
+/// Description of an intercepted operation
+#[derive(Debug)]
+pub struct Operation<'a, TId: FileIdType> {
+    /// Name of the operation, eg: "lookup"
+    ///
+    /// Operations with several entry points are reported under the same name, whichever is called by the driver:
+    /// `read_buf` as "read", `write_buf` as "write", `readdir_iter` as "readdir" and `readdirplus_iter` as
+    /// "readdirplus".
+    pub name: &'static str,
+    /// Name of the `FuseHandler` method called, which is `name` unless called through another entry point
+    pub method: &'static str,
+    /// Information about the request, only missing for `destroy`
+    pub req: Option<&'a RequestInfo>,
+    /// File targeted by the operation (the parent for operations on directory entries)
+    pub file_id: Option<&'a TId>,
+    /// Remaining arguments of the operation, by name and in declaration order
+    ///
+    /// File handles are given by their raw value, and data buffers by their length (with a `_len` suffix to their name).
+    pub args: &'a [(&'static str, &'a dyn Debug)],
+    /// Instant at which the operation was received by the middleware
+    pub started_at: Instant,
+}
+
+impl<'a, TId: FileIdType> Operation<'a, TId> {
+    fn new(
+        name: &'static str,
+        req: Option<&'a RequestInfo>,
+        file_id: Option<&'a TId>,
+        args: &'a [(&'static str, &'a dyn Debug)],
+    ) -> Self {
+        Operation {
+            name,
+            method: name,
+            req,
+            file_id,
+            args,
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Sets the method called, for the entry points reported under the name of another method
+    fn through(mut self, method: &'static str) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Time elapsed since the operation was received
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Returns the argument with the given name, if any
+    pub fn arg(&self, name: &str) -> Option<&'a dyn Debug> {
+        self.args
+            .iter()
+            .find(|(arg_name, _)| *arg_name == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+trait ResultSlot {
+    fn error(&self) -> Option<&PosixError>;
+    fn set_error(&mut self, error: PosixError);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> ResultSlot for FuseResult<T> {
+    fn error(&self) -> Option<&PosixError> {
+        self.as_ref().err()
+    }
+
+    fn set_error(&mut self, error: PosixError) {
+        *self = Err(error);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Result of an intercepted operation, as seen by [`Middleware::after`]
+///
+/// The concrete result type depends on the operation, it is the return type of the `FuseHandler` method
+/// called ([`Operation::method`], `()` for `forget` and `destroy`). It can be accessed with `result` and `result_mut`.
+pub struct Outcome<'a> {
+    result: &'a mut dyn ResultSlot,
+}
+
+impl<'a> Outcome<'a> {
+    fn new<T: 'static>(result: &'a mut FuseResult<T>) -> Self {
+        Outcome { result }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.result.error().is_none()
+    }
+
+    /// Returns the error of the operation, if it failed
+    pub fn error(&self) -> Option<&PosixError> {
+        self.result.error()
+    }
+
+    /// Replaces the result of the operation with an error
+    ///
+    /// Note: Resources returned by a successful operation (eg: an `OwnedFileHandle`) are dropped and not released.
+    pub fn set_error(&mut self, error: PosixError) {
+        self.result.set_error(error)
+    }
+
+    /// Returns the typed result, or None if `T` is not the result type of the operation
+    ///
+    /// ```text
+    /// if let Some(Ok(data)) = outcome.result::<Vec<u8>>() {
+    ///     bytes_read += data.len();
+    /// }
+    /// ```
+    pub fn result<T: 'static>(&self) -> Option<&FuseResult<T>> {
+        self.result.as_any().downcast_ref()
+    }
+
+    /// Returns the typed result mutably, or None if `T` is not the result type of the operation
+    pub fn result_mut<T: 'static>(&mut self) -> Option<&mut FuseResult<T>> {
+        self.result.as_any_mut().downcast_mut()
+    }
+}
+
+impl Debug for Outcome<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.error() {
+            None => write!(f, "Ok"),
+            Some(e) => write!(f, "Err({:?})", e),
+        }
+    }
+}
+
+/// A handler wrapped with a [`Middleware`], created by [`FuseHandler::layer`]
+pub struct Layered<THandler, TMiddleware> {
+    inner: THandler,
+    middleware: TMiddleware,
+}
+
+impl<THandler, TMiddleware> Layered<THandler, TMiddleware> {
+    pub fn new(inner: THandler, middleware: TMiddleware) -> Self {
+        Layered { inner, middleware }
+    }
+
+    pub fn get_middleware(&self) -> &TMiddleware {
+        &self.middleware
+    }
+
+    /// Returns the wrapped handler
+    pub fn into_inner(self) -> THandler {
+        self.inner
+    }
+
+    fn intercept<TId, T>(
+        &self,
+        op: Operation<'_, TId>,
+        call: impl FnOnce() -> FuseResult<T>,
+    ) -> FuseResult<T>
+    where
+        TId: FileIdType,
+        T: 'static,
+        TMiddleware: Middleware<TId>,
+    {
+        let mut result = self.middleware.before(&op).and_then(|_| call());
+        self.middleware.after(&op, &mut Outcome::new(&mut result));
+        result
+    }
+}
+
+impl<TId, THandler, TMiddleware> FuseHandler<TId> for Layered<THandler, TMiddleware>
+where
+    TId: FileIdType,
+    THandler: FuseHandler<TId>,
+    TMiddleware: Middleware<TId>,
+{
+    fn get_inner(&self) -> &dyn FuseHandler<TId> {
+        &self.inner
+    }
+
+    fn get_default_ttl(&self) -> Duration {
+        self.inner.get_default_ttl()
+    }
+
+    fn get_default_entry_ttl(&self) -> Duration {
+        self.inner.get_default_entry_ttl()
+    }
+
+    fn get_default_attr_ttl(&self) -> Duration {
+        self.inner.get_default_attr_ttl()
+    }
+
+    fn get_negative_ttl(&self) -> Option<Duration> {
+        self.inner.get_negative_ttl()
+    }
+
+    fn remote_locks(&self) -> Option<LockKind> {
+        self.inner.remote_locks()
+    }
+
+    fn pooled_writes(&self) -> bool {
+        self.inner.pooled_writes()
+    }
+
+    fn destroy(&self) {
+        let _ = self.intercept(Operation::<TId>::new("destroy", None, None, &[]), || {
+            self.inner.destroy();
+            Ok(())
+        });
+    }
+
+    fn init(
+        &self,
+        req: &RequestInfo,
+        config: &mut KernelConfig,
+        notifier: Notifier<TId>,
+    ) -> FuseResult<()> {
+        self.intercept(Operation::new("init", Some(req), None, &[]), || {
+            self.inner.init(req, config, notifier)
+        })
+    }
+
+    fn access(&self, req: &RequestInfo, file_id: TId, mask: AccessMask) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "access",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("mask", &mask)],
+            ),
+            || self.inner.access(req, file_id, mask),
+        )
+    }
+
+    fn bmap(&self, req: &RequestInfo, file_id: TId, blocksize: u32, idx: u64) -> FuseResult<u64> {
+        self.intercept(
+            Operation::new(
+                "bmap",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("blocksize", &blocksize), ("idx", &idx)],
+            ),
+            || self.inner.bmap(req, file_id, blocksize, idx),
+        )
+    }
+
+    fn copy_file_range(
+        &self,
+        req: &RequestInfo,
+        file_in: TId,
+        file_handle_in: BorrowedFileHandle,
+        offset_in: i64,
+        file_out: TId,
+        file_handle_out: BorrowedFileHandle,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+    ) -> FuseResult<u32> {
+        self.intercept(
+            Operation::new(
+                "copy_file_range",
+                Some(req),
+                Some(&file_in.clone()),
+                &[
+                    ("file_handle_in", &file_handle_in.as_raw()),
+                    ("offset_in", &offset_in),
+                    ("file_out", &file_out.clone()),
+                    ("file_handle_out", &file_handle_out.as_raw()),
+                    ("offset_out", &offset_out),
+                    ("len", &len),
+                    ("flags", &flags),
+                ],
+            ),
+            || {
+                self.inner.copy_file_range(
+                    req,
+                    file_in,
+                    file_handle_in,
+                    offset_in,
+                    file_out,
+                    file_handle_out,
+                    offset_out,
+                    len,
+                    flags,
+                )
+            },
+        )
+    }
+
+    fn create(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, TId::Metadata, FUSEOpenResponseFlags)> {
+        self.intercept(
+            Operation::new(
+                "create",
+                Some(req),
+                Some(&parent_id.clone()),
+                &[
+                    ("name", &name),
+                    ("mode", &mode),
+                    ("umask", &umask),
+                    ("flags", &flags),
+                ],
+            ),
+            || self.inner.create(req, parent_id, name, mode, umask, flags),
+        )
+    }
+
+    fn fallocate(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+        length: i64,
+        mode: FallocateFlags,
+    ) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "fallocate",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("offset", &offset),
+                    ("length", &length),
+                    ("mode", &mode),
+                ],
+            ),
+            || {
+                self.inner
+                    .fallocate(req, file_id, file_handle, offset, length, mode)
+            },
+        )
+    }
+
+    fn flush(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        lock_owner: u64,
+    ) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "flush",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("lock_owner", &lock_owner),
+                ],
+            ),
+            || self.inner.flush(req, file_id, file_handle, lock_owner),
+        )
+    }
+
+    fn forget(&self, req: &RequestInfo, file_id: TId, nlookup: u64) {
+        let _ = self.intercept(
+            Operation::new(
+                "forget",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("nlookup", &nlookup)],
+            ),
+            || {
+                self.inner.forget(req, file_id, nlookup);
+                Ok(())
+            },
+        );
+    }
+
+    fn fsync(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        datasync: bool,
+    ) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "fsync",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("datasync", &datasync),
+                ],
+            ),
+            || self.inner.fsync(req, file_id, file_handle, datasync),
+        )
+    }
+
+    fn fsyncdir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        datasync: bool,
+    ) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "fsyncdir",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("datasync", &datasync),
+                ],
+            ),
+            || self.inner.fsyncdir(req, file_id, file_handle, datasync),
+        )
+    }
+
+    fn getattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: Option<BorrowedFileHandle>,
+    ) -> FuseResult<FileAttribute> {
+        self.intercept(
+            Operation::new(
+                "getattr",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("file_handle", &file_handle.map(|fh| fh.as_raw()))],
+            ),
+            || self.inner.getattr(req, file_id, file_handle),
+        )
+    }
+
+    fn getlk(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        lock_owner: u64,
+        lock_info: LockInfo,
+    ) -> FuseResult<LockInfo> {
+        self.intercept(
+            Operation::new(
+                "getlk",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("lock_owner", &lock_owner),
+                    ("lock_info", &lock_info.clone()),
+                ],
+            ),
+            || {
+                self.inner
+                    .getlk(req, file_id, file_handle, lock_owner, lock_info)
+            },
+        )
+    }
+
+    fn getxattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        name: &OsStr,
+        size: u32,
+    ) -> FuseResult<Vec<u8>> {
+        self.intercept(
+            Operation::new(
+                "getxattr",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("name", &name), ("size", &size)],
+            ),
+            || self.inner.getxattr(req, file_id, name, size),
+        )
+    }
+
+    fn ioctl(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        flags: IOCtlFlags,
+        cmd: u32,
+        in_data: Vec<u8>,
+        out_size: u32,
+    ) -> FuseResult<(i32, Vec<u8>)> {
+        self.intercept(
+            Operation::new(
+                "ioctl",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("flags", &flags),
+                    ("cmd", &cmd),
+                    ("in_data_len", &in_data.len()),
+                    ("out_size", &out_size),
+                ],
+            ),
+            || {
+                self.inner
+                    .ioctl(req, file_id, file_handle, flags, cmd, in_data, out_size)
+            },
+        )
+    }
+
+    fn link(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        newparent: TId,
+        newname: &OsStr,
+    ) -> FuseResult<TId::Metadata> {
+        self.intercept(
+            Operation::new(
+                "link",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("newparent", &newparent.clone()), ("newname", &newname)],
+            ),
+            || self.inner.link(req, file_id, newparent, newname),
+        )
+    }
+
+    fn listxattr(&self, req: &RequestInfo, file_id: TId, size: u32) -> FuseResult<Vec<u8>> {
+        self.intercept(
+            Operation::new(
+                "listxattr",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("size", &size)],
+            ),
+            || self.inner.listxattr(req, file_id, size),
+        )
+    }
+
+    fn lookup(&self, req: &RequestInfo, parent_id: TId, name: &OsStr) -> FuseResult<TId::Metadata> {
+        self.intercept(
+            Operation::new(
+                "lookup",
+                Some(req),
+                Some(&parent_id.clone()),
+                &[("name", &name)],
+            ),
+            || self.inner.lookup(req, parent_id, name),
+        )
+    }
+
+    fn lseek(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        seek: LseekFrom,
+    ) -> FuseResult<i64> {
+        self.intercept(
+            Operation::new(
+                "lseek",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("file_handle", &file_handle.as_raw()), ("seek", &seek)],
+            ),
+            || self.inner.lseek(req, file_id, file_handle, seek),
+        )
+    }
+
+    fn mkdir(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+    ) -> FuseResult<TId::Metadata> {
+        self.intercept(
+            Operation::new(
+                "mkdir",
+                Some(req),
+                Some(&parent_id.clone()),
+                &[("name", &name), ("mode", &mode), ("umask", &umask)],
+            ),
+            || self.inner.mkdir(req, parent_id, name, mode, umask),
+        )
+    }
+
+    fn mknod(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: DeviceType,
+    ) -> FuseResult<TId::Metadata> {
+        self.intercept(
+            Operation::new(
+                "mknod",
+                Some(req),
+                Some(&parent_id.clone()),
+                &[
+                    ("name", &name),
+                    ("mode", &mode),
+                    ("umask", &umask),
+                    ("rdev", &rdev),
+                ],
+            ),
+            || self.inner.mknod(req, parent_id, name, mode, umask, rdev),
+        )
+    }
+
+    fn open(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        self.intercept(
+            Operation::new(
+                "open",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("flags", &flags)],
+            ),
+            || self.inner.open(req, file_id, flags),
+        )
+    }
+
+    fn opendir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        self.intercept(
+            Operation::new(
+                "opendir",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("flags", &flags)],
+            ),
+            || self.inner.opendir(req, file_id, flags),
+        )
+    }
+
+    fn poll(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        poll_handle: PollHandle,
+        events: PollEvents,
+        flags: FUSEPollFlags,
+    ) -> FuseResult<PollEvents> {
+        self.intercept(
+            Operation::new(
+                "poll",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("poll_handle", &poll_handle.clone()),
+                    ("events", &events),
+                    ("flags", &flags),
+                ],
+            ),
+            || {
+                self.inner
+                    .poll(req, file_id, file_handle, poll_handle, events, flags)
+            },
+        )
+    }
+
+    fn read(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<Vec<u8>> {
+        self.intercept(
+            Operation::new(
+                "read",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("seek", &seek),
+                    ("size", &size),
+                    ("flags", &flags),
+                    ("lock_owner", &lock_owner),
+                ],
+            ),
+            || {
+                self.inner
+                    .read(req, file_id, file_handle, seek, size, flags, lock_owner)
+            },
+        )
+    }
+
+    fn read_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<ReadData> {
+        self.intercept(
+            Operation::new(
+                "read",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("seek", &seek),
+                    ("size", &size),
+                    ("flags", &flags),
+                    ("lock_owner", &lock_owner),
+                ],
+            )
+            .through("read_buf"),
+            || {
+                self.inner
+                    .read_buf(req, file_id, file_handle, seek, size, flags, lock_owner)
+            },
+        )
+    }
+
+    fn readdir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+    ) -> FuseResult<Vec<(OsString, TId::MinimalMetadata)>> {
+        self.intercept(
+            Operation::new(
+                "readdir",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("file_handle", &file_handle.as_raw())],
+            ),
+            || self.inner.readdir(req, file_id, file_handle),
+        )
+    }
+
+    fn readdirplus(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+    ) -> FuseResult<Vec<(OsString, TId::Metadata)>> {
+        self.intercept(
+            Operation::new(
+                "readdirplus",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("file_handle", &file_handle.as_raw())],
+            ),
+            || self.inner.readdirplus(req, file_id, file_handle),
+        )
+    }
+
+    fn readdir_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::MinimalMetadata>> {
+        self.intercept(
+            Operation::new(
+                "readdir",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("file_handle", &file_handle.as_raw()), ("offset", &offset)],
+            )
+            .through("readdir_iter"),
+            || self.inner.readdir_iter(req, file_id, file_handle, offset),
+        )
+    }
+
+    fn readdirplus_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::Metadata>> {
+        self.intercept(
+            Operation::new(
+                "readdirplus",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("file_handle", &file_handle.as_raw()), ("offset", &offset)],
+            )
+            .through("readdirplus_iter"),
+            || {
+                self.inner
+                    .readdirplus_iter(req, file_id, file_handle, offset)
+            },
+        )
+    }
+
+    fn readlink(&self, req: &RequestInfo, file_id: TId) -> FuseResult<Vec<u8>> {
+        self.intercept(
+            Operation::new("readlink", Some(req), Some(&file_id.clone()), &[]),
+            || self.inner.readlink(req, file_id),
+        )
+    }
+
+    fn release(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: OwnedFileHandle,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+        flush: bool,
+    ) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "release",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("flags", &flags),
+                    ("lock_owner", &lock_owner),
+                    ("flush", &flush),
+                ],
+            ),
+            || {
+                self.inner
+                    .release(req, file_id, file_handle, flags, lock_owner, flush)
+            },
+        )
+    }
+
+    fn releasedir(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: OwnedFileHandle,
+        flags: OpenFlags,
+    ) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "releasedir",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("file_handle", &file_handle.as_raw()), ("flags", &flags)],
+            ),
+            || self.inner.releasedir(req, file_id, file_handle, flags),
+        )
+    }
+
+    fn removexattr(&self, req: &RequestInfo, file_id: TId, name: &OsStr) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "removexattr",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("name", &name)],
+            ),
+            || self.inner.removexattr(req, file_id, name),
+        )
+    }
+
+    fn rename(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        name: &OsStr,
+        newparent: TId,
+        newname: &OsStr,
+        flags: RenameFlags,
+    ) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "rename",
+                Some(req),
+                Some(&parent_id.clone()),
+                &[
+                    ("name", &name),
+                    ("newparent", &newparent.clone()),
+                    ("newname", &newname),
+                    ("flags", &flags),
+                ],
+            ),
+            || {
+                self.inner
+                    .rename(req, parent_id, name, newparent, newname, flags)
+            },
+        )
+    }
+
+    fn rmdir(&self, req: &RequestInfo, parent_id: TId, name: &OsStr) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "rmdir",
+                Some(req),
+                Some(&parent_id.clone()),
+                &[("name", &name)],
+            ),
+            || self.inner.rmdir(req, parent_id, name),
+        )
+    }
+
+    fn setattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        attrs: SetAttrRequest,
+    ) -> FuseResult<FileAttribute> {
+        self.intercept(
+            Operation::new(
+                "setattr",
+                Some(req),
+                Some(&file_id.clone()),
+                &[("attrs", &attrs.clone())],
+            ),
+            || self.inner.setattr(req, file_id, attrs),
+        )
+    }
+
+    fn setlk(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        lock_owner: u64,
+        lock_info: LockInfo,
+        sleep: bool,
+    ) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "setlk",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("lock_owner", &lock_owner),
+                    ("lock_info", &lock_info.clone()),
+                    ("sleep", &sleep),
+                ],
+            ),
+            || {
+                self.inner
+                    .setlk(req, file_id, file_handle, lock_owner, lock_info, sleep)
+            },
+        )
+    }
+
+    fn setxattr(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: FUSESetXAttrFlags,
+        position: u32,
+    ) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "setxattr",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("name", &name),
+                    ("value_len", &value.len()),
+                    ("flags", &flags),
+                    ("position", &position),
+                ],
+            ),
+            || {
+                self.inner
+                    .setxattr(req, file_id, name, value, flags, position)
+            },
+        )
+    }
+
+    fn statfs(&self, req: &RequestInfo, file_id: TId) -> FuseResult<StatFs> {
+        self.intercept(
+            Operation::new("statfs", Some(req), Some(&file_id.clone()), &[]),
+            || self.inner.statfs(req, file_id),
+        )
+    }
+
+    fn symlink(
+        &self,
+        req: &RequestInfo,
+        parent_id: TId,
+        link_name: &OsStr,
+        target: &Path,
+    ) -> FuseResult<TId::Metadata> {
+        self.intercept(
+            Operation::new(
+                "symlink",
+                Some(req),
+                Some(&parent_id.clone()),
+                &[("link_name", &link_name), ("target", &target)],
+            ),
+            || self.inner.symlink(req, parent_id, link_name, target),
+        )
+    }
+
+    fn write(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        data: Vec<u8>,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        self.intercept(
+            Operation::new(
+                "write",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("seek", &seek),
+                    ("data_len", &data.len()),
+                    ("write_flags", &write_flags),
+                    ("flags", &flags),
+                    ("lock_owner", &lock_owner),
+                ],
+            ),
+            || {
+                self.inner.write(
+                    req,
+                    file_id,
+                    file_handle,
+                    seek,
+                    data,
+                    write_flags,
+                    flags,
+                    lock_owner,
+                )
+            },
+        )
+    }
+
+    fn write_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        data: WriteData,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        self.intercept(
+            Operation::new(
+                "write",
+                Some(req),
+                Some(&file_id.clone()),
+                &[
+                    ("file_handle", &file_handle.as_raw()),
+                    ("seek", &seek),
+                    ("data_len", &data.len()),
+                    ("write_flags", &write_flags),
+                    ("flags", &flags),
+                    ("lock_owner", &lock_owner),
+                ],
+            )
+            .through("write_buf"),
+            || {
+                self.inner.write_buf(
+                    req,
+                    file_id,
+                    file_handle,
+                    seek,
+                    data,
+                    write_flags,
+                    flags,
+                    lock_owner,
+                )
+            },
+        )
+    }
+
+    fn unlink(&self, req: &RequestInfo, parent_id: TId, name: &OsStr) -> FuseResult<()> {
+        self.intercept(
+            Operation::new(
+                "unlink",
+                Some(req),
+                Some(&parent_id.clone()),
+                &[("name", &name)],
+            ),
+            || self.inner.unlink(req, parent_id, name),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::DefaultFuseHandler;
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder {
+        label: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware<Inode> for Recorder {
+        fn before(&self, op: &Operation<'_, Inode>) -> FuseResult<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} before {}", self.label, op.name));
+            Ok(())
+        }
+
+        fn after(&self, op: &Operation<'_, Inode>, outcome: &mut Outcome<'_>) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} after {}", self.label, op.name));
+            if self.label == "inner" {
+                // Rewrite the result of the wrapped handler
+                *outcome.result_mut::<u64>().unwrap() = Ok(42);
+            }
+        }
+    }
+
+    struct Deny;
+
+    impl Middleware<Inode> for Deny {
+        fn before(&self, _op: &Operation<'_, Inode>) -> FuseResult<()> {
+            Err(ErrorKind::PermissionDenied.to_error(""))
+        }
+    }
+
+    fn request() -> RequestInfo {
+        RequestInfo {
+            id: 0,
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        }
+    }
+
+    #[test]
+    fn test_layer_order_and_rewrite() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let fs = DefaultFuseHandler::new()
+            .layer(Recorder {
+                label: "inner",
+                calls: calls.clone(),
+            })
+            .layer(Recorder {
+                label: "outer",
+                calls: calls.clone(),
+            });
+
+        let result = fs.bmap(&request(), ROOT_INODE, 512, 0);
+        assert_eq!(result, Ok(42));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "outer before bmap",
+                "inner before bmap",
+                "inner after bmap",
+                "outer after bmap"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layer_short_circuit() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let fs = DefaultFuseHandler::new_with_panic()
+            .layer(Recorder {
+                label: "outer",
+                calls: calls.clone(),
+            })
+            .layer(Deny);
+
+        // The panicking handler is never reached
+        let result = FuseHandler::<Inode>::readlink(&fs, &request(), ROOT_INODE);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    struct Methods(Arc<Mutex<Vec<(&'static str, &'static str)>>>);
+
+    impl Middleware<Inode> for Methods {
+        fn after(&self, op: &Operation<'_, Inode>, _outcome: &mut Outcome<'_>) {
+            self.0.lock().unwrap().push((op.name, op.method));
+        }
+    }
+
+    #[test]
+    fn test_layer_entry_point_names() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let fs = DefaultFuseHandler::new().layer(Methods(calls.clone()));
+        let handle = || unsafe { BorrowedFileHandle::from_raw(0) };
+
+        let _ = FuseHandler::<Inode>::read_buf(
+            &fs,
+            &request(),
+            ROOT_INODE,
+            handle(),
+            SeekFrom::Start(0),
+            16,
+            FUSEOpenFlags::empty(),
+            None,
+        );
+        let _ = FuseHandler::<Inode>::write_buf(
+            &fs,
+            &request(),
+            ROOT_INODE,
+            handle(),
+            SeekFrom::Start(0),
+            WriteData::Borrowed(b"data"),
+            FUSEWriteFlags::empty(),
+            OpenFlags::empty(),
+            None,
+        );
+        let _ = FuseHandler::<Inode>::readdir_iter(&fs, &request(), ROOT_INODE, handle(), 0);
+        let _ = FuseHandler::<Inode>::readdirplus_iter(&fs, &request(), ROOT_INODE, handle(), 0);
+        let _ = FuseHandler::<Inode>::readlink(&fs, &request(), ROOT_INODE);
+        // The entry points used by the driver are reported under the name of the operation
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                ("read", "read_buf"),
+                ("write", "write_buf"),
+                ("readdir", "readdir_iter"),
+                ("readdirplus", "readdirplus_iter"),
+                ("readlink", "readlink"),
+            ]
+        );
+    }
+}
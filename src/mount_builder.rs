@@ -0,0 +1,332 @@
+//! Configuration of a mount, with the same signature whatever the threading feature.
+//!
+//! [`MountBuilder`] gathers the arguments of [`mount`] and [`spawn_mount`]
+//! with the settings otherwise left to the handler or to the kernel: default and negative TTLs, `KernelConfig` tuning,
+//! logging, auto-unmount and the resolver attributing inode numbers.
+//!
+//! ```no_run
+//! use easy_fuser::prelude::*;
+//! use easy_fuser::templates::{mirror_fs::*, DefaultFuseHandler};
+//! use std::path::PathBuf;
+//! use std::time::Duration;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let fs = MirrorFs::new(PathBuf::from("/srv/data"), DefaultFuseHandler::new());
+//! MountBuilder::new(fs)
+//!     .option(MountOption::RO)
+//!     .num_threads(8)
+//!     .default_ttl(Duration::from_secs(30))
+//!     .max_write(1 << 20)
+//!     .auto_unmount(true)
+//!     .mount("/mnt/data")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Settings of the builder take precedence over the handler: the TTLs replace the ones given by the `get_*_ttl`
+//! methods of the handler, and the `KernelConfig` is tuned before `init` is called, so the handler can still adjust it.
+
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use log::LevelFilter;
+
+use crate::core::{DriverSettings, FuseDriver, FuseDriverHandler};
+use crate::inode_store::InodeStore;
+use crate::prelude::*;
+
+enum ResolverChoice<TResolver> {
+    Default,
+    Resolver(TResolver),
+    InodeStore(InodeStore),
+}
+
+/// Builder of a mount, see the module documentation
+pub struct MountBuilder<TId: FileIdType, THandler> {
+    filesystem: THandler,
+    options: Vec<MountOption>,
+    num_threads: usize,
+    resolver: ResolverChoice<TId::Resolver>,
+    settings: DriverSettings,
+    log_level: Option<LevelFilter>,
+}
+
+impl<TId, THandler> MountBuilder<TId, THandler>
+where
+    TId: FileIdType,
+    THandler: FuseDriverHandler<TId>,
+{
+    /// Starts the configuration of a mount of `filesystem`
+    ///
+    /// Without further settings, the filesystem is mounted without options, with one thread per
+    /// available CPU, and the handler decides the TTLs.
+    pub fn new(filesystem: THandler) -> Self {
+        MountBuilder {
+            filesystem,
+            options: Vec::new(),
+            num_threads: thread::available_parallelism().map_or(1, usize::from),
+            resolver: ResolverChoice::Default,
+            settings: DriverSettings::default(),
+            log_level: None,
+        }
+    }
+
+    /// Adds a mount option
+    pub fn option(mut self, option: MountOption) -> Self {
+        if !self.options.contains(&option) {
+            self.options.push(option);
+        }
+        self
+    }
+
+    /// Adds several mount options
+    pub fn options(self, options: impl IntoIterator<Item = MountOption>) -> Self {
+        options.into_iter().fold(self, Self::option)
+    }
+
+    /// Number of threads handling the operations, or of workers of the tokio runtime with the `async` feature
+    ///
+    /// Ignored with the `serial` feature, where operations are handled by the thread of the session.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// TTL of the entries and attributes returned without their own TTL, replacing `get_default_ttl` of the handler
+    pub fn default_ttl(self, ttl: Duration) -> Self {
+        self.entry_ttl(ttl).attr_ttl(ttl)
+    }
+
+    /// TTL of the names of the entries returned without their own TTL, replacing `get_default_entry_ttl` of the handler
+    ///
+    /// Note: fuser replies lookups, creations and readdirplus with a single TTL. The attributes returned in these
+    /// replies are cached by the kernel for the entry TTL, not for `attr_ttl`.
+    pub fn entry_ttl(mut self, ttl: Duration) -> Self {
+        self.settings.entry_ttl = Some(ttl);
+        self
+    }
+
+    /// TTL of the attributes returned without their own TTL, replacing `get_default_attr_ttl` of the handler
+    ///
+    /// Only applies to the replies of getattr and setattr, see `entry_ttl` for the other replies.
+    pub fn attr_ttl(mut self, ttl: Duration) -> Self {
+        self.settings.attr_ttl = Some(ttl);
+        self
+    }
+
+    /// TTL of the negative entries replied for missing files, replacing `get_negative_ttl` of the handler
+    pub fn negative_ttl(mut self, ttl: Duration) -> Self {
+        self.settings.negative_ttl = Some(ttl);
+        self
+    }
+
+    /// Maximal size of a write request, in bytes
+    ///
+    /// Unsupported values are logged during `init` and the nearest supported value is used instead.
+    pub fn max_write(mut self, bytes: u32) -> Self {
+        self.settings.max_write = Some(bytes);
+        self
+    }
+
+    /// Maximal size of the readahead of the kernel, in bytes
+    ///
+    /// Unsupported values are logged during `init` and the nearest supported value is used instead.
+    pub fn max_readahead(mut self, bytes: u32) -> Self {
+        self.settings.max_readahead = Some(bytes);
+        self
+    }
+
+    /// Requests capabilities from the kernel during `init`, in addition to the ones requested by default
+    ///
+    /// Capabilities unsupported by the kernel are logged and ignored.
+    pub fn capabilities(mut self, capabilities: FUSECapabilities) -> Self {
+        self.settings.capabilities |= capabilities.bits();
+        self
+    }
+
+    /// Sets the maximal level of the messages logged through `log`, applied by `mount` and `spawn`
+    ///
+    /// The level applies to the whole process. A logger still has to be installed, eg: with `env_logger`.
+    pub fn log_level(mut self, level: LevelFilter) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Unmounts the filesystem when the process exits, even if it crashes
+    ///
+    /// This relies on `fusermount`, and requires `user_allow_other` in `/etc/fuse.conf` unless `AllowOther` or
+    /// `AllowRoot` is set.
+    pub fn auto_unmount(mut self, enabled: bool) -> Self {
+        self.options
+            .retain(|option| *option != MountOption::AutoUnmount);
+        if enabled {
+            self.options.push(MountOption::AutoUnmount);
+        }
+        self
+    }
+
+    /// Translates inode numbers with `resolver` instead of a new default one, eg: to set its `EvictionPolicy`
+    ///
+    /// Replaces the resolver given to a previous call of `resolver` or `inode_store`.
+    pub fn resolver(mut self, resolver: TId::Resolver) -> Self {
+        self.resolver = ResolverChoice::Resolver(resolver);
+        self
+    }
+
+    /// Keeps the inode numbers attributed to paths in `store` across remounts, see the [`inode_store`](crate::inode_store) module
+    ///
+    /// Only `PathBuf` and `Vec<OsString>` identifiers support inode stores, other types fail to mount with `InvalidInput`.
+    /// Replaces the resolver given to a previous call of `resolver` or `inode_store`.
+    pub fn inode_store(mut self, store: InodeStore) -> Self {
+        self.resolver = ResolverChoice::InodeStore(store);
+        self
+    }
+
+    pub(crate) fn into_driver(self) -> io::Result<(FuseDriver<TId, THandler>, Vec<MountOption>)> {
+        let resolver = match self.resolver {
+            ResolverChoice::Default => TId::create_resolver(),
+            ResolverChoice::Resolver(resolver) => resolver,
+            ResolverChoice::InodeStore(store) => TId::create_resolver_with_inode_store(store)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "this file id type does not support inode stores",
+                    )
+                })?,
+        };
+        let mut driver = FuseDriver::with_resolver(self.filesystem, resolver, self.num_threads);
+        driver.settings = self.settings;
+        Ok((driver, self.options))
+    }
+
+    fn into_session(self, mountpoint: &Path) -> io::Result<Session<FuseDriver<TId, THandler>>> {
+        if let Some(level) = self.log_level {
+            log::set_max_level(level);
+        }
+        let (driver, options) = self.into_driver()?;
+        let notifier = driver.get_notifier();
+        let session = Session::new(driver, mountpoint, &options)?;
+        notifier.attach(session.notifier());
+        Ok(session)
+    }
+
+    /// Mounts the filesystem at `mountpoint`, returning once it is unmounted
+    ///
+    /// With the `async` feature, this function must not be called from within an asynchronous context,
+    /// because the runtime driving the filesystem is owned by the mount. Use `spawn` instead.
+    pub fn mount(self, mountpoint: impl AsRef<Path>) -> io::Result<()> {
+        self.into_session(mountpoint.as_ref())?.run()
+    }
+
+    /// Mounts the filesystem at `mountpoint` and runs it in a background thread
+    ///
+    /// The filesystem is unmounted when the returned session is dropped.
+    pub fn spawn(self, mountpoint: impl AsRef<Path>) -> io::Result<BackgroundSession>
+    where
+        THandler: Send,
+    {
+        self.into_session(mountpoint.as_ref())?.spawn()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
+
+    use crate::templates::memory_fs::MemoryFs;
+    use crate::templates::DefaultFuseHandler;
+    use crate::testing::TestSession;
+
+    #[test]
+    fn test_mount_builder() {
+        let builder = MountBuilder::<Inode, _>::new(MemoryFs::new())
+            .options([MountOption::RO, MountOption::NoExec, MountOption::RO])
+            .auto_unmount(true)
+            .auto_unmount(false)
+            .option(MountOption::AutoUnmount)
+            .log_level(LevelFilter::Debug)
+            .log_level(LevelFilter::Warn);
+        assert_eq!(builder.log_level, Some(LevelFilter::Warn));
+        assert_eq!(
+            builder.options,
+            [
+                MountOption::RO,
+                MountOption::NoExec,
+                MountOption::AutoUnmount
+            ]
+        );
+
+        let session = TestSession::from_builder(
+            MountBuilder::<Inode, _>::new(MemoryFs::new())
+                .num_threads(1)
+                .default_ttl(Duration::from_secs(30)),
+        )
+        .unwrap();
+        let entry = session
+            .mkdir(TestSession::ROOT_INO, OsStr::new("dir"), 0o755, 0)
+            .unwrap();
+        assert_eq!(entry.entry_ttl, Duration::from_secs(30));
+        let attr = session.getattr(entry.ino, None).unwrap();
+        assert_eq!(attr.ttl, Some(Duration::from_secs(30)));
+
+        // Entry replies carry the entry TTL for both the name and the attributes, getattr the attribute TTL
+        for (entry_ttl, attr_ttl) in [(60, 2), (1, 30)] {
+            let session = TestSession::from_builder(
+                MountBuilder::<Inode, _>::new(MemoryFs::new())
+                    .entry_ttl(Duration::from_secs(entry_ttl))
+                    .attr_ttl(Duration::from_secs(attr_ttl)),
+            )
+            .unwrap();
+            let root = TestSession::ROOT_INO;
+            let entry_ttl = Duration::from_secs(entry_ttl);
+            let mkdir = session.mkdir(root, OsStr::new("dir"), 0o755, 0).unwrap();
+            let (create, _, _) = session
+                .create(root, OsStr::new("file"), 0o644, 0, OpenFlags::READ_WRITE)
+                .unwrap();
+            let lookup = session.lookup(root, OsStr::new("dir")).unwrap();
+            let (fh, _) = session.opendir(root, OpenFlags::READ_ONLY).unwrap();
+            let listing = session.readdirplus(root, fh, 0, 4096).unwrap();
+            let (_, readdirplus) = listing
+                .into_iter()
+                .find(|(entry, _)| entry.name == "file")
+                .unwrap();
+            for entry in [&mkdir, &create, &lookup, &readdirplus] {
+                assert_eq!(
+                    (entry.entry_ttl, entry.attr.ttl),
+                    (entry_ttl, Some(entry_ttl))
+                );
+            }
+            let attr = session.getattr(lookup.ino, None).unwrap();
+            assert_eq!(attr.ttl, Some(Duration::from_secs(attr_ttl)));
+        }
+
+        // Inode ids do not support inode stores
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = InodeStore::open(dir.path().join("inodes")).unwrap();
+        let builder = MountBuilder::<Inode, _>::new(DefaultFuseHandler::new()).inode_store(store);
+        assert_eq!(
+            builder.into_driver().err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
+        let store = InodeStore::open(dir.path().join("inodes")).unwrap();
+        assert!(MountBuilder::<PathBuf, _>::new(DefaultFuseHandler::new())
+            .inode_store(store)
+            .into_driver()
+            .is_ok());
+
+        // Capabilities not offered by the kernel are left out
+        let session = TestSession::from_builder(
+            MountBuilder::<Inode, _>::new(MemoryFs::new())
+                .capabilities(FUSECapabilities::FLOCK_LOCKS | FUSECapabilities::WRITEBACK_CACHE),
+        )
+        .unwrap();
+        let negotiated = FUSECapabilities::from_bits_retain(session.init_flags() as u64);
+        assert!(negotiated.contains(FUSECapabilities::FLOCK_LOCKS));
+        assert!(!negotiated.intersects(FUSECapabilities::WRITEBACK_CACHE));
+    }
+}
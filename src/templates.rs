@@ -26,7 +26,13 @@
 //!
 //! - `DefaultFuseHandler`: A complete implementation of basic FUSE operations.
 //! - `fd_handler_helper`: Utilities for handling file descriptors in FUSE operations.
+//! - `lock_manager`: POSIX record locks tracked in userspace, for handlers not backed by file descriptors.
+//! - `memory_fs`: A filesystem stored in memory, usable on its own or as a backing store for tests.
+//! - `metrics`: A middleware collecting per-operation metrics, with a Prometheus exporter.
 //! - `mirror_fs`: Templates for creating mirror filesystems.
+//! - `overlay_fs`: A copy-on-write filesystem merging a read-only lower directory with a writable upper directory.
+//! - `tracer`: A middleware tracing every operation of the handler it wraps.
+//! - `union_fs`: A filesystem merging several source directories, with configurable create policies.
 //!
 //! For detailed information on each template, refer to their respective documentation.
 
@@ -35,4 +41,16 @@ pub use default_fuse_handler::DefaultFuseHandler;
 
 pub mod fd_handler_helper;
 
+pub mod lock_manager;
+
+pub mod memory_fs;
+
+pub mod metrics;
+
 pub mod mirror_fs;
+
+pub mod overlay_fs;
+
+pub mod tracer;
+
+pub mod union_fs;
@@ -1,6 +1,7 @@
 use std::{
     ffi::{OsStr, OsString},
     path::Path,
+    sync::atomic::{AtomicU64, Ordering},
     time::Duration,
 };
 
@@ -47,6 +48,8 @@ This is a basic skeleton. For more complete implementations, refer to the templa
 */
 pub struct DefaultFuseHandler {
     handling: HandlingMethod,
+    remote_locks: Option<LockKind>,
+    negative_ttl: Option<Duration>,
 }
 
 enum HandlingMethod {
@@ -62,6 +65,8 @@ impl DefaultFuseHandler {
     pub fn new() -> Self {
         DefaultFuseHandler {
             handling: HandlingMethod::Error(ErrorKind::FunctionNotImplemented),
+            remote_locks: None,
+            negative_ttl: None,
         }
     }
 
@@ -72,6 +77,8 @@ impl DefaultFuseHandler {
     pub fn new_with_panic() -> Self {
         DefaultFuseHandler {
             handling: HandlingMethod::Panic,
+            remote_locks: None,
+            negative_ttl: None,
         }
     }
 
@@ -81,8 +88,26 @@ impl DefaultFuseHandler {
     pub fn new_with_custom_error(error_kind: ErrorKind) -> Self {
         DefaultFuseHandler {
             handling: HandlingMethod::Error(error_kind),
+            remote_locks: None,
+            negative_ttl: None,
         }
     }
+
+    /// Forwards the locks of the given kind to the filesystem, see `FuseHandler::remote_locks`
+    ///
+    /// ```text
+    /// let fs = MirrorFs::new(source_path, DefaultFuseHandler::new().with_remote_locks(LockKind::Flock));
+    /// ```
+    pub fn with_remote_locks(mut self, kind: LockKind) -> Self {
+        self.remote_locks = Some(kind);
+        self
+    }
+
+    /// Lets the kernel cache missing entries for `ttl`, see `FuseHandler::get_negative_ttl`
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = Some(ttl);
+        self
+    }
 }
 
 impl<TId: FileIdType> FuseHandler<TId> for DefaultFuseHandler {
@@ -94,7 +119,28 @@ impl<TId: FileIdType> FuseHandler<TId> for DefaultFuseHandler {
         Duration::from_secs(1)
     }
 
-    fn init(&self, _req: &RequestInfo, _config: &mut KernelConfig) -> FuseResult<()> {
+    fn get_negative_ttl(&self) -> Option<Duration> {
+        self.negative_ttl
+    }
+
+    fn remote_locks(&self) -> Option<LockKind> {
+        self.remote_locks
+    }
+
+    fn pooled_writes(&self) -> bool {
+        false
+    }
+
+    fn is_default_handler(&self) -> bool {
+        true
+    }
+
+    fn init(
+        &self,
+        _req: &RequestInfo,
+        _config: &mut KernelConfig,
+        _notifier: Notifier<TId>,
+    ) -> FuseResult<()> {
         Ok(())
     }
 
@@ -533,7 +579,7 @@ impl<TId: FileIdType> FuseHandler<TId> for DefaultFuseHandler {
         _req: &RequestInfo,
         file_id: TId,
         file_handle: BorrowedFileHandle,
-        seek: SeekFrom,
+        seek: LseekFrom,
     ) -> FuseResult<i64> {
         match self.handling {
             HandlingMethod::Error(kind) => Err(PosixError::new(
@@ -655,13 +701,49 @@ impl<TId: FileIdType> FuseHandler<TId> for DefaultFuseHandler {
         _file_id: TId,
         _flags: OpenFlags,
     ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        // Each open directory gets its own handle, so that the driver keeps a directory stream per handle
+        static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
         // Safe because in releasedir we don't use it
         Ok((
-            unsafe { OwnedFileHandle::from_raw(0) },
+            unsafe { OwnedFileHandle::from_raw(NEXT_HANDLE.fetch_add(1, Ordering::Relaxed)) },
             FUSEOpenResponseFlags::empty(),
         ))
     }
 
+    fn poll(
+        &self,
+        _req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        _poll_handle: PollHandle,
+        events: PollEvents,
+        flags: FUSEPollFlags,
+    ) -> FuseResult<PollEvents> {
+        match self.handling {
+            HandlingMethod::Error(kind) => Err(PosixError::new(
+                kind,
+                if cfg!(debug_assertions) {
+                    format!(
+                        "poll(file_id: {}, file_handle: {:?}, events: {:?}, flags: {:?})",
+                        file_id.display(),
+                        file_handle,
+                        events,
+                        flags
+                    )
+                } else {
+                    String::new()
+                },
+            )),
+            HandlingMethod::Panic => panic!(
+                "[Not Implemented] poll(file_id: {}, file_handle: {:?}, events: {:?}, flags: {:?})",
+                file_id.display(),
+                file_handle,
+                events,
+                flags
+            ),
+        }
+    }
+
     fn read(
         &self,
         _req: &RequestInfo,
@@ -700,6 +782,29 @@ impl<TId: FileIdType> FuseHandler<TId> for DefaultFuseHandler {
         }
     }
 
+    fn read_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<ReadData> {
+        FuseHandler::<TId>::read(
+            self,
+            req,
+            file_id,
+            file_handle,
+            seek,
+            size,
+            flags,
+            lock_owner,
+        )
+        .map(ReadData::from)
+    }
+
     fn readdir(
         &self,
         _req: &RequestInfo,
@@ -754,6 +859,28 @@ impl<TId: FileIdType> FuseHandler<TId> for DefaultFuseHandler {
         }
     }
 
+    fn readdir_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::MinimalMetadata>> {
+        let entries = FuseHandler::<TId>::readdir(self, req, file_id, file_handle)?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
+    fn readdirplus_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<TId::Metadata>> {
+        let entries = FuseHandler::<TId>::readdirplus(self, req, file_id, file_handle)?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
     fn readlink(&self, _req: &RequestInfo, file_id: TId) -> FuseResult<Vec<u8>> {
         match self.handling {
             HandlingMethod::Error(kind) => Err(PosixError::new(
@@ -1086,4 +1213,28 @@ impl<TId: FileIdType> FuseHandler<TId> for DefaultFuseHandler {
             ),
         }
     }
+
+    fn write_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        data: WriteData,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        FuseHandler::<TId>::write(
+            self,
+            req,
+            file_id,
+            file_handle,
+            seek,
+            data.into_vec(),
+            write_flags,
+            flags,
+            lock_owner,
+        )
+    }
 }
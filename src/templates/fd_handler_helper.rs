@@ -18,9 +18,13 @@ Both helpers assume that file handles represent file descriptors on the filesyst
 Implements the following `FuseHandler<T>` methods:
 
 - `read`: Reads data from a file using the file descriptor.
+- `read_buf`: Reads data into a buffer of the global `BufferPool`, which is replied without further copy.
 - `write`: Writes data to a file using the file descriptor.
+- `write_buf`: Writes the borrowed payload of the request without copying it into a new buffer.
+- `pooled_writes`: Returns true, so that the payloads of writes handled by other threads are copied into reused buffers.
 - `flush`: Flushes the file associated with the file descriptor.
 - `release`: Releases (closes) the file descriptor.
+- `setlk`: Forwards `flock` requests to the file descriptor, POSIX ones are delegated to the inner handler.
 - `fsync`: Synchronizes the file's in-core state with storage device.
 - `fallocate`: Manipulates the allocated disk space for the file.
 - `lseek`: Repositions the file offset of the file descriptor.
@@ -31,11 +35,17 @@ Implements the following `FuseHandler<T>` methods:
 Implements a subset of `FuseHandler<T>` methods for read-only operations:
 
 - `read`: Reads data from a file using the file descriptor.
+- `read_buf`: Reads data into a buffer of the global `BufferPool`, which is replied without further copy.
 - `flush`: Flushes the file associated with the file descriptor.
 - `release`: Releases (closes) the file descriptor.
+- `setlk`: Forwards `flock` requests to the file descriptor, POSIX ones are delegated to the inner handler.
 - `fsync`: Synchronizes the file's in-core state with storage device.
 - `lseek`: Repositions the file offset of the file descriptor.
 
+`flock` requests are only sent by the kernel once enabled with `FuseHandler::remote_locks`. As each open file
+has its own file descriptor, the locks behave as on the source filesystem, and are released with the
+file descriptor. With the `serial` feature, a request which would wait fails with `EWOULDBLOCK` instead.
+
 ## Usage
 
 To use these helpers:
@@ -99,7 +109,7 @@ macro_rules! fd_handler_readonly_methods {
             _req: &RequestInfo,
             _file_id: TId,
             file_handle: BorrowedFileHandle,
-            seek: SeekFrom,
+            seek: LseekFrom,
         ) -> FuseResult<i64> {
             unix_fs::lseek(file_handle.as_borrowed_fd(), seek)
         }
@@ -117,6 +127,26 @@ macro_rules! fd_handler_readonly_methods {
             unix_fs::read(file_handle.as_borrowed_fd(), seek, size as usize)
         }
 
+        fn read_buf(
+            &self,
+            _req: &RequestInfo,
+            _file_id: TId,
+            file_handle: BorrowedFileHandle,
+            seek: SeekFrom,
+            size: u32,
+            _flags: FUSEOpenFlags,
+            _lock_owner: Option<u64>,
+        ) -> FuseResult<ReadData> {
+            let mut buffer = BufferPool::global().get(size as usize);
+            unix_fs::read_into(
+                file_handle.as_borrowed_fd(),
+                seek,
+                size as usize,
+                &mut buffer,
+            )?;
+            Ok(ReadData::from(buffer))
+        }
+
         fn release(
             &self,
             _req: &RequestInfo,
@@ -128,6 +158,28 @@ macro_rules! fd_handler_readonly_methods {
         ) -> FuseResult<()> {
             unix_fs::release(file_handle.into_owned_fd())
         }
+
+        fn setlk(
+            &self,
+            req: &RequestInfo,
+            file_id: TId,
+            file_handle: BorrowedFileHandle,
+            lock_owner: u64,
+            lock_info: LockInfo,
+            sleep: bool,
+        ) -> FuseResult<()> {
+            match lock_info.kind {
+                LockKind::Flock => {
+                    // Waiting would stall a serial session, which may have to process the unlock
+                    let sleep = sleep && !cfg!(feature = "serial");
+                    unix_fs::flock(file_handle.as_borrowed_fd(), lock_info.lock_type, sleep)
+                }
+                LockKind::Posix => {
+                    self.inner
+                        .setlk(req, file_id, file_handle, lock_owner, lock_info, sleep)
+                }
+            }
+        }
     };
 }
 
@@ -179,6 +231,24 @@ macro_rules! fd_handler_readwrite_methods {
         ) -> FuseResult<u32> {
             unix_fs::write(file_handle.as_borrowed_fd(), seek, &data).map(|res| res as u32)
         }
+
+        fn pooled_writes(&self) -> bool {
+            true
+        }
+
+        fn write_buf(
+            &self,
+            _req: &RequestInfo,
+            _file_id: TId,
+            file_handle: BorrowedFileHandle,
+            seek: SeekFrom,
+            data: WriteData,
+            _write_flags: FUSEWriteFlags,
+            _flags: OpenFlags,
+            _lock_owner: Option<u64>,
+        ) -> FuseResult<u32> {
+            unix_fs::write(file_handle.as_borrowed_fd(), seek, &data).map(|res| res as u32)
+        }
     };
 }
 
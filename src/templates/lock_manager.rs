@@ -0,0 +1,656 @@
+/*!
+# Lock manager
+
+POSIX record locks (`fcntl` with `F_GETLK`, `F_SETLK` and `F_SETLKW`) implemented in userspace.
+
+## Overview
+
+Handlers forwarding their operations to file descriptors get record locks from the underlying filesystem.
+Other handlers (eg: `MemoryFs`, or a filesystem backed by a database) have to track the locks themselves,
+which is what this module provides:
+
+- [`LockTable`] keeps the locks held on each file, by lock owner, and can be used from any handler's
+  `getlk`, `setlk`, `flush` and `release` methods.
+- [`LockManager`] wraps a handler and implements these methods with a `LockTable`, delegating every other
+  operation to the wrapped handler.
+
+The table follows the POSIX semantics:
+
+- A lock owner only ever holds one lock on a given byte: locking a range already locked by the same owner
+  replaces the lock type of that range, splitting the existing locks if needed.
+- Adjacent or overlapping ranges of the same owner and type are merged.
+- Unlocking a range splits the locks partially covered by it.
+- Read locks of different owners can overlap, a write lock conflicts with any lock of another owner.
+- A request with `sleep` set waits until the conflicting locks are released, unless waiting would deadlock,
+  in which case it fails with `ResourceDeadlockAvoided` (EDEADLK).
+- All the locks of an owner on a file are released on `flush` and on `release`, as closing any file
+  descriptor of a file releases the locks of the process on it.
+
+## Usage
+
+```text
+let fs = LockManager::new(MemoryFs::new());
+mount(fs, "/mnt/scratch", &[], 4)?;
+```
+
+The kernel only forwards the locks of the kind returned by `FuseHandler::remote_locks`, `LockManager` asks
+for POSIX locks unless configured otherwise with [`LockManager::with_remote_locks`].
+
+## Limitations
+
+- Ranges are inclusive, as sent by the kernel: a lock up to the end of the file ends at `i64::MAX`.
+- Waiting requests cannot be interrupted, they only return once the lock is granted.
+- Waiting requires the session to handle other requests meanwhile: with the `serial` feature, a request which
+  would wait fails with `ResourceDeadlockAvoided` instead. With the `parallel` and `async` features, the driver
+  handles requests with `sleep` set on a dedicated thread (`spawn_blocking` with `async`), so that waiting requests
+  never take the threads needed to handle the unlock, whatever their number.
+- `flock` requests (see `FuseHandler::remote_locks`) are tracked the same way: the kernel sends them for the
+  whole file, with the open file as lock owner.
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+use crate::prelude::*;
+
+/// A lock held on a range of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct HeldLock {
+    owner: u64,
+    start: u64,
+    /// Last byte of the range (inclusive)
+    end: u64,
+    exclusive: bool,
+    pid: u32,
+    kind: LockKind,
+}
+
+impl HeldLock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start <= end && start <= self.end
+    }
+
+    fn to_lock_info(&self) -> LockInfo {
+        LockInfo {
+            start: self.start,
+            end: self.end,
+            lock_type: if self.exclusive {
+                LockType::WRITE_LOCK
+            } else {
+                LockType::READ_LOCK
+            },
+            pid: self.pid,
+            kind: self.kind,
+        }
+    }
+}
+
+struct LockState<TId> {
+    locks: HashMap<TId, Vec<HeldLock>>,
+    /// Owners waiting for a lock, with the owners currently holding a conflicting lock
+    waiting: HashMap<u64, Vec<u64>>,
+}
+
+impl<TId: FileIdType> LockState<TId> {
+    /// Returns the owners holding a lock conflicting with the given one, without duplicates
+    fn blockers(
+        &self,
+        file_id: &TId,
+        owner: u64,
+        start: u64,
+        end: u64,
+        exclusive: bool,
+    ) -> Vec<u64> {
+        let mut blockers: Vec<u64> = self
+            .locks
+            .get(file_id)
+            .into_iter()
+            .flatten()
+            .filter(|lock| {
+                lock.owner != owner && lock.overlaps(start, end) && (exclusive || lock.exclusive)
+            })
+            .map(|lock| lock.owner)
+            .collect();
+        blockers.sort_unstable();
+        blockers.dedup();
+        blockers
+    }
+
+    /// Whether `owner` waiting for `blockers` would close a cycle of waiting owners
+    fn would_deadlock(&self, owner: u64, blockers: &[u64]) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = blockers.to_vec();
+        while let Some(current) = stack.pop() {
+            if current == owner {
+                return true;
+            }
+            if visited.insert(current) {
+                if let Some(next) = self.waiting.get(&current) {
+                    stack.extend(next);
+                }
+            }
+        }
+        false
+    }
+
+    /// Removes the given range from the locks of `owner`, splitting the locks partially covered
+    fn unlock_range(&mut self, file_id: &TId, owner: u64, start: u64, end: u64) {
+        let Some(locks) = self.locks.get_mut(file_id) else {
+            return;
+        };
+        let mut remaining = Vec::with_capacity(locks.len());
+        for lock in locks.drain(..) {
+            if lock.owner != owner || !lock.overlaps(start, end) {
+                remaining.push(lock);
+                continue;
+            }
+            if lock.start < start {
+                remaining.push(HeldLock {
+                    end: start - 1,
+                    ..lock.clone()
+                });
+            }
+            if lock.end > end {
+                remaining.push(HeldLock {
+                    start: end + 1,
+                    ..lock
+                });
+            }
+        }
+        if remaining.is_empty() {
+            self.locks.remove(file_id);
+        } else {
+            *locks = remaining;
+        }
+    }
+
+    /// Adds a lock, the range must not be locked by the same owner anymore
+    fn insert(&mut self, file_id: &TId, mut new_lock: HeldLock) {
+        let locks = self.locks.entry(file_id.clone()).or_default();
+        // Merge with the adjacent locks of the same owner and type
+        locks.retain(|lock| {
+            let mergeable = lock.owner == new_lock.owner
+                && lock.exclusive == new_lock.exclusive
+                && (lock.end.checked_add(1) == Some(new_lock.start)
+                    || new_lock.end.checked_add(1) == Some(lock.start));
+            if mergeable {
+                new_lock.start = new_lock.start.min(lock.start);
+                new_lock.end = new_lock.end.max(lock.end);
+            }
+            !mergeable
+        });
+        locks.push(new_lock);
+    }
+}
+
+/// Record locks held on files, by lock owner
+///
+/// See the [module documentation](self) for the semantics.
+pub struct LockTable<TId: FileIdType> {
+    state: Mutex<LockState<TId>>,
+    released: Condvar,
+}
+
+impl<TId: FileIdType> Default for LockTable<TId> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<TId: FileIdType> LockTable<TId> {
+    pub fn new() -> Self {
+        LockTable {
+            state: Mutex::new(LockState {
+                locks: HashMap::new(),
+                waiting: HashMap::new(),
+            }),
+            released: Condvar::new(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, LockState<TId>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns a lock conflicting with `lock_info`, or `lock_info` with the `UNLOCKED` type if the lock could be placed
+    pub fn getlk(
+        &self,
+        file_id: &TId,
+        lock_owner: u64,
+        lock_info: LockInfo,
+    ) -> FuseResult<LockInfo> {
+        let exclusive = match lock_kind(&lock_info)? {
+            Some(exclusive) => exclusive,
+            None => return Err(ErrorKind::InvalidArgument.to_error("getlk with an unlock request")),
+        };
+        let state = self.state();
+        let conflict = state.locks.get(file_id).into_iter().flatten().find(|lock| {
+            lock.owner != lock_owner
+                && lock.overlaps(lock_info.start, lock_info.end)
+                && (exclusive || lock.exclusive)
+        });
+        Ok(match conflict {
+            Some(lock) => lock.to_lock_info(),
+            None => LockInfo {
+                lock_type: LockType::UNLOCKED,
+                ..lock_info
+            },
+        })
+    }
+
+    /// Places, changes or removes a lock
+    ///
+    /// A conflicting lock makes the request fail with `ResourceUnavailableTryAgain` (EAGAIN),
+    /// or wait for its release if `sleep` is set.
+    pub fn setlk(
+        &self,
+        file_id: &TId,
+        lock_owner: u64,
+        lock_info: LockInfo,
+        sleep: bool,
+    ) -> FuseResult<()> {
+        if lock_info.start > lock_info.end {
+            return Err(ErrorKind::InvalidArgument.to_error(format!(
+                "Invalid lock range {}-{}",
+                lock_info.start, lock_info.end
+            )));
+        }
+        let mut state = self.state();
+        let Some(exclusive) = lock_kind(&lock_info)? else {
+            state.unlock_range(file_id, lock_owner, lock_info.start, lock_info.end);
+            self.released.notify_all();
+            return Ok(());
+        };
+        loop {
+            let blockers = state.blockers(
+                file_id,
+                lock_owner,
+                lock_info.start,
+                lock_info.end,
+                exclusive,
+            );
+            if blockers.is_empty() {
+                state.waiting.remove(&lock_owner);
+                state.unlock_range(file_id, lock_owner, lock_info.start, lock_info.end);
+                state.insert(
+                    file_id,
+                    HeldLock {
+                        owner: lock_owner,
+                        start: lock_info.start,
+                        end: lock_info.end,
+                        exclusive,
+                        pid: lock_info.pid,
+                        kind: lock_info.kind,
+                    },
+                );
+                // Converting a write lock to a read lock can unblock readers
+                self.released.notify_all();
+                return Ok(());
+            }
+            if !sleep {
+                return Err(ErrorKind::ResourceUnavailableTryAgain.to_error(format!(
+                    "Range {}-{} is locked by another owner",
+                    lock_info.start, lock_info.end
+                )));
+            }
+            if cfg!(feature = "serial") {
+                return Err(ErrorKind::ResourceDeadlockAvoided
+                    .to_error("Waiting for a lock would block the serial session"));
+            }
+            if state.would_deadlock(lock_owner, &blockers) {
+                state.waiting.remove(&lock_owner);
+                return Err(ErrorKind::ResourceDeadlockAvoided.to_error(format!(
+                    "Waiting for range {}-{} would deadlock",
+                    lock_info.start, lock_info.end
+                )));
+            }
+            state.waiting.insert(lock_owner, blockers);
+            state = self.released.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Releases all the locks held by `lock_owner` on a file
+    pub fn release_owner(&self, file_id: &TId, lock_owner: u64) {
+        let mut state = self.state();
+        state.unlock_range(file_id, lock_owner, 0, u64::MAX);
+        self.released.notify_all();
+    }
+
+    /// Returns the locks held on a file along with their owner, ordered by start offset
+    pub fn locks(&self, file_id: &TId) -> Vec<(u64, LockInfo)> {
+        let state = self.state();
+        let mut locks: Vec<_> = state.locks.get(file_id).into_iter().flatten().collect();
+        locks.sort_by_key(|lock| (lock.start, lock.owner));
+        locks
+            .into_iter()
+            .map(|lock| (lock.owner, lock.to_lock_info()))
+            .collect()
+    }
+}
+
+/// Returns whether the lock is exclusive, or None for an unlock request
+fn lock_kind(lock_info: &LockInfo) -> FuseResult<Option<bool>> {
+    match lock_info.lock_type.bits() {
+        bits if bits == LockType::READ_LOCK.bits() => Ok(Some(false)),
+        bits if bits == LockType::WRITE_LOCK.bits() => Ok(Some(true)),
+        bits if bits == LockType::UNLOCKED.bits() => Ok(None),
+        bits => Err(ErrorKind::InvalidArgument.to_error(format!("Invalid lock type {}", bits))),
+    }
+}
+
+/// A handler implementing record locks with a [`LockTable`], on top of the handler it wraps
+pub struct LockManager<TId: FileIdType, THandler: FuseHandler<TId>> {
+    inner: THandler,
+    locks: LockTable<TId>,
+    remote_locks: LockKind,
+}
+
+impl<TId: FileIdType, THandler: FuseHandler<TId>> LockManager<TId, THandler> {
+    /// Tracks the POSIX record locks of the files of `inner`
+    pub fn new(inner: THandler) -> Self {
+        LockManager {
+            inner,
+            locks: LockTable::new(),
+            remote_locks: LockKind::Posix,
+        }
+    }
+
+    /// Tracks the locks of the given kind instead, see `FuseHandler::remote_locks`
+    pub fn with_remote_locks(mut self, kind: LockKind) -> Self {
+        self.remote_locks = kind;
+        self
+    }
+
+    pub fn lock_table(&self) -> &LockTable<TId> {
+        &self.locks
+    }
+
+    /// Returns the wrapped handler
+    pub fn into_inner(self) -> THandler {
+        self.inner
+    }
+}
+
+impl<TId: FileIdType, THandler: FuseHandler<TId>> FuseHandler<TId> for LockManager<TId, THandler> {
+    fn get_inner(&self) -> &dyn FuseHandler<TId> {
+        &self.inner
+    }
+
+    fn remote_locks(&self) -> Option<LockKind> {
+        Some(self.remote_locks)
+    }
+
+    fn flush(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: BorrowedFileHandle,
+        lock_owner: u64,
+    ) -> FuseResult<()> {
+        self.locks.release_owner(&file_id, lock_owner);
+        self.inner.flush(req, file_id, file_handle, lock_owner)
+    }
+
+    fn getlk(
+        &self,
+        _req: &RequestInfo,
+        file_id: TId,
+        _file_handle: BorrowedFileHandle,
+        lock_owner: u64,
+        lock_info: LockInfo,
+    ) -> FuseResult<LockInfo> {
+        self.locks.getlk(&file_id, lock_owner, lock_info)
+    }
+
+    fn release(
+        &self,
+        req: &RequestInfo,
+        file_id: TId,
+        file_handle: OwnedFileHandle,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+        flush: bool,
+    ) -> FuseResult<()> {
+        if let Some(lock_owner) = lock_owner {
+            self.locks.release_owner(&file_id, lock_owner);
+        }
+        self.inner
+            .release(req, file_id, file_handle, flags, lock_owner, flush)
+    }
+
+    fn setlk(
+        &self,
+        _req: &RequestInfo,
+        file_id: TId,
+        _file_handle: BorrowedFileHandle,
+        lock_owner: u64,
+        lock_info: LockInfo,
+        sleep: bool,
+    ) -> FuseResult<()> {
+        self.locks.setlk(&file_id, lock_owner, lock_info, sleep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::memory_fs::MemoryFs;
+    use std::ffi::OsStr;
+
+    fn lock(start: u64, end: u64, lock_type: LockType) -> LockInfo {
+        LockInfo {
+            start,
+            end,
+            lock_type,
+            pid: 42,
+            kind: LockKind::Posix,
+        }
+    }
+
+    /// Ranges of the locks held on a file, as (owner, start, end, exclusive)
+    fn ranges(table: &LockTable<Inode>, file_id: u64) -> Vec<(u64, u64, u64, bool)> {
+        table
+            .locks(&Inode::from(file_id))
+            .into_iter()
+            .map(|(owner, info)| {
+                (
+                    owner,
+                    info.start,
+                    info.end,
+                    info.lock_type.bits() == LockType::WRITE_LOCK.bits(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_and_merge() {
+        let table = LockTable::new();
+        let file = Inode::from(2);
+        table
+            .setlk(&file, 1, lock(0, 99, LockType::WRITE_LOCK), false)
+            .unwrap();
+        table
+            .setlk(&file, 1, lock(40, 59, LockType::UNLOCKED), false)
+            .unwrap();
+        assert_eq!(ranges(&table, 2), vec![(1, 0, 39, true), (1, 60, 99, true)]);
+
+        // Filling the hole merges the three ranges
+        table
+            .setlk(&file, 1, lock(40, 59, LockType::WRITE_LOCK), false)
+            .unwrap();
+        assert_eq!(ranges(&table, 2), vec![(1, 0, 99, true)]);
+
+        // Changing the type of a sub-range splits the lock
+        table
+            .setlk(&file, 1, lock(10, 19, LockType::READ_LOCK), false)
+            .unwrap();
+        assert_eq!(
+            ranges(&table, 2),
+            vec![(1, 0, 9, true), (1, 10, 19, false), (1, 20, 99, true)]
+        );
+
+        // Overlapping locks of the same type are merged
+        table
+            .setlk(&file, 1, lock(5, 24, LockType::READ_LOCK), false)
+            .unwrap();
+        assert_eq!(
+            ranges(&table, 2),
+            vec![(1, 0, 4, true), (1, 5, 24, false), (1, 25, 99, true)]
+        );
+
+        table
+            .setlk(&file, 1, lock(0, u64::MAX, LockType::UNLOCKED), false)
+            .unwrap();
+        assert!(ranges(&table, 2).is_empty());
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let table = LockTable::new();
+        let file = Inode::from(2);
+        table
+            .setlk(&file, 1, lock(0, 9, LockType::READ_LOCK), false)
+            .unwrap();
+        table
+            .setlk(&file, 2, lock(5, 14, LockType::READ_LOCK), false)
+            .unwrap();
+        let error = table
+            .setlk(&file, 2, lock(0, 4, LockType::WRITE_LOCK), false)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ResourceUnavailableTryAgain);
+        // The failed request leaves the locks of the owner untouched
+        assert_eq!(ranges(&table, 2), vec![(1, 0, 9, false), (2, 5, 14, false)]);
+
+        let conflict = table
+            .getlk(&file, 3, lock(8, 8, LockType::WRITE_LOCK))
+            .unwrap();
+        assert_eq!(conflict.lock_type.bits(), LockType::READ_LOCK.bits());
+        assert_eq!((conflict.start, conflict.pid), (0, 42));
+        let free = table
+            .getlk(&file, 3, lock(15, 20, LockType::WRITE_LOCK))
+            .unwrap();
+        assert_eq!(free.lock_type.bits(), LockType::UNLOCKED.bits());
+        assert_eq!((free.start, free.end), (15, 20));
+        // The owner's own locks never conflict
+        let own = table
+            .getlk(&file, 1, lock(0, 4, LockType::WRITE_LOCK))
+            .unwrap();
+        assert_eq!(own.lock_type.bits(), LockType::UNLOCKED.bits());
+
+        // Locks are per file
+        table
+            .setlk(&Inode::from(3), 2, lock(0, 9, LockType::WRITE_LOCK), false)
+            .unwrap();
+
+        table.release_owner(&file, 1);
+        assert_eq!(ranges(&table, 2), vec![(2, 5, 14, false)]);
+        assert_eq!(ranges(&table, 3), vec![(2, 0, 9, true)]);
+    }
+
+    #[cfg(not(feature = "serial"))]
+    #[test]
+    fn test_blocking_requests() {
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::Duration;
+
+        let table = Arc::new(LockTable::new());
+        let file = Inode::from(2);
+        table
+            .setlk(&file, 1, lock(0, 9, LockType::WRITE_LOCK), false)
+            .unwrap();
+        table
+            .setlk(&file, 2, lock(10, 19, LockType::WRITE_LOCK), false)
+            .unwrap();
+
+        let waiter = {
+            let table = table.clone();
+            let file = file.clone();
+            thread::spawn(move || table.setlk(&file, 1, lock(10, 19, LockType::WRITE_LOCK), true))
+        };
+        while !table.state().waiting.contains_key(&1) {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Owner 2 waiting for owner 1 would deadlock
+        let error = table
+            .setlk(&file, 2, lock(0, 9, LockType::READ_LOCK), true)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ResourceDeadlockAvoided);
+
+        table.release_owner(&file, 2);
+        waiter.join().unwrap().unwrap();
+        assert_eq!(ranges(&table, 2), vec![(1, 0, 19, true)]);
+        assert!(table.state().waiting.is_empty());
+    }
+
+    #[test]
+    fn test_lock_manager() {
+        let fs = LockManager::new(MemoryFs::new());
+        let handler: &dyn FuseHandler<Inode> = &fs;
+        let req = RequestInfo {
+            id: 0,
+            uid: 1000,
+            gid: 1000,
+            pid: 0,
+        };
+        let (file_handle, (file_id, _), _) = handler
+            .create(
+                &req,
+                ROOT_INODE,
+                OsStr::new("file"),
+                0o644,
+                0o022,
+                OpenFlags::READ_WRITE,
+            )
+            .unwrap();
+        let borrowed = || unsafe { BorrowedFileHandle::from_raw(file_handle.as_raw()) };
+
+        handler
+            .setlk(
+                &req,
+                file_id.clone(),
+                borrowed(),
+                1,
+                lock(0, 99, LockType::WRITE_LOCK),
+                false,
+            )
+            .unwrap();
+        let conflict = handler
+            .getlk(
+                &req,
+                file_id.clone(),
+                borrowed(),
+                2,
+                lock(50, 50, LockType::READ_LOCK),
+            )
+            .unwrap();
+        assert_eq!(conflict.lock_type.bits(), LockType::WRITE_LOCK.bits());
+
+        // Closing a file descriptor releases the locks of its owner
+        handler.flush(&req, file_id.clone(), borrowed(), 1).unwrap();
+        assert!(fs.lock_table().locks(&file_id).is_empty());
+        handler
+            .setlk(
+                &req,
+                file_id.clone(),
+                borrowed(),
+                2,
+                lock(0, 9, LockType::READ_LOCK),
+                false,
+            )
+            .unwrap();
+        handler
+            .release(
+                &req,
+                file_id.clone(),
+                file_handle,
+                OpenFlags::READ_WRITE,
+                Some(2),
+                false,
+            )
+            .unwrap();
+        assert!(fs.lock_table().locks(&file_id).is_empty());
+    }
+}
@@ -0,0 +1,1386 @@
+/*!
+# MemoryFs
+
+A FUSE (Filesystem in Userspace) handler storing the whole filesystem in memory, similarly to tmpfs.
+
+## Overview
+
+The `MemoryFs` struct implements the `FuseHandler<Inode>` trait and supports:
+
+- Regular files, directories, symbolic links and special files (named pipes, sockets, devices).
+- Hard links, with the number of links reported in `nlink`.
+- Extended attributes.
+- `setattr` (mode, owner, size, timestamps) and `fallocate`.
+- `rename`, including `RenameFlags::NOREPLACE` and `RenameFlags::EXCHANGE`.
+- `statfs`, reporting the memory used by the content of the filesystem against an optional size limit.
+
+Besides being mounted on its own, it can be used as a backing store in tests, by calling the `FuseHandler` methods
+directly or by wrapping it in other handlers or middlewares.
+
+## Implementation Details
+
+- Every node is kept in a table indexed by inode number, behind a single mutex.
+- The content of a node is freed once it has no link left and is not open anymore.
+- The size accounted against the limit is the total length of file contents, symlink targets and extended attributes.
+  An operation which would exceed it fails with `NoSpaceLeftOnDevice` (ENOSPC).
+- File handles are only used to keep unlinked files alive while they are open, reads and writes use the inode.
+- File contents are stored densely, holes are not tracked: the zeros left by a write past the end of file are
+  reported as data by `lseek`, and `SEEK_HOLE` only finds the end of file.
+- Files are limited to `DEFAULT_MAX_FILE_SIZE` bytes unless configured otherwise with `MemoryFs::with_max_file_size`,
+  so that a write at a large offset cannot exhaust the memory. Exceeding it fails with `FileTooLarge` (EFBIG).
+
+## Usage
+
+```text
+let fs = MemoryFs::new().with_size_limit(64 * 1024 * 1024);
+mount(fs, "/mnt/scratch", &[], 4)?;
+```
+
+## Limitations
+
+- Permissions are only checked by `access`: mount with the `default_permissions` option to have the kernel enforce them
+  on every operation.
+- Ioctl, poll and bmap are not implemented. Record locks can be added by wrapping it in a `lock_manager::LockManager`.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::prelude::*;
+use crate::templates::DefaultFuseHandler;
+
+const BLOCK_SIZE: u32 = 4096;
+const MAX_NAME_LENGTH: u32 = 255;
+
+/// Default maximal size of a file, as its content is allocated up to its end
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1 << 30;
+
+enum NodeContent {
+    File(Vec<u8>),
+    Directory {
+        parent: Inode,
+        entries: BTreeMap<OsString, Inode>,
+    },
+    Symlink(Vec<u8>),
+    Special,
+}
+
+struct Node {
+    attr: FileAttribute,
+    content: NodeContent,
+    xattrs: BTreeMap<OsString, Vec<u8>>,
+    open_count: u64,
+}
+
+impl Node {
+    /// Size accounted against the size limit
+    fn accounted_size(&self) -> u64 {
+        let content = match &self.content {
+            NodeContent::File(data) | NodeContent::Symlink(data) => data.len(),
+            _ => 0,
+        };
+        let xattrs: usize = self
+            .xattrs
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum();
+        (content + xattrs) as u64
+    }
+
+    fn set_size(&mut self, size: u64) {
+        self.attr.size = size;
+        self.attr.blocks = size.div_ceil(512);
+    }
+
+    fn touch_content(&mut self) {
+        let now = SystemTime::now();
+        self.attr.mtime = now;
+        self.attr.ctime = now;
+    }
+}
+
+struct MemoryData {
+    nodes: HashMap<Inode, Node>,
+    next_inode: Inode,
+    next_file_handle: u64,
+    used_bytes: u64,
+    size_limit: Option<u64>,
+    max_file_size: u64,
+}
+
+fn not_found(file_id: &Inode) -> PosixError {
+    ErrorKind::FileNotFound.to_error(format!("{:?}: no such node", file_id))
+}
+
+fn apply_umask(mode: u32, umask: u32) -> u16 {
+    (mode & !umask & 0o7777) as u16
+}
+
+fn no_attribute(name: &OsStr) -> PosixError {
+    PosixError::new(libc::ENODATA, format!("{:?}: no such attribute", name))
+}
+
+impl MemoryData {
+    fn new() -> Self {
+        let mut data = Self {
+            nodes: HashMap::new(),
+            next_inode: ROOT_INODE.add_one(),
+            next_file_handle: 1,
+            used_bytes: 0,
+            size_limit: None,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        };
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut root = Self::new_node(
+            uid,
+            gid,
+            FileKind::Directory,
+            0o755,
+            NodeContent::Directory {
+                parent: ROOT_INODE,
+                entries: BTreeMap::new(),
+            },
+        );
+        root.attr.nlink = 2;
+        data.nodes.insert(ROOT_INODE, root);
+        data
+    }
+
+    fn new_node(uid: u32, gid: u32, kind: FileKind, perm: u16, content: NodeContent) -> Node {
+        let now = SystemTime::now();
+        Node {
+            attr: FileAttribute {
+                size: 0,
+                blocks: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                crtime: now,
+                kind,
+                perm,
+                nlink: 0,
+                uid,
+                gid,
+                rdev: 0,
+                blksize: BLOCK_SIZE,
+                flags: 0,
+                ttl: None,
+                generation: None,
+            },
+            content,
+            xattrs: BTreeMap::new(),
+            open_count: 0,
+        }
+    }
+
+    fn get(&self, file_id: &Inode) -> FuseResult<&Node> {
+        self.nodes.get(file_id).ok_or_else(|| not_found(file_id))
+    }
+
+    fn get_mut(&mut self, file_id: &Inode) -> FuseResult<&mut Node> {
+        self.nodes
+            .get_mut(file_id)
+            .ok_or_else(|| not_found(file_id))
+    }
+
+    fn entries(&self, file_id: &Inode) -> FuseResult<&BTreeMap<OsString, Inode>> {
+        match &self.get(file_id)?.content {
+            NodeContent::Directory { entries, .. } => Ok(entries),
+            _ => Err(ErrorKind::NotADirectory.to_error(format!("{:?}", file_id))),
+        }
+    }
+
+    fn entries_mut(&mut self, file_id: &Inode) -> FuseResult<&mut BTreeMap<OsString, Inode>> {
+        match &mut self.get_mut(file_id)?.content {
+            NodeContent::Directory { entries, .. } => Ok(entries),
+            _ => Err(ErrorKind::NotADirectory.to_error(format!("{:?}", file_id))),
+        }
+    }
+
+    fn parent_of(&self, file_id: &Inode) -> FuseResult<Inode> {
+        match &self.get(file_id)?.content {
+            NodeContent::Directory { parent, .. } => Ok(parent.clone()),
+            _ => Err(ErrorKind::NotADirectory.to_error(format!("{:?}", file_id))),
+        }
+    }
+
+    fn child(&self, parent_id: &Inode, name: &OsStr) -> FuseResult<Inode> {
+        match name.as_bytes() {
+            b"." => Ok(parent_id.clone()),
+            b".." => self.parent_of(parent_id),
+            _ => self.entries(parent_id)?.get(name).cloned().ok_or_else(|| {
+                ErrorKind::FileNotFound.to_error(format!("{:?} in {:?}", name, parent_id))
+            }),
+        }
+    }
+
+    fn metadata(&self, file_id: &Inode) -> FuseResult<(Inode, FileAttribute)> {
+        Ok((file_id.clone(), self.get(file_id)?.attr.clone()))
+    }
+
+    /// Reserves `new_size - old_size` bytes, failing if the size limit would be exceeded
+    fn charge(&mut self, old_size: u64, new_size: u64) -> FuseResult<()> {
+        let used_bytes = (self.used_bytes + new_size).saturating_sub(old_size);
+        if new_size > old_size && self.size_limit.is_some_and(|limit| used_bytes > limit) {
+            return Err(ErrorKind::NoSpaceLeftOnDevice.to_error(format!(
+                "size limit of {} bytes reached",
+                self.size_limit.unwrap_or_default()
+            )));
+        }
+        self.used_bytes = used_bytes;
+        Ok(())
+    }
+
+    /// Fails with `FileTooLarge` if a file cannot grow to `size`
+    fn check_file_size(&self, size: u64) -> FuseResult<()> {
+        if size > self.max_file_size {
+            return Err(ErrorKind::FileTooLarge.to_error(format!(
+                "maximal file size of {} bytes exceeded",
+                self.max_file_size
+            )));
+        }
+        Ok(())
+    }
+
+    fn allocate_file_handle(&mut self, file_id: &Inode) -> FuseResult<OwnedFileHandle> {
+        self.get_mut(file_id)?.open_count += 1;
+        let file_handle = self.next_file_handle;
+        self.next_file_handle += 1;
+        // Memory file handles are opaque numbers, never used as file descriptors
+        Ok(unsafe { OwnedFileHandle::from_raw(file_handle) })
+    }
+
+    /// Inserts a new node as `name` in `parent_id`
+    fn insert_node(
+        &mut self,
+        parent_id: &Inode,
+        name: &OsStr,
+        mut node: Node,
+    ) -> FuseResult<(Inode, FileAttribute)> {
+        if name.len() > MAX_NAME_LENGTH as usize {
+            return Err(ErrorKind::FileNameTooLong.to_error(format!("{:?}", name)));
+        }
+        if self.entries(parent_id)?.contains_key(name) {
+            return Err(ErrorKind::FileExists.to_error(format!("{:?}", name)));
+        }
+        self.charge(0, node.accounted_size())?;
+        let file_id = self.next_inode.clone();
+        self.next_inode = file_id.add_one();
+        let is_dir = node.attr.kind == FileKind::Directory;
+        node.attr.nlink = if is_dir { 2 } else { 1 };
+        self.entries_mut(parent_id)?
+            .insert(name.to_owned(), file_id.clone());
+        let parent = self.get_mut(parent_id)?;
+        parent.touch_content();
+        if is_dir {
+            parent.attr.nlink += 1;
+        }
+        let file_attr = node.attr.clone();
+        self.nodes.insert(file_id.clone(), node);
+        Ok((file_id, file_attr))
+    }
+
+    /// Removes the entry `name` of `parent_id`, freeing the node if it was its last link
+    fn remove_entry(&mut self, parent_id: &Inode, name: &OsStr) -> FuseResult<()> {
+        let file_id = self
+            .entries_mut(parent_id)?
+            .remove(name)
+            .ok_or_else(|| ErrorKind::FileNotFound.to_error(format!("{:?}", name)))?;
+        let node = self.get_mut(&file_id)?;
+        let is_dir = node.attr.kind == FileKind::Directory;
+        node.attr.nlink = if is_dir { 0 } else { node.attr.nlink - 1 };
+        node.attr.ctime = SystemTime::now();
+        let parent = self.get_mut(parent_id)?;
+        parent.touch_content();
+        if is_dir {
+            parent.attr.nlink -= 1;
+        }
+        self.release_if_unused(&file_id);
+        Ok(())
+    }
+
+    fn release_if_unused(&mut self, file_id: &Inode) {
+        let unused = self
+            .nodes
+            .get(file_id)
+            .is_some_and(|node| node.attr.nlink == 0 && node.open_count == 0);
+        if unused {
+            let node = self.nodes.remove(file_id).unwrap();
+            self.used_bytes = self.used_bytes.saturating_sub(node.accounted_size());
+        }
+    }
+
+    /// Checks whether `file_id` is `ancestor` or one of its descendants
+    fn is_within(&self, file_id: &Inode, ancestor: &Inode) -> FuseResult<bool> {
+        let mut current = file_id.clone();
+        loop {
+            if current == *ancestor {
+                return Ok(true);
+            }
+            if current == ROOT_INODE {
+                return Ok(false);
+            }
+            current = self.parent_of(&current)?;
+        }
+    }
+
+    /// Moves the directory `file_id` from `parent_id` to `newparent`, updating link counts
+    fn reparent(
+        &mut self,
+        file_id: &Inode,
+        parent_id: &Inode,
+        newparent: &Inode,
+    ) -> FuseResult<()> {
+        if parent_id == newparent {
+            return Ok(());
+        }
+        if let NodeContent::Directory { parent, .. } = &mut self.get_mut(file_id)?.content {
+            *parent = newparent.clone();
+            self.get_mut(parent_id)?.attr.nlink -= 1;
+            self.get_mut(newparent)?.attr.nlink += 1;
+        }
+        Ok(())
+    }
+
+    fn rename(
+        &mut self,
+        parent_id: &Inode,
+        name: &OsStr,
+        newparent: &Inode,
+        newname: &OsStr,
+        flags: RenameFlags,
+    ) -> FuseResult<()> {
+        let file_id = self.child(parent_id, name)?;
+        let target_id = self.entries(newparent)?.get(newname).cloned();
+        let is_dir = self.get(&file_id)?.attr.kind == FileKind::Directory;
+        if is_dir && self.is_within(newparent, &file_id)? {
+            return Err(ErrorKind::InvalidArgument
+                .to_error(format!("{:?}: cannot move a directory inside itself", name)));
+        }
+
+        #[cfg(target_os = "linux")]
+        if flags.contains(RenameFlags::EXCHANGE) {
+            let target_id = target_id.ok_or_else(|| {
+                ErrorKind::FileNotFound.to_error(format!("{:?} in {:?}", newname, newparent))
+            })?;
+            if self.get(&target_id)?.attr.kind == FileKind::Directory
+                && self.is_within(parent_id, &target_id)?
+            {
+                return Err(ErrorKind::InvalidArgument.to_error(format!(
+                    "{:?}: cannot move a directory inside itself",
+                    newname
+                )));
+            }
+            self.entries_mut(parent_id)?
+                .insert(name.to_owned(), target_id.clone());
+            self.entries_mut(newparent)?
+                .insert(newname.to_owned(), file_id.clone());
+            self.reparent(&file_id, parent_id, newparent)?;
+            self.reparent(&target_id, newparent, parent_id)?;
+            let now = SystemTime::now();
+            for id in [&file_id, &target_id] {
+                self.get_mut(id)?.attr.ctime = now;
+            }
+            for id in [parent_id, newparent] {
+                self.get_mut(id)?.touch_content();
+            }
+            return Ok(());
+        }
+
+        if let Some(target_id) = target_id {
+            #[cfg(target_os = "linux")]
+            if flags.contains(RenameFlags::NOREPLACE) {
+                return Err(ErrorKind::FileExists.to_error(format!("{:?}", newname)));
+            }
+            if target_id == file_id {
+                return Ok(());
+            }
+            let target = self.get(&target_id)?;
+            match (is_dir, target.attr.kind == FileKind::Directory) {
+                (true, false) => {
+                    return Err(ErrorKind::NotADirectory.to_error(format!("{:?}", newname)))
+                }
+                (false, true) => {
+                    return Err(ErrorKind::IsADirectory.to_error(format!("{:?}", newname)))
+                }
+                (true, true) if !self.entries(&target_id)?.is_empty() => {
+                    return Err(ErrorKind::DirectoryNotEmpty.to_error(format!("{:?}", newname)))
+                }
+                _ => {}
+            }
+            self.remove_entry(newparent, newname)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = flags;
+
+        self.entries_mut(parent_id)?.remove(name);
+        self.entries_mut(newparent)?
+            .insert(newname.to_owned(), file_id.clone());
+        self.reparent(&file_id, parent_id, newparent)?;
+        self.get_mut(&file_id)?.attr.ctime = SystemTime::now();
+        self.get_mut(parent_id)?.touch_content();
+        self.get_mut(newparent)?.touch_content();
+        Ok(())
+    }
+}
+
+/// Specific documentation is located in parent module documentation.
+pub struct MemoryFs {
+    inner: DefaultFuseHandler,
+    data: Mutex<MemoryData>,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryFs {
+    /// Creates an empty filesystem, whose root belongs to the current user
+    pub fn new() -> Self {
+        Self {
+            inner: DefaultFuseHandler::new(),
+            data: Mutex::new(MemoryData::new()),
+        }
+    }
+
+    /// Limits the total size of file contents, symlink targets and extended attributes
+    pub fn with_size_limit(self, size_limit: u64) -> Self {
+        self.data.lock().unwrap().size_limit = Some(size_limit);
+        self
+    }
+
+    /// Limits the size of each file, `DEFAULT_MAX_FILE_SIZE` by default
+    pub fn with_max_file_size(self, max_file_size: u64) -> Self {
+        self.data.lock().unwrap().max_file_size = max_file_size;
+        self
+    }
+
+    pub fn size_limit(&self) -> Option<u64> {
+        self.data.lock().unwrap().size_limit
+    }
+
+    /// Number of bytes currently accounted against the size limit
+    pub fn used_bytes(&self) -> u64 {
+        self.data.lock().unwrap().used_bytes
+    }
+
+    fn create_node(
+        &self,
+        req: &RequestInfo,
+        parent_id: &Inode,
+        name: &OsStr,
+        kind: FileKind,
+        perm: u16,
+        content: NodeContent,
+    ) -> FuseResult<(Inode, FileAttribute)> {
+        let node = MemoryData::new_node(req.uid, req.gid, kind, perm, content);
+        self.data.lock().unwrap().insert_node(parent_id, name, node)
+    }
+}
+
+impl FuseHandler<Inode> for MemoryFs {
+    fn get_inner(&self) -> &dyn FuseHandler<Inode> {
+        &self.inner
+    }
+
+    fn access(&self, req: &RequestInfo, file_id: Inode, mask: AccessMask) -> FuseResult<()> {
+        let data = self.data.lock().unwrap();
+        let attr = &data.get(&file_id)?.attr;
+        if req.uid == 0 {
+            return Ok(());
+        }
+        let perm = if req.uid == attr.uid {
+            attr.perm >> 6
+        } else if req.gid == attr.gid {
+            attr.perm >> 3
+        } else {
+            attr.perm
+        };
+        let mut allowed = AccessMask::EXISTS;
+        if perm & 0o4 != 0 {
+            allowed |= AccessMask::CAN_READ;
+        }
+        if perm & 0o2 != 0 {
+            allowed |= AccessMask::CAN_WRITE;
+        }
+        if perm & 0o1 != 0 {
+            allowed |= AccessMask::CAN_EXEC;
+        }
+        if allowed.contains(mask) {
+            Ok(())
+        } else {
+            Err(ErrorKind::PermissionDenied.to_error(format!("{:?}: {:?}", file_id, mask)))
+        }
+    }
+
+    fn create(
+        &self,
+        req: &RequestInfo,
+        parent_id: Inode,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        _flags: OpenFlags,
+    ) -> FuseResult<(
+        OwnedFileHandle,
+        (Inode, FileAttribute),
+        FUSEOpenResponseFlags,
+    )> {
+        let (file_id, file_attr) = self.create_node(
+            req,
+            &parent_id,
+            name,
+            FileKind::RegularFile,
+            apply_umask(mode, umask),
+            NodeContent::File(Vec::new()),
+        )?;
+        let file_handle = self.data.lock().unwrap().allocate_file_handle(&file_id)?;
+        Ok((
+            file_handle,
+            (file_id, file_attr),
+            FUSEOpenResponseFlags::empty(),
+        ))
+    }
+
+    fn fallocate(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        _file_handle: BorrowedFileHandle,
+        offset: i64,
+        length: i64,
+        mode: FallocateFlags,
+    ) -> FuseResult<()> {
+        if offset < 0 || length <= 0 {
+            return Err(ErrorKind::InvalidArgument.to_error("invalid fallocate range"));
+        }
+        let supported =
+            FallocateFlags::KEEP_SIZE | FallocateFlags::PUNCH_HOLE | FallocateFlags::ZERO_RANGE;
+        if !supported.contains(mode) {
+            return Err(ErrorKind::NotSupported.to_error(format!("fallocate mode {:?}", mode)));
+        }
+        let mut data = self.data.lock().unwrap();
+        let old_size = match &data.get(&file_id)?.content {
+            NodeContent::File(content) => content.len(),
+            _ => return Err(ErrorKind::InvalidArgument.to_error("not a regular file")),
+        };
+        let (start, end) = (offset as u64, offset as u64 + length as u64);
+        let new_size = if mode.contains(FallocateFlags::KEEP_SIZE) {
+            old_size
+        } else {
+            data.check_file_size(end)?;
+            old_size.max(end as usize)
+        };
+        data.charge(old_size as u64, new_size as u64)?;
+        let node = data.get_mut(&file_id)?;
+        if let NodeContent::File(content) = &mut node.content {
+            content.resize(new_size, 0);
+            if mode.intersects(FallocateFlags::PUNCH_HOLE | FallocateFlags::ZERO_RANGE) {
+                let end = end.min(new_size as u64) as usize;
+                if (start as usize) < end {
+                    content[start as usize..end].fill(0);
+                }
+            }
+        }
+        node.set_size(new_size as u64);
+        node.touch_content();
+        Ok(())
+    }
+
+    fn flush(
+        &self,
+        _req: &RequestInfo,
+        _file_id: Inode,
+        _file_handle: BorrowedFileHandle,
+        _lock_owner: u64,
+    ) -> FuseResult<()> {
+        Ok(())
+    }
+
+    fn fsync(
+        &self,
+        _req: &RequestInfo,
+        _file_id: Inode,
+        _file_handle: BorrowedFileHandle,
+        _datasync: bool,
+    ) -> FuseResult<()> {
+        Ok(())
+    }
+
+    fn getattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        _file_handle: Option<BorrowedFileHandle>,
+    ) -> FuseResult<FileAttribute> {
+        Ok(self.data.lock().unwrap().get(&file_id)?.attr.clone())
+    }
+
+    fn getxattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        name: &OsStr,
+        _size: u32,
+    ) -> FuseResult<Vec<u8>> {
+        let data = self.data.lock().unwrap();
+        data.get(&file_id)?
+            .xattrs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| no_attribute(name))
+    }
+
+    fn link(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        newparent: Inode,
+        newname: &OsStr,
+    ) -> FuseResult<(Inode, FileAttribute)> {
+        let mut data = self.data.lock().unwrap();
+        if data.get(&file_id)?.attr.kind == FileKind::Directory {
+            return Err(ErrorKind::PermissionDenied.to_error("cannot hard link a directory"));
+        }
+        if newname.len() > MAX_NAME_LENGTH as usize {
+            return Err(ErrorKind::FileNameTooLong.to_error(format!("{:?}", newname)));
+        }
+        let entries = data.entries_mut(&newparent)?;
+        if entries.contains_key(newname) {
+            return Err(ErrorKind::FileExists.to_error(format!("{:?}", newname)));
+        }
+        entries.insert(newname.to_owned(), file_id.clone());
+        data.get_mut(&newparent)?.touch_content();
+        let node = data.get_mut(&file_id)?;
+        node.attr.nlink += 1;
+        node.attr.ctime = SystemTime::now();
+        Ok((file_id, node.attr.clone()))
+    }
+
+    fn listxattr(&self, _req: &RequestInfo, file_id: Inode, _size: u32) -> FuseResult<Vec<u8>> {
+        let data = self.data.lock().unwrap();
+        let mut names = Vec::new();
+        for name in data.get(&file_id)?.xattrs.keys() {
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        Ok(names)
+    }
+
+    fn lookup(
+        &self,
+        _req: &RequestInfo,
+        parent_id: Inode,
+        name: &OsStr,
+    ) -> FuseResult<(Inode, FileAttribute)> {
+        let data = self.data.lock().unwrap();
+        data.metadata(&data.child(&parent_id, name)?)
+    }
+
+    fn lseek(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        _file_handle: BorrowedFileHandle,
+        seek: LseekFrom,
+    ) -> FuseResult<i64> {
+        let data = self.data.lock().unwrap();
+        let NodeContent::File(content) = &data.get(&file_id)?.content else {
+            return Err(ErrorKind::IsADirectory.to_error(format!("{:?}", file_id)));
+        };
+        let size = content.len() as i64;
+        // Holes are not tracked: the whole content is data, followed by the hole at the end of file
+        let offset = match seek {
+            LseekFrom::Seek(SeekFrom::Start(offset)) => offset as i64,
+            LseekFrom::Seek(SeekFrom::End(offset)) => size + offset,
+            LseekFrom::Seek(SeekFrom::Current(_)) => {
+                return Err(ErrorKind::InvalidArgument.to_error("relative seek is not supported"))
+            }
+            LseekFrom::Data(offset) if (offset as i64) < size => offset as i64,
+            LseekFrom::Hole(offset) if (offset as i64) < size => size,
+            LseekFrom::Data(offset) | LseekFrom::Hole(offset) => {
+                return Err(ErrorKind::NoSuchDeviceOrAddress
+                    .to_error(format!("offset {} is past the end of file", offset)))
+            }
+        };
+        if offset < 0 {
+            return Err(ErrorKind::InvalidArgument.to_error("negative offset"));
+        }
+        Ok(offset)
+    }
+
+    fn mkdir(
+        &self,
+        req: &RequestInfo,
+        parent_id: Inode,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+    ) -> FuseResult<(Inode, FileAttribute)> {
+        self.create_node(
+            req,
+            &parent_id,
+            name,
+            FileKind::Directory,
+            apply_umask(mode, umask),
+            NodeContent::Directory {
+                parent: parent_id.clone(),
+                entries: BTreeMap::new(),
+            },
+        )
+    }
+
+    fn mknod(
+        &self,
+        req: &RequestInfo,
+        parent_id: Inode,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: DeviceType,
+    ) -> FuseResult<(Inode, FileAttribute)> {
+        let (kind, content) = match mode & libc::S_IFMT {
+            0 | libc::S_IFREG => (FileKind::RegularFile, NodeContent::File(Vec::new())),
+            libc::S_IFIFO => (FileKind::NamedPipe, NodeContent::Special),
+            libc::S_IFSOCK => (FileKind::Socket, NodeContent::Special),
+            libc::S_IFCHR => (FileKind::CharDevice, NodeContent::Special),
+            libc::S_IFBLK => (FileKind::BlockDevice, NodeContent::Special),
+            _ => {
+                return Err(ErrorKind::InvalidArgument
+                    .to_error(format!("mknod: unsupported mode {:o}", mode)))
+            }
+        };
+        let (file_id, _) = self.create_node(
+            req,
+            &parent_id,
+            name,
+            kind,
+            apply_umask(mode, umask),
+            content,
+        )?;
+        let mut data = self.data.lock().unwrap();
+        let node = data.get_mut(&file_id)?;
+        if let DeviceType::CharacterDevice { major, minor }
+        | DeviceType::BlockDevice { major, minor } = rdev
+        {
+            node.attr.rdev = libc::makedev(major, minor) as u32;
+        }
+        Ok((file_id, node.attr.clone()))
+    }
+
+    fn open(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        let mut data = self.data.lock().unwrap();
+        let writable = flags.intersects(OpenFlags::WRITE_ONLY | OpenFlags::READ_WRITE);
+        let old_size = match &data.get(&file_id)?.content {
+            NodeContent::Directory { .. } if writable => {
+                return Err(ErrorKind::IsADirectory.to_error(format!("{:?}", file_id)))
+            }
+            NodeContent::File(content) => Some(content.len() as u64),
+            _ => None,
+        };
+        if let Some(old_size) = old_size.filter(|_| writable && flags.contains(OpenFlags::TRUNCATE))
+        {
+            data.charge(old_size, 0)?;
+            let node = data.get_mut(&file_id)?;
+            node.content = NodeContent::File(Vec::new());
+            node.set_size(0);
+            node.touch_content();
+        }
+        let file_handle = data.allocate_file_handle(&file_id)?;
+        Ok((file_handle, FUSEOpenResponseFlags::empty()))
+    }
+
+    fn read(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        _file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        size: u32,
+        _flags: FUSEOpenFlags,
+        _lock_owner: Option<u64>,
+    ) -> FuseResult<Vec<u8>> {
+        let mut data = self.data.lock().unwrap();
+        let node = data.get_mut(&file_id)?;
+        let NodeContent::File(content) = &node.content else {
+            return Err(ErrorKind::IsADirectory.to_error(format!("{:?}", file_id)));
+        };
+        let start = match seek {
+            SeekFrom::Start(offset) => offset as usize,
+            SeekFrom::End(offset) => (content.len() as i64 + offset).max(0) as usize,
+            SeekFrom::Current(_) => {
+                return Err(ErrorKind::InvalidArgument.to_error("relative seek is not supported"))
+            }
+        };
+        let start = start.min(content.len());
+        let end = start.saturating_add(size as usize).min(content.len());
+        let result = content[start..end].to_vec();
+        node.attr.atime = SystemTime::now();
+        Ok(result)
+    }
+
+    fn readdir(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        _file_handle: BorrowedFileHandle,
+    ) -> FuseResult<Vec<(OsString, (Inode, FileKind))>> {
+        let data = self.data.lock().unwrap();
+        let mut result = vec![
+            (OsString::from("."), (file_id.clone(), FileKind::Directory)),
+            (
+                OsString::from(".."),
+                (data.parent_of(&file_id)?, FileKind::Directory),
+            ),
+        ];
+        for (name, child_id) in data.entries(&file_id)? {
+            let kind = data.get(child_id)?.attr.kind;
+            result.push((name.clone(), (child_id.clone(), kind)));
+        }
+        Ok(result)
+    }
+
+    fn readdirplus(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        _file_handle: BorrowedFileHandle,
+    ) -> FuseResult<Vec<(OsString, (Inode, FileAttribute))>> {
+        let data = self.data.lock().unwrap();
+        let mut result = vec![
+            (OsString::from("."), data.metadata(&file_id)?),
+            (
+                OsString::from(".."),
+                data.metadata(&data.parent_of(&file_id)?)?,
+            ),
+        ];
+        for (name, child_id) in data.entries(&file_id)? {
+            result.push((name.clone(), data.metadata(child_id)?));
+        }
+        Ok(result)
+    }
+
+    fn readlink(&self, _req: &RequestInfo, file_id: Inode) -> FuseResult<Vec<u8>> {
+        match &self.data.lock().unwrap().get(&file_id)?.content {
+            NodeContent::Symlink(target) => Ok(target.clone()),
+            _ => Err(ErrorKind::InvalidArgument.to_error(format!("{:?}: not a symlink", file_id))),
+        }
+    }
+
+    fn release(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        _file_handle: OwnedFileHandle,
+        _flags: OpenFlags,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+    ) -> FuseResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let node = data.get_mut(&file_id)?;
+        node.open_count = node.open_count.saturating_sub(1);
+        data.release_if_unused(&file_id);
+        Ok(())
+    }
+
+    fn removexattr(&self, _req: &RequestInfo, file_id: Inode, name: &OsStr) -> FuseResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let node = data.get_mut(&file_id)?;
+        let value = node.xattrs.remove(name).ok_or_else(|| no_attribute(name))?;
+        node.attr.ctime = SystemTime::now();
+        data.charge((name.len() + value.len()) as u64, 0)
+    }
+
+    fn rename(
+        &self,
+        _req: &RequestInfo,
+        parent_id: Inode,
+        name: &OsStr,
+        newparent: Inode,
+        newname: &OsStr,
+        flags: RenameFlags,
+    ) -> FuseResult<()> {
+        if newname.len() > MAX_NAME_LENGTH as usize {
+            return Err(ErrorKind::FileNameTooLong.to_error(format!("{:?}", newname)));
+        }
+        self.data
+            .lock()
+            .unwrap()
+            .rename(&parent_id, name, &newparent, newname, flags)
+    }
+
+    fn rmdir(&self, _req: &RequestInfo, parent_id: Inode, name: &OsStr) -> FuseResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let file_id = data.child(&parent_id, name)?;
+        if !data.entries(&file_id)?.is_empty() {
+            return Err(ErrorKind::DirectoryNotEmpty.to_error(format!("{:?}", name)));
+        }
+        data.remove_entry(&parent_id, name)
+    }
+
+    fn setattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        attrs: SetAttrRequest,
+    ) -> FuseResult<FileAttribute> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(size) = attrs.size {
+            let old_size = match &data.get(&file_id)?.content {
+                NodeContent::File(content) => content.len() as u64,
+                NodeContent::Directory { .. } => {
+                    return Err(ErrorKind::IsADirectory.to_error(format!("{:?}", file_id)))
+                }
+                _ => return Err(ErrorKind::InvalidArgument.to_error("not a regular file")),
+            };
+            data.check_file_size(size)?;
+            data.charge(old_size, size)?;
+            let node = data.get_mut(&file_id)?;
+            if let NodeContent::File(content) = &mut node.content {
+                content.resize(size as usize, 0);
+            }
+            node.set_size(size);
+            node.touch_content();
+        }
+        let node = data.get_mut(&file_id)?;
+        let now = SystemTime::now();
+        if let Some(mode) = attrs.mode {
+            node.attr.perm = (mode & 0o7777) as u16;
+        }
+        if let Some(uid) = attrs.uid {
+            node.attr.uid = uid;
+        }
+        if let Some(gid) = attrs.gid {
+            node.attr.gid = gid;
+        }
+        if let Some(atime) = attrs.atime {
+            node.attr.atime = match atime {
+                TimeOrNow::SpecificTime(time) => time,
+                TimeOrNow::Now => now,
+            };
+        }
+        if let Some(mtime) = attrs.mtime {
+            node.attr.mtime = match mtime {
+                TimeOrNow::SpecificTime(time) => time,
+                TimeOrNow::Now => now,
+            };
+        }
+        if let Some(crtime) = attrs.crtime {
+            node.attr.crtime = crtime;
+        }
+        node.attr.ctime = attrs.ctime.unwrap_or(now);
+        Ok(node.attr.clone())
+    }
+
+    fn setxattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: FUSESetXAttrFlags,
+        _position: u32,
+    ) -> FuseResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let old_value = data.get(&file_id)?.xattrs.get(name).map(Vec::len);
+        match old_value {
+            Some(_) if flags.bits() & libc::XATTR_CREATE != 0 => {
+                return Err(ErrorKind::FileExists.to_error(format!("{:?}", name)))
+            }
+            None if flags.bits() & libc::XATTR_REPLACE != 0 => return Err(no_attribute(name)),
+            _ => {}
+        }
+        let old_size = old_value.map_or(0, |len| name.len() + len);
+        data.charge(old_size as u64, (name.len() + value.len()) as u64)?;
+        let node = data.get_mut(&file_id)?;
+        node.xattrs.insert(name.to_owned(), value);
+        node.attr.ctime = SystemTime::now();
+        Ok(())
+    }
+
+    fn statfs(&self, _req: &RequestInfo, _file_id: Inode) -> FuseResult<StatFs> {
+        let data = self.data.lock().unwrap();
+        let block_size = u64::from(BLOCK_SIZE);
+        let total_blocks = data.size_limit.unwrap_or(u64::MAX) / block_size;
+        let free_blocks = total_blocks.saturating_sub(data.used_bytes.div_ceil(block_size));
+        let total_files = u64::MAX / 2;
+        Ok(StatFs {
+            total_blocks,
+            free_blocks,
+            available_blocks: free_blocks,
+            total_files,
+            free_files: total_files - data.nodes.len() as u64,
+            block_size: BLOCK_SIZE,
+            max_filename_length: MAX_NAME_LENGTH,
+            fragment_size: BLOCK_SIZE,
+        })
+    }
+
+    fn symlink(
+        &self,
+        req: &RequestInfo,
+        parent_id: Inode,
+        link_name: &OsStr,
+        target: &Path,
+    ) -> FuseResult<(Inode, FileAttribute)> {
+        let target = target.as_os_str().as_bytes().to_vec();
+        let size = target.len() as u64;
+        let (file_id, _) = self.create_node(
+            req,
+            &parent_id,
+            link_name,
+            FileKind::Symlink,
+            0o777,
+            NodeContent::Symlink(target),
+        )?;
+        let mut data = self.data.lock().unwrap();
+        let node = data.get_mut(&file_id)?;
+        node.set_size(size);
+        Ok((file_id, node.attr.clone()))
+    }
+
+    fn unlink(&self, _req: &RequestInfo, parent_id: Inode, name: &OsStr) -> FuseResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let file_id = data.child(&parent_id, name)?;
+        if data.get(&file_id)?.attr.kind == FileKind::Directory {
+            return Err(ErrorKind::IsADirectory.to_error(format!("{:?}", name)));
+        }
+        data.remove_entry(&parent_id, name)
+    }
+
+    fn write(
+        &self,
+        _req: &RequestInfo,
+        file_id: Inode,
+        _file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        data: Vec<u8>,
+        _write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        _lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        let mut memory = self.data.lock().unwrap();
+        let NodeContent::File(content) = &memory.get(&file_id)?.content else {
+            return Err(ErrorKind::IsADirectory.to_error(format!("{:?}", file_id)));
+        };
+        let old_size = content.len();
+        let start = if flags.contains(OpenFlags::APPEND_MODE) {
+            old_size
+        } else {
+            match seek {
+                SeekFrom::Start(offset) => offset as usize,
+                SeekFrom::End(offset) => (old_size as i64 + offset).max(0) as usize,
+                SeekFrom::Current(_) => {
+                    return Err(
+                        ErrorKind::InvalidArgument.to_error("relative seek is not supported")
+                    )
+                }
+            }
+        };
+        let end = (start as u64).saturating_add(data.len() as u64);
+        memory.check_file_size(end)?;
+        let end = end as usize;
+        let new_size = old_size.max(end);
+        memory.charge(old_size as u64, new_size as u64)?;
+        let node = memory.get_mut(&file_id)?;
+        if let NodeContent::File(content) = &mut node.content {
+            content.resize(new_size, 0);
+            content[start..end].copy_from_slice(&data);
+        }
+        node.set_size(new_size as u64);
+        node.touch_content();
+        Ok(data.len() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req() -> RequestInfo {
+        RequestInfo {
+            id: 0,
+            uid: 1000,
+            gid: 1000,
+            pid: 0,
+        }
+    }
+
+    fn handler(fs: &MemoryFs) -> &dyn FuseHandler<Inode> {
+        fs
+    }
+
+    fn write_file(fs: &MemoryFs, parent: Inode, name: &str, content: &[u8]) -> FuseResult<Inode> {
+        let fs = handler(fs);
+        let (file_handle, (file_id, _), _) = fs.create(
+            &req(),
+            parent,
+            OsStr::new(name),
+            0o644,
+            0o022,
+            OpenFlags::WRITE_ONLY,
+        )?;
+        let result = fs.write(
+            &req(),
+            file_id.clone(),
+            file_handle.borrow(),
+            SeekFrom::Start(0),
+            content.to_vec(),
+            FUSEWriteFlags::empty(),
+            OpenFlags::WRITE_ONLY,
+            None,
+        );
+        fs.release(
+            &req(),
+            file_id.clone(),
+            file_handle,
+            OpenFlags::WRITE_ONLY,
+            None,
+            false,
+        )?;
+        result.map(|_| file_id)
+    }
+
+    fn read_file(fs: &MemoryFs, file_id: Inode) -> Vec<u8> {
+        let fs = handler(fs);
+        let (file_handle, _) = fs
+            .open(&req(), file_id.clone(), OpenFlags::READ_ONLY)
+            .unwrap();
+        let content = fs
+            .read(
+                &req(),
+                file_id.clone(),
+                file_handle.borrow(),
+                SeekFrom::Start(0),
+                4096,
+                FUSEOpenFlags::empty(),
+                None,
+            )
+            .unwrap();
+        fs.release(
+            &req(),
+            file_id,
+            file_handle,
+            OpenFlags::READ_ONLY,
+            None,
+            false,
+        )
+        .unwrap();
+        content
+    }
+
+    #[test]
+    fn test_files_and_hard_links() {
+        let memory_fs = MemoryFs::new();
+        let fs = handler(&memory_fs);
+        let (dir_id, dir_attr) = fs
+            .mkdir(&req(), ROOT_INODE, OsStr::new("dir"), 0o755, 0)
+            .unwrap();
+        assert_eq!(dir_attr.nlink, 2);
+        assert_eq!(fs.getattr(&req(), ROOT_INODE, None).unwrap().nlink, 3);
+
+        let file_id = write_file(&memory_fs, dir_id.clone(), "file.txt", b"hello").unwrap();
+        assert_eq!(read_file(&memory_fs, file_id.clone()), b"hello");
+        let file_attr = fs.getattr(&req(), file_id.clone(), None).unwrap();
+        assert_eq!((file_attr.size, file_attr.perm), (5, 0o644));
+
+        let (link_id, link_attr) = fs
+            .link(&req(), file_id.clone(), ROOT_INODE, OsStr::new("link.txt"))
+            .unwrap();
+        assert_eq!((link_id.clone(), link_attr.nlink), (file_id.clone(), 2));
+        fs.unlink(&req(), dir_id.clone(), OsStr::new("file.txt"))
+            .unwrap();
+        assert_eq!(read_file(&memory_fs, link_id.clone()), b"hello");
+        assert_eq!(memory_fs.used_bytes(), 5);
+
+        fs.unlink(&req(), ROOT_INODE, OsStr::new("link.txt"))
+            .unwrap();
+        assert!(fs.getattr(&req(), link_id, None).is_err());
+        assert_eq!(memory_fs.used_bytes(), 0);
+        fs.rmdir(&req(), ROOT_INODE, OsStr::new("dir")).unwrap();
+        let names: Vec<_> = fs
+            .readdir(&req(), ROOT_INODE, unsafe {
+                BorrowedFileHandle::from_raw(0)
+            })
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec![OsString::from("."), OsString::from("..")]);
+    }
+
+    #[test]
+    fn test_rename_flags() {
+        let memory_fs = MemoryFs::new();
+        let fs = handler(&memory_fs);
+        let a = write_file(&memory_fs, ROOT_INODE, "a", b"a").unwrap();
+        let b = write_file(&memory_fs, ROOT_INODE, "b", b"b").unwrap();
+
+        let result = fs.rename(
+            &req(),
+            ROOT_INODE,
+            OsStr::new("a"),
+            ROOT_INODE,
+            OsStr::new("b"),
+            RenameFlags::NOREPLACE,
+        );
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::FileExists);
+
+        fs.rename(
+            &req(),
+            ROOT_INODE,
+            OsStr::new("a"),
+            ROOT_INODE,
+            OsStr::new("b"),
+            RenameFlags::EXCHANGE,
+        )
+        .unwrap();
+        let lookup = |name: &str| fs.lookup(&req(), ROOT_INODE, OsStr::new(name)).unwrap().0;
+        assert_eq!((lookup("a"), lookup("b")), (b.clone(), a.clone()));
+
+        fs.rename(
+            &req(),
+            ROOT_INODE,
+            OsStr::new("a"),
+            ROOT_INODE,
+            OsStr::new("b"),
+            RenameFlags::empty(),
+        )
+        .unwrap();
+        assert_eq!(lookup("b"), b);
+        assert!(fs.getattr(&req(), a, None).is_err());
+
+        let (dir_id, _) = fs
+            .mkdir(&req(), ROOT_INODE, OsStr::new("dir"), 0o755, 0)
+            .unwrap();
+        let result = fs.rename(
+            &req(),
+            ROOT_INODE,
+            OsStr::new("dir"),
+            dir_id,
+            OsStr::new("inside"),
+            RenameFlags::empty(),
+        );
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidArgument);
+    }
+
+    #[test]
+    fn test_size_limit_and_xattrs() {
+        let memory_fs = MemoryFs::new().with_size_limit(8);
+        let fs = handler(&memory_fs);
+        let result = write_file(&memory_fs, ROOT_INODE, "big", b"0123456789");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::NoSpaceLeftOnDevice);
+
+        let file_id = fs.lookup(&req(), ROOT_INODE, OsStr::new("big")).unwrap().0;
+        fs.setxattr(
+            &req(),
+            file_id.clone(),
+            OsStr::new("user.a"),
+            b"1".to_vec(),
+            FUSESetXAttrFlags::empty(),
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            fs.getxattr(&req(), file_id.clone(), OsStr::new("user.a"), 0)
+                .unwrap(),
+            b"1"
+        );
+        assert_eq!(
+            fs.listxattr(&req(), file_id.clone(), 0).unwrap(),
+            b"user.a\0"
+        );
+        assert_eq!(memory_fs.used_bytes(), 7);
+        let statfs = fs.statfs(&req(), ROOT_INODE).unwrap();
+        assert_eq!(statfs.total_blocks, 0);
+
+        fs.removexattr(&req(), file_id.clone(), OsStr::new("user.a"))
+            .unwrap();
+        let result = fs.getxattr(&req(), file_id, OsStr::new("user.a"), 0);
+        assert_eq!(result.unwrap_err().raw_error(), libc::ENODATA);
+        assert_eq!(memory_fs.used_bytes(), 0);
+    }
+
+    #[test]
+    fn test_max_file_size() {
+        let memory_fs = MemoryFs::new();
+        let fs = handler(&memory_fs);
+        let file_id = write_file(&memory_fs, ROOT_INODE, "file", b"hello").unwrap();
+        let file_handle = unsafe { BorrowedFileHandle::from_raw(0) };
+        let write = |offset: u64, data: &[u8]| {
+            fs.write(
+                &req(),
+                file_id.clone(),
+                file_handle,
+                SeekFrom::Start(offset),
+                data.to_vec(),
+                FUSEWriteFlags::empty(),
+                OpenFlags::WRITE_ONLY,
+                None,
+            )
+        };
+
+        // Growing a file far past its end fails instead of allocating its whole content
+        let far = 1 << 40;
+        assert_eq!(
+            write(far, b"x").unwrap_err().kind(),
+            ErrorKind::FileTooLarge
+        );
+        let result = fs.setattr(&req(), file_id.clone(), SetAttrRequest::new().size(far));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::FileTooLarge);
+        let result = fs.fallocate(
+            &req(),
+            file_id.clone(),
+            file_handle,
+            0,
+            far as i64,
+            FallocateFlags::empty(),
+        );
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::FileTooLarge);
+        fs.fallocate(
+            &req(),
+            file_id.clone(),
+            file_handle,
+            0,
+            far as i64,
+            FallocateFlags::KEEP_SIZE,
+        )
+        .unwrap();
+        assert_eq!(read_file(&memory_fs, file_id.clone()), b"hello");
+        assert_eq!(memory_fs.used_bytes(), 5);
+
+        // A write past the end of file up to the limit leaves zeros before it
+        let memory_fs = MemoryFs::new().with_max_file_size(16);
+        let fs = handler(&memory_fs);
+        let file_id = write_file(&memory_fs, ROOT_INODE, "file", b"hello").unwrap();
+        let write = |offset: u64, data: &[u8]| {
+            fs.write(
+                &req(),
+                file_id.clone(),
+                file_handle,
+                SeekFrom::Start(offset),
+                data.to_vec(),
+                FUSEWriteFlags::empty(),
+                OpenFlags::WRITE_ONLY,
+                None,
+            )
+        };
+        assert_eq!(
+            write(13, b"abcd").unwrap_err().kind(),
+            ErrorKind::FileTooLarge
+        );
+        assert_eq!(write(12, b"abcd").unwrap(), 4);
+        assert_eq!(read_file(&memory_fs, file_id), b"hello\0\0\0\0\0\0\0abcd");
+    }
+}
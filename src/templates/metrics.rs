@@ -0,0 +1,399 @@
+/*!
+# Metrics
+
+A middleware collecting per-operation metrics of the handler it wraps.
+
+## Overview
+
+The `Metrics` middleware can wrap any `FuseHandler<TId>` through `FuseHandler::layer`. It collects:
+
+- the number of calls of each operation
+- the number of errors of each operation, by `ErrorKind`
+- a latency histogram of each operation
+- the number of bytes read and written
+
+Counters are atomic, so collecting stays cheap on hot paths (`lookup`, `getattr`, `read`...), but it is not lock-free:
+each call takes the read lock of the table of operations (its write lock the first time an operation is seen), and each
+error takes the mutex of the error counts of its operation.
+
+`Metrics` is a cheap handle to shared counters: keep a clone of it before layering to read the metrics while the filesystem is mounted.
+The collected values are exported through a [`MetricsExporter`], this module provides a [`PrometheusExporter`] using the Prometheus text format.
+
+## Usage
+
+```text
+let metrics = Metrics::new();
+let fs = MirrorFs::new(source_path, DefaultFuseHandler::new()).layer(metrics.clone());
+let _session = spawn_mount(fs, mountpoint, &[], 4)?;
+
+let exporter = PrometheusExporter::to_file("/var/lib/node_exporter/easy_fuser.prom");
+loop {
+    std::thread::sleep(Duration::from_secs(15));
+    metrics.export(&exporter)?;
+}
+```
+*/
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::prelude::*;
+
+/// Upper bounds of the latency histogram buckets, in seconds
+pub const LATENCY_BUCKETS: [f64; 12] = [
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0,
+];
+
+struct OperationMetrics {
+    calls: AtomicU64,
+    /// Number of calls by bucket, the last one being for latencies above all bounds
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_sum_ns: AtomicU64,
+    /// Number of errors by raw error code
+    errors: Mutex<HashMap<i32, u64>>,
+}
+
+impl OperationMetrics {
+    fn new() -> Self {
+        OperationMetrics {
+            calls: AtomicU64::new(0),
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            latency_sum_ns: AtomicU64::new(0),
+            errors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn record(&self, latency: Duration, error: Option<&PosixError>) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_ns
+            .fetch_add(latency.as_nanos() as u64, Ordering::Relaxed);
+        if let Some(e) = error {
+            *self
+                .errors
+                .lock()
+                .unwrap()
+                .entry(e.raw_error())
+                .or_insert(0) += 1;
+        }
+    }
+}
+
+#[derive(Default)]
+struct MetricsData {
+    operations: RwLock<HashMap<&'static str, Arc<OperationMetrics>>>,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+/// Middleware collecting metrics on each operation, see the module documentation
+#[derive(Clone, Default)]
+pub struct Metrics {
+    data: Arc<MetricsData>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn operation(&self, name: &'static str) -> Arc<OperationMetrics> {
+        if let Some(operation) = self.data.operations.read().unwrap().get(name) {
+            return operation.clone();
+        }
+        self.data
+            .operations
+            .write()
+            .unwrap()
+            .entry(name)
+            .or_insert_with(|| Arc::new(OperationMetrics::new()))
+            .clone()
+    }
+
+    /// Returns a copy of the values collected so far
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut operations: Vec<_> = self
+            .data
+            .operations
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, operation)| {
+                let mut errors: Vec<_> = operation
+                    .errors
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(code, count)| (ErrorKind::from(*code), *code, *count))
+                    .collect();
+                errors.sort_by_key(|(_, code, _)| *code);
+                OperationSnapshot {
+                    name,
+                    calls: operation.calls.load(Ordering::Relaxed),
+                    errors,
+                    latency_buckets: operation
+                        .buckets
+                        .iter()
+                        .map(|bucket| bucket.load(Ordering::Relaxed))
+                        .collect(),
+                    latency_sum: Duration::from_nanos(
+                        operation.latency_sum_ns.load(Ordering::Relaxed),
+                    ),
+                }
+            })
+            .collect();
+        operations.sort_by_key(|operation| operation.name);
+        MetricsSnapshot {
+            operations,
+            bytes_read: self.data.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.data.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Exports the values collected so far
+    pub fn export(&self, exporter: &dyn MetricsExporter) -> io::Result<()> {
+        exporter.export(&self.snapshot())
+    }
+}
+
+impl<TId: FileIdType> Middleware<TId> for Metrics {
+    fn after(&self, op: &Operation<'_, TId>, outcome: &mut Outcome<'_>) {
+        self.operation(op.name)
+            .record(op.elapsed(), outcome.error());
+        match op.name {
+            "read" => {
+                let bytes_read = match outcome.result::<Vec<u8>>() {
+                    Some(Ok(data)) => data.len(),
+                    _ => match outcome.result::<ReadData>() {
+                        Some(Ok(data)) => data.len(),
+                        _ => 0,
+                    },
+                };
+                self.data
+                    .bytes_read
+                    .fetch_add(bytes_read as u64, Ordering::Relaxed);
+            }
+            "write" => {
+                if let Some(Ok(written)) = outcome.result::<u32>() {
+                    self.data
+                        .bytes_written
+                        .fetch_add(*written as u64, Ordering::Relaxed);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Values collected by [`Metrics`] at a given time
+#[derive(Debug, Clone)]
+pub struct MetricsSnapshot {
+    /// Metrics of each operation called at least once, sorted by name
+    pub operations: Vec<OperationSnapshot>,
+    /// Bytes returned by successful reads
+    pub bytes_read: u64,
+    /// Bytes written by successful writes
+    pub bytes_written: u64,
+}
+
+/// Values collected for a single operation
+#[derive(Debug, Clone)]
+pub struct OperationSnapshot {
+    pub name: &'static str,
+    pub calls: u64,
+    /// Number of errors by kind, along with the raw error code
+    pub errors: Vec<(ErrorKind, i32, u64)>,
+    /// Number of calls in each bucket of `LATENCY_BUCKETS` (not cumulative),
+    /// followed by the number of calls above the last bound
+    pub latency_buckets: Vec<u64>,
+    pub latency_sum: Duration,
+}
+
+/// Destination of the values collected by [`Metrics`]
+pub trait MetricsExporter {
+    fn export(&self, snapshot: &MetricsSnapshot) -> io::Result<()>;
+}
+
+/// Exporter rendering the metrics in the Prometheus text exposition format
+///
+/// The rendered text can be written to a file (eg: for the textfile collector of node_exporter),
+/// or retrieved with `render` to be served by the application.
+pub struct PrometheusExporter {
+    namespace: String,
+    path: Option<PathBuf>,
+}
+
+impl PrometheusExporter {
+    /// Creates an exporter which only renders the metrics, see `render`
+    pub fn new() -> Self {
+        PrometheusExporter {
+            namespace: "easy_fuser".to_string(),
+            path: None,
+        }
+    }
+
+    /// Creates an exporter writing the metrics to `path`
+    ///
+    /// The file is replaced atomically, so that readers never see a partial export.
+    pub fn to_file<P: Into<PathBuf>>(path: P) -> Self {
+        PrometheusExporter {
+            namespace: "easy_fuser".to_string(),
+            path: Some(path.into()),
+        }
+    }
+
+    /// Changes the prefix of the metric names, "easy_fuser" by default
+    pub fn with_namespace<S: Into<String>>(mut self, namespace: S) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// Renders the snapshot in the Prometheus text format
+    pub fn render(&self, snapshot: &MetricsSnapshot) -> String {
+        let ns = &self.namespace;
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP {ns}_operations_total Number of operations handled."
+        );
+        let _ = writeln!(out, "# TYPE {ns}_operations_total counter");
+        for operation in &snapshot.operations {
+            let _ = writeln!(
+                out,
+                "{ns}_operations_total{{operation=\"{}\"}} {}",
+                operation.name, operation.calls
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP {ns}_operation_errors_total Number of operations which failed, by error kind."
+        );
+        let _ = writeln!(out, "# TYPE {ns}_operation_errors_total counter");
+        for operation in &snapshot.operations {
+            for (kind, code, count) in &operation.errors {
+                let _ = writeln!(
+                    out,
+                    "{ns}_operation_errors_total{{operation=\"{}\",kind=\"{:?}\",code=\"{}\"}} {}",
+                    operation.name, kind, code, count
+                );
+            }
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP {ns}_operation_duration_seconds Latency of the operations."
+        );
+        let _ = writeln!(out, "# TYPE {ns}_operation_duration_seconds histogram");
+        for operation in &snapshot.operations {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&operation.latency_buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{ns}_operation_duration_seconds_bucket{{operation=\"{}\",le=\"{}\"}} {}",
+                    operation.name, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{ns}_operation_duration_seconds_bucket{{operation=\"{}\",le=\"+Inf\"}} {}",
+                operation.name, operation.calls
+            );
+            let _ = writeln!(
+                out,
+                "{ns}_operation_duration_seconds_sum{{operation=\"{}\"}} {}",
+                operation.name,
+                operation.latency_sum.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "{ns}_operation_duration_seconds_count{{operation=\"{}\"}} {}",
+                operation.name, operation.calls
+            );
+        }
+
+        let _ = writeln!(out, "# HELP {ns}_read_bytes_total Bytes read.");
+        let _ = writeln!(out, "# TYPE {ns}_read_bytes_total counter");
+        let _ = writeln!(out, "{ns}_read_bytes_total {}", snapshot.bytes_read);
+        let _ = writeln!(out, "# HELP {ns}_written_bytes_total Bytes written.");
+        let _ = writeln!(out, "# TYPE {ns}_written_bytes_total counter");
+        let _ = writeln!(out, "{ns}_written_bytes_total {}", snapshot.bytes_written);
+        out
+    }
+}
+
+impl Default for PrometheusExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsExporter for PrometheusExporter {
+    /// Writes the rendered metrics to the file of the exporter, does nothing if it has none
+    fn export(&self, snapshot: &MetricsSnapshot) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, self.render(snapshot))?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::DefaultFuseHandler;
+
+    #[test]
+    fn test_metrics_collection() {
+        let metrics = Metrics::new();
+        let fs = FuseHandler::<Inode>::layer(DefaultFuseHandler::new(), metrics.clone());
+        let req = RequestInfo {
+            id: 0,
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        };
+        for _ in 0..3 {
+            let _ = FuseHandler::<Inode>::readlink(&fs, &req, ROOT_INODE);
+        }
+        let _ = FuseHandler::<Inode>::statfs(&fs, &req, ROOT_INODE);
+
+        let snapshot = metrics.snapshot();
+        let names: Vec<_> = snapshot.operations.iter().map(|op| op.name).collect();
+        assert_eq!(names, vec!["readlink", "statfs"]);
+        let readlink = &snapshot.operations[0];
+        assert_eq!(readlink.calls, 3);
+        assert_eq!(readlink.latency_buckets.iter().sum::<u64>(), 3);
+        assert_eq!(readlink.errors.len(), 1);
+        assert_eq!(readlink.errors[0].0, ErrorKind::FunctionNotImplemented);
+        assert_eq!(readlink.errors[0].2, 3);
+        assert!(snapshot.operations[1].errors.is_empty());
+
+        let text = PrometheusExporter::new().render(&snapshot);
+        assert!(text.contains("easy_fuser_operations_total{operation=\"readlink\"} 3\n"));
+        assert!(text.contains(&format!(
+            "easy_fuser_operation_errors_total{{operation=\"readlink\",kind=\"FunctionNotImplemented\",code=\"{}\"}} 3\n",
+            libc::ENOSYS
+        )));
+        assert!(text.contains(
+            "easy_fuser_operation_duration_seconds_bucket{operation=\"statfs\",le=\"+Inf\"} 1\n"
+        ));
+        assert!(text.contains("easy_fuser_read_bytes_total 0\n"));
+    }
+}
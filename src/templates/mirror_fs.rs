@@ -15,6 +15,7 @@ The `MirrorFs` struct implements the `FuseHandler` trait, providing a way to cre
 - Both variants use a `PathBuf` to represent the repository path they're mirroring.
 - They wrap another `FuseHandler<PathBuf>` implementation, allowing for composition of filesystem behaviors.
 - Most FUSE operations are implemented by translating paths and delegating to the `unix_fs` module.
+- Directories are streamed from the source with `readdir_iter` and `readdirplus_iter`, so that large directories are never listed at once.
 - The implementation uses macros to define common methods for both read-only and read-write variants.
 
 ## Usage
@@ -33,14 +34,24 @@ To use these handlers:
 
 3. Alternatively, you can use MirrorFs or MirrorFsReadOnly as delegators in your own FUSE implementation (see FuseHandler documentation for more details).
 
+## Ioctl and bmap
+`ioctl` requests are forwarded to the source files for an allowlist of commands, which can be changed with
+`with_forwarded_ioctls`. On Linux, it defaults to [`DEFAULT_FORWARDED_IOCTLS`] for `MirrorFs`, allowing tools such as
+`lsattr` and `chattr` to work through the mirror, and to [`READ_ONLY_FORWARDED_IOCTLS`] for `MirrorFsReadOnly`.
+Other commands are delegated to the inner handler.
+
+Only commands whose argument is a buffer of the size encoded in the command can be forwarded, as the kernel sends
+the content of this buffer. Clone and deduplication requests (`FICLONE`, `FICLONERANGE`, `FIDEDUPERANGE`) never
+reach the filesystem: the kernel handles them itself, and their argument is a file descriptor of the calling process.
+
+`bmap` maps the blocks of the source file with the `FIBMAP` ioctl (Linux only, requires `CAP_SYS_RAWIO`).
+The kernel only sends it for filesystems mounted with the `blkdev` option.
+
 ## Unimplemented Functions
 The following FUSE operations are not implemented in either variant:
 
-- link
-- setlk
+- setlk (except `flock` requests, see `FuseHandler::remote_locks`, which are forwarded to the source file)
 - getlk
-- bmap
-- ioctl
 
 
 ## Important Note
@@ -79,6 +90,37 @@ use crate::prelude::*;
 use crate::templates::*;
 use crate::unix_fs;
 
+/// Ioctl commands forwarded to the source files by `MirrorFs` by default
+#[cfg(target_os = "linux")]
+pub const DEFAULT_FORWARDED_IOCTLS: &[u32] = &[
+    libc::FS_IOC_GETFLAGS as u32,
+    libc::FS_IOC_SETFLAGS as u32,
+    libc::FS_IOC_GETVERSION as u32,
+    libc::FIONREAD as u32,
+];
+/// Ioctl commands forwarded to the source files by `MirrorFs` by default
+#[cfg(not(target_os = "linux"))]
+pub const DEFAULT_FORWARDED_IOCTLS: &[u32] = &[];
+
+/// Ioctl commands forwarded to the source files by `MirrorFsReadOnly` by default
+#[cfg(target_os = "linux")]
+pub const READ_ONLY_FORWARDED_IOCTLS: &[u32] = &[
+    libc::FS_IOC_GETFLAGS as u32,
+    libc::FS_IOC_GETVERSION as u32,
+    libc::FIONREAD as u32,
+];
+/// Ioctl commands forwarded to the source files by `MirrorFsReadOnly` by default
+#[cfg(not(target_os = "linux"))]
+pub const READ_ONLY_FORWARDED_IOCTLS: &[u32] = &[];
+
+/// Entries of a source directory preceded by `.` and `..`, read lazily from the source
+fn dir_entries(
+    folder_path: &Path,
+) -> FuseResult<impl Iterator<Item = FuseResult<(OsString, FileKind)>> + Send + 'static> {
+    let dots = [".", ".."].map(|name| Ok((OsString::from(name), FileKind::Directory)));
+    Ok(dots.into_iter().chain(unix_fs::readdir_iter(folder_path)?))
+}
+
 macro_rules! mirror_fs_readonly_methods {
     () => {
         fn access(&self, _req: &RequestInfo, file_id: PathBuf, mask: AccessMask) -> FuseResult<()> {
@@ -86,6 +128,17 @@ macro_rules! mirror_fs_readonly_methods {
             unix_fs::access(&file_path, mask)
         }
 
+        fn bmap(
+            &self,
+            _req: &RequestInfo,
+            file_id: PathBuf,
+            blocksize: u32,
+            idx: u64,
+        ) -> FuseResult<u64> {
+            let file_path = self.source_path.join(file_id);
+            unix_fs::bmap(&file_path, blocksize, idx)
+        }
+
         fn getattr(
             &self,
             _req: &RequestInfo,
@@ -129,6 +182,38 @@ macro_rules! mirror_fs_readonly_methods {
             unix_fs::lookup(&file_path)
         }
 
+        fn ioctl(
+            &self,
+            req: &RequestInfo,
+            file_id: PathBuf,
+            file_handle: BorrowedFileHandle,
+            flags: IOCtlFlags,
+            cmd: u32,
+            in_data: Vec<u8>,
+            out_size: u32,
+        ) -> FuseResult<(i32, Vec<u8>)> {
+            if !self.forwarded_ioctls.contains(&cmd) {
+                return FuseHandler::ioctl(
+                    self.inner.as_ref(),
+                    req,
+                    file_id,
+                    file_handle,
+                    flags,
+                    cmd,
+                    in_data,
+                    out_size,
+                );
+            }
+            if flags.contains(IOCtlFlags::DIR) {
+                // Directories are not opened by the mirror, their file handle is not a file descriptor
+                let file_path = self.source_path.join(file_id);
+                let fd = unix_fs::open(file_path.as_ref(), OpenFlags::READ_ONLY)?;
+                unix_fs::ioctl(fd.as_fd(), cmd, &in_data, out_size)
+            } else {
+                unix_fs::ioctl(file_handle.as_borrowed_fd(), cmd, &in_data, out_size)
+            }
+        }
+
         fn open(
             &self,
             _req: &RequestInfo,
@@ -142,6 +227,29 @@ macro_rules! mirror_fs_readonly_methods {
             Ok((file_handle, FUSEOpenResponseFlags::empty()))
         }
 
+        fn read_buf(
+            &self,
+            req: &RequestInfo,
+            file_id: PathBuf,
+            file_handle: BorrowedFileHandle,
+            seek: SeekFrom,
+            size: u32,
+            flags: FUSEOpenFlags,
+            lock_owner: Option<u64>,
+        ) -> FuseResult<ReadData> {
+            // Served by FdHandlerHelper from pooled buffers
+            FuseHandler::read_buf(
+                self.inner.as_ref(),
+                req,
+                file_id,
+                file_handle,
+                seek,
+                size,
+                flags,
+                lock_owner,
+            )
+        }
+
         fn readdir(
             &self,
             _req: &RequestInfo,
@@ -159,6 +267,33 @@ macro_rules! mirror_fs_readonly_methods {
             Ok(result)
         }
 
+        fn readdir_iter(
+            &self,
+            _req: &RequestInfo,
+            file_id: PathBuf,
+            _file_handle: BorrowedFileHandle,
+            offset: i64,
+        ) -> FuseResult<ReaddirIter<FileKind>> {
+            let entries = dir_entries(&self.source_path.join(file_id))?;
+            Ok(Box::new(entries.skip(offset as usize)))
+        }
+
+        fn readdirplus_iter(
+            &self,
+            _req: &RequestInfo,
+            file_id: PathBuf,
+            _file_handle: BorrowedFileHandle,
+            offset: i64,
+        ) -> FuseResult<ReaddirIter<FileAttribute>> {
+            let folder_path = self.source_path.join(file_id);
+            let entries = dir_entries(&folder_path)?;
+            Ok(Box::new(entries.skip(offset as usize).map(move |entry| {
+                let (name, _) = entry?;
+                let file_attr = unix_fs::lookup(&folder_path.join(&name))?;
+                Ok((name, file_attr))
+            })))
+        }
+
         fn readlink(&self, _req: &RequestInfo, file_id: PathBuf) -> FuseResult<Vec<u8>> {
             let file_path = self.source_path.join(file_id);
             unix_fs::readlink(&file_path)
@@ -189,6 +324,18 @@ macro_rules! mirror_fs_readwrite_methods {
             Ok((file_handle, file_attr, FUSEOpenResponseFlags::empty()))
         }
 
+        fn link(
+            &self,
+            _req: &RequestInfo,
+            file_id: PathBuf,
+            newparent: PathBuf,
+            newname: &OsStr,
+        ) -> FuseResult<FileAttribute> {
+            let file_path = self.source_path.join(file_id);
+            let link_path = self.source_path.join(newparent).join(newname);
+            unix_fs::link(&file_path, &link_path)
+        }
+
         fn mkdir(
             &self,
             _req: &RequestInfo,
@@ -281,6 +428,31 @@ macro_rules! mirror_fs_readwrite_methods {
             let file_path = self.source_path.join(parent_id).join(name);
             unix_fs::unlink(&file_path)
         }
+
+        fn write_buf(
+            &self,
+            req: &RequestInfo,
+            file_id: PathBuf,
+            file_handle: BorrowedFileHandle,
+            seek: SeekFrom,
+            data: WriteData,
+            write_flags: FUSEWriteFlags,
+            flags: OpenFlags,
+            lock_owner: Option<u64>,
+        ) -> FuseResult<u32> {
+            // Served by FdHandlerHelper without copying the payload
+            FuseHandler::write_buf(
+                self.inner.as_ref(),
+                req,
+                file_id,
+                file_handle,
+                seek,
+                data,
+                write_flags,
+                flags,
+                lock_owner,
+            )
+        }
     };
 }
 
@@ -293,13 +465,23 @@ pub trait MirrorFsTrait: FuseHandler<PathBuf> {
 /// Specific documentation is located in parent module documentation.
 pub struct MirrorFs {
     source_path: PathBuf,
+    forwarded_ioctls: Vec<u32>,
     inner: Box<FdHandlerHelper<PathBuf>>,
 }
 
+impl MirrorFs {
+    /// Replaces the ioctl commands forwarded to the source files, see the module documentation
+    pub fn with_forwarded_ioctls(mut self, commands: impl IntoIterator<Item = u32>) -> Self {
+        self.forwarded_ioctls = commands.into_iter().collect();
+        self
+    }
+}
+
 impl MirrorFsTrait for MirrorFs {
     fn new<U: FuseHandler<PathBuf>>(source_path: PathBuf, inner: U) -> Self {
         Self {
             source_path,
+            forwarded_ioctls: DEFAULT_FORWARDED_IOCTLS.to_vec(),
             inner: Box::new(FdHandlerHelper::new(inner)),
         }
     }
@@ -321,13 +503,23 @@ impl FuseHandler<PathBuf> for MirrorFs {
 /// Specific documentation is located in parent module documentation.
 pub struct MirrorFsReadOnly {
     source_path: PathBuf,
+    forwarded_ioctls: Vec<u32>,
     inner: Box<FdHandlerHelperReadOnly<PathBuf>>,
 }
 
+impl MirrorFsReadOnly {
+    /// Replaces the ioctl commands forwarded to the source files, see the module documentation
+    pub fn with_forwarded_ioctls(mut self, commands: impl IntoIterator<Item = u32>) -> Self {
+        self.forwarded_ioctls = commands.into_iter().collect();
+        self
+    }
+}
+
 impl MirrorFsTrait for MirrorFsReadOnly {
     fn new<THandler: FuseHandler<PathBuf>>(source_path: PathBuf, inner: THandler) -> Self {
         Self {
             source_path,
+            forwarded_ioctls: READ_ONLY_FORWARDED_IOCTLS.to_vec(),
             inner: Box::new(FdHandlerHelperReadOnly::new(inner)),
         }
     }
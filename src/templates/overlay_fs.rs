@@ -0,0 +1,700 @@
+/*!
+# OverlayFs
+
+A FUSE (Filesystem in Userspace) handler merging a read-only lower directory with a writable upper directory, with copy-on-write semantics.
+
+## Overview
+
+The `OverlayFs` struct implements the `FuseHandler<PathBuf>` trait, presenting the content of both directories as a single tree:
+
+- Entries of the upper directory shadow the entries of the lower directory with the same path.
+- Directories present in both are merged.
+- The lower directory is never modified: every modification is done in the upper directory.
+
+This makes it possible to give a disposable writable view of a shared dataset: discarding the upper directory reverts every change.
+
+## Implementation Details
+
+- **Copy up**: A file of the lower directory is copied into the upper directory (along with its parent directories) the
+  first time it is modified, either by being opened for writing, or by a change of its attributes or extended attributes.
+- **Whiteouts**: Removing an entry which exists in the lower directory creates a whiteout in the upper directory, named after
+  the removed entry with a `.wh.` prefix (eg: `.wh.file.txt`), which hides the lower entry.
+- **Opaque directories**: A directory of the upper directory containing a `.wh..wh..opq` file is opaque, the content of the
+  lower directory with the same path is hidden. Directories created in place of a removed one are made opaque.
+
+Whiteouts and opaque markers are regular files using the naming conventions of aufs, so they don't require any privilege.
+Names starting with `.wh.` are reserved and can't be used inside the filesystem.
+
+Read and write operations on open files are handled through `FdHandlerHelper`.
+
+## Usage
+
+```text
+let fs = OverlayFs::new(PathBuf::from("/data/shared"), PathBuf::from("/tmp/job-42"), DefaultFuseHandler::new());
+mount(fs, "/mnt/dataset", &[], 4)?;
+```
+
+## Limitations
+
+- Renaming a directory which exists in the lower directory fails with `InvalidCrossDeviceLink` (EXDEV), as in the overlayfs
+  of the Linux kernel. Tools like `mv` then fall back to a copy.
+- `RenameFlags::EXCHANGE` is not supported.
+- Extended attributes, ownership, sockets and devices are not copied up.
+- A file opened for reading before being copied up keeps reading the content of the lower directory.
+- The lower and upper directories should not be modified while mounted.
+- link is not implemented.
+*/
+
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use fd_handler_helper::*;
+
+use crate::prelude::*;
+use crate::templates::*;
+use crate::unix_fs;
+
+const WHITEOUT_PREFIX: &[u8] = b".wh.";
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+fn is_reserved(name: &OsStr) -> bool {
+    name.as_bytes().starts_with(WHITEOUT_PREFIX)
+}
+
+fn whiteout_name(name: &OsStr) -> OsString {
+    let mut whiteout = OsString::from(OsStr::from_bytes(WHITEOUT_PREFIX));
+    whiteout.push(name);
+    whiteout
+}
+
+fn check_name(name: &OsStr) -> FuseResult<()> {
+    if is_reserved(name) {
+        return Err(ErrorKind::InvalidArgument
+            .to_error(format!("{:?}: names starting with .wh. are reserved", name)));
+    }
+    Ok(())
+}
+
+/// Specific documentation is located in parent module documentation.
+pub struct OverlayFs {
+    lower_path: PathBuf,
+    upper_path: PathBuf,
+    inner: Box<FdHandlerHelper<PathBuf>>,
+}
+
+impl OverlayFs {
+    pub fn new<THandler: FuseHandler<PathBuf>>(
+        lower_path: PathBuf,
+        upper_path: PathBuf,
+        inner: THandler,
+    ) -> Self {
+        Self {
+            lower_path,
+            upper_path,
+            inner: Box::new(FdHandlerHelper::new(inner)),
+        }
+    }
+
+    pub fn lower_dir(&self) -> &Path {
+        self.lower_path.as_path()
+    }
+
+    pub fn upper_dir(&self) -> &Path {
+        self.upper_path.as_path()
+    }
+
+    /// Checks that no whiteout or opaque directory of the upper directory hides the lower entry of `file_id`
+    fn is_lower_visible(&self, file_id: &Path) -> bool {
+        let mut upper_dir = self.upper_path.clone();
+        for component in file_id.iter() {
+            if is_reserved(component)
+                || exists(&upper_dir.join(OPAQUE_MARKER))
+                || exists(&upper_dir.join(whiteout_name(component)))
+            {
+                return false;
+            }
+            upper_dir.push(component);
+        }
+        true
+    }
+
+    /// Returns the path of `file_id` in the lower directory, if it exists and is not hidden
+    fn lower_entry(&self, file_id: &Path) -> Option<PathBuf> {
+        let lower_path = self.lower_path.join(file_id);
+        (exists(&lower_path) && self.is_lower_visible(file_id)).then_some(lower_path)
+    }
+
+    /// Returns the real path of `file_id`, in the upper directory if it exists there, otherwise in the lower directory
+    fn resolve(&self, file_id: &Path) -> FuseResult<PathBuf> {
+        let upper_path = self.upper_path.join(file_id);
+        if exists(&upper_path) {
+            return Ok(upper_path);
+        }
+        self.lower_entry(file_id).ok_or_else(|| {
+            ErrorKind::FileNotFound.to_error(format!("{}: not found in overlay", file_id.display()))
+        })
+    }
+
+    /// Copies `file_id` and its parent directories from the lower directory into the upper directory if needed,
+    /// and returns its path in the upper directory
+    fn copy_up(&self, file_id: &Path) -> FuseResult<PathBuf> {
+        let upper_path = self.upper_path.join(file_id);
+        if exists(&upper_path) {
+            return Ok(upper_path);
+        }
+        if let Some(parent) = file_id.parent() {
+            self.copy_up(parent)?;
+        }
+        let lower_path = self.lower_entry(file_id).ok_or_else(|| {
+            ErrorKind::FileNotFound.to_error(format!("{}: not found in overlay", file_id.display()))
+        })?;
+        let file_attr = unix_fs::lookup(&lower_path)?;
+        match file_attr.kind {
+            FileKind::Directory => {
+                unix_fs::mkdir(&upper_path, file_attr.perm.into(), 0)?;
+            }
+            FileKind::Symlink => {
+                let target = unix_fs::readlink(&lower_path)?;
+                unix_fs::symlink(&upper_path, Path::new(OsStr::from_bytes(&target)))?;
+                return Ok(upper_path);
+            }
+            FileKind::RegularFile => {
+                fs::copy(&lower_path, &upper_path)?;
+            }
+            FileKind::NamedPipe => {
+                unix_fs::mknod(
+                    &upper_path,
+                    libc::S_IFIFO | u32::from(file_attr.perm),
+                    0,
+                    DeviceType::NamedPipe,
+                )?;
+            }
+            kind => {
+                return Err(ErrorKind::NotSupported.to_error(format!(
+                    "{}: copy up of {:?} is not supported",
+                    file_id.display(),
+                    kind
+                )))
+            }
+        }
+        unix_fs::setattr(
+            &upper_path,
+            SetAttrRequest::new()
+                .atime(TimeOrNow::SpecificTime(file_attr.atime))
+                .mtime(TimeOrNow::SpecificTime(file_attr.mtime)),
+        )?;
+        Ok(upper_path)
+    }
+
+    /// Creates a whiteout hiding the lower entry `name` of `parent_id`
+    fn create_whiteout(&self, parent_id: &Path, name: &OsStr) -> FuseResult<()> {
+        let upper_parent = self.copy_up(parent_id)?;
+        File::create(upper_parent.join(whiteout_name(name)))?;
+        Ok(())
+    }
+
+    /// Removes the whiteout of `name` in `upper_parent`, returns whether it existed
+    fn remove_whiteout(&self, upper_parent: &Path, name: &OsStr) -> bool {
+        fs::remove_file(upper_parent.join(whiteout_name(name))).is_ok()
+    }
+
+    /// Prepares the creation of the entry `name` in `parent_id`, returns its path in the upper directory
+    /// and whether it replaces a removed entry
+    fn prepare_new_entry(&self, parent_id: &Path, name: &OsStr) -> FuseResult<(PathBuf, bool)> {
+        check_name(name)?;
+        let upper_parent = self.copy_up(parent_id)?;
+        let replaces_removed = self.remove_whiteout(&upper_parent, name);
+        Ok((upper_parent.join(name), replaces_removed))
+    }
+
+    /// Lists the merged content of the directory `file_id`
+    fn merged_entries(&self, file_id: &Path) -> FuseResult<Vec<(OsString, FileKind)>> {
+        let upper_dir = self.upper_path.join(file_id);
+        let mut result = Vec::new();
+        let mut names = HashSet::new();
+        let mut opaque = false;
+        if exists(&upper_dir) {
+            for (name, kind) in unix_fs::readdir(&upper_dir)? {
+                if name == OPAQUE_MARKER {
+                    opaque = true;
+                } else if let Some(hidden) = name.as_bytes().strip_prefix(WHITEOUT_PREFIX) {
+                    names.insert(OsStr::from_bytes(hidden).to_os_string());
+                } else {
+                    names.insert(name.clone());
+                    result.push((name, kind));
+                }
+            }
+        }
+        if !opaque {
+            if let Some(lower_dir) = self.lower_entry(file_id) {
+                for (name, kind) in unix_fs::readdir(&lower_dir)? {
+                    if !names.contains(&name) {
+                        result.push((name, kind));
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl FuseHandler<PathBuf> for OverlayFs {
+    fn get_inner(&self) -> &dyn FuseHandler<PathBuf> {
+        self.inner.as_ref()
+    }
+
+    fn access(&self, _req: &RequestInfo, file_id: PathBuf, mask: AccessMask) -> FuseResult<()> {
+        unix_fs::access(&self.resolve(&file_id)?, mask)
+    }
+
+    fn create(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FileAttribute, FUSEOpenResponseFlags)> {
+        let (file_path, _) = self.prepare_new_entry(&parent_id, name)?;
+        let (fd, file_attr) = unix_fs::create(&file_path, mode, umask, flags)?;
+        // Open by definition returns positive Fd or error
+        let file_handle = OwnedFileHandle::from_owned_fd(fd).unwrap();
+        Ok((file_handle, file_attr, FUSEOpenResponseFlags::empty()))
+    }
+
+    fn getattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        _file_handle: Option<BorrowedFileHandle>,
+    ) -> FuseResult<FileAttribute> {
+        unix_fs::lookup(&self.resolve(&file_id)?)
+    }
+
+    fn getxattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        name: &OsStr,
+        size: u32,
+    ) -> FuseResult<Vec<u8>> {
+        unix_fs::getxattr(&self.resolve(&file_id)?, name, size)
+    }
+
+    fn listxattr(&self, _req: &RequestInfo, file_id: PathBuf, size: u32) -> FuseResult<Vec<u8>> {
+        unix_fs::listxattr(&self.resolve(&file_id)?, size)
+    }
+
+    fn lookup(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+    ) -> FuseResult<FileAttribute> {
+        if is_reserved(name) {
+            return Err(ErrorKind::FileNotFound.to_error(""));
+        }
+        unix_fs::lookup(&self.resolve(&parent_id.join(name))?)
+    }
+
+    fn mkdir(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+    ) -> FuseResult<FileAttribute> {
+        let (file_path, replaces_removed) = self.prepare_new_entry(&parent_id, name)?;
+        let file_attr = unix_fs::mkdir(&file_path, mode, umask)?;
+        if replaces_removed {
+            // The content of the removed directory must not reappear
+            File::create(file_path.join(OPAQUE_MARKER))?;
+        }
+        Ok(file_attr)
+    }
+
+    fn mknod(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: DeviceType,
+    ) -> FuseResult<FileAttribute> {
+        let (file_path, _) = self.prepare_new_entry(&parent_id, name)?;
+        unix_fs::mknod(&file_path, mode, umask, rdev)
+    }
+
+    fn open(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        let writable =
+            flags.intersects(OpenFlags::WRITE_ONLY | OpenFlags::READ_WRITE | OpenFlags::TRUNCATE);
+        let file_path = if writable {
+            self.copy_up(&file_id)?
+        } else {
+            self.resolve(&file_id)?
+        };
+        let fd = unix_fs::open(&file_path, flags)?;
+        // Open by definition returns positive Fd or error
+        let file_handle = OwnedFileHandle::from_owned_fd(fd).unwrap();
+        Ok((file_handle, FUSEOpenResponseFlags::empty()))
+    }
+
+    fn read_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: PathBuf,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<ReadData> {
+        // Served by FdHandlerHelper from pooled buffers
+        FuseHandler::read_buf(
+            self.inner.as_ref(),
+            req,
+            file_id,
+            file_handle,
+            seek,
+            size,
+            flags,
+            lock_owner,
+        )
+    }
+
+    fn write_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: PathBuf,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        data: WriteData,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        // Served by FdHandlerHelper without copying the payload
+        FuseHandler::write_buf(
+            self.inner.as_ref(),
+            req,
+            file_id,
+            file_handle,
+            seek,
+            data,
+            write_flags,
+            flags,
+            lock_owner,
+        )
+    }
+
+    fn readdir(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        _file_handle: BorrowedFileHandle,
+    ) -> FuseResult<Vec<(OsString, FileKind)>> {
+        let mut result = vec![
+            (OsString::from("."), FileKind::Directory),
+            (OsString::from(".."), FileKind::Directory),
+        ];
+        result.extend(self.merged_entries(&file_id)?);
+        Ok(result)
+    }
+
+    fn readdir_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: PathBuf,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<FileKind>> {
+        // The entries are merged, they cannot be streamed from the inner handler
+        let entries = FuseHandler::readdir(self, req, file_id, file_handle)?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
+    fn readdirplus_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: PathBuf,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<FileAttribute>> {
+        let entries = FuseHandler::readdirplus(self, req, file_id, file_handle)?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
+    fn readlink(&self, _req: &RequestInfo, file_id: PathBuf) -> FuseResult<Vec<u8>> {
+        unix_fs::readlink(&self.resolve(&file_id)?)
+    }
+
+    fn removexattr(&self, _req: &RequestInfo, file_id: PathBuf, name: &OsStr) -> FuseResult<()> {
+        unix_fs::removexattr(&self.copy_up(&file_id)?, name)
+    }
+
+    fn rename(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+        newparent: PathBuf,
+        newname: &OsStr,
+        flags: RenameFlags,
+    ) -> FuseResult<()> {
+        #[cfg(target_os = "linux")]
+        if flags.contains(RenameFlags::EXCHANGE) {
+            return Err(ErrorKind::InvalidArgument
+                .to_error("RENAME_EXCHANGE is not supported by OverlayFs"));
+        }
+        check_name(newname)?;
+        let file_id = parent_id.join(name);
+        let new_file_id = newparent.join(newname);
+        let file_attr = unix_fs::lookup(&self.resolve(&file_id)?)?;
+        let in_lower = self.lower_entry(&file_id).is_some();
+        if file_attr.kind == FileKind::Directory && in_lower {
+            return Err(ErrorKind::InvalidCrossDeviceLink.to_error(format!(
+                "{}: renaming a directory of the lower layer is not supported",
+                file_id.display()
+            )));
+        }
+        #[cfg(target_os = "linux")]
+        if flags.contains(RenameFlags::NOREPLACE) && self.resolve(&new_file_id).is_ok() {
+            return Err(ErrorKind::FileExists.to_error(new_file_id.display()));
+        }
+        let new_in_lower = self.lower_entry(&new_file_id).is_some();
+
+        let upper_path = self.copy_up(&file_id)?;
+        let (new_upper_path, _) = self.prepare_new_entry(&newparent, newname)?;
+        unix_fs::rename(&upper_path, &new_upper_path, flags)?;
+        if file_attr.kind == FileKind::Directory && new_in_lower {
+            // The content of the replaced directory must not reappear
+            File::create(new_upper_path.join(OPAQUE_MARKER))?;
+        }
+        if in_lower {
+            self.create_whiteout(&parent_id, name)?;
+        }
+        Ok(())
+    }
+
+    fn rmdir(&self, _req: &RequestInfo, parent_id: PathBuf, name: &OsStr) -> FuseResult<()> {
+        let file_id = parent_id.join(name);
+        self.resolve(&file_id)?;
+        if !self.merged_entries(&file_id)?.is_empty() {
+            return Err(ErrorKind::DirectoryNotEmpty.to_error(file_id.display()));
+        }
+        let upper_path = self.upper_path.join(&file_id);
+        if exists(&upper_path) {
+            // Only whiteouts and the opaque marker remain
+            for (child, _) in unix_fs::readdir(&upper_path)? {
+                fs::remove_file(upper_path.join(child))?;
+            }
+            unix_fs::rmdir(&upper_path)?;
+        }
+        if self.lower_entry(&file_id).is_some() {
+            self.create_whiteout(&parent_id, name)?;
+        }
+        Ok(())
+    }
+
+    fn setattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        attrs: SetAttrRequest,
+    ) -> FuseResult<FileAttribute> {
+        unix_fs::setattr(&self.copy_up(&file_id)?, attrs)
+    }
+
+    fn setxattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: FUSESetXAttrFlags,
+        position: u32,
+    ) -> FuseResult<()> {
+        unix_fs::setxattr(&self.copy_up(&file_id)?, name, &value, flags, position)
+    }
+
+    fn statfs(&self, _req: &RequestInfo, _file_id: PathBuf) -> FuseResult<StatFs> {
+        unix_fs::statfs(&self.upper_path)
+    }
+
+    fn symlink(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        link_name: &OsStr,
+        target: &Path,
+    ) -> FuseResult<FileAttribute> {
+        let (file_path, _) = self.prepare_new_entry(&parent_id, link_name)?;
+        unix_fs::symlink(&file_path, target)
+    }
+
+    fn unlink(&self, _req: &RequestInfo, parent_id: PathBuf, name: &OsStr) -> FuseResult<()> {
+        let file_id = parent_id.join(name);
+        self.resolve(&file_id)?;
+        let upper_path = self.upper_path.join(&file_id);
+        if exists(&upper_path) {
+            unix_fs::unlink(&upper_path)?;
+        }
+        if self.lower_entry(&file_id).is_some() {
+            self.create_whiteout(&parent_id, name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    struct Overlay {
+        lower: TempDir,
+        upper: TempDir,
+        fs: OverlayFs,
+    }
+
+    impl Overlay {
+        fn handler(&self) -> &dyn FuseHandler<PathBuf> {
+            &self.fs
+        }
+    }
+
+    fn setup() -> Overlay {
+        let lower = TempDir::new().unwrap();
+        let upper = TempDir::new().unwrap();
+        fs::create_dir(lower.path().join("dir")).unwrap();
+        fs::write(lower.path().join("dir/file.txt"), "lower").unwrap();
+        fs::write(lower.path().join("root.txt"), "root").unwrap();
+        let fs = OverlayFs::new(
+            lower.path().to_path_buf(),
+            upper.path().to_path_buf(),
+            DefaultFuseHandler::new(),
+        );
+        Overlay { lower, upper, fs }
+    }
+
+    fn req() -> RequestInfo {
+        RequestInfo {
+            id: 0,
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        }
+    }
+
+    fn names(overlay: &Overlay, dir: &str) -> Vec<String> {
+        let mut names: Vec<_> = overlay
+            .fs
+            .merged_entries(Path::new(dir))
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name.into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_copy_up_on_write() {
+        let overlay = setup();
+        let (handle, _) = overlay
+            .handler()
+            .open(&req(), PathBuf::from("dir/file.txt"), OpenFlags::READ_WRITE)
+            .unwrap();
+        unix_fs::write(handle.borrow_as_fd(), SeekFrom::Start(0), b"upper").unwrap();
+        unix_fs::release(handle.into_owned_fd()).unwrap();
+
+        let upper_file = overlay.upper.path().join("dir/file.txt");
+        assert_eq!(fs::read_to_string(upper_file).unwrap(), "upper");
+        let lower_file = overlay.lower.path().join("dir/file.txt");
+        assert_eq!(fs::read_to_string(lower_file).unwrap(), "lower");
+        assert_eq!(names(&overlay, "dir"), vec!["file.txt"]);
+    }
+
+    #[test]
+    fn test_whiteout_and_opaque_dir() {
+        let overlay = setup();
+        overlay
+            .handler()
+            .unlink(&req(), PathBuf::from("dir"), OsStr::new("file.txt"))
+            .unwrap();
+        assert!(overlay.lower.path().join("dir/file.txt").exists());
+        assert!(overlay
+            .handler()
+            .lookup(&req(), PathBuf::from("dir"), OsStr::new("file.txt"))
+            .is_err());
+        assert!(names(&overlay, "dir").is_empty());
+
+        overlay
+            .handler()
+            .rmdir(&req(), PathBuf::new(), OsStr::new("dir"))
+            .unwrap();
+        assert_eq!(names(&overlay, ""), vec!["root.txt"]);
+
+        // A directory created in place of the removed one doesn't show the lower content
+        overlay
+            .handler()
+            .mkdir(&req(), PathBuf::new(), OsStr::new("dir"), 0o755, 0)
+            .unwrap();
+        assert_eq!(names(&overlay, ""), vec!["dir", "root.txt"]);
+        assert!(names(&overlay, "dir").is_empty());
+        assert!(overlay
+            .handler()
+            .lookup(&req(), PathBuf::from("dir"), OsStr::new("file.txt"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_rename_lower_file() {
+        let overlay = setup();
+        overlay
+            .handler()
+            .rename(
+                &req(),
+                PathBuf::new(),
+                OsStr::new("root.txt"),
+                PathBuf::from("dir"),
+                OsStr::new("moved.txt"),
+                RenameFlags::empty(),
+            )
+            .unwrap();
+        assert_eq!(names(&overlay, ""), vec!["dir"]);
+        assert_eq!(names(&overlay, "dir"), vec!["file.txt", "moved.txt"]);
+        assert_eq!(
+            fs::read_to_string(overlay.upper.path().join("dir/moved.txt")).unwrap(),
+            "root"
+        );
+
+        let result = overlay.handler().rename(
+            &req(),
+            PathBuf::new(),
+            OsStr::new("dir"),
+            PathBuf::new(),
+            OsStr::new("dir2"),
+            RenameFlags::empty(),
+        );
+        assert_eq!(
+            result.unwrap_err().kind(),
+            ErrorKind::InvalidCrossDeviceLink
+        );
+    }
+}
@@ -0,0 +1,399 @@
+/*!
+# Tracer
+
+A middleware emitting one record per operation of the handler it wraps, to help debugging a filesystem.
+
+## Overview
+
+The `Tracer` implements the `Middleware` trait, and can therefore wrap any `FuseHandler<TId>` through
+`FuseHandler::layer`. For each operation, a `TraceRecord` is built, holding:
+
+- the operation name
+- the `RequestInfo` (request id, uid, gid, pid)
+- the file id targeted by the operation (the parent for operations on directory entries)
+- the other arguments of the operation
+- the latency of the wrapped handler (including the inner layers)
+- the result of the operation, along with the number of bytes read or written
+
+## Output formats
+
+- `TraceOutput::Log`: Emits the records through the `log` crate with the given level, in the strace-like format.
+- `TraceOutput::JsonLines`: Writes one JSON object per line to the given writer.
+- `TraceOutput::Strace`: Writes one human readable line per record to the given writer, eg:
+
+```text
+[pid 4242] lookup(dir, name="file.txt") = 0 <0.000051>
+[pid 4242] read(dir/file.txt, file_handle=3, seek=Start(0), size=4096, flags=FUSEOpenFlags(0x0), lock_owner=None) = 512 <0.000023>
+[pid 4242] lookup(dir, name="missing") = -2 FileNotFound <0.000012>
+```
+
+## Usage
+
+```text
+let fs = MirrorFs::new(source_path, DefaultFuseHandler::new())
+    .layer(Tracer::strace(std::io::stderr()));
+```
+
+Note: Writing to the output is done synchronously and behind a lock, which serializes the operations.
+Errors while writing the records are ignored.
+*/
+
+use std::fmt::{self, Display, Write as _};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::Level;
+
+use crate::prelude::*;
+
+/// Destination and format of the records emitted by a [`Tracer`]
+pub enum TraceOutput {
+    /// Emits the records through the `log` crate, in the strace-like format
+    Log(Level),
+    /// Writes one JSON object per line
+    JsonLines(Mutex<Box<dyn Write + Send>>),
+    /// Writes one human readable line per record, in a format inspired by strace
+    Strace(Mutex<Box<dyn Write + Send>>),
+}
+
+/// Record of a single operation
+#[derive(Debug, Clone)]
+pub struct TraceRecord {
+    pub operation: &'static str,
+    /// Missing for `destroy`
+    pub request: Option<RequestInfo>,
+    /// Display of the file id targeted by the operation
+    pub file_id: Option<String>,
+    /// Arguments of the operation, in their debug representation
+    pub args: Vec<(&'static str, String)>,
+    pub latency: Duration,
+    pub result: Result<(), PosixError>,
+    /// Number of bytes returned by `read`, `getxattr`, `listxattr` and `readlink`, or written by `write`
+    pub size: Option<usize>,
+}
+
+impl TraceRecord {
+    pub fn new<TId: FileIdType>(op: &Operation<'_, TId>, outcome: &Outcome<'_>) -> Self {
+        let size = match op.method {
+            "write" | "write_buf" => outcome
+                .result::<u32>()
+                .and_then(|result| result.as_ref().ok())
+                .map(|written| *written as usize),
+            "read_buf" => outcome
+                .result::<ReadData>()
+                .and_then(|result| result.as_ref().ok())
+                .map(|data| data.len()),
+            _ => outcome
+                .result::<Vec<u8>>()
+                .and_then(|result| result.as_ref().ok())
+                .map(|data| data.len()),
+        };
+        TraceRecord {
+            operation: op.name,
+            request: op.req.cloned(),
+            file_id: op.file_id.map(|file_id| file_id.display().to_string()),
+            args: op
+                .args
+                .iter()
+                .map(|(name, value)| (*name, format!("{:?}", value)))
+                .collect(),
+            latency: op.elapsed(),
+            result: match outcome.error() {
+                None => Ok(()),
+                Some(e) => Err(e.clone()),
+            },
+            size,
+        }
+    }
+
+    /// Serializes the record as a single line JSON object
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        let _ = write!(json, "\"operation\":{}", json_string(self.operation));
+        if let Some(req) = &self.request {
+            let _ = write!(
+                json,
+                ",\"request\":{{\"id\":{},\"uid\":{},\"gid\":{},\"pid\":{}}}",
+                req.id, req.uid, req.gid, req.pid
+            );
+        }
+        if let Some(file_id) = &self.file_id {
+            let _ = write!(json, ",\"file_id\":{}", json_string(file_id));
+        }
+        json.push_str(",\"args\":{");
+        for (i, (name, value)) in self.args.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{}:{}", json_string(name), json_string(value));
+        }
+        let _ = write!(json, "}},\"latency_us\":{}", self.latency.as_micros());
+        match &self.result {
+            Ok(()) => json.push_str(",\"result\":\"ok\""),
+            Err(e) => {
+                let _ = write!(
+                    json,
+                    ",\"result\":\"error\",\"error\":{{\"kind\":{},\"code\":{},\"message\":{}}}",
+                    json_string(&format!("{:?}", e.kind())),
+                    e.raw_error(),
+                    json_string(&e.msg)
+                );
+            }
+        }
+        if let Some(size) = self.size {
+            let _ = write!(json, ",\"size\":{}", size);
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// Strace-like representation of the record
+impl Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(req) = &self.request {
+            write!(f, "[pid {}] ", req.pid)?;
+        }
+        write!(f, "{}(", self.operation)?;
+        let file_id = self.file_id.iter().cloned();
+        let args = self
+            .args
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value));
+        for (i, arg) in file_id.chain(args).enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ") = ")?;
+        match &self.result {
+            Ok(()) => write!(f, "{}", self.size.unwrap_or(0))?,
+            Err(e) => write!(f, "-{} {:?}", e.raw_error(), e.kind())?,
+        }
+        write!(f, " <{:.6}>", self.latency.as_secs_f64())
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(result, "\\u{:04x}", c as u32);
+            }
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Middleware emitting a [`TraceRecord`] for each operation, see the module documentation
+pub struct Tracer {
+    output: TraceOutput,
+}
+
+impl Tracer {
+    pub fn new(output: TraceOutput) -> Self {
+        Tracer { output }
+    }
+
+    /// Emits the records through the `log` crate with the given level
+    pub fn log(level: Level) -> Self {
+        Self::new(TraceOutput::Log(level))
+    }
+
+    /// Writes the records as JSON lines into `writer`
+    pub fn json_lines<W: Write + Send + 'static>(writer: W) -> Self {
+        Self::new(TraceOutput::JsonLines(Mutex::new(Box::new(writer))))
+    }
+
+    /// Writes the records in a strace-like format into `writer`
+    pub fn strace<W: Write + Send + 'static>(writer: W) -> Self {
+        Self::new(TraceOutput::Strace(Mutex::new(Box::new(writer))))
+    }
+
+    fn emit(&self, record: &TraceRecord) {
+        match &self.output {
+            TraceOutput::Log(level) => log::log!(*level, "{}", record),
+            TraceOutput::JsonLines(writer) => {
+                if let Ok(mut writer) = writer.lock() {
+                    let _ = writeln!(writer, "{}", record.to_json());
+                }
+            }
+            TraceOutput::Strace(writer) => {
+                if let Ok(mut writer) = writer.lock() {
+                    let _ = writeln!(writer, "{}", record);
+                }
+            }
+        }
+    }
+}
+
+impl<TId: FileIdType> Middleware<TId> for Tracer {
+    fn after(&self, op: &Operation<'_, TId>, outcome: &mut Outcome<'_>) {
+        if let TraceOutput::Log(level) = self.output {
+            if !log::log_enabled!(level) {
+                return;
+            }
+        }
+        self.emit(&TraceRecord::new(op, outcome));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+    use std::sync::Arc;
+
+    use crate::templates::memory_fs::MemoryFs;
+    use crate::testing::TestSession;
+
+    /// Writer whose content stays readable once given to a `Tracer`
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedWriter {
+        /// Takes the lines written so far
+        fn take_lines(&self) -> Vec<String> {
+            let content = std::mem::take(&mut *self.0.lock().unwrap());
+            String::from_utf8(content)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    /// Runs a lookup, a failed lookup and a read through `tracer`, returning the lines it wrote into `writer`
+    fn trace_session(tracer: Tracer, writer: &SharedWriter) -> Vec<String> {
+        let session = TestSession::new::<Inode, _>(MemoryFs::new().layer(tracer)).unwrap();
+        session.set_credentials(1000, 1000, 4242);
+        let root = TestSession::ROOT_INO;
+        let (entry, fh, _) = session
+            .create(root, OsStr::new("file"), 0o644, 0, OpenFlags::READ_WRITE)
+            .unwrap();
+        session
+            .write(entry.ino, fh, 0, b"hello", OpenFlags::READ_WRITE)
+            .unwrap();
+        writer.take_lines();
+
+        session.lookup(root, OsStr::new("file")).unwrap();
+        session.lookup(root, OsStr::new("missing")).unwrap_err();
+        session.read(entry.ino, fh, 0, 4096).unwrap();
+        writer.take_lines()
+    }
+
+    fn record(result: Result<(), PosixError>) -> TraceRecord {
+        TraceRecord {
+            operation: "lookup",
+            request: Some(RequestInfo {
+                id: 1,
+                uid: 1000,
+                gid: 1000,
+                pid: 4242,
+            }),
+            file_id: Some("dir".to_string()),
+            args: vec![("name", format!("{:?}", "file\t1"))],
+            latency: Duration::from_micros(51),
+            result,
+            size: None,
+        }
+    }
+
+    #[test]
+    fn test_strace_format() {
+        assert_eq!(
+            record(Ok(())).to_string(),
+            r#"[pid 4242] lookup(dir, name="file\t1") = 0 <0.000051>"#
+        );
+        assert_eq!(
+            record(Err(ErrorKind::FileNotFound.to_error(""))).to_string(),
+            r#"[pid 4242] lookup(dir, name="file\t1") = -2 FileNotFound <0.000051>"#
+        );
+    }
+
+    #[test]
+    fn test_json_format() {
+        assert_eq!(
+            record(Err(ErrorKind::FileNotFound.to_error("missing"))).to_json(),
+            concat!(
+                r#"{"operation":"lookup","request":{"id":1,"uid":1000,"gid":1000,"pid":4242},"#,
+                r#""file_id":"dir","args":{"name":"\"file\\t1\""},"latency_us":51,"#,
+                r#""result":"error","error":{"kind":"FileNotFound","code":2,"message":"missing"}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_traced_session() {
+        // Latencies vary between runs
+        let writer = SharedWriter::default();
+        let lines: Vec<_> = trace_session(Tracer::strace(writer.clone()), &writer)
+            .iter()
+            .map(|line| line.rsplit_once(" <").unwrap().0.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                r#"[pid 4242] lookup(Inode(1), name="file") = 0"#,
+                r#"[pid 4242] lookup(Inode(1), name="missing") = -2 FileNotFound"#,
+                concat!(
+                    r#"[pid 4242] read(Inode(2), file_handle=1, seek=Start(0), size=4096, "#,
+                    r#"flags=FUSEOpenFlags(0x0), lock_owner=None) = 5"#
+                ),
+            ]
+        );
+
+        let writer = SharedWriter::default();
+        let lines: Vec<_> = trace_session(Tracer::json_lines(writer.clone()), &writer)
+            .iter()
+            .map(|line| {
+                let (start, end) = line.split_once(r#""latency_us":"#).unwrap();
+                let end = end.trim_start_matches(|c: char| c.is_ascii_digit());
+                format!(r#"{}"latency_us":0{}"#, start, end)
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                concat!(
+                    r#"{"operation":"lookup","request":{"id":4,"uid":1000,"gid":1000,"pid":4242},"#,
+                    r#""file_id":"Inode(1)","args":{"name":"\"file\""},"latency_us":0,"result":"ok"}"#
+                ),
+                concat!(
+                    r#"{"operation":"lookup","request":{"id":5,"uid":1000,"gid":1000,"pid":4242},"#,
+                    r#""file_id":"Inode(1)","args":{"name":"\"missing\""},"latency_us":0,"#,
+                    r#""result":"error","error":{"kind":"FileNotFound","code":2,"#,
+                    r#""message":"\"missing\" in Inode(1)"}}"#
+                ),
+                concat!(
+                    r#"{"operation":"read","request":{"id":6,"uid":1000,"gid":1000,"pid":4242},"#,
+                    r#""file_id":"Inode(2)","args":{"file_handle":"1","seek":"Start(0)","size":"4096","#,
+                    r#""flags":"FUSEOpenFlags(0x0)","lock_owner":"None"},"latency_us":0,"result":"ok","size":5}"#
+                ),
+            ]
+        );
+    }
+}
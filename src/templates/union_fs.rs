@@ -0,0 +1,791 @@
+/*!
+# UnionFs
+
+A FUSE (Filesystem in Userspace) handler presenting several source directories, called branches, as a single tree, similarly to mergerfs.
+
+## Overview
+
+The `UnionFs` struct implements the `FuseHandler<PathBuf>` trait. Each branch is either read-write or read-only, and branches
+are ordered by priority: the first branch containing a path provides its content, and directories present in several
+branches are merged.
+
+## Implementation Details
+
+- **Lookup**: Read operations (lookup, getattr, open, readlink, xattrs...) use the highest priority branch containing the path.
+- **Directory listing**: `readdir` lists every branch containing the directory, and deduplicates the entries by name,
+  keeping the kind of the entry of the highest priority branch.
+- **Creation**: The branch receiving new entries (create, mknod, mkdir, symlink) is selected by the [`CreatePolicy`]:
+  - `FirstWritable`: the first read-write branch.
+  - `MostFreeSpace`: the read-write branch with the most available space, as reported by `unix_fs::statfs`.
+  - `ExistingPath`: the first read-write branch in which the parent directory already exists.
+
+  With the first two policies, missing parent directories are created in the selected branch, copying the permissions of
+  the directory they mirror.
+- **Modification**: unlink, rmdir, setattr and xattr changes are applied in every branch containing the path, and fail with
+  `ReadOnlyFileSystem` (EROFS) if one of them is read-only. Opening a file for writing fails the same way if the file is
+  provided by a read-only branch.
+- **Rename**: The entry is renamed inside every branch containing it, creating the new parent directory where needed, then
+  entries of other branches which would shadow the renamed entry are removed. As with `rename(2)`, a directory can only
+  replace an empty directory and a file can only replace a file, which is checked in every branch before renaming
+  anything. Renaming fails with `ReadOnlyFileSystem` (EROFS) if the entry or the replaced one is provided by a read-only
+  branch.
+- **Statfs**: The statistics of every branch are summed, counting branches sharing the same device once.
+
+Read and write operations on open files are handled through `FdHandlerHelper`.
+
+## Usage
+
+```text
+let fs = UnionFs::new(
+    vec![Branch::read_write("/mnt/disk1"), Branch::read_write("/mnt/disk2"), Branch::read_only("/mnt/archive")],
+    DefaultFuseHandler::new(),
+)
+.with_create_policy(CreatePolicy::MostFreeSpace);
+mount(fs, "/mnt/storage", &[], 4)?;
+```
+
+## Limitations
+
+- Renames are not atomic across branches.
+- The branches should not be modified while mounted, they are not watched for changes.
+- link is not implemented.
+*/
+
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use fd_handler_helper::*;
+
+use crate::prelude::*;
+use crate::templates::*;
+use crate::unix_fs;
+
+fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+/// A source directory of a `UnionFs`
+#[derive(Debug, Clone)]
+pub struct Branch {
+    path: PathBuf,
+    writable: bool,
+}
+
+impl Branch {
+    pub fn read_write<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            writable: true,
+        }
+    }
+
+    pub fn read_only<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            writable: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+}
+
+/// Selects the branch receiving newly created entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CreatePolicy {
+    /// The first read-write branch
+    #[default]
+    FirstWritable,
+    /// The read-write branch with the most available space
+    MostFreeSpace,
+    /// The first read-write branch in which the parent directory exists
+    ExistingPath,
+}
+
+/// Specific documentation is located in parent module documentation.
+pub struct UnionFs {
+    branches: Vec<Branch>,
+    create_policy: CreatePolicy,
+    inner: Box<FdHandlerHelper<PathBuf>>,
+}
+
+impl UnionFs {
+    /// Creates a union of `branches`, the first branch having the highest priority
+    pub fn new<THandler: FuseHandler<PathBuf>>(branches: Vec<Branch>, inner: THandler) -> Self {
+        Self {
+            branches,
+            create_policy: CreatePolicy::default(),
+            inner: Box::new(FdHandlerHelper::new(inner)),
+        }
+    }
+
+    pub fn with_create_policy(mut self, create_policy: CreatePolicy) -> Self {
+        self.create_policy = create_policy;
+        self
+    }
+
+    pub fn branches(&self) -> &[Branch] {
+        &self.branches
+    }
+
+    pub fn create_policy(&self) -> CreatePolicy {
+        self.create_policy
+    }
+
+    fn not_found(file_id: &Path) -> PosixError {
+        ErrorKind::FileNotFound.to_error(format!("{}: not found in any branch", file_id.display()))
+    }
+
+    fn read_only(file_id: &Path, branch: &Branch) -> PosixError {
+        ErrorKind::ReadOnlyFileSystem.to_error(format!(
+            "{}: provided by the read-only branch {}",
+            file_id.display(),
+            branch.path.display()
+        ))
+    }
+
+    /// Returns the branches containing `file_id`, by priority
+    fn branches_with<'a>(&'a self, file_id: &'a Path) -> impl Iterator<Item = &'a Branch> + 'a {
+        self.branches
+            .iter()
+            .filter(move |branch| exists(&branch.path.join(file_id)))
+    }
+
+    /// Returns the real path of `file_id` in the highest priority branch containing it
+    fn resolve(&self, file_id: &Path) -> FuseResult<PathBuf> {
+        self.branches_with(file_id)
+            .next()
+            .map(|branch| branch.path.join(file_id))
+            .ok_or_else(|| Self::not_found(file_id))
+    }
+
+    /// Returns the real paths of `file_id` in every branch containing it,
+    /// failing if one of these branches is read-only
+    fn resolve_all_writable(&self, file_id: &Path) -> FuseResult<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for branch in self.branches_with(file_id) {
+            if !branch.writable {
+                return Err(Self::read_only(file_id, branch));
+            }
+            paths.push(branch.path.join(file_id));
+        }
+        if paths.is_empty() {
+            return Err(Self::not_found(file_id));
+        }
+        Ok(paths)
+    }
+
+    /// Checks that an entry can replace `new_file_id` in every branch containing it, as `rename(2)` would
+    ///
+    /// The replaced entries are only removed after the entry is renamed, nothing is changed if this check fails.
+    fn check_rename_target(&self, is_dir: bool, new_file_id: &Path) -> FuseResult<()> {
+        for branch in self.branches_with(new_file_id) {
+            if !branch.writable {
+                return Err(Self::read_only(new_file_id, branch));
+            }
+            let target_path = branch.path.join(new_file_id);
+            let target_is_dir = fs::symlink_metadata(&target_path)?.is_dir();
+            if is_dir && !target_is_dir {
+                return Err(ErrorKind::NotADirectory.to_error(new_file_id.display()));
+            }
+            if !is_dir && target_is_dir {
+                return Err(ErrorKind::IsADirectory.to_error(new_file_id.display()));
+            }
+            if target_is_dir && fs::read_dir(&target_path)?.next().is_some() {
+                return Err(ErrorKind::DirectoryNotEmpty.to_error(new_file_id.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the missing directories of `dir_id` in `branch`, copying the permissions of the directories they mirror
+    fn clone_path(&self, branch: &Branch, dir_id: &Path) -> FuseResult<()> {
+        let target = branch.path.join(dir_id);
+        if exists(&target) {
+            return Ok(());
+        }
+        if let Some(parent) = dir_id.parent() {
+            self.clone_path(branch, parent)?;
+        }
+        let file_attr = unix_fs::lookup(&self.resolve(dir_id)?)?;
+        if file_attr.kind != FileKind::Directory {
+            return Err(ErrorKind::NotADirectory.to_error(dir_id.display()));
+        }
+        unix_fs::mkdir(&target, file_attr.perm.into(), 0)?;
+        Ok(())
+    }
+
+    fn available_space(branch: &Branch) -> u64 {
+        unix_fs::statfs(&branch.path)
+            .map(|stat| stat.available_blocks.saturating_mul(stat.block_size.into()))
+            .unwrap_or(0)
+    }
+
+    /// Selects the branch receiving a new entry in `parent_id`, according to the create policy,
+    /// and returns the path of the new entry in this branch
+    fn create_path(&self, parent_id: &Path, name: &OsStr) -> FuseResult<PathBuf> {
+        if self.branches_with(&parent_id.join(name)).next().is_some() {
+            return Err(ErrorKind::FileExists.to_error(parent_id.join(name).display()));
+        }
+        let mut writable = self.branches.iter().filter(|branch| branch.writable);
+        let branch = match self.create_policy {
+            CreatePolicy::FirstWritable => writable.next(),
+            CreatePolicy::MostFreeSpace => {
+                let mut best: Option<(&Branch, u64)> = None;
+                for branch in writable {
+                    let space = Self::available_space(branch);
+                    if best.is_none_or(|(_, best_space)| space > best_space) {
+                        best = Some((branch, space));
+                    }
+                }
+                best.map(|(branch, _)| branch)
+            }
+            CreatePolicy::ExistingPath => {
+                writable.find(|branch| exists(&branch.path.join(parent_id)))
+            }
+        };
+        let branch = branch.ok_or_else(|| {
+            ErrorKind::ReadOnlyFileSystem.to_error(format!(
+                "{}: no writable branch available",
+                parent_id.display()
+            ))
+        })?;
+        self.clone_path(branch, parent_id)?;
+        Ok(branch.path.join(parent_id).join(name))
+    }
+
+    /// Lists the merged content of the directory `file_id`
+    fn merged_entries(&self, file_id: &Path) -> FuseResult<Vec<(OsString, FileKind)>> {
+        let mut result = Vec::new();
+        let mut names = HashSet::new();
+        let mut found = false;
+        for branch in self.branches_with(file_id) {
+            found = true;
+            for (name, kind) in unix_fs::readdir(&branch.path.join(file_id))? {
+                if names.insert(name.clone()) {
+                    result.push((name, kind));
+                }
+            }
+        }
+        if !found {
+            return Err(Self::not_found(file_id));
+        }
+        Ok(result)
+    }
+}
+
+impl FuseHandler<PathBuf> for UnionFs {
+    fn get_inner(&self) -> &dyn FuseHandler<PathBuf> {
+        self.inner.as_ref()
+    }
+
+    fn access(&self, _req: &RequestInfo, file_id: PathBuf, mask: AccessMask) -> FuseResult<()> {
+        unix_fs::access(&self.resolve(&file_id)?, mask)
+    }
+
+    fn create(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FileAttribute, FUSEOpenResponseFlags)> {
+        let file_path = self.create_path(&parent_id, name)?;
+        let (fd, file_attr) = unix_fs::create(&file_path, mode, umask, flags)?;
+        // Open by definition returns positive Fd or error
+        let file_handle = OwnedFileHandle::from_owned_fd(fd).unwrap();
+        Ok((file_handle, file_attr, FUSEOpenResponseFlags::empty()))
+    }
+
+    fn getattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        _file_handle: Option<BorrowedFileHandle>,
+    ) -> FuseResult<FileAttribute> {
+        unix_fs::lookup(&self.resolve(&file_id)?)
+    }
+
+    fn getxattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        name: &OsStr,
+        size: u32,
+    ) -> FuseResult<Vec<u8>> {
+        unix_fs::getxattr(&self.resolve(&file_id)?, name, size)
+    }
+
+    fn listxattr(&self, _req: &RequestInfo, file_id: PathBuf, size: u32) -> FuseResult<Vec<u8>> {
+        unix_fs::listxattr(&self.resolve(&file_id)?, size)
+    }
+
+    fn lookup(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+    ) -> FuseResult<FileAttribute> {
+        unix_fs::lookup(&self.resolve(&parent_id.join(name))?)
+    }
+
+    fn mkdir(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+    ) -> FuseResult<FileAttribute> {
+        unix_fs::mkdir(&self.create_path(&parent_id, name)?, mode, umask)
+    }
+
+    fn mknod(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: DeviceType,
+    ) -> FuseResult<FileAttribute> {
+        unix_fs::mknod(&self.create_path(&parent_id, name)?, mode, umask, rdev)
+    }
+
+    fn open(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        flags: OpenFlags,
+    ) -> FuseResult<(OwnedFileHandle, FUSEOpenResponseFlags)> {
+        let branch = self
+            .branches_with(&file_id)
+            .next()
+            .ok_or_else(|| Self::not_found(&file_id))?;
+        if !branch.writable
+            && flags.intersects(OpenFlags::WRITE_ONLY | OpenFlags::READ_WRITE | OpenFlags::TRUNCATE)
+        {
+            return Err(Self::read_only(&file_id, branch));
+        }
+        let fd = unix_fs::open(&branch.path.join(&file_id), flags)?;
+        // Open by definition returns positive Fd or error
+        let file_handle = OwnedFileHandle::from_owned_fd(fd).unwrap();
+        Ok((file_handle, FUSEOpenResponseFlags::empty()))
+    }
+
+    fn read_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: PathBuf,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        size: u32,
+        flags: FUSEOpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<ReadData> {
+        // Served by FdHandlerHelper from pooled buffers
+        FuseHandler::read_buf(
+            self.inner.as_ref(),
+            req,
+            file_id,
+            file_handle,
+            seek,
+            size,
+            flags,
+            lock_owner,
+        )
+    }
+
+    fn write_buf(
+        &self,
+        req: &RequestInfo,
+        file_id: PathBuf,
+        file_handle: BorrowedFileHandle,
+        seek: SeekFrom,
+        data: WriteData,
+        write_flags: FUSEWriteFlags,
+        flags: OpenFlags,
+        lock_owner: Option<u64>,
+    ) -> FuseResult<u32> {
+        // Served by FdHandlerHelper without copying the payload
+        FuseHandler::write_buf(
+            self.inner.as_ref(),
+            req,
+            file_id,
+            file_handle,
+            seek,
+            data,
+            write_flags,
+            flags,
+            lock_owner,
+        )
+    }
+
+    fn readdir(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        _file_handle: BorrowedFileHandle,
+    ) -> FuseResult<Vec<(OsString, FileKind)>> {
+        let mut result = vec![
+            (OsString::from("."), FileKind::Directory),
+            (OsString::from(".."), FileKind::Directory),
+        ];
+        result.extend(self.merged_entries(&file_id)?);
+        Ok(result)
+    }
+
+    fn readdir_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: PathBuf,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<FileKind>> {
+        // The entries are merged, they cannot be streamed from the inner handler
+        let entries = FuseHandler::readdir(self, req, file_id, file_handle)?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
+    fn readdirplus_iter(
+        &self,
+        req: &RequestInfo,
+        file_id: PathBuf,
+        file_handle: BorrowedFileHandle,
+        offset: i64,
+    ) -> FuseResult<ReaddirIter<FileAttribute>> {
+        let entries = FuseHandler::readdirplus(self, req, file_id, file_handle)?;
+        Ok(Box::new(entries.into_iter().skip(offset as usize).map(Ok)))
+    }
+
+    fn readlink(&self, _req: &RequestInfo, file_id: PathBuf) -> FuseResult<Vec<u8>> {
+        unix_fs::readlink(&self.resolve(&file_id)?)
+    }
+
+    fn removexattr(&self, _req: &RequestInfo, file_id: PathBuf, name: &OsStr) -> FuseResult<()> {
+        for file_path in self.resolve_all_writable(&file_id)? {
+            unix_fs::removexattr(&file_path, name)?;
+        }
+        Ok(())
+    }
+
+    fn rename(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        name: &OsStr,
+        newparent: PathBuf,
+        newname: &OsStr,
+        flags: RenameFlags,
+    ) -> FuseResult<()> {
+        let file_id = parent_id.join(name);
+        let new_file_id = newparent.join(newname);
+        let file_paths = self.resolve_all_writable(&file_id)?;
+        if file_id == new_file_id {
+            return Ok(());
+        }
+        #[cfg(target_os = "linux")]
+        if flags.contains(RenameFlags::NOREPLACE) && self.resolve(&new_file_id).is_ok() {
+            return Err(ErrorKind::FileExists.to_error(new_file_id.display()));
+        }
+        // resolve_all_writable returns at least one path
+        let is_dir = fs::symlink_metadata(&file_paths[0])?.is_dir();
+        self.check_rename_target(is_dir, &new_file_id)?;
+        let mut renamed_in = Vec::new();
+        for branch in self.branches.iter() {
+            let file_path = branch.path.join(&file_id);
+            if !exists(&file_path) {
+                continue;
+            }
+            self.clone_path(branch, &newparent)?;
+            unix_fs::rename(&file_path, &branch.path.join(&new_file_id), flags)?;
+            renamed_in.push(branch.path.as_path());
+        }
+        // Remove the entries of other branches which would shadow or be merged with the renamed entry
+        for branch in self.branches.iter().filter(|branch| branch.writable) {
+            if renamed_in.contains(&branch.path.as_path()) {
+                continue;
+            }
+            // Checked by check_rename_target: directories are empty, and no read-only branch holds the entry
+            let stale_path = branch.path.join(&new_file_id);
+            match fs::symlink_metadata(&stale_path) {
+                Ok(metadata) if metadata.is_dir() => unix_fs::rmdir(&stale_path)?,
+                Ok(_) => unix_fs::unlink(&stale_path)?,
+                Err(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn rmdir(&self, _req: &RequestInfo, parent_id: PathBuf, name: &OsStr) -> FuseResult<()> {
+        let file_id = parent_id.join(name);
+        let dir_paths = self.resolve_all_writable(&file_id)?;
+        if !self.merged_entries(&file_id)?.is_empty() {
+            return Err(ErrorKind::DirectoryNotEmpty.to_error(file_id.display()));
+        }
+        for dir_path in dir_paths {
+            unix_fs::rmdir(&dir_path)?;
+        }
+        Ok(())
+    }
+
+    fn setattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        attrs: SetAttrRequest,
+    ) -> FuseResult<FileAttribute> {
+        let mut result = None;
+        for file_path in self.resolve_all_writable(&file_id)? {
+            let file_attr = unix_fs::setattr(&file_path, attrs.clone())?;
+            result.get_or_insert(file_attr);
+        }
+        // resolve_all_writable returns at least one path
+        Ok(result.unwrap())
+    }
+
+    fn setxattr(
+        &self,
+        _req: &RequestInfo,
+        file_id: PathBuf,
+        name: &OsStr,
+        value: Vec<u8>,
+        flags: FUSESetXAttrFlags,
+        position: u32,
+    ) -> FuseResult<()> {
+        for file_path in self.resolve_all_writable(&file_id)? {
+            unix_fs::setxattr(&file_path, name, &value, flags, position)?;
+        }
+        Ok(())
+    }
+
+    fn statfs(&self, _req: &RequestInfo, _file_id: PathBuf) -> FuseResult<StatFs> {
+        let mut devices = HashSet::new();
+        let mut result: Option<StatFs> = None;
+        for branch in self.branches.iter() {
+            if !devices.insert(fs::metadata(&branch.path)?.dev()) {
+                continue;
+            }
+            let stat = unix_fs::statfs(&branch.path)?;
+            match result.as_mut() {
+                None => result = Some(stat),
+                Some(total) => {
+                    // Express the statistics of the branch in blocks of the first branch
+                    let scale = |blocks: u64| {
+                        blocks.saturating_mul(stat.block_size.into()) / u64::from(total.block_size)
+                    };
+                    total.total_blocks =
+                        total.total_blocks.saturating_add(scale(stat.total_blocks));
+                    total.free_blocks = total.free_blocks.saturating_add(scale(stat.free_blocks));
+                    total.available_blocks = total
+                        .available_blocks
+                        .saturating_add(scale(stat.available_blocks));
+                    total.total_files = total.total_files.saturating_add(stat.total_files);
+                    total.free_files = total.free_files.saturating_add(stat.free_files);
+                    total.max_filename_length =
+                        total.max_filename_length.min(stat.max_filename_length);
+                }
+            }
+        }
+        result.ok_or_else(|| ErrorKind::InvalidArgument.to_error("UnionFs has no branch"))
+    }
+
+    fn symlink(
+        &self,
+        _req: &RequestInfo,
+        parent_id: PathBuf,
+        link_name: &OsStr,
+        target: &Path,
+    ) -> FuseResult<FileAttribute> {
+        unix_fs::symlink(&self.create_path(&parent_id, link_name)?, target)
+    }
+
+    fn unlink(&self, _req: &RequestInfo, parent_id: PathBuf, name: &OsStr) -> FuseResult<()> {
+        for file_path in self.resolve_all_writable(&parent_id.join(name))? {
+            unix_fs::unlink(&file_path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    struct Union {
+        dirs: Vec<TempDir>,
+        fs: UnionFs,
+    }
+
+    impl Union {
+        fn handler(&self) -> &dyn FuseHandler<PathBuf> {
+            &self.fs
+        }
+
+        fn branch(&self, index: usize) -> &Path {
+            self.dirs[index].path()
+        }
+    }
+
+    /// Two read-write branches and a read-only one
+    fn setup(create_policy: CreatePolicy) -> Union {
+        let dirs: Vec<_> = (0..3).map(|_| TempDir::new().unwrap()).collect();
+        fs::create_dir(dirs[1].path().join("dir")).unwrap();
+        fs::write(dirs[1].path().join("dir/b.txt"), "b").unwrap();
+        fs::create_dir(dirs[2].path().join("dir")).unwrap();
+        fs::write(dirs[2].path().join("dir/b.txt"), "shadowed").unwrap();
+        fs::write(dirs[2].path().join("dir/c.txt"), "c").unwrap();
+        let fs = UnionFs::new(
+            vec![
+                Branch::read_write(dirs[0].path()),
+                Branch::read_write(dirs[1].path()),
+                Branch::read_only(dirs[2].path()),
+            ],
+            DefaultFuseHandler::new(),
+        )
+        .with_create_policy(create_policy);
+        Union { dirs, fs }
+    }
+
+    fn req() -> RequestInfo {
+        RequestInfo {
+            id: 0,
+            uid: 0,
+            gid: 0,
+            pid: 0,
+        }
+    }
+
+    #[test]
+    fn test_merged_readdir() {
+        let union = setup(CreatePolicy::FirstWritable);
+        let mut names: Vec<_> = union
+            .fs
+            .merged_entries(Path::new("dir"))
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name.into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["b.txt", "c.txt"]);
+        let path = union.fs.resolve(Path::new("dir/b.txt")).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "b");
+    }
+
+    #[test]
+    fn test_create_policies() {
+        let union = setup(CreatePolicy::FirstWritable);
+        union
+            .handler()
+            .mkdir(&req(), PathBuf::from("dir"), OsStr::new("new"), 0o755, 0)
+            .unwrap();
+        assert!(union.branch(0).join("dir/new").is_dir());
+
+        let union = setup(CreatePolicy::ExistingPath);
+        union
+            .handler()
+            .mkdir(&req(), PathBuf::from("dir"), OsStr::new("new"), 0o755, 0)
+            .unwrap();
+        assert!(union.branch(1).join("dir/new").is_dir());
+        assert!(!union.branch(0).join("dir").exists());
+    }
+
+    #[test]
+    fn test_read_only_branch() {
+        let union = setup(CreatePolicy::FirstWritable);
+        let result = union
+            .handler()
+            .unlink(&req(), PathBuf::from("dir"), OsStr::new("c.txt"));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ReadOnlyFileSystem);
+        let result =
+            union
+                .handler()
+                .open(&req(), PathBuf::from("dir/c.txt"), OpenFlags::WRITE_ONLY);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ReadOnlyFileSystem);
+
+        // b.txt is also provided by the read-only branch
+        let result = union.handler().rename(
+            &req(),
+            PathBuf::from("dir"),
+            OsStr::new("b.txt"),
+            PathBuf::new(),
+            OsStr::new("moved.txt"),
+            RenameFlags::empty(),
+        );
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::ReadOnlyFileSystem);
+    }
+
+    #[test]
+    fn test_rename_creates_parent() {
+        let union = setup(CreatePolicy::FirstWritable);
+        fs::write(union.branch(0).join("a.txt"), "a").unwrap();
+        union
+            .handler()
+            .rename(
+                &req(),
+                PathBuf::new(),
+                OsStr::new("a.txt"),
+                PathBuf::from("dir"),
+                OsStr::new("a.txt"),
+                RenameFlags::empty(),
+            )
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(union.branch(0).join("dir/a.txt")).unwrap(),
+            "a"
+        );
+    }
+    #[test]
+    fn test_rename_over_existing() {
+        let union = setup(CreatePolicy::FirstWritable);
+        fs::write(union.branch(0).join("file"), "file").unwrap();
+        fs::create_dir(union.branch(0).join("source")).unwrap();
+        fs::create_dir(union.branch(1).join("tree")).unwrap();
+        fs::write(union.branch(1).join("tree/x"), "x").unwrap();
+        fs::create_dir(union.branch(1).join("empty")).unwrap();
+        fs::write(union.branch(1).join("plain"), "plain").unwrap();
+        let rename = |name: &str, newname: &str| {
+            union.handler().rename(
+                &req(),
+                PathBuf::new(),
+                OsStr::new(name),
+                PathBuf::from(Path::new(newname).parent().unwrap()),
+                Path::new(newname).file_name().unwrap(),
+                RenameFlags::empty(),
+            )
+        };
+
+        // Nothing is changed when the entry cannot replace the existing one
+        for (name, newname, kind) in [
+            ("file", "tree", ErrorKind::IsADirectory),
+            ("source", "tree", ErrorKind::DirectoryNotEmpty),
+            ("source", "plain", ErrorKind::NotADirectory),
+            ("file", "dir/c.txt", ErrorKind::ReadOnlyFileSystem),
+        ] {
+            assert_eq!(rename(name, newname).unwrap_err().kind(), kind);
+            assert!(union.branch(0).join(name).exists());
+            assert!(union.fs.resolve(Path::new(newname)).is_ok());
+        }
+        assert!(union.branch(1).join("tree/x").exists());
+
+        // Empty directories and files of other branches are replaced
+        rename("source", "empty").unwrap();
+        assert!(union.branch(0).join("empty").is_dir());
+        assert!(!union.branch(1).join("empty").exists());
+        rename("file", "plain").unwrap();
+        assert!(!union.branch(1).join("plain").exists());
+        let path = union.fs.resolve(Path::new("plain")).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "file");
+    }
+}
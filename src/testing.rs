@@ -0,0 +1,2206 @@
+//! In-process test harness for FUSE handlers.
+//!
+//! This module runs a handler through the same `FuseDriver` used by [`mount`](crate::mount()), without any kernel
+//! involvement: no `/dev/fuse`, no mountpoint and no privilege is required, which makes it usable in containers
+//! and CI environments.
+//!
+//! # How it works
+//!
+//! [`TestSession`] connects a fuser session to a socket pair instead of `/dev/fuse`. Each method of the session
+//! encodes a request of the FUSE protocol, as the kernel would, and decodes the reply of the driver. Every request
+//! therefore goes through the whole stack: request parsing by fuser, inode resolution by the `FileIdResolver` of the
+//! handler, directory pagination, and the conversion of handler results and errors into FUSE replies.
+//!
+//! Requests are identified by inode numbers, as in the kernel protocol. The root directory is
+//! [`ROOT_INO`](TestSession::ROOT_INO), and inode numbers of other files are obtained from the entries returned by
+//! `lookup`, `create`, `mkdir`... Those calls increase the lookup count of the inode, which can be decreased with
+//! `forget`.
+//!
+//! # Example
+//!
+//! ```
+//! use easy_fuser::prelude::*;
+//! use easy_fuser::templates::memory_fs::MemoryFs;
+//! use easy_fuser::testing::TestSession;
+//! use std::ffi::OsStr;
+//!
+//! let session = TestSession::new::<Inode, _>(MemoryFs::new()).unwrap();
+//! let (entry, fh, _) = session
+//!     .create(TestSession::ROOT_INO, OsStr::new("hello.txt"), 0o644, 0o022, OpenFlags::READ_WRITE)
+//!     .unwrap();
+//! session.write(entry.ino, fh, 0, b"hello", OpenFlags::READ_WRITE).unwrap();
+//! assert_eq!(session.read(entry.ino, fh, 0, 4096).unwrap(), b"hello");
+//! session.release(entry.ino, fh, OpenFlags::READ_WRITE).unwrap();
+//!
+//! let names: Vec<_> = session
+//!     .list_dir(TestSession::ROOT_INO)
+//!     .unwrap()
+//!     .into_iter()
+//!     .map(|entry| entry.name)
+//!     .collect();
+//! assert_eq!(names, vec![".", "..", "hello.txt"]);
+//! ```
+//!
+//! # Limitations
+//!
+//! - Requests are sent one at a time, except for the `send_*` methods, whose reply is awaited with `wait_reply`.
+//! - The requests available are those of the FUSE protocol version fuser is compiled with. Without the `abi-7-31`
+//!   feature of this crate, or the `abi-7-*` features of fuser, `rename` with flags, `lseek` and `readdirplus` fail
+//!   with `FunctionNotImplemented` (ENOSYS) before reaching the handler.
+//! - Messages are bounded by the size of the socket buffers, so reads and writes should stay below 128 KiB.
+//! - Only the Linux layout of the protocol is supported.
+//!
+//! The [`conformance`] module builds a POSIX conformance suite on top of this harness.
+
+pub mod conformance;
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Component, Path};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fuser::{BackgroundSession, SessionACL};
+
+use crate::core::{FuseDriver, FuseDriverHandler, ROOT_INO};
+use crate::inode_store::InodeStore;
+use crate::prelude::*;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+const INIT_FLAGS: u32 = 1 // FUSE_ASYNC_READ
+    | 1 << 1 // FUSE_POSIX_LOCKS
+    | 1 << 3 // FUSE_ATOMIC_O_TRUNC
+    | 1 << 5 // FUSE_BIG_WRITES
+    | 1 << 10; // FUSE_FLOCK_LOCKS
+const MAX_READAHEAD: u32 = 128 * 1024;
+/// Buffer size used by `list_dir`, small enough to exercise the pagination of the driver
+const LIST_DIR_BUFFER_SIZE: u32 = 1024;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+const OUT_HEADER_SIZE: usize = 16;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_SETATTR: u32 = 4;
+const FUSE_READLINK: u32 = 5;
+const FUSE_SYMLINK: u32 = 6;
+const FUSE_MKNOD: u32 = 8;
+const FUSE_MKDIR: u32 = 9;
+const FUSE_UNLINK: u32 = 10;
+const FUSE_RMDIR: u32 = 11;
+const FUSE_RENAME: u32 = 12;
+const FUSE_LINK: u32 = 13;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_WRITE: u32 = 16;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FSYNC: u32 = 20;
+const FUSE_SETXATTR: u32 = 21;
+const FUSE_GETXATTR: u32 = 22;
+const FUSE_LISTXATTR: u32 = 23;
+const FUSE_REMOVEXATTR: u32 = 24;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_SETLK: u32 = 32;
+const FUSE_SETLKW: u32 = 33;
+const FUSE_ACCESS: u32 = 34;
+const FUSE_CREATE: u32 = 35;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_POLL: u32 = 40;
+const FUSE_FALLOCATE: u32 = 43;
+const FUSE_READDIRPLUS: u32 = 44;
+const FUSE_RENAME2: u32 = 45;
+const FUSE_LSEEK: u32 = 46;
+
+const FATTR_MODE: u32 = 1 << 0;
+const FATTR_UID: u32 = 1 << 1;
+const FATTR_GID: u32 = 1 << 2;
+const FATTR_SIZE: u32 = 1 << 3;
+const FATTR_ATIME: u32 = 1 << 4;
+const FATTR_MTIME: u32 = 1 << 5;
+const FATTR_FH: u32 = 1 << 6;
+const FATTR_ATIME_NOW: u32 = 1 << 7;
+const FATTR_MTIME_NOW: u32 = 1 << 8;
+
+/// An entry returned by `lookup` and the operations creating files
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Inode number attributed by the driver, 0 for a negative entry cached by the kernel for `entry_ttl`
+    pub ino: u64,
+    pub generation: u64,
+    /// Time-to-live of the name of the entry
+    pub entry_ttl: Duration,
+    /// Attributes of the entry, with `ttl` set to their time-to-live
+    pub attr: FileAttribute,
+}
+
+/// An entry returned by `readdir`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub ino: u64,
+    /// Offset to pass to `readdir` to continue after this entry
+    pub offset: i64,
+    pub kind: FileKind,
+    pub name: OsString,
+}
+
+/// Reply of `getxattr` and `listxattr`, depending on the size requested
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XattrReply {
+    /// Size of the value, returned when the requested size is zero
+    Size(u32),
+    Data(Vec<u8>),
+}
+
+/// A notification sent by the handler through its [`Notifier`]
+#[derive(Debug, Clone)]
+pub struct Notification {
+    /// FUSE notification code (eg: 1 for a poll wakeup, 2 for `inval_inode`, 3 for `inval_entry`)
+    pub code: i32,
+    /// Raw payload of the notification
+    pub data: Vec<u8>,
+}
+
+struct Client {
+    socket: OwnedFd,
+    next_unique: u64,
+    uid: u32,
+    gid: u32,
+    pid: u32,
+    notifications: Vec<Notification>,
+    /// Replies received while waiting for the reply of another request
+    replies: HashMap<u64, (i32, Vec<u8>)>,
+}
+
+/// Builds a request of the FUSE protocol
+struct Request {
+    opcode: u32,
+    nodeid: u64,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn new(opcode: u32, nodeid: u64) -> Self {
+        Self {
+            opcode,
+            nodeid,
+            body: Vec::new(),
+        }
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.body.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn i32(self, value: i32) -> Self {
+        self.u32(value as u32)
+    }
+
+    fn u64(mut self, value: u64) -> Self {
+        self.body.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn i64(self, value: i64) -> Self {
+        self.u64(value as u64)
+    }
+
+    fn bytes(mut self, value: &[u8]) -> Self {
+        self.body.extend_from_slice(value);
+        self
+    }
+
+    /// Appends a null-terminated name
+    fn name(self, name: &OsStr) -> Self {
+        self.bytes(name.as_bytes()).bytes(&[0])
+    }
+}
+
+/// Decodes a reply of the FUSE protocol
+struct Reply<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reply<'a> {
+    fn take(&mut self, len: usize) -> FuseResult<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(ErrorKind::BadMessage.to_error("truncated reply"));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> FuseResult<u32> {
+        Ok(u32::from_ne_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> FuseResult<u64> {
+        Ok(u64::from_ne_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> FuseResult<i64> {
+        Ok(self.u64()? as i64)
+    }
+
+    fn time(&mut self, secs: i64, nsecs: u32) -> SystemTime {
+        let duration = Duration::new(secs.unsigned_abs(), nsecs);
+        if secs >= 0 {
+            UNIX_EPOCH + duration
+        } else {
+            UNIX_EPOCH - duration
+        }
+    }
+
+    fn ttl(&mut self, secs: u64, nsecs: u32) -> Duration {
+        Duration::new(secs, nsecs)
+    }
+
+    /// Decodes a `fuse_attr`, returns the inode number and the attributes
+    fn attr(&mut self, ttl: Duration) -> FuseResult<(u64, FileAttribute)> {
+        let ino = self.u64()?;
+        let size = self.u64()?;
+        let blocks = self.u64()?;
+        let (atime, mtime, ctime) = (self.i64()?, self.i64()?, self.i64()?);
+        let (atimensec, mtimensec, ctimensec) = (self.u32()?, self.u32()?, self.u32()?);
+        let mode = self.u32()?;
+        let nlink = self.u32()?;
+        let uid = self.u32()?;
+        let gid = self.u32()?;
+        let rdev = self.u32()?;
+        let blksize = self.u32()?;
+        let _padding = self.u32()?;
+        Ok((
+            ino,
+            FileAttribute {
+                size,
+                blocks,
+                atime: self.time(atime, atimensec),
+                mtime: self.time(mtime, mtimensec),
+                ctime: self.time(ctime, ctimensec),
+                crtime: UNIX_EPOCH,
+                kind: kind_from_mode(mode)?,
+                perm: (mode & 0o7777) as u16,
+                nlink,
+                uid,
+                gid,
+                rdev,
+                blksize,
+                flags: 0,
+                ttl: Some(ttl),
+                generation: None,
+            },
+        ))
+    }
+
+    /// Decodes a `fuse_entry_out`
+    fn entry(&mut self) -> FuseResult<Entry> {
+        let _nodeid = self.u64()?;
+        let generation = self.u64()?;
+        let (entry_valid, attr_valid) = (self.u64()?, self.u64()?);
+        let (entry_valid_nsec, attr_valid_nsec) = (self.u32()?, self.u32()?);
+        let attr_ttl = self.ttl(attr_valid, attr_valid_nsec);
+        let (ino, mut attr) = self.attr(attr_ttl)?;
+        attr.generation = Some(generation);
+        Ok(Entry {
+            ino,
+            generation,
+            entry_ttl: self.ttl(entry_valid, entry_valid_nsec),
+            attr,
+        })
+    }
+
+    /// Decodes a `fuse_dirent`, aligned on 8 bytes
+    fn dirent(&mut self) -> FuseResult<DirEntry> {
+        let ino = self.u64()?;
+        let offset = self.i64()?;
+        let namelen = self.u32()? as usize;
+        let kind = kind_from_dirent_type(self.u32()?)?;
+        let name = OsString::from_vec(self.take(namelen)?.to_vec());
+        let padding = (8 - (24 + namelen) % 8) % 8;
+        self.take(padding.min(self.data.len()))?;
+        Ok(DirEntry {
+            ino,
+            offset,
+            kind,
+            name,
+        })
+    }
+
+    /// Decodes a `fuse_open_out`
+    fn open(&mut self) -> FuseResult<(u64, FUSEOpenResponseFlags)> {
+        let fh = self.u64()?;
+        let open_flags = self.u32()?;
+        let _padding = self.u32()?;
+        Ok((fh, FUSEOpenResponseFlags::from_bits_retain(open_flags)))
+    }
+
+    fn xattr(&mut self, size: u32) -> FuseResult<XattrReply> {
+        if size == 0 {
+            Ok(XattrReply::Size(self.u32()?))
+        } else {
+            Ok(XattrReply::Data(self.data.to_vec()))
+        }
+    }
+}
+
+fn kind_from_mode(mode: u32) -> FuseResult<FileKind> {
+    Ok(match mode & libc::S_IFMT {
+        libc::S_IFREG => FileKind::RegularFile,
+        libc::S_IFDIR => FileKind::Directory,
+        libc::S_IFLNK => FileKind::Symlink,
+        libc::S_IFIFO => FileKind::NamedPipe,
+        libc::S_IFSOCK => FileKind::Socket,
+        libc::S_IFCHR => FileKind::CharDevice,
+        libc::S_IFBLK => FileKind::BlockDevice,
+        _ => return Err(ErrorKind::BadMessage.to_error(format!("invalid mode {:o}", mode))),
+    })
+}
+
+fn kind_from_dirent_type(typ: u32) -> FuseResult<FileKind> {
+    kind_from_mode(typ << 12)
+}
+
+fn syscall_result(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+impl Client {
+    fn send(&mut self, request: Request) -> io::Result<u64> {
+        let unique = self.next_unique;
+        self.next_unique += 1;
+        let len = 40 + request.body.len();
+        let mut message = Vec::with_capacity(len);
+        message.extend_from_slice(&(len as u32).to_ne_bytes());
+        message.extend_from_slice(&request.opcode.to_ne_bytes());
+        message.extend_from_slice(&unique.to_ne_bytes());
+        message.extend_from_slice(&request.nodeid.to_ne_bytes());
+        message.extend_from_slice(&self.uid.to_ne_bytes());
+        message.extend_from_slice(&self.gid.to_ne_bytes());
+        message.extend_from_slice(&self.pid.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&request.body);
+        let ret = unsafe {
+            libc::send(
+                self.socket.as_raw_fd(),
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unique)
+    }
+
+    /// Waits for the reply to `unique`, keeping the notifications received meanwhile
+    fn receive(&mut self, unique: u64, capacity: usize) -> io::Result<(i32, Vec<u8>)> {
+        if let Some(reply) = self.replies.remove(&unique) {
+            return Ok(reply);
+        }
+        let mut buffer = vec![0u8; capacity + 4096];
+        loop {
+            let ret = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if ret == -1 {
+                let error = io::Error::last_os_error();
+                return match error.kind() {
+                    io::ErrorKind::WouldBlock => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "no reply from the FUSE driver",
+                    )),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => Err(error),
+                };
+            }
+            let len = ret as usize;
+            if len < OUT_HEADER_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "FUSE session closed",
+                ));
+            }
+            let error = i32::from_ne_bytes(buffer[4..8].try_into().unwrap());
+            let reply_unique = u64::from_ne_bytes(buffer[8..16].try_into().unwrap());
+            let data = buffer[OUT_HEADER_SIZE..len].to_vec();
+            if reply_unique == unique {
+                return Ok((error, data));
+            }
+            if reply_unique == 0 {
+                self.notifications.push(Notification { code: error, data });
+            } else {
+                self.replies.insert(reply_unique, (error, data));
+            }
+        }
+    }
+
+    /// Keeps the notifications already sent by the driver, without waiting for more
+    fn receive_notifications(&mut self) {
+        let mut buffer = vec![0u8; 1 << 20];
+        loop {
+            let ret = unsafe {
+                libc::recv(
+                    self.socket.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if ret < OUT_HEADER_SIZE as isize {
+                return;
+            }
+            let error = i32::from_ne_bytes(buffer[4..8].try_into().unwrap());
+            if u64::from_ne_bytes(buffer[8..16].try_into().unwrap()) == 0 {
+                let data = buffer[OUT_HEADER_SIZE..ret as usize].to_vec();
+                self.notifications.push(Notification { code: error, data });
+            }
+        }
+    }
+}
+
+/// A handler driven through the `FuseDriver` without a kernel, see the module documentation.
+pub struct TestSession {
+    client: Mutex<Client>,
+    session: Option<BackgroundSession>,
+    protocol_version: (u32, u32),
+    init_flags: u32,
+}
+
+impl TestSession {
+    /// Inode number of the root directory
+    pub const ROOT_INO: u64 = ROOT_INO;
+
+    /// Starts a driver for `filesystem` and initializes it, as a mount would.
+    ///
+    /// Requests are sent with the credentials of the current process, see `set_credentials`.
+    pub fn new<TId, THandler>(filesystem: THandler) -> io::Result<Self>
+    where
+        TId: FileIdType,
+        THandler: FuseDriverHandler<TId> + Send,
+    {
+        Self::from_driver(FuseDriver::new(filesystem, 1))
+    }
+
+    /// Same as `new`, with the inode numbers kept in `store`, as with [`MountBuilder::inode_store`](crate::MountBuilder::inode_store)
+    pub fn with_inode_store<TId, THandler>(
+        filesystem: THandler,
+        store: InodeStore,
+    ) -> io::Result<Self>
+    where
+        TId: FileIdType,
+        THandler: FuseDriverHandler<TId> + Send,
+    {
+        Self::from_builder(
+            MountBuilder::new(filesystem)
+                .num_threads(1)
+                .inode_store(store),
+        )
+    }
+
+    /// Starts the driver configured by `builder`, as `MountBuilder::mount` would. Mount options are ignored.
+    pub fn from_builder<TId, THandler>(builder: MountBuilder<TId, THandler>) -> io::Result<Self>
+    where
+        TId: FileIdType,
+        THandler: FuseDriverHandler<TId> + Send,
+    {
+        Self::from_driver(builder.into_driver()?.0)
+    }
+
+    fn from_driver<TId, THandler>(driver: FuseDriver<TId, THandler>) -> io::Result<Self>
+    where
+        TId: FileIdType,
+        THandler: FuseDriverHandler<TId> + Send,
+    {
+        let mut fds = [0; 2];
+        syscall_result(unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        })?;
+        let (client_socket, driver_socket) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        let timeout = libc::timeval {
+            tv_sec: REPLY_TIMEOUT.as_secs() as libc::time_t,
+            tv_usec: 0,
+        };
+        syscall_result(unsafe {
+            libc::setsockopt(
+                client_socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        })?;
+
+        let notifier = driver.get_notifier();
+        let session = Session::from_fd(driver, driver_socket, SessionACL::All);
+        notifier.attach(session.notifier());
+        let session = session.spawn()?;
+
+        let (uid, gid, pid) = unsafe { (libc::getuid(), libc::getgid(), libc::getpid() as u32) };
+        let mut test_session = Self {
+            client: Mutex::new(Client {
+                socket: client_socket,
+                next_unique: 1,
+                uid,
+                gid,
+                pid,
+                notifications: Vec::new(),
+                replies: HashMap::new(),
+            }),
+            session: Some(session),
+            protocol_version: (0, 0),
+            init_flags: 0,
+        };
+        let init = Request::new(FUSE_INIT, 0)
+            .u32(FUSE_KERNEL_VERSION)
+            .u32(FUSE_KERNEL_MINOR_VERSION)
+            .u32(MAX_READAHEAD)
+            .u32(INIT_FLAGS)
+            // flags2 and unused fields of newer protocol versions
+            .bytes(&[0; 48]);
+        let to_io_error = |e: PosixError| io::Error::from_raw_os_error(e.raw_error());
+        let data = test_session.call(init, 0).map_err(to_io_error)?;
+        let mut reply = Reply { data: &data };
+        test_session.protocol_version = (
+            reply.u32().map_err(to_io_error)?,
+            reply.u32().map_err(to_io_error)?,
+        );
+        let _max_readahead = reply.u32().map_err(to_io_error)?;
+        test_session.init_flags = reply.u32().map_err(to_io_error)?;
+        Ok(test_session)
+    }
+
+    /// Sets the credentials used for the following requests
+    pub fn set_credentials(&self, uid: u32, gid: u32, pid: u32) {
+        let mut client = self.client.lock().unwrap();
+        client.uid = uid;
+        client.gid = gid;
+        client.pid = pid;
+    }
+
+    /// Version of the FUSE protocol replied by the driver at initialization
+    pub fn protocol_version(&self) -> (u32, u32) {
+        self.protocol_version
+    }
+
+    /// Capabilities enabled by the driver at initialization, among the ones offered by the session
+    pub fn init_flags(&self) -> u32 {
+        self.init_flags
+    }
+
+    /// Returns the notifications received so far, and clears them
+    pub fn take_notifications(&self) -> Vec<Notification> {
+        let mut client = self.client.lock().unwrap();
+        client.receive_notifications();
+        std::mem::take(&mut client.notifications)
+    }
+
+    /// Sends `request` and waits for its reply, `capacity` is the expected size of the reply
+    fn call(&self, request: Request, capacity: usize) -> FuseResult<Vec<u8>> {
+        let mut client = self.client.lock().unwrap();
+        let unique = client.send(request)?;
+        Self::reply(client.receive(unique, capacity)?)
+    }
+
+    fn reply((error, data): (i32, Vec<u8>)) -> FuseResult<Vec<u8>> {
+        if error != 0 {
+            return Err(PosixError::new(-error, "error replied by the FUSE driver"));
+        }
+        Ok(data)
+    }
+
+    /// Waits for the reply to a request sent by one of the `send_*` methods, which returned `unique`
+    pub fn wait_reply(&self, unique: u64) -> FuseResult<()> {
+        let mut client = self.client.lock().unwrap();
+        Self::reply(client.receive(unique, 0)?).map(|_| ())
+    }
+
+    fn call_entry(&self, request: Request) -> FuseResult<Entry> {
+        let data = self.call(request, 0)?;
+        Reply { data: &data }.entry()
+    }
+
+    fn call_empty(&self, request: Request) -> FuseResult<()> {
+        self.call(request, 0).map(|_| ())
+    }
+
+    /// Looks up `name` in `parent`, a missing entry is either an error or a negative entry with the inode number 0
+    pub fn lookup(&self, parent: u64, name: &OsStr) -> FuseResult<Entry> {
+        self.call_entry(Request::new(FUSE_LOOKUP, parent).name(name))
+    }
+
+    /// Looks up every component of a relative `path` from the root, returns the entry of the last one
+    pub fn lookup_path(&self, path: &Path) -> FuseResult<Entry> {
+        let mut entry = None;
+        for component in path.components() {
+            let Component::Normal(name) = component else {
+                return Err(ErrorKind::InvalidArgument.to_error(format!(
+                    "{}: only normal components are supported",
+                    path.display()
+                )));
+            };
+            let parent = entry.as_ref().map_or(ROOT_INO, |entry: &Entry| entry.ino);
+            if parent == 0 {
+                return Err(
+                    ErrorKind::FileNotFound.to_error(format!("{}: negative entry", path.display()))
+                );
+            }
+            entry = Some(self.lookup(parent, name)?);
+        }
+        entry.ok_or_else(|| ErrorKind::InvalidArgument.to_error("empty path"))
+    }
+
+    /// Decreases the lookup count of `ino` by `nlookup`, this request has no reply
+    pub fn forget(&self, ino: u64, nlookup: u64) -> FuseResult<()> {
+        let mut client = self.client.lock().unwrap();
+        client.send(Request::new(FUSE_FORGET, ino).u64(nlookup))?;
+        Ok(())
+    }
+
+    pub fn getattr(&self, ino: u64, fh: Option<u64>) -> FuseResult<FileAttribute> {
+        let request = Request::new(FUSE_GETATTR, ino)
+            .u32(if fh.is_some() { 1 } else { 0 }) // FUSE_GETATTR_FH
+            .u32(0)
+            .u64(fh.unwrap_or_default());
+        let data = self.call(request, 0)?;
+        let mut reply = Reply { data: &data };
+        let (attr_valid, attr_valid_nsec) = (reply.u64()?, reply.u32()?);
+        let _dummy = reply.u32()?;
+        let ttl = reply.ttl(attr_valid, attr_valid_nsec);
+        Ok(reply.attr(ttl)?.1)
+    }
+
+    pub fn setattr(&self, ino: u64, attrs: SetAttrRequest) -> FuseResult<FileAttribute> {
+        let mut valid = 0;
+        let mut flag = |value: bool, bit: u32| {
+            if value {
+                valid |= bit
+            }
+        };
+        flag(attrs.mode.is_some(), FATTR_MODE);
+        flag(attrs.uid.is_some(), FATTR_UID);
+        flag(attrs.gid.is_some(), FATTR_GID);
+        flag(attrs.size.is_some(), FATTR_SIZE);
+        flag(attrs.atime.is_some(), FATTR_ATIME);
+        flag(attrs.mtime.is_some(), FATTR_MTIME);
+        flag(attrs.file_handle.is_some(), FATTR_FH);
+        flag(matches!(attrs.atime, Some(TimeOrNow::Now)), FATTR_ATIME_NOW);
+        flag(matches!(attrs.mtime, Some(TimeOrNow::Now)), FATTR_MTIME_NOW);
+        let timestamp = |time: Option<TimeOrNow>| match time {
+            Some(TimeOrNow::SpecificTime(time)) => {
+                let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                (since_epoch.as_secs() as i64, since_epoch.subsec_nanos())
+            }
+            _ => (0, 0),
+        };
+        let (atime, atimensec) = timestamp(attrs.atime);
+        let (mtime, mtimensec) = timestamp(attrs.mtime);
+        let request = Request::new(FUSE_SETATTR, ino)
+            .u32(valid)
+            .u32(0)
+            .u64(attrs.file_handle.map_or(0, |fh| fh.as_raw()))
+            .u64(attrs.size.unwrap_or_default())
+            .u64(0) // lock_owner
+            .i64(atime)
+            .i64(mtime)
+            .i64(0) // ctime
+            .u32(atimensec)
+            .u32(mtimensec)
+            .u32(0) // ctimensec
+            .u32(attrs.mode.unwrap_or_default())
+            .u32(0)
+            .u32(attrs.uid.unwrap_or_default())
+            .u32(attrs.gid.unwrap_or_default())
+            .u32(0);
+        let data = self.call(request, 0)?;
+        let mut reply = Reply { data: &data };
+        let (attr_valid, attr_valid_nsec) = (reply.u64()?, reply.u32()?);
+        let _dummy = reply.u32()?;
+        let ttl = reply.ttl(attr_valid, attr_valid_nsec);
+        Ok(reply.attr(ttl)?.1)
+    }
+
+    pub fn readlink(&self, ino: u64) -> FuseResult<Vec<u8>> {
+        self.call(Request::new(FUSE_READLINK, ino), libc::PATH_MAX as usize)
+    }
+
+    pub fn symlink(&self, parent: u64, name: &OsStr, target: &Path) -> FuseResult<Entry> {
+        self.call_entry(
+            Request::new(FUSE_SYMLINK, parent)
+                .name(name)
+                .name(target.as_os_str()),
+        )
+    }
+
+    pub fn mknod(
+        &self,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: u32,
+    ) -> FuseResult<Entry> {
+        self.call_entry(
+            Request::new(FUSE_MKNOD, parent)
+                .u32(mode)
+                .u32(rdev)
+                .u32(umask)
+                .u32(0)
+                .name(name),
+        )
+    }
+
+    pub fn mkdir(&self, parent: u64, name: &OsStr, mode: u32, umask: u32) -> FuseResult<Entry> {
+        self.call_entry(
+            Request::new(FUSE_MKDIR, parent)
+                .u32(mode)
+                .u32(umask)
+                .name(name),
+        )
+    }
+
+    pub fn unlink(&self, parent: u64, name: &OsStr) -> FuseResult<()> {
+        self.call_empty(Request::new(FUSE_UNLINK, parent).name(name))
+    }
+
+    pub fn rmdir(&self, parent: u64, name: &OsStr) -> FuseResult<()> {
+        self.call_empty(Request::new(FUSE_RMDIR, parent).name(name))
+    }
+
+    /// Renames an entry, flags are sent with a `RENAME2` request, which requires fuser to support it
+    pub fn rename(
+        &self,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: RenameFlags,
+    ) -> FuseResult<()> {
+        let request = if flags.is_empty() {
+            Request::new(FUSE_RENAME, parent).u64(newparent)
+        } else {
+            Request::new(FUSE_RENAME2, parent)
+                .u64(newparent)
+                .u32(flags.bits())
+                .u32(0)
+        };
+        self.call_empty(request.name(name).name(newname))
+    }
+
+    pub fn link(&self, ino: u64, newparent: u64, newname: &OsStr) -> FuseResult<Entry> {
+        self.call_entry(Request::new(FUSE_LINK, newparent).u64(ino).name(newname))
+    }
+
+    pub fn open(&self, ino: u64, flags: OpenFlags) -> FuseResult<(u64, FUSEOpenResponseFlags)> {
+        let data = self.call(Request::new(FUSE_OPEN, ino).i32(flags.bits()).u32(0), 0)?;
+        Reply { data: &data }.open()
+    }
+
+    pub fn read(&self, ino: u64, fh: u64, offset: i64, size: u32) -> FuseResult<Vec<u8>> {
+        let request = Request::new(FUSE_READ, ino)
+            .u64(fh)
+            .i64(offset)
+            .u32(size)
+            .u32(0)
+            .u64(0)
+            .i32(0)
+            .u32(0);
+        self.call(request, size as usize)
+    }
+
+    pub fn write(
+        &self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: OpenFlags,
+    ) -> FuseResult<u32> {
+        let request = Request::new(FUSE_WRITE, ino)
+            .u64(fh)
+            .i64(offset)
+            .u32(data.len() as u32)
+            .u32(0)
+            .u64(0)
+            .i32(flags.bits())
+            .u32(0)
+            .bytes(data);
+        let reply = self.call(request, 0)?;
+        Reply { data: &reply }.u32()
+    }
+
+    pub fn statfs(&self, ino: u64) -> FuseResult<StatFs> {
+        let data = self.call(Request::new(FUSE_STATFS, ino), 0)?;
+        let mut reply = Reply { data: &data };
+        let (total_blocks, free_blocks, available_blocks) =
+            (reply.u64()?, reply.u64()?, reply.u64()?);
+        let (total_files, free_files) = (reply.u64()?, reply.u64()?);
+        let (block_size, max_filename_length, fragment_size) =
+            (reply.u32()?, reply.u32()?, reply.u32()?);
+        Ok(StatFs {
+            total_blocks,
+            free_blocks,
+            available_blocks,
+            total_files,
+            free_files,
+            block_size,
+            max_filename_length,
+            fragment_size,
+        })
+    }
+
+    pub fn release(&self, ino: u64, fh: u64, flags: OpenFlags) -> FuseResult<()> {
+        self.call_empty(
+            Request::new(FUSE_RELEASE, ino)
+                .u64(fh)
+                .i32(flags.bits())
+                .u32(0)
+                .u64(0),
+        )
+    }
+
+    pub fn fsync(&self, ino: u64, fh: u64, datasync: bool) -> FuseResult<()> {
+        self.call_empty(
+            Request::new(FUSE_FSYNC, ino)
+                .u64(fh)
+                .u32(datasync as u32)
+                .u32(0),
+        )
+    }
+
+    pub fn flush(&self, ino: u64, fh: u64, lock_owner: u64) -> FuseResult<()> {
+        self.call_empty(
+            Request::new(FUSE_FLUSH, ino)
+                .u64(fh)
+                .u32(0)
+                .u32(0)
+                .u64(lock_owner),
+        )
+    }
+
+    fn setlk_request(ino: u64, fh: u64, lock_owner: u64, lock: &LockInfo, sleep: bool) -> Request {
+        Request::new(if sleep { FUSE_SETLKW } else { FUSE_SETLK }, ino)
+            .u64(fh)
+            .u64(lock_owner)
+            .u64(lock.start)
+            .u64(lock.end)
+            .i32(lock.lock_type.bits())
+            .u32(lock.pid)
+            .u32((lock.kind == LockKind::Flock) as u32)
+            .u32(0)
+    }
+
+    /// Places, changes or removes a lock, waiting for the release of the conflicting locks if `sleep` is set
+    pub fn setlk(
+        &self,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        lock: LockInfo,
+        sleep: bool,
+    ) -> FuseResult<()> {
+        self.call_empty(Self::setlk_request(ino, fh, lock_owner, &lock, sleep))
+    }
+
+    /// Sends the request of `setlk` without waiting for its reply, returns the id to pass to `wait_reply`
+    pub fn send_setlk(
+        &self,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        lock: LockInfo,
+        sleep: bool,
+    ) -> FuseResult<u64> {
+        let request = Self::setlk_request(ino, fh, lock_owner, &lock, sleep);
+        Ok(self.client.lock().unwrap().send(request)?)
+    }
+
+    /// Polls an open file, `kernel_handle` is the handle sent back by the poll wakeup notification
+    pub fn poll(
+        &self,
+        ino: u64,
+        fh: u64,
+        kernel_handle: u64,
+        events: PollEvents,
+        flags: FUSEPollFlags,
+    ) -> FuseResult<PollEvents> {
+        let request = Request::new(FUSE_POLL, ino)
+            .u64(fh)
+            .u64(kernel_handle)
+            .u32(flags.bits())
+            .u32(events.bits());
+        let data = self.call(request, 0)?;
+        Ok(PollEvents::from_bits_retain(Reply { data: &data }.u32()?))
+    }
+
+    /// Sets an extended attribute, `flags` takes `XATTR_CREATE` or `XATTR_REPLACE`
+    pub fn setxattr(&self, ino: u64, name: &OsStr, value: &[u8], flags: i32) -> FuseResult<()> {
+        self.call_empty(
+            Request::new(FUSE_SETXATTR, ino)
+                .u32(value.len() as u32)
+                .i32(flags)
+                .name(name)
+                .bytes(value),
+        )
+    }
+
+    /// Gets an extended attribute, a `size` of zero requests the size of the value
+    pub fn getxattr(&self, ino: u64, name: &OsStr, size: u32) -> FuseResult<XattrReply> {
+        let request = Request::new(FUSE_GETXATTR, ino).u32(size).u32(0).name(name);
+        let data = self.call(request, size as usize)?;
+        Reply { data: &data }.xattr(size)
+    }
+
+    /// Lists extended attributes, a `size` of zero requests the size of the list
+    pub fn listxattr(&self, ino: u64, size: u32) -> FuseResult<XattrReply> {
+        let data = self.call(
+            Request::new(FUSE_LISTXATTR, ino).u32(size).u32(0),
+            size as usize,
+        )?;
+        Reply { data: &data }.xattr(size)
+    }
+
+    pub fn removexattr(&self, ino: u64, name: &OsStr) -> FuseResult<()> {
+        self.call_empty(Request::new(FUSE_REMOVEXATTR, ino).name(name))
+    }
+
+    pub fn access(&self, ino: u64, mask: AccessMask) -> FuseResult<()> {
+        self.call_empty(Request::new(FUSE_ACCESS, ino).i32(mask.bits()).u32(0))
+    }
+
+    pub fn create(
+        &self,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: OpenFlags,
+    ) -> FuseResult<(Entry, u64, FUSEOpenResponseFlags)> {
+        let request = Request::new(FUSE_CREATE, parent)
+            .i32(flags.bits() | libc::O_CREAT)
+            .u32(mode)
+            .u32(umask)
+            .u32(0)
+            .name(name);
+        let data = self.call(request, 0)?;
+        let mut reply = Reply { data: &data };
+        let entry = reply.entry()?;
+        let (fh, open_flags) = reply.open()?;
+        Ok((entry, fh, open_flags))
+    }
+
+    pub fn fallocate(
+        &self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: FallocateFlags,
+    ) -> FuseResult<()> {
+        self.call_empty(
+            Request::new(FUSE_FALLOCATE, ino)
+                .u64(fh)
+                .i64(offset)
+                .i64(length)
+                .i32(mode.bits())
+                .u32(0),
+        )
+    }
+
+    /// Repositions the offset of a file, `whence` takes the `SEEK_*` constants of libc
+    pub fn lseek(&self, ino: u64, fh: u64, offset: i64, whence: i32) -> FuseResult<i64> {
+        let request = Request::new(FUSE_LSEEK, ino)
+            .u64(fh)
+            .i64(offset)
+            .i32(whence)
+            .u32(0);
+        let data = self.call(request, 0)?;
+        Reply { data: &data }.i64()
+    }
+
+    pub fn opendir(&self, ino: u64, flags: OpenFlags) -> FuseResult<(u64, FUSEOpenResponseFlags)> {
+        let data = self.call(Request::new(FUSE_OPENDIR, ino).i32(flags.bits()).u32(0), 0)?;
+        Reply { data: &data }.open()
+    }
+
+    fn readdir_request(opcode: u32, ino: u64, fh: u64, offset: i64, size: u32) -> Request {
+        Request::new(opcode, ino)
+            .u64(fh)
+            .i64(offset)
+            .u32(size)
+            .u32(0)
+            .u64(0)
+            .i32(0)
+            .u32(0)
+    }
+
+    /// Reads the entries following `offset` which fit in a buffer of `size` bytes
+    pub fn readdir(&self, ino: u64, fh: u64, offset: i64, size: u32) -> FuseResult<Vec<DirEntry>> {
+        let request = Self::readdir_request(FUSE_READDIR, ino, fh, offset, size);
+        let data = self.call(request, size as usize)?;
+        let mut reply = Reply { data: &data };
+        let mut entries = Vec::new();
+        while !reply.data.is_empty() {
+            entries.push(reply.dirent()?);
+        }
+        Ok(entries)
+    }
+
+    /// Reads the entries following `offset` which fit in a buffer of `size` bytes, with their lookup entry
+    pub fn readdirplus(
+        &self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+    ) -> FuseResult<Vec<(DirEntry, Entry)>> {
+        let request = Self::readdir_request(FUSE_READDIRPLUS, ino, fh, offset, size);
+        let data = self.call(request, size as usize)?;
+        let mut reply = Reply { data: &data };
+        let mut entries = Vec::new();
+        while !reply.data.is_empty() {
+            let entry = reply.entry()?;
+            entries.push((reply.dirent()?, entry));
+        }
+        Ok(entries)
+    }
+
+    pub fn releasedir(&self, ino: u64, fh: u64, flags: OpenFlags) -> FuseResult<()> {
+        self.call_empty(
+            Request::new(FUSE_RELEASEDIR, ino)
+                .u64(fh)
+                .i32(flags.bits())
+                .u32(0)
+                .u64(0),
+        )
+    }
+
+    /// Lists a whole directory with successive small `readdir` requests, as `ls` would
+    pub fn list_dir(&self, ino: u64) -> FuseResult<Vec<DirEntry>> {
+        let (fh, _) = self.opendir(ino, OpenFlags::READ_ONLY)?;
+        let mut result = Vec::new();
+        let mut offset = 0;
+        let listing = loop {
+            match self.readdir(ino, fh, offset, LIST_DIR_BUFFER_SIZE) {
+                Ok(entries) if entries.is_empty() => break Ok(result),
+                Ok(entries) => {
+                    offset = entries.last().unwrap().offset;
+                    result.extend(entries);
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        self.releasedir(ino, fh, OpenFlags::READ_ONLY)?;
+        listing
+    }
+}
+
+impl Drop for TestSession {
+    fn drop(&mut self) {
+        let _ = self.call_empty(Request::new(FUSE_DESTROY, 0));
+        if let Some(session) = self.session.take() {
+            // Shutting down our end of the socket pair ends the session loop
+            let client = self.client.get_mut().unwrap();
+            unsafe { libc::shutdown(client.socket.as_raw_fd(), libc::SHUT_RDWR) };
+            let _ = session.guard.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::memory_fs::MemoryFs;
+    #[cfg(not(feature = "serial"))]
+    use crate::templates::{mirror_fs::*, DefaultFuseHandler};
+    #[cfg(not(feature = "serial"))]
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_memory_fs_round_trip() {
+        let session = TestSession::new::<Inode, _>(MemoryFs::new()).unwrap();
+        assert_eq!(session.protocol_version().0, FUSE_KERNEL_VERSION);
+        let dir = session
+            .mkdir(ROOT_INO, OsStr::new("dir"), 0o755, 0)
+            .unwrap();
+        assert_eq!(dir.attr.kind, FileKind::Directory);
+        let (file, fh, _) = session
+            .create(dir.ino, OsStr::new("file"), 0o644, 0, OpenFlags::READ_WRITE)
+            .unwrap();
+        assert_eq!(
+            session
+                .write(file.ino, fh, 0, b"content", OpenFlags::READ_WRITE)
+                .unwrap(),
+            7
+        );
+        assert_eq!(session.read(file.ino, fh, 3, 100).unwrap(), b"tent");
+        session
+            .release(file.ino, fh, OpenFlags::READ_WRITE)
+            .unwrap();
+        assert_eq!(
+            session
+                .lookup_path(Path::new("dir/file"))
+                .unwrap()
+                .attr
+                .size,
+            7
+        );
+        let error = session.lookup(dir.ino, OsStr::new("missing")).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::FileNotFound);
+
+        session
+            .setxattr(file.ino, OsStr::new("user.key"), b"value", 0)
+            .unwrap();
+        assert_eq!(
+            session
+                .getxattr(file.ino, OsStr::new("user.key"), 0)
+                .unwrap(),
+            XattrReply::Size(5)
+        );
+        assert_eq!(
+            session
+                .getxattr(file.ino, OsStr::new("user.key"), 2)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ResultTooLarge
+        );
+    }
+
+    #[test]
+    fn test_lock_capabilities() {
+        use crate::templates::lock_manager::LockManager;
+
+        let posix_locks = fuser::consts::FUSE_POSIX_LOCKS as u32;
+        let flock_locks = fuser::consts::FUSE_FLOCK_LOCKS as u32;
+        // Locks are kept local by the kernel unless the handler asks for them
+        let session = TestSession::new::<Inode, _>(MemoryFs::new()).unwrap();
+        assert_eq!(session.init_flags() & (posix_locks | flock_locks), 0);
+
+        let session = TestSession::new::<Inode, _>(LockManager::new(MemoryFs::new())).unwrap();
+        assert_eq!(
+            session.init_flags() & (posix_locks | flock_locks),
+            posix_locks
+        );
+
+        let session = TestSession::new::<Inode, _>(
+            LockManager::new(MemoryFs::new()).with_remote_locks(LockKind::Flock),
+        )
+        .unwrap();
+        assert_eq!(
+            session.init_flags() & (posix_locks | flock_locks),
+            flock_locks
+        );
+    }
+
+    #[cfg(not(feature = "serial"))]
+    #[test]
+    fn test_blocking_locks() {
+        use crate::templates::lock_manager::LockManager;
+
+        let session = TestSession::from_builder(
+            MountBuilder::<Inode, _>::new(LockManager::new(MemoryFs::new())).num_threads(2),
+        )
+        .unwrap();
+        let (entry, fh, _) = session
+            .create(
+                ROOT_INO,
+                OsStr::new("file"),
+                0o644,
+                0,
+                OpenFlags::READ_WRITE,
+            )
+            .unwrap();
+        let lock = |lock_type| LockInfo {
+            start: 0,
+            end: i64::MAX as u64,
+            lock_type,
+            pid: 1,
+            kind: LockKind::Posix,
+        };
+        session
+            .setlk(entry.ino, fh, 1, lock(LockType::WRITE_LOCK), false)
+            .unwrap();
+
+        // More requests wait for the lock than there are threads handling the requests
+        let waiting: Vec<u64> = (2..6)
+            .map(|owner| {
+                session
+                    .send_setlk(entry.ino, fh, owner, lock(LockType::READ_LOCK), true)
+                    .unwrap()
+            })
+            .collect();
+        session.getattr(entry.ino, None).unwrap();
+        session
+            .setlk(entry.ino, fh, 1, lock(LockType::UNLOCKED), false)
+            .unwrap();
+        for unique in waiting {
+            session.wait_reply(unique).unwrap();
+        }
+        assert_eq!(
+            session
+                .setlk(entry.ino, fh, 1, lock(LockType::WRITE_LOCK), false)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ResourceUnavailableTryAgain
+        );
+    }
+
+    #[test]
+    fn test_readdir_pagination() {
+        let session = TestSession::new::<Inode, _>(MemoryFs::new()).unwrap();
+        let mut expected = vec![OsString::from("."), OsString::from("..")];
+        for i in 0..200 {
+            let name = OsString::from(format!("file_with_a_long_name_{:03}", i));
+            session
+                .mknod(ROOT_INO, &name, libc::S_IFREG | 0o644, 0, 0)
+                .unwrap();
+            expected.push(name);
+        }
+        let entries = session.list_dir(ROOT_INO).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.clone()).collect();
+        assert_eq!(names, expected);
+        let offsets: Vec<_> = entries.iter().map(|entry| entry.offset).collect();
+        assert_eq!(offsets, (1..=202).collect::<Vec<_>>());
+
+        // Resuming from an arbitrary offset
+        let (fh, _) = session.opendir(ROOT_INO, OpenFlags::READ_ONLY).unwrap();
+        let entries = session.readdir(ROOT_INO, fh, 100, 1024).unwrap();
+        assert_eq!(entries[0].name, expected[100]);
+        session
+            .releasedir(ROOT_INO, fh, OpenFlags::READ_ONLY)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_readdir_cursors() {
+        const ENTRIES: usize = 100;
+
+        /// Streams synthetic entries, counting the streams started and still alive
+        struct StreamFs {
+            inner: MemoryFs,
+            started: Arc<AtomicUsize>,
+            alive: Arc<AtomicUsize>,
+        }
+
+        struct Stream {
+            next: usize,
+            alive: Arc<AtomicUsize>,
+        }
+
+        impl Iterator for Stream {
+            type Item = FuseResult<(OsString, (Inode, FileKind))>;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                let i = self.next;
+                self.next += 1;
+                (i < ENTRIES).then(|| {
+                    let name = OsString::from(format!("entry_{:03}", i));
+                    Ok((name, (Inode::from(1000 + i as u64), FileKind::RegularFile)))
+                })
+            }
+        }
+
+        impl Drop for Stream {
+            fn drop(&mut self) {
+                self.alive.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+
+        impl FuseHandler<Inode> for StreamFs {
+            fn get_inner(&self) -> &dyn FuseHandler<Inode> {
+                &self.inner
+            }
+
+            fn readdir_iter(
+                &self,
+                _req: &RequestInfo,
+                _file_id: Inode,
+                _file_handle: BorrowedFileHandle,
+                offset: i64,
+            ) -> FuseResult<ReaddirIter<(Inode, FileKind)>> {
+                self.started.fetch_add(1, Ordering::SeqCst);
+                self.alive.fetch_add(1, Ordering::SeqCst);
+                Ok(Box::new(Stream {
+                    next: offset as usize,
+                    alive: self.alive.clone(),
+                }))
+            }
+        }
+
+        let (started, alive) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let session = TestSession::new::<Inode, _>(StreamFs {
+            inner: MemoryFs::new(),
+            started: started.clone(),
+            alive: alive.clone(),
+        })
+        .unwrap();
+        let expected: Vec<_> = (0..ENTRIES)
+            .map(|i| OsString::from(format!("entry_{:03}", i)))
+            .collect();
+
+        // Two handles of the same directory read at the same offset without sharing their stream
+        let (fh1, _) = session.opendir(ROOT_INO, OpenFlags::READ_ONLY).unwrap();
+        let (fh2, _) = session.opendir(ROOT_INO, OpenFlags::READ_ONLY).unwrap();
+        assert_ne!(fh1, fh2);
+        let first = session.readdir(ROOT_INO, fh1, 0, 256).unwrap();
+        assert!(first.len() < ENTRIES);
+        assert_eq!(session.readdir(ROOT_INO, fh2, 0, 256).unwrap(), first);
+
+        // Each full reply buffer is resumed from the stream of the handle
+        let mut names: Vec<_> = first.iter().map(|entry| entry.name.clone()).collect();
+        let mut offset = first.last().unwrap().offset;
+        loop {
+            let entries = session.readdir(ROOT_INO, fh1, offset, 256).unwrap();
+            let Some(last) = entries.last() else {
+                break;
+            };
+            offset = last.offset;
+            names.extend(entries.into_iter().map(|entry| entry.name));
+        }
+        assert_eq!(names, expected);
+        assert_eq!(started.load(Ordering::SeqCst), 2);
+        assert_eq!(alive.load(Ordering::SeqCst), 2);
+
+        // Reading at another offset starts a new stream in place of the previous one
+        let entries = session.readdir(ROOT_INO, fh2, 50, 256).unwrap();
+        assert_eq!(entries[0].name, expected[50]);
+        assert_eq!(started.load(Ordering::SeqCst), 3);
+        assert_eq!(alive.load(Ordering::SeqCst), 2);
+
+        // Streams end with their handle, read to the end or not
+        session
+            .releasedir(ROOT_INO, fh1, OpenFlags::READ_ONLY)
+            .unwrap();
+        session
+            .releasedir(ROOT_INO, fh2, OpenFlags::READ_ONLY)
+            .unwrap();
+        assert_eq!(alive.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_write_payload() {
+        /// Records how the payload of each write is given to the handler
+        struct PayloadFs {
+            inner: MemoryFs,
+            pooled: bool,
+            payloads: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl FuseHandler<Inode> for PayloadFs {
+            fn get_inner(&self) -> &dyn FuseHandler<Inode> {
+                &self.inner
+            }
+
+            fn pooled_writes(&self) -> bool {
+                self.pooled
+            }
+
+            fn write_buf(
+                &self,
+                req: &RequestInfo,
+                file_id: Inode,
+                file_handle: BorrowedFileHandle,
+                seek: SeekFrom,
+                data: WriteData,
+                write_flags: FUSEWriteFlags,
+                flags: OpenFlags,
+                lock_owner: Option<u64>,
+            ) -> FuseResult<u32> {
+                self.payloads.lock().unwrap().push(format!("{:?}", data));
+                // MemoryFs only implements `write`, which takes the payload by value
+                FuseHandler::write_buf(
+                    &self.inner,
+                    req,
+                    file_id,
+                    file_handle,
+                    seek,
+                    data,
+                    write_flags,
+                    flags,
+                    lock_owner,
+                )
+            }
+        }
+
+        for pooled in [true, false] {
+            let payloads = Arc::new(Mutex::new(Vec::new()));
+            let session = TestSession::new::<Inode, _>(PayloadFs {
+                inner: MemoryFs::new(),
+                pooled,
+                payloads: payloads.clone(),
+            })
+            .unwrap();
+            let (entry, fh, _) = session
+                .create(
+                    ROOT_INO,
+                    OsStr::new("file"),
+                    0o644,
+                    0,
+                    OpenFlags::READ_WRITE,
+                )
+                .unwrap();
+            let payload = vec![0xa5u8; 64 * 1024];
+            for offset in [0, 64 * 1024] {
+                assert_eq!(
+                    session
+                        .write(entry.ino, fh, offset, &payload, OpenFlags::READ_WRITE)
+                        .unwrap(),
+                    payload.len() as u32
+                );
+            }
+            assert_eq!(
+                session.read(entry.ino, fh, 64 * 1024, 64 * 1024).unwrap(),
+                payload
+            );
+            // The payload is borrowed from the request unless it is handled by another thread, then it is
+            // copied once, into a pooled buffer only if the handler writes from it
+            let expected = if cfg!(feature = "serial") {
+                "Borrowed(65536 bytes)"
+            } else if pooled {
+                "Pooled(65536 bytes)"
+            } else {
+                "Owned(65536 bytes)"
+            };
+            assert_eq!(*payloads.lock().unwrap(), vec![expected; 2]);
+        }
+    }
+
+    #[test]
+    fn test_wrapped_buffers_and_streams() {
+        /// Replies reads from a shared buffer, lists synthetic entries and records the write payloads
+        struct BufferFs {
+            inner: MemoryFs,
+            content: Arc<[u8]>,
+            payloads: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl FuseHandler<Inode> for BufferFs {
+            fn get_inner(&self) -> &dyn FuseHandler<Inode> {
+                &self.inner
+            }
+
+            fn read_buf(
+                &self,
+                _req: &RequestInfo,
+                _file_id: Inode,
+                _file_handle: BorrowedFileHandle,
+                _seek: SeekFrom,
+                _size: u32,
+                _flags: FUSEOpenFlags,
+                _lock_owner: Option<u64>,
+            ) -> FuseResult<ReadData> {
+                Ok(ReadData::from(self.content.clone()))
+            }
+
+            fn readdir_iter(
+                &self,
+                _req: &RequestInfo,
+                _file_id: Inode,
+                _file_handle: BorrowedFileHandle,
+                offset: i64,
+            ) -> FuseResult<ReaddirIter<(Inode, FileKind)>> {
+                let entries = (0..3).map(|i| {
+                    let name = OsString::from(format!("entry_{}", i));
+                    Ok((name, (Inode::from(1000 + i), FileKind::RegularFile)))
+                });
+                Ok(Box::new(entries.skip(offset as usize)))
+            }
+
+            fn write_buf(
+                &self,
+                req: &RequestInfo,
+                file_id: Inode,
+                file_handle: BorrowedFileHandle,
+                seek: SeekFrom,
+                data: WriteData,
+                write_flags: FUSEWriteFlags,
+                flags: OpenFlags,
+                lock_owner: Option<u64>,
+            ) -> FuseResult<u32> {
+                self.payloads.lock().unwrap().push(format!("{:?}", data));
+                FuseHandler::write_buf(
+                    &self.inner,
+                    req,
+                    file_id,
+                    file_handle,
+                    seek,
+                    data,
+                    write_flags,
+                    flags,
+                    lock_owner,
+                )
+            }
+        }
+
+        /// Only delegates to its inner handler
+        struct Wrapper {
+            inner: BufferFs,
+        }
+
+        impl FuseHandler<Inode> for Wrapper {
+            fn get_inner(&self) -> &dyn FuseHandler<Inode> {
+                &self.inner
+            }
+        }
+
+        let payloads = Arc::new(Mutex::new(Vec::new()));
+        let session = TestSession::new::<Inode, _>(Wrapper {
+            inner: BufferFs {
+                inner: MemoryFs::new(),
+                content: Arc::from(&b"shared"[..]),
+                payloads: payloads.clone(),
+            },
+        })
+        .unwrap();
+        let (entry, fh, _) = session
+            .create(
+                ROOT_INO,
+                OsStr::new("file"),
+                0o644,
+                0,
+                OpenFlags::READ_WRITE,
+            )
+            .unwrap();
+
+        // The wrapper uses the zero-copy and streaming methods of its inner handler
+        session
+            .write(entry.ino, fh, 0, b"hello", OpenFlags::READ_WRITE)
+            .unwrap();
+        assert_eq!(payloads.lock().unwrap().len(), 1);
+        assert_eq!(session.read(entry.ino, fh, 0, 4096).unwrap(), b"shared");
+        let names: Vec<_> = session
+            .list_dir(ROOT_INO)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, ["entry_0", "entry_1", "entry_2"]);
+    }
+
+    #[test]
+    fn test_poll_wakeup() {
+        /// Files become readable once `ready` is set, waking up the latest poll handle
+        struct EventFs {
+            inner: MemoryFs,
+            ready: Arc<AtomicBool>,
+            waiting: Arc<Mutex<Option<PollHandle>>>,
+        }
+
+        impl FuseHandler<Inode> for EventFs {
+            fn get_inner(&self) -> &dyn FuseHandler<Inode> {
+                &self.inner
+            }
+
+            fn poll(
+                &self,
+                _req: &RequestInfo,
+                _file_id: Inode,
+                _file_handle: BorrowedFileHandle,
+                poll_handle: PollHandle,
+                _events: PollEvents,
+                flags: FUSEPollFlags,
+            ) -> FuseResult<PollEvents> {
+                if self.ready.load(Ordering::SeqCst) {
+                    return Ok(PollEvents::READABLE);
+                }
+                if flags.contains(FUSEPollFlags::SCHEDULE_NOTIFY) {
+                    *self.waiting.lock().unwrap() = Some(poll_handle);
+                }
+                Ok(PollEvents::empty())
+            }
+        }
+
+        let ready = Arc::new(AtomicBool::new(false));
+        let waiting = Arc::new(Mutex::new(None));
+        let session = TestSession::new::<Inode, _>(EventFs {
+            inner: MemoryFs::new(),
+            ready: ready.clone(),
+            waiting: waiting.clone(),
+        })
+        .unwrap();
+        let (entry, fh, _) = session
+            .create(
+                ROOT_INO,
+                OsStr::new("events"),
+                0o644,
+                0,
+                OpenFlags::READ_WRITE,
+            )
+            .unwrap();
+
+        let events = session
+            .poll(
+                entry.ino,
+                fh,
+                42,
+                PollEvents::READABLE,
+                FUSEPollFlags::SCHEDULE_NOTIFY,
+            )
+            .unwrap();
+        assert!(events.is_empty());
+        assert!(session.take_notifications().is_empty());
+
+        // The handler wakes up the kernel once the file becomes ready
+        ready.store(true, Ordering::SeqCst);
+        let poll_handle: PollHandle = waiting.lock().unwrap().take().unwrap();
+        poll_handle.notify().unwrap();
+        let notifications = session.take_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].code, 1);
+        assert_eq!(notifications[0].data, 42u64.to_ne_bytes());
+
+        let events = session
+            .poll(
+                entry.ino,
+                fh,
+                42,
+                PollEvents::READABLE,
+                FUSEPollFlags::empty(),
+            )
+            .unwrap();
+        assert_eq!(events.bits(), PollEvents::READABLE.bits());
+    }
+
+    #[test]
+    fn test_custom_file_id() {
+        use crate::templates::DefaultFuseHandler;
+        use std::collections::HashMap;
+
+        /// Key of an object of a flat store, the root directory being the empty key
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        struct ObjectKey(String);
+
+        impl FileIdType for ObjectKey {
+            type _Id = ObjectKey;
+            type Metadata = (ObjectKey, FileAttribute);
+            type MinimalMetadata = (ObjectKey, FileKind);
+
+            fn display(&self) -> impl std::fmt::Display {
+                self.0.clone()
+            }
+
+            fn is_filesystem_root(&self) -> bool {
+                self.0.is_empty()
+            }
+
+            fn extract_metadata(metadata: Self::Metadata) -> (ObjectKey, FileAttribute) {
+                metadata
+            }
+
+            fn extract_minimal_metadata(metadata: Self::MinimalMetadata) -> (ObjectKey, FileKind) {
+                metadata
+            }
+        }
+
+        struct ObjectInodes {
+            /// Key and lookup count of each inode
+            inodes: HashMap<u64, (ObjectKey, u64)>,
+            keys: HashMap<ObjectKey, u64>,
+            next_inode: u64,
+        }
+
+        /// Attributes inode numbers to keys as they are looked up, and releases them when forgotten
+        struct ObjectResolver {
+            inodes: Mutex<ObjectInodes>,
+        }
+
+        impl FileIdResolver for ObjectResolver {
+            type ResolvedType = ObjectKey;
+
+            fn new() -> Self {
+                let root = ObjectKey(String::new());
+                ObjectResolver {
+                    inodes: Mutex::new(ObjectInodes {
+                        inodes: HashMap::from([(ROOT_INO, (root.clone(), 1))]),
+                        keys: HashMap::from([(root, ROOT_INO)]),
+                        next_inode: ROOT_INO + 1,
+                    }),
+                }
+            }
+
+            fn resolve_id(&self, ino: u64) -> FuseResult<ObjectKey> {
+                match self.inodes.lock().unwrap().inodes.get(&ino) {
+                    Some((key, _)) => Ok(key.clone()),
+                    None => Err(ErrorKind::StaleFileHandle.to_error(format!("inode {}", ino))),
+                }
+            }
+
+            fn find_ino(&self, key: &ObjectKey) -> Option<u64> {
+                self.inodes.lock().unwrap().keys.get(key).copied()
+            }
+
+            fn lookup(
+                &self,
+                _parent: u64,
+                _child: &OsStr,
+                key: ObjectKey,
+                increment: bool,
+            ) -> FuseResult<u64> {
+                let mut guard = self.inodes.lock().unwrap();
+                let ObjectInodes {
+                    inodes,
+                    keys,
+                    next_inode,
+                } = &mut *guard;
+                let ino = *keys.entry(key.clone()).or_insert_with(|| {
+                    *next_inode += 1;
+                    *next_inode - 1
+                });
+                inodes.entry(ino).or_insert((key, 0)).1 += increment as u64;
+                Ok(ino)
+            }
+
+            fn add_children(
+                &self,
+                parent: u64,
+                children: Vec<(OsString, ObjectKey)>,
+                increment: bool,
+            ) -> FuseResult<Vec<(OsString, u64)>> {
+                children
+                    .into_iter()
+                    .map(|(name, key)| {
+                        Ok((name.clone(), self.lookup(parent, &name, key, increment)?))
+                    })
+                    .collect()
+            }
+
+            fn forget(&self, ino: u64, nlookup: u64) -> FuseResult<()> {
+                let mut guard = self.inodes.lock().unwrap();
+                let ObjectInodes { inodes, keys, .. } = &mut *guard;
+                if let Some((key, count)) = inodes.get_mut(&ino) {
+                    *count = count.saturating_sub(nlookup);
+                    if *count == 0 && ino != ROOT_INO {
+                        keys.remove(key);
+                        inodes.remove(&ino);
+                    }
+                }
+                Ok(())
+            }
+
+            fn rename(
+                &self,
+                _parent: u64,
+                _name: &OsStr,
+                _newparent: u64,
+                _newname: &OsStr,
+            ) -> FuseResult<()> {
+                Ok(())
+            }
+        }
+
+        impl InodeResolvable for ObjectKey {
+            type Resolver = ObjectResolver;
+
+            fn create_resolver() -> ObjectResolver {
+                ObjectResolver::new()
+            }
+        }
+
+        /// Read-only store of two objects
+        struct ObjectFs {
+            inner: DefaultFuseHandler,
+        }
+
+        impl ObjectFs {
+            fn attr(&self, key: &ObjectKey) -> FuseResult<FileAttribute> {
+                let (kind, size) = match key.0.as_str() {
+                    "" => (FileKind::Directory, 0),
+                    "alpha" => (FileKind::RegularFile, 5),
+                    "beta" => (FileKind::RegularFile, 4),
+                    _ => return Err(ErrorKind::FileNotFound.to_error(key.0.clone())),
+                };
+                Ok(FileAttribute {
+                    size,
+                    blocks: 0,
+                    atime: UNIX_EPOCH,
+                    mtime: UNIX_EPOCH,
+                    ctime: UNIX_EPOCH,
+                    crtime: UNIX_EPOCH,
+                    kind,
+                    perm: 0o755,
+                    nlink: 1,
+                    uid: 0,
+                    gid: 0,
+                    rdev: 0,
+                    blksize: 4096,
+                    flags: 0,
+                    ttl: None,
+                    generation: None,
+                })
+            }
+        }
+
+        impl FuseHandler<ObjectKey> for ObjectFs {
+            fn get_inner(&self) -> &dyn FuseHandler<ObjectKey> {
+                &self.inner
+            }
+
+            fn lookup(
+                &self,
+                _req: &RequestInfo,
+                _parent_id: ObjectKey,
+                name: &OsStr,
+            ) -> FuseResult<(ObjectKey, FileAttribute)> {
+                let key = ObjectKey(name.to_string_lossy().into_owned());
+                let attr = self.attr(&key)?;
+                Ok((key, attr))
+            }
+
+            fn getattr(
+                &self,
+                _req: &RequestInfo,
+                file_id: ObjectKey,
+                _file_handle: Option<BorrowedFileHandle>,
+            ) -> FuseResult<FileAttribute> {
+                self.attr(&file_id)
+            }
+
+            fn readdir(
+                &self,
+                _req: &RequestInfo,
+                file_id: ObjectKey,
+                _file_handle: BorrowedFileHandle,
+            ) -> FuseResult<Vec<(OsString, (ObjectKey, FileKind))>> {
+                Ok(vec![
+                    (OsString::from("."), (file_id.clone(), FileKind::Directory)),
+                    (OsString::from(".."), (file_id, FileKind::Directory)),
+                    (
+                        OsString::from("alpha"),
+                        (ObjectKey("alpha".into()), FileKind::RegularFile),
+                    ),
+                    (
+                        OsString::from("beta"),
+                        (ObjectKey("beta".into()), FileKind::RegularFile),
+                    ),
+                ])
+            }
+        }
+
+        let session = TestSession::new::<ObjectKey, _>(ObjectFs {
+            inner: DefaultFuseHandler::new(),
+        })
+        .unwrap();
+        let alpha = session.lookup(ROOT_INO, OsStr::new("alpha")).unwrap();
+        assert_eq!(alpha.attr.size, 5);
+        assert_eq!(session.getattr(alpha.ino, None).unwrap().size, 5);
+        assert_eq!(
+            session.lookup(ROOT_INO, OsStr::new("alpha")).unwrap().ino,
+            alpha.ino
+        );
+        let beta = session.lookup(ROOT_INO, OsStr::new("beta")).unwrap();
+        assert_ne!(beta.ino, alpha.ino);
+        assert_eq!(
+            session
+                .lookup(ROOT_INO, OsStr::new("gamma"))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::FileNotFound
+        );
+        let entries = session.list_dir(ROOT_INO).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| entry.name.clone()).collect();
+        assert_eq!(names, vec![".", "..", "alpha", "beta"]);
+        assert_eq!(entries[2].ino, alpha.ino);
+
+        // The resolver releases the inode once every lookup is forgotten
+        session.forget(alpha.ino, 2).unwrap();
+        let released = (0..1000).any(|_| {
+            let stale = session.getattr(alpha.ino, None).is_err();
+            if !stale {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            stale
+        });
+        assert!(released);
+        assert_eq!(
+            session.getattr(alpha.ino, None).unwrap_err().kind(),
+            ErrorKind::StaleFileHandle
+        );
+    }
+
+    #[test]
+    fn test_negative_lookup() {
+        struct ProbedFs {
+            inner: MemoryFs,
+        }
+
+        impl FuseHandler<Inode> for ProbedFs {
+            fn get_inner(&self) -> &dyn FuseHandler<Inode> {
+                &self.inner
+            }
+
+            fn get_negative_ttl(&self) -> Option<Duration> {
+                Some(Duration::from_secs(2))
+            }
+
+            fn lookup(
+                &self,
+                req: &RequestInfo,
+                parent_id: Inode,
+                name: &OsStr,
+            ) -> FuseResult<(Inode, FileAttribute)> {
+                if name == "probe" {
+                    return Err(ErrorKind::FileNotFound
+                        .to_error("probe")
+                        .with_negative_ttl(Duration::from_secs(7)));
+                }
+                FuseHandler::lookup(&self.inner, req, parent_id, name)
+            }
+        }
+
+        // Without negative TTL, missing entries are errors
+        let session = TestSession::new::<Inode, _>(MemoryFs::new()).unwrap();
+        let error = session.lookup(ROOT_INO, OsStr::new("missing")).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::FileNotFound);
+
+        let session = TestSession::new::<Inode, _>(ProbedFs {
+            inner: MemoryFs::new(),
+        })
+        .unwrap();
+        let entry = session.lookup(ROOT_INO, OsStr::new("missing")).unwrap();
+        assert_eq!((entry.ino, entry.entry_ttl), (0, Duration::from_secs(2)));
+        let entry = session.lookup(ROOT_INO, OsStr::new("probe")).unwrap();
+        assert_eq!((entry.ino, entry.entry_ttl), (0, Duration::from_secs(7)));
+        assert_eq!(
+            session
+                .lookup_path(Path::new("missing/file"))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::FileNotFound
+        );
+        // Other errors are not cached
+        session
+            .mknod(ROOT_INO, OsStr::new("file"), libc::S_IFREG | 0o644, 0, 0)
+            .unwrap();
+        let file = session.lookup(ROOT_INO, OsStr::new("file")).unwrap();
+        assert!(session.lookup(file.ino, OsStr::new("child")).is_err());
+
+        // The TTL of the builder takes precedence over the handler
+        let session = TestSession::from_builder(
+            MountBuilder::new(ProbedFs {
+                inner: MemoryFs::new(),
+            })
+            .negative_ttl(Duration::from_secs(5)),
+        )
+        .unwrap();
+        let entry = session.lookup(ROOT_INO, OsStr::new("missing")).unwrap();
+        assert_eq!(entry.entry_ttl, Duration::from_secs(5));
+        let entry = session.lookup(ROOT_INO, OsStr::new("probe")).unwrap();
+        assert_eq!(entry.entry_ttl, Duration::from_secs(7));
+    }
+
+    // MirrorFs is not `Send` with the serial feature
+    #[cfg(not(feature = "serial"))]
+    #[test]
+    fn test_inode_store_remount() {
+        let source = tempfile::TempDir::new().unwrap();
+        let store_dir = tempfile::TempDir::new().unwrap();
+        let store_path = store_dir.path().join("inodes");
+        std::fs::create_dir_all(source.path().join("a/b")).unwrap();
+        std::fs::write(source.path().join("a/b/file"), "").unwrap();
+        let mount = || {
+            let handler = MirrorFs::new(source.path().to_path_buf(), DefaultFuseHandler::new());
+            let store = InodeStore::open(&store_path).unwrap();
+            TestSession::with_inode_store::<PathBuf, _>(handler, store).unwrap()
+        };
+
+        let session = mount();
+        // Names looked up in a different order get the same inode numbers after a remount
+        let other = session
+            .mkdir(ROOT_INO, OsStr::new("other"), 0o755, 0)
+            .unwrap();
+        let file = session.lookup_path(Path::new("a/b/file")).unwrap();
+        let generation = file.generation;
+        let b = session.lookup_path(Path::new("a/b")).unwrap();
+        session
+            .rename(
+                b.ino,
+                OsStr::new("file"),
+                other.ino,
+                OsStr::new("moved"),
+                RenameFlags::empty(),
+            )
+            .unwrap();
+        drop(session);
+
+        let session = mount();
+        let moved = session.lookup_path(Path::new("other/moved")).unwrap();
+        assert_eq!(moved.ino, file.ino);
+        assert_eq!(moved.generation, generation);
+        assert_eq!(session.lookup_path(Path::new("a/b")).unwrap().ino, b.ino);
+        assert_eq!(
+            session.lookup(ROOT_INO, OsStr::new("other")).unwrap().ino,
+            other.ino
+        );
+
+        // A file created again after its deletion gets its inode number back, with a new generation
+        session.unlink(other.ino, OsStr::new("moved")).unwrap();
+        let (recreated, fh, _) = session
+            .create(
+                other.ino,
+                OsStr::new("moved"),
+                0o644,
+                0,
+                OpenFlags::WRITE_ONLY,
+            )
+            .unwrap();
+        session
+            .release(recreated.ino, fh, OpenFlags::WRITE_ONLY)
+            .unwrap();
+        assert_eq!(recreated.ino, file.ino);
+        assert_ne!(recreated.generation, generation);
+        drop(session);
+        let session = mount();
+        let moved = session.lookup_path(Path::new("other/moved")).unwrap();
+        assert_eq!(moved.ino, file.ino);
+        assert_eq!(moved.generation, recreated.generation);
+        drop(session);
+
+        assert!(TestSession::with_inode_store::<Inode, _>(
+            MemoryFs::new(),
+            InodeStore::open(&store_path).unwrap()
+        )
+        .is_err());
+    }
+
+    // MirrorFs is not `Send` with the serial feature
+    #[cfg(not(feature = "serial"))]
+    #[test]
+    fn test_notifier_known_entries() {
+        use std::sync::OnceLock;
+
+        /// Keeps the notifier given by init
+        struct NotifiedFs {
+            inner: MirrorFs,
+            notifier: Arc<OnceLock<Notifier<PathBuf>>>,
+        }
+
+        impl FuseHandler<PathBuf> for NotifiedFs {
+            fn get_inner(&self) -> &dyn FuseHandler<PathBuf> {
+                &self.inner
+            }
+
+            fn init(
+                &self,
+                req: &RequestInfo,
+                config: &mut KernelConfig,
+                notifier: Notifier<PathBuf>,
+            ) -> FuseResult<()> {
+                let _ = self.notifier.set(notifier.clone());
+                FuseHandler::init(&self.inner, req, config, notifier)
+            }
+        }
+
+        let source = tempfile::TempDir::new().unwrap();
+        std::fs::write(source.path().join("file"), "data").unwrap();
+        let notifier = Arc::new(OnceLock::new());
+        let session = TestSession::new::<PathBuf, _>(NotifiedFs {
+            inner: MirrorFs::new(source.path().to_path_buf(), DefaultFuseHandler::new()),
+            notifier: notifier.clone(),
+        })
+        .unwrap();
+        let notifier = notifier.get().unwrap();
+        let codes = || -> Vec<i32> {
+            session
+                .take_notifications()
+                .into_iter()
+                .map(|notification| notification.code)
+                .collect()
+        };
+
+        // Entries listed by readdir are not known by the kernel
+        session.list_dir(ROOT_INO).unwrap();
+        notifier.inval_inode(PathBuf::from("file"), 0, 0).unwrap();
+        assert_eq!(codes(), Vec::<i32>::new());
+
+        let entry = session.lookup(ROOT_INO, OsStr::new("file")).unwrap();
+        notifier.inval_inode(PathBuf::from("file"), 0, 0).unwrap();
+        notifier
+            .inval_entry(PathBuf::new(), OsStr::new("file"))
+            .unwrap();
+        assert_eq!(codes(), vec![2, 3]);
+
+        // Nor are they once forgotten
+        session.forget(entry.ino, 1).unwrap();
+        // Forget has no reply, wait until the driver processed it
+        for _ in 0..1000 {
+            if notifier.resolver_stats().referenced_entries == 1 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        notifier.inval_inode(PathBuf::from("file"), 0, 0).unwrap();
+        assert_eq!(codes(), Vec::<i32>::new());
+    }
+
+    // MirrorFs is not `Send` with the serial feature
+    #[cfg(not(feature = "serial"))]
+    #[test]
+    fn test_path_resolver() {
+        let source = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(source.path().join("sub")).unwrap();
+        std::fs::write(source.path().join("sub/data.txt"), "mirrored").unwrap();
+        let handler = MirrorFs::new(source.path().to_path_buf(), DefaultFuseHandler::new());
+        let session = TestSession::new::<PathBuf, _>(handler).unwrap();
+
+        let entry = session.lookup_path(Path::new("sub/data.txt")).unwrap();
+        let (fh, _) = session.open(entry.ino, OpenFlags::READ_ONLY).unwrap();
+        assert_eq!(session.read(entry.ino, fh, 0, 100).unwrap(), b"mirrored");
+        session
+            .release(entry.ino, fh, OpenFlags::READ_ONLY)
+            .unwrap();
+
+        let sub = session.lookup(ROOT_INO, OsStr::new("sub")).unwrap();
+        session
+            .rename(
+                sub.ino,
+                OsStr::new("data.txt"),
+                ROOT_INO,
+                OsStr::new("moved.txt"),
+                RenameFlags::empty(),
+            )
+            .unwrap();
+        assert!(source.path().join("moved.txt").exists());
+        let names: Vec<_> = session
+            .list_dir(ROOT_INO)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert!(names.contains(&OsString::from("moved.txt")));
+
+        // Inodes unknown to the resolver are rejected without bringing the filesystem down
+        assert_eq!(
+            session.getattr(0xdead, None).unwrap_err().kind(),
+            ErrorKind::StaleFileHandle
+        );
+        session.forget(0xdead, 1).unwrap();
+        assert!(session.lookup(ROOT_INO, OsStr::new("moved.txt")).is_ok());
+    }
+}
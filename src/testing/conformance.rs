@@ -0,0 +1,1004 @@
+//! POSIX conformance checks runnable against any handler.
+//!
+//! [`run_conformance_suite`] runs a battery of checks through a [`TestSession`], the results being reported per check
+//! in a [`ConformanceReport`]:
+//!
+//! - `create_write_read`: data written through a handle is read back, from the same and from a new handle.
+//! - `write_offsets_and_truncate`: writes past the end of file leave a hole of zeros, truncation shrinks and extends.
+//! - `rename_replace`: rename atomically replaces an existing file.
+//! - `rename_noreplace`: `RenameFlags::NOREPLACE` fails with EEXIST when the target exists.
+//! - `rename_exchange`: `RenameFlags::EXCHANGE` swaps two entries, and fails with ENOENT when one is missing.
+//! - `rename_directory`: directories cannot be moved into themselves nor replace non-empty directories or files.
+//! - `unlink_while_open`: an unlinked file stays readable and writable through its open handles.
+//! - `hard_link`: a hard link has the inode number of its file, and `nlink` counts the names of the file.
+//! - `rmdir_non_empty`: rmdir fails with ENOTEMPTY on non-empty directories.
+//! - `readdir_offsets`: directories listed with small buffers are complete, and listings resume at any offset.
+//! - `xattr_size_negotiation`: getxattr and listxattr report sizes and fail with ERANGE on small buffers.
+//! - `lseek_data_hole`: `SEEK_DATA` and `SEEK_HOLE` report data and holes of sparse files.
+//!
+//! Checks are skipped, rather than failed, when an operation they need fails with ENOSYS or EOPNOTSUPP.
+//!
+//! Some checks need a minimal version of the FUSE protocol, which depends on the `abi-7-*` features fuser is compiled
+//! with: `rename_noreplace` and `rename_exchange` need `abi-7-23` (rename with flags), and `lseek_data_hole` needs
+//! `abi-7-24` (lseek). Below those versions fuser never forwards the requests to the handler, so these checks are
+//! skipped. The `abi-7-31` feature of this crate enables every check. [`ConformanceReport::protocol_version`] gives
+//! the version the checks ran with.
+//!
+//! Each check starts a new session with a handler from the constructor, and works in a directory named
+//! `conformance-<check>` created at the root of the filesystem.
+//!
+//! # Example
+//!
+//! ```
+//! use easy_fuser::prelude::*;
+//! use easy_fuser::templates::memory_fs::MemoryFs;
+//! use easy_fuser::testing::conformance::run_conformance_suite;
+//!
+//! let report = run_conformance_suite::<Inode, _, _>(MemoryFs::new);
+//! println!("{}", report);
+//! assert!(report.is_success());
+//! ```
+
+use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Display};
+use std::io;
+
+use super::{DirEntry, Entry, TestSession, XattrReply};
+use crate::core::FuseDriverHandler;
+use crate::prelude::*;
+
+/// Outcome of a single check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Passed,
+    /// The handler does not conform, with a description of the violation
+    Failed(String),
+    /// The check could not run because an operation it needs is not implemented
+    Skipped(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+}
+
+/// Results of the conformance suite, in the order the checks ran
+#[derive(Debug, Clone, Default)]
+pub struct ConformanceReport {
+    results: Vec<CheckResult>,
+    protocol_version: (u32, u32),
+}
+
+impl ConformanceReport {
+    pub fn results(&self) -> &[CheckResult] {
+        &self.results
+    }
+
+    /// Version of the FUSE protocol negotiated by the sessions, which decides the checks run
+    pub fn protocol_version(&self) -> (u32, u32) {
+        self.protocol_version
+    }
+
+    /// Returns the outcome of the check named `name`
+    pub fn outcome(&self, name: &str) -> Option<&CheckOutcome> {
+        self.results
+            .iter()
+            .find(|result| result.name == name)
+            .map(|result| &result.outcome)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.results
+            .iter()
+            .filter(|result| matches!(result.outcome, CheckOutcome::Failed(_)))
+    }
+
+    /// Returns true if no check failed, skipped checks are not counted as failures
+    pub fn is_success(&self) -> bool {
+        self.failures().next().is_none()
+    }
+}
+
+impl Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+        for result in &self.results {
+            match &result.outcome {
+                CheckOutcome::Passed => {
+                    passed += 1;
+                    writeln!(f, "PASS {}", result.name)?
+                }
+                CheckOutcome::Failed(reason) => {
+                    failed += 1;
+                    writeln!(f, "FAIL {}: {}", result.name, reason)?
+                }
+                CheckOutcome::Skipped(reason) => {
+                    skipped += 1;
+                    writeln!(f, "SKIP {}: {}", result.name, reason)?
+                }
+            }
+        }
+        write!(
+            f,
+            "{} passed, {} failed, {} skipped",
+            passed, failed, skipped
+        )
+    }
+}
+
+type Check = fn(&TestSession, u64) -> Result<(), CheckOutcome>;
+
+/// Minor version of the FUSE protocol introducing rename with flags
+const RENAME2_MINOR_VERSION: u32 = 23;
+/// Minor version of the FUSE protocol introducing lseek
+const LSEEK_MINOR_VERSION: u32 = 24;
+
+/// Name of each check, the minor version of the protocol it needs and the check itself
+const CHECKS: &[(&str, u32, Check)] = &[
+    ("create_write_read", 0, check_create_write_read),
+    (
+        "write_offsets_and_truncate",
+        0,
+        check_write_offsets_and_truncate,
+    ),
+    ("rename_replace", 0, check_rename_replace),
+    (
+        "rename_noreplace",
+        RENAME2_MINOR_VERSION,
+        check_rename_noreplace,
+    ),
+    (
+        "rename_exchange",
+        RENAME2_MINOR_VERSION,
+        check_rename_exchange,
+    ),
+    ("rename_directory", 0, check_rename_directory),
+    ("unlink_while_open", 0, check_unlink_while_open),
+    ("hard_link", 0, check_hard_link),
+    ("rmdir_non_empty", 0, check_rmdir_non_empty),
+    ("readdir_offsets", 0, check_readdir_offsets),
+    ("xattr_size_negotiation", 0, check_xattr_size_negotiation),
+    (
+        "lseek_data_hole",
+        LSEEK_MINOR_VERSION,
+        check_lseek_data_hole,
+    ),
+];
+
+/// Runs every check against handlers built by `new_handler`, see the module documentation.
+pub fn run_conformance_suite<TId, THandler, F>(new_handler: F) -> ConformanceReport
+where
+    TId: FileIdType,
+    THandler: FuseDriverHandler<TId> + Send,
+    F: Fn() -> THandler,
+{
+    let mut report = ConformanceReport::default();
+    for (name, minor_version, check) in CHECKS {
+        let outcome = match TestSession::new::<TId, _>(new_handler()) {
+            Ok(session) => {
+                report.protocol_version = session.protocol_version();
+                if report.protocol_version.1 < *minor_version {
+                    CheckOutcome::Skipped(format!("needs FUSE protocol 7.{}", minor_version))
+                } else {
+                    run_check(&session, name, *check)
+                }
+            }
+            Err(e) => CheckOutcome::Failed(format!("session could not start: {}", e)),
+        };
+        report.results.push(CheckResult { name, outcome });
+    }
+    report
+}
+
+fn run_check(session: &TestSession, name: &str, check: Check) -> CheckOutcome {
+    let directory = OsString::from(format!("conformance-{}", name));
+    let result = call(
+        session.mkdir(TestSession::ROOT_INO, &directory, 0o755, 0),
+        "mkdir of the working directory",
+    )
+    .and_then(|entry| check(session, entry.ino));
+    match result {
+        Ok(()) => CheckOutcome::Passed,
+        Err(outcome) => outcome,
+    }
+}
+
+fn is_unsupported(error: &PosixError) -> bool {
+    matches!(error.raw_error(), libc::ENOSYS | libc::EOPNOTSUPP)
+}
+
+/// Converts the result of an operation, skipping the check if the operation is not implemented
+fn call<T>(result: FuseResult<T>, operation: &str) -> Result<T, CheckOutcome> {
+    result.map_err(|e| {
+        if is_unsupported(&e) {
+            CheckOutcome::Skipped(format!("{} is not supported ({})", operation, e))
+        } else {
+            CheckOutcome::Failed(format!("{} failed: {}", operation, e))
+        }
+    })
+}
+
+/// Expects an operation to fail with `errno`
+fn expect_errno<T>(result: FuseResult<T>, errno: i32, operation: &str) -> Result<(), CheckOutcome> {
+    let expected = io::Error::from_raw_os_error(errno);
+    match result {
+        Err(e) if e.raw_error() == errno => Ok(()),
+        Err(e) if is_unsupported(&e) => Err(CheckOutcome::Skipped(format!(
+            "{} is not supported ({})",
+            operation, e
+        ))),
+        Err(e) => Err(CheckOutcome::Failed(format!(
+            "{} failed with {}, expected {}",
+            operation, e, expected
+        ))),
+        Ok(_) => Err(CheckOutcome::Failed(format!(
+            "{} succeeded, expected {}",
+            operation, expected
+        ))),
+    }
+}
+
+fn ensure<F: FnOnce() -> String>(condition: bool, message: F) -> Result<(), CheckOutcome> {
+    if condition {
+        Ok(())
+    } else {
+        Err(CheckOutcome::Failed(message()))
+    }
+}
+
+fn name(name: &str) -> &OsStr {
+    OsStr::new(name)
+}
+
+/// Creates a file containing `content`, and returns its entry
+fn create_file(
+    session: &TestSession,
+    parent: u64,
+    file_name: &str,
+    content: &[u8],
+) -> Result<Entry, CheckOutcome> {
+    let (entry, fh, _) = call(
+        session.create(parent, name(file_name), 0o644, 0, OpenFlags::READ_WRITE),
+        "create",
+    )?;
+    if !content.is_empty() {
+        let written = call(
+            session.write(entry.ino, fh, 0, content, OpenFlags::READ_WRITE),
+            "write",
+        )?;
+        ensure(written as usize == content.len(), || {
+            format!("write returned {}, expected {}", written, content.len())
+        })?;
+    }
+    call(
+        session.release(entry.ino, fh, OpenFlags::READ_WRITE),
+        "release",
+    )?;
+    Ok(entry)
+}
+
+/// Reads the whole content of a file through a new handle
+fn read_file(session: &TestSession, ino: u64) -> Result<Vec<u8>, CheckOutcome> {
+    let (fh, _) = call(session.open(ino, OpenFlags::READ_ONLY), "open")?;
+    let mut content = Vec::new();
+    loop {
+        let chunk = call(
+            session.read(ino, fh, content.len() as i64, 64 * 1024),
+            "read",
+        )?;
+        if chunk.is_empty() {
+            break;
+        }
+        content.extend(chunk);
+    }
+    call(session.release(ino, fh, OpenFlags::READ_ONLY), "release")?;
+    Ok(content)
+}
+
+fn lookup_content(
+    session: &TestSession,
+    parent: u64,
+    file_name: &str,
+) -> Result<Vec<u8>, CheckOutcome> {
+    let entry = call(
+        session.lookup(parent, name(file_name)),
+        &format!("lookup of {}", file_name),
+    )?;
+    read_file(session, entry.ino)
+}
+
+fn check_create_write_read(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    let (entry, fh, _) = call(
+        session.create(dir, name("file"), 0o640, 0, OpenFlags::READ_WRITE),
+        "create",
+    )?;
+    ensure(entry.attr.kind == FileKind::RegularFile, || {
+        format!("created file has kind {:?}", entry.attr.kind)
+    })?;
+    ensure(entry.attr.perm == 0o640, || {
+        format!("created file has mode {:o}, expected 640", entry.attr.perm)
+    })?;
+    ensure(entry.attr.size == 0, || {
+        format!("created file has size {}", entry.attr.size)
+    })?;
+    let content = b"The quick brown fox jumps over the lazy dog";
+    call(
+        session.write(entry.ino, fh, 0, content, OpenFlags::READ_WRITE),
+        "write",
+    )?;
+    let read = call(session.read(entry.ino, fh, 4, 5), "read")?;
+    ensure(read == b"quick", || {
+        format!(
+            "read at offset 4 returned {:?}",
+            String::from_utf8_lossy(&read)
+        )
+    })?;
+    let read = call(session.read(entry.ino, fh, 40, 100), "read")?;
+    ensure(read == b"dog", || {
+        format!(
+            "read across the end of file returned {:?}",
+            String::from_utf8_lossy(&read)
+        )
+    })?;
+    let read = call(session.read(entry.ino, fh, 100, 10), "read")?;
+    ensure(read.is_empty(), || {
+        format!("read past the end of file returned {} bytes", read.len())
+    })?;
+    call(
+        session.release(entry.ino, fh, OpenFlags::READ_WRITE),
+        "release",
+    )?;
+
+    let attr = call(session.getattr(entry.ino, None), "getattr")?;
+    ensure(attr.size == content.len() as u64, || {
+        format!("size is {}, expected {}", attr.size, content.len())
+    })?;
+    let read = lookup_content(session, dir, "file")?;
+    ensure(read == content, || {
+        "content read from a new handle differs from the content written".to_string()
+    })
+}
+
+fn check_write_offsets_and_truncate(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    let entry = create_file(session, dir, "file", b"0123456789")?;
+    let (fh, _) = call(session.open(entry.ino, OpenFlags::READ_WRITE), "open")?;
+    call(
+        session.write(entry.ino, fh, 3, b"abc", OpenFlags::READ_WRITE),
+        "write in the middle of the file",
+    )?;
+    call(
+        session.write(entry.ino, fh, 16, b"end", OpenFlags::READ_WRITE),
+        "write past the end of file",
+    )?;
+    call(
+        session.release(entry.ino, fh, OpenFlags::READ_WRITE),
+        "release",
+    )?;
+    let content = read_file(session, entry.ino)?;
+    ensure(content == b"012abc6789\0\0\0\0\0\0end", || {
+        format!(
+            "content is {:?} after writes at offsets 3 and 16",
+            String::from_utf8_lossy(&content)
+        )
+    })?;
+
+    let attr = call(
+        session.setattr(entry.ino, SetAttrRequest::new().size(4)),
+        "truncate",
+    )?;
+    ensure(attr.size == 4, || {
+        format!("size is {} after truncating to 4", attr.size)
+    })?;
+    call(
+        session.setattr(entry.ino, SetAttrRequest::new().size(8)),
+        "extend",
+    )?;
+    let content = read_file(session, entry.ino)?;
+    ensure(content == b"012a\0\0\0\0", || {
+        format!(
+            "content is {:?} after truncating to 4 then extending to 8",
+            String::from_utf8_lossy(&content)
+        )
+    })
+}
+
+fn check_rename_replace(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    create_file(session, dir, "source", b"source")?;
+    create_file(session, dir, "target", b"target")?;
+    call(
+        session.rename(
+            dir,
+            name("source"),
+            dir,
+            name("target"),
+            RenameFlags::empty(),
+        ),
+        "rename",
+    )?;
+    expect_errno(
+        session.lookup(dir, name("source")),
+        libc::ENOENT,
+        "lookup of the old name",
+    )?;
+    let content = lookup_content(session, dir, "target")?;
+    ensure(content == b"source", || {
+        format!(
+            "replaced target contains {:?}",
+            String::from_utf8_lossy(&content)
+        )
+    })?;
+
+    // Renaming to the same name is a no-op
+    call(
+        session.rename(
+            dir,
+            name("target"),
+            dir,
+            name("target"),
+            RenameFlags::empty(),
+        ),
+        "rename to the same name",
+    )?;
+    call(
+        session.lookup(dir, name("target")),
+        "lookup after renaming to the same name",
+    )?;
+    expect_errno(
+        session.rename(
+            dir,
+            name("missing"),
+            dir,
+            name("other"),
+            RenameFlags::empty(),
+        ),
+        libc::ENOENT,
+        "rename of a missing file",
+    )
+}
+
+fn check_rename_noreplace(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    create_file(session, dir, "source", b"source")?;
+    create_file(session, dir, "target", b"target")?;
+    expect_errno(
+        session.rename(
+            dir,
+            name("source"),
+            dir,
+            name("target"),
+            RenameFlags::NOREPLACE,
+        ),
+        libc::EEXIST,
+        "rename with NOREPLACE over an existing file",
+    )?;
+    let content = lookup_content(session, dir, "target")?;
+    ensure(content == b"target", || {
+        "target was modified by a rejected NOREPLACE rename".to_string()
+    })?;
+    call(
+        session.rename(
+            dir,
+            name("source"),
+            dir,
+            name("new"),
+            RenameFlags::NOREPLACE,
+        ),
+        "rename with NOREPLACE to a free name",
+    )?;
+    expect_errno(
+        session.lookup(dir, name("source")),
+        libc::ENOENT,
+        "lookup of the old name",
+    )?;
+    let content = lookup_content(session, dir, "new")?;
+    ensure(content == b"source", || {
+        "renamed file has the wrong content".to_string()
+    })
+}
+
+fn check_rename_exchange(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    create_file(session, dir, "file", b"file")?;
+    let subdir = call(session.mkdir(dir, name("subdir"), 0o755, 0), "mkdir")?;
+    create_file(session, subdir.ino, "child", b"")?;
+    call(
+        session.rename(
+            dir,
+            name("file"),
+            dir,
+            name("subdir"),
+            RenameFlags::EXCHANGE,
+        ),
+        "rename with EXCHANGE",
+    )?;
+    let file = call(session.lookup(dir, name("subdir")), "lookup")?;
+    ensure(file.attr.kind == FileKind::RegularFile, || {
+        format!("exchanged entry has kind {:?}", file.attr.kind)
+    })?;
+    let content = read_file(session, file.ino)?;
+    ensure(content == b"file", || {
+        "exchanged file has the wrong content".to_string()
+    })?;
+    let subdir = call(session.lookup(dir, name("file")), "lookup")?;
+    ensure(subdir.attr.kind == FileKind::Directory, || {
+        format!("exchanged entry has kind {:?}", subdir.attr.kind)
+    })?;
+    call(
+        session.lookup(subdir.ino, name("child")),
+        "lookup in the exchanged directory",
+    )?;
+    expect_errno(
+        session.rename(
+            dir,
+            name("file"),
+            dir,
+            name("missing"),
+            RenameFlags::EXCHANGE,
+        ),
+        libc::ENOENT,
+        "rename with EXCHANGE to a missing entry",
+    )
+}
+
+fn check_rename_directory(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    let parent = call(session.mkdir(dir, name("parent"), 0o755, 0), "mkdir")?;
+    let child = call(session.mkdir(parent.ino, name("child"), 0o755, 0), "mkdir")?;
+    expect_errno(
+        session.rename(
+            dir,
+            name("parent"),
+            child.ino,
+            name("moved"),
+            RenameFlags::empty(),
+        ),
+        libc::EINVAL,
+        "rename of a directory into its own subdirectory",
+    )?;
+    create_file(session, dir, "file", b"")?;
+    expect_errno(
+        session.rename(dir, name("file"), dir, name("parent"), RenameFlags::empty()),
+        libc::EISDIR,
+        "rename of a file over a directory",
+    )?;
+    expect_errno(
+        session.rename(dir, name("parent"), dir, name("file"), RenameFlags::empty()),
+        libc::ENOTDIR,
+        "rename of a directory over a file",
+    )?;
+    call(session.mkdir(dir, name("empty"), 0o755, 0), "mkdir")?;
+    expect_errno(
+        session.rename(
+            dir,
+            name("empty"),
+            dir,
+            name("parent"),
+            RenameFlags::empty(),
+        ),
+        libc::ENOTEMPTY,
+        "rename of a directory over a non-empty directory",
+    )?;
+    call(
+        session.rename(
+            dir,
+            name("parent"),
+            dir,
+            name("empty"),
+            RenameFlags::empty(),
+        ),
+        "rename of a directory over an empty directory",
+    )?;
+    let moved = call(
+        session.lookup(dir, name("empty")),
+        "lookup of the renamed directory",
+    )?;
+    call(
+        session.lookup(moved.ino, name("child")),
+        "lookup in the renamed directory",
+    )
+    .map(|_| ())
+}
+
+fn check_unlink_while_open(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    let entry = create_file(session, dir, "file", b"content")?;
+    let (fh, _) = call(session.open(entry.ino, OpenFlags::READ_WRITE), "open")?;
+    call(session.unlink(dir, name("file")), "unlink")?;
+    expect_errno(
+        session.lookup(dir, name("file")),
+        libc::ENOENT,
+        "lookup of the unlinked file",
+    )?;
+    let read = call(
+        session.read(entry.ino, fh, 0, 100),
+        "read of the unlinked file",
+    )?;
+    ensure(read == b"content", || {
+        format!(
+            "read of the unlinked file returned {:?}",
+            String::from_utf8_lossy(&read)
+        )
+    })?;
+    call(
+        session.write(entry.ino, fh, 7, b" updated", OpenFlags::READ_WRITE),
+        "write to the unlinked file",
+    )?;
+    let read = call(
+        session.read(entry.ino, fh, 0, 100),
+        "read of the unlinked file",
+    )?;
+    ensure(read == b"content updated", || {
+        format!(
+            "read of the unlinked file returned {:?} after a write",
+            String::from_utf8_lossy(&read)
+        )
+    })?;
+    call(
+        session.release(entry.ino, fh, OpenFlags::READ_WRITE),
+        "release",
+    )?;
+    // The name can be reused
+    create_file(session, dir, "file", b"")?;
+    let content = lookup_content(session, dir, "file")?;
+    ensure(content.is_empty(), || {
+        "a new file created with the name of an unlinked file is not empty".to_string()
+    })
+}
+
+fn check_hard_link(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    let file = create_file(session, dir, "file", b"content")?;
+    let link = call(session.link(file.ino, dir, name("link")), "link")?;
+    ensure(link.ino == file.ino, || {
+        format!(
+            "link returned the inode {:x} for a file of inode {:x}",
+            link.ino, file.ino
+        )
+    })?;
+    ensure(link.attr.nlink == 2, || {
+        format!("nlink is {} after a link, expected 2", link.attr.nlink)
+    })?;
+    let entry = call(session.lookup(dir, name("link")), "lookup of the link")?;
+    ensure(entry.ino == file.ino, || {
+        format!(
+            "lookup of the link returned the inode {:x} for a file of inode {:x}",
+            entry.ino, file.ino
+        )
+    })?;
+    call(session.unlink(dir, name("file")), "unlink")?;
+    let attr = call(
+        session.getattr(file.ino, None),
+        "getattr of the file after an unlink",
+    )?;
+    ensure(attr.nlink == 1, || {
+        format!("nlink is {} after an unlink, expected 1", attr.nlink)
+    })?;
+    let content = lookup_content(session, dir, "link")?;
+    ensure(content == b"content", || {
+        format!(
+            "read through the link returned {:?}",
+            String::from_utf8_lossy(&content)
+        )
+    })
+}
+
+fn check_rmdir_non_empty(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    let subdir = call(session.mkdir(dir, name("subdir"), 0o755, 0), "mkdir")?;
+    create_file(session, subdir.ino, "file", b"")?;
+    expect_errno(
+        session.rmdir(dir, name("subdir")),
+        libc::ENOTEMPTY,
+        "rmdir of a non-empty directory",
+    )?;
+    create_file(session, dir, "file", b"")?;
+    expect_errno(
+        session.rmdir(dir, name("file")),
+        libc::ENOTDIR,
+        "rmdir of a file",
+    )?;
+    expect_errno(
+        session.unlink(dir, name("subdir")),
+        libc::EISDIR,
+        "unlink of a directory",
+    )
+    .or_else(|outcome| {
+        // POSIX also allows EPERM
+        expect_errno(
+            session.unlink(dir, name("subdir")),
+            libc::EPERM,
+            "unlink of a directory",
+        )
+        .map_err(|_| outcome)
+    })?;
+    call(session.unlink(subdir.ino, name("file")), "unlink")?;
+    call(
+        session.rmdir(dir, name("subdir")),
+        "rmdir of an empty directory",
+    )?;
+    expect_errno(
+        session.lookup(dir, name("subdir")),
+        libc::ENOENT,
+        "lookup of the removed directory",
+    )
+}
+
+fn check_readdir_offsets(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    let mut expected: Vec<OsString> = vec![".".into(), "..".into()];
+    for i in 0..100 {
+        let file_name = format!("entry-with-a-rather-long-name-{:03}", i);
+        create_file(session, dir, &file_name, b"")?;
+        expected.push(file_name.into());
+    }
+    let entries = call(session.list_dir(dir), "readdir")?;
+    let mut names: Vec<OsString> = entries.iter().map(|entry| entry.name.clone()).collect();
+    names.sort();
+    expected.sort();
+    ensure(names == expected, || {
+        format!(
+            "listing with small buffers returned {} entries, expected {} distinct entries",
+            names.len(),
+            expected.len()
+        )
+    })?;
+
+    let (fh, _) = call(session.opendir(dir, OpenFlags::READ_ONLY), "opendir")?;
+    let result = check_readdir_resume(session, dir, fh, &entries);
+    call(
+        session.releasedir(dir, fh, OpenFlags::READ_ONLY),
+        "releasedir",
+    )?;
+    result
+}
+
+/// Checks that reading from the offset of each entry returns the entries following it
+fn check_readdir_resume(
+    session: &TestSession,
+    dir: u64,
+    fh: u64,
+    entries: &[DirEntry],
+) -> Result<(), CheckOutcome> {
+    for (index, entry) in entries.iter().enumerate().step_by(7) {
+        let following = call(session.readdir(dir, fh, entry.offset, 4096), "readdir")?;
+        match entries.get(index + 1) {
+            Some(next) => ensure(
+                following.first().map(|e| &e.name) == Some(&next.name),
+                || {
+                    format!(
+                        "readdir from the offset of {:?} returned {:?}, expected {:?}",
+                        entry.name,
+                        following.first().map(|e| &e.name),
+                        next.name
+                    )
+                },
+            )?,
+            None => ensure(following.is_empty(), || {
+                format!(
+                    "readdir from the offset of the last entry returned {} entries",
+                    following.len()
+                )
+            })?,
+        }
+    }
+    let last_offset = entries.last().map_or(0, |entry| entry.offset);
+    let following = call(session.readdir(dir, fh, last_offset, 4096), "readdir")?;
+    ensure(following.is_empty(), || {
+        format!(
+            "readdir from the offset of the last entry returned {} entries",
+            following.len()
+        )
+    })
+}
+
+fn check_xattr_size_negotiation(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    let entry = create_file(session, dir, "file", b"")?;
+    let (key, value) = (name("user.conformance"), b"some value");
+    call(session.setxattr(entry.ino, key, value, 0), "setxattr")?;
+
+    let size = call(session.getxattr(entry.ino, key, 0), "getxattr of the size")?;
+    ensure(size == XattrReply::Size(value.len() as u32), || {
+        format!(
+            "getxattr with size 0 returned {:?}, expected the size {}",
+            size,
+            value.len()
+        )
+    })?;
+    expect_errno(
+        session.getxattr(entry.ino, key, value.len() as u32 - 1),
+        libc::ERANGE,
+        "getxattr with a buffer too small",
+    )?;
+    let data = call(
+        session.getxattr(entry.ino, key, value.len() as u32),
+        "getxattr",
+    )?;
+    ensure(data == XattrReply::Data(value.to_vec()), || {
+        format!("getxattr returned {:?}", data)
+    })?;
+
+    let XattrReply::Size(list_size) = call(session.listxattr(entry.ino, 0), "listxattr")? else {
+        return Err(CheckOutcome::Failed(
+            "listxattr with size 0 returned data".to_string(),
+        ));
+    };
+    ensure(list_size > 0, || {
+        "listxattr with size 0 returned 0 on a file with an attribute".to_string()
+    })?;
+    expect_errno(
+        session.listxattr(entry.ino, list_size - 1),
+        libc::ERANGE,
+        "listxattr with a buffer too small",
+    )?;
+    let list = call(session.listxattr(entry.ino, list_size), "listxattr")?;
+    let XattrReply::Data(list) = list else {
+        return Err(CheckOutcome::Failed(format!(
+            "listxattr returned {:?}",
+            list
+        )));
+    };
+    ensure(list.len() == list_size as usize, || {
+        format!(
+            "listxattr returned {} bytes, the size reported was {}",
+            list.len(),
+            list_size
+        )
+    })?;
+    ensure(
+        list.split(|byte| *byte == 0)
+            .any(|item| item == key.as_encoded_bytes()),
+        || "listxattr does not contain the attribute set".to_string(),
+    )?;
+
+    expect_errno(
+        session.setxattr(entry.ino, key, b"other", libc::XATTR_CREATE),
+        libc::EEXIST,
+        "setxattr with XATTR_CREATE on an existing attribute",
+    )?;
+    expect_errno(
+        session.setxattr(
+            entry.ino,
+            name("user.missing"),
+            b"other",
+            libc::XATTR_REPLACE,
+        ),
+        libc::ENODATA,
+        "setxattr with XATTR_REPLACE on a missing attribute",
+    )?;
+    call(session.removexattr(entry.ino, key), "removexattr")?;
+    expect_errno(
+        session.getxattr(entry.ino, key, 0),
+        libc::ENODATA,
+        "getxattr of a removed attribute",
+    )
+}
+
+fn check_lseek_data_hole(session: &TestSession, dir: u64) -> Result<(), CheckOutcome> {
+    const HOLE_END: i64 = 1024 * 1024;
+    let (entry, fh, _) = call(
+        session.create(dir, name("sparse"), 0o644, 0, OpenFlags::READ_WRITE),
+        "create",
+    )?;
+    call(
+        session.write(entry.ino, fh, 0, &[1; 4096], OpenFlags::READ_WRITE),
+        "write",
+    )?;
+    call(
+        session.write(entry.ino, fh, HOLE_END, &[2; 4096], OpenFlags::READ_WRITE),
+        "write",
+    )?;
+    let size = HOLE_END + 4096;
+
+    let data = call(
+        session.lseek(entry.ino, fh, 0, libc::SEEK_DATA),
+        "lseek with SEEK_DATA",
+    )?;
+    ensure(data == 0, || {
+        format!("SEEK_DATA from 0 returned {}, expected 0", data)
+    })?;
+    // Filesystems without hole detection may report a single hole at the end of file
+    let hole = call(
+        session.lseek(entry.ino, fh, 0, libc::SEEK_HOLE),
+        "lseek with SEEK_HOLE",
+    )?;
+    ensure(hole == size || (4096..=HOLE_END).contains(&hole), || {
+        format!(
+            "SEEK_HOLE from 0 returned {}, expected 4096 or {}",
+            hole, size
+        )
+    })?;
+    let data = call(
+        session.lseek(entry.ino, fh, 8192, libc::SEEK_DATA),
+        "lseek with SEEK_DATA",
+    )?;
+    ensure((8192..=HOLE_END).contains(&data), || {
+        format!(
+            "SEEK_DATA from 8192 returned {}, expected at most {}",
+            data, HOLE_END
+        )
+    })?;
+    let hole = call(
+        session.lseek(entry.ino, fh, HOLE_END, libc::SEEK_HOLE),
+        "lseek with SEEK_HOLE",
+    )?;
+    ensure(hole == size, || {
+        format!(
+            "SEEK_HOLE from the last data returned {}, expected the size {}",
+            hole, size
+        )
+    })?;
+    expect_errno(
+        session.lseek(entry.ino, fh, size, libc::SEEK_DATA),
+        libc::ENXIO,
+        "lseek with SEEK_DATA at the end of file",
+    )?;
+    call(
+        session.release(entry.ino, fh, OpenFlags::READ_WRITE),
+        "release",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::memory_fs::MemoryFs;
+    use crate::templates::DefaultFuseHandler;
+
+    #[test]
+    fn test_memory_fs_conformance() {
+        let report = run_conformance_suite::<Inode, _, _>(MemoryFs::new);
+        // The tests of the crate enable the `abi-7-31` feature of fuser, so that every check runs
+        assert_eq!(report.protocol_version(), (7, 31));
+        assert_eq!(report.results().len(), CHECKS.len());
+        assert!(report.is_success(), "{}", report);
+        for name in ["rename_noreplace", "rename_exchange", "lseek_data_hole"] {
+            assert_eq!(
+                report.outcome(name),
+                Some(&CheckOutcome::Passed),
+                "{}",
+                report
+            );
+        }
+        assert_eq!(
+            report.outcome("create_write_read"),
+            Some(&CheckOutcome::Passed)
+        );
+        assert_eq!(
+            report.outcome("readdir_offsets"),
+            Some(&CheckOutcome::Passed)
+        );
+        assert_eq!(
+            report.outcome("xattr_size_negotiation"),
+            Some(&CheckOutcome::Passed)
+        );
+    }
+
+    // MirrorFs is not `Send` with the serial feature
+    #[cfg(not(feature = "serial"))]
+    #[test]
+    fn test_mirror_fs_conformance() {
+        use crate::templates::mirror_fs::{MirrorFs, MirrorFsTrait};
+        use std::path::PathBuf;
+
+        let source = tempfile::TempDir::new().unwrap();
+        let report = run_conformance_suite::<PathBuf, _, _>(|| {
+            MirrorFs::new(source.path().to_path_buf(), DefaultFuseHandler::new())
+        });
+        assert_eq!(
+            report.outcome("hard_link"),
+            Some(&CheckOutcome::Passed),
+            "{}",
+            report
+        );
+        assert_eq!(
+            report.outcome("unlink_while_open"),
+            Some(&CheckOutcome::Passed),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn test_failures_are_reported() {
+        let report = run_conformance_suite::<Inode, _, _>(|| {
+            DefaultFuseHandler::new_with_custom_error(ErrorKind::PermissionDenied)
+        });
+        assert!(!report.is_success());
+        assert_eq!(report.failures().count(), CHECKS.len());
+        assert!(report.to_string().contains("FAIL create_write_read"));
+    }
+}
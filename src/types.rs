@@ -6,11 +6,13 @@
 //! # Modules
 //!
 //! - \[arguments\]: Defines argument types and structures for FUSE operations.
+//! - \[buffer\]: Provides the buffers used to reply to reads without copies.
 //! - \[errors\]: Contains error types and handling for FUSE operations.
 //! - \[file_descriptor\]: Provides types related to file descriptors.
 //! - \[file_id_type\]: Defines traits for file identification.
 //! - \[flags\]: Contains flag definitions for various FUSE operations.
 //! - \[inode\]: Defines the `Inode` type for representing filesystem objects.
+//! - \[notifier\]: Provides the `Notifier` used to invalidate kernel caches.
 //!
 //! # Re-exports
 //!
@@ -18,12 +20,17 @@
 //! some types from the `fuser` crate that are commonly used in FUSE operations.
 
 pub mod arguments;
+pub mod buffer;
 pub mod errors;
 pub mod file_handle;
 mod file_id_type;
 pub mod flags;
 mod inode;
+pub mod notifier;
 
-pub use self::{arguments::*, errors::*, file_handle::*, file_id_type::*, flags::*, inode::*};
+pub use self::{
+    arguments::*, buffer::*, errors::*, file_handle::*, file_id_type::*, flags::*, inode::*,
+    notifier::*,
+};
 
-pub use fuser::{FileType as FileKind, KernelConfig, TimeOrNow};
+pub use fuser::{FileType as FileKind, KernelConfig, PollHandle, TimeOrNow};
@@ -8,13 +8,16 @@
 //! - [`RequestInfo`]: Encapsulates essential information about a FUSE request.
 //! - [`FileAttribute`]: Represents file attributes for FUSE operations with optional caching parameters.
 //! - [`SetAttrRequest`]: Represents a request to set file attributes in a FUSE file system.
+//! - [`ReaddirIter`]: Lazily produced directory entries, used by the streaming readdir operations.
 //!
 //! # Functions
 //!
 //! - [`seek_from_raw`]: Converts raw seek parameters to a `SeekFrom` enum.
+//! - [`lseek_from_raw`]: Converts the raw parameters of `lseek` to a [`LseekFrom`], including `SEEK_DATA` and `SEEK_HOLE`.
 //!
 //! This module also re-exports `SeekFrom` from the standard library for convenience.
 
+use std::ffi::OsString;
 use std::time::{Duration, SystemTime};
 
 use fuser::FileAttr as FuseFileAttr;
@@ -22,6 +25,7 @@ use fuser::{FileType, Request, TimeOrNow};
 use libc::mode_t;
 
 use super::BorrowedFileHandle;
+use super::FuseResult;
 use super::LockType;
 
 pub use std::io::SeekFrom;
@@ -46,6 +50,37 @@ pub fn seek_from_raw(whence: Option<i32>, offset: i64) -> SeekFrom {
     }
 }
 
+/// Position requested by `lseek`: a [`SeekFrom`], or a search of the data and holes of a sparse file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LseekFrom {
+    Seek(SeekFrom),
+    /// Offset of the first data at or after the given offset (`SEEK_DATA`), failing with ENXIO if there is none.
+    Data(u64),
+    /// Offset of the first hole at or after the given offset (`SEEK_HOLE`), the end of file counting as a hole.
+    Hole(u64),
+}
+
+impl From<SeekFrom> for LseekFrom {
+    fn from(seek: SeekFrom) -> Self {
+        LseekFrom::Seek(seek)
+    }
+}
+
+/// Converts the raw parameters of `lseek`, returns None for an unknown `whence` or a negative absolute offset.
+pub fn lseek_from_raw(whence: i32, offset: i64) -> Option<LseekFrom> {
+    let absolute = || u64::try_from(offset).ok();
+    match whence {
+        libc::SEEK_SET => Some(LseekFrom::Seek(SeekFrom::Start(absolute()?))),
+        libc::SEEK_CUR => Some(LseekFrom::Seek(SeekFrom::Current(offset))),
+        libc::SEEK_END => Some(LseekFrom::Seek(SeekFrom::End(offset))),
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+        libc::SEEK_DATA => Some(LseekFrom::Data(absolute()?)),
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+        libc::SEEK_HOLE => Some(LseekFrom::Hole(absolute()?)),
+        _ => None,
+    }
+}
+
 /// Represents POSIX device types based on the `rdev` value.
 ///
 /// This enum encapsulates various file system object types, including:
@@ -209,6 +244,9 @@ pub struct FileAttribute {
     /// File flags
     pub flags: u32,
     /// Time-to-live for caching this attribute (None for default)
+    ///
+    /// When returned by lookup, creation or readdirplus, it also applies to the name of the entry, as fuser replies
+    /// entries with a single TTL.
     pub ttl: Option<Duration>,
     // File generation number (None for random)
     /// If set, it must follow these constraints:
@@ -249,7 +287,7 @@ impl FileAttribute {
 ///
 /// This struct uses the builder pattern to construct a request with optional fields.
 /// Each field corresponds to a file attribute that can be modified.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SetAttrRequest<'a> {
     /// File mode (permissions)
     pub mode: Option<u32>,
@@ -358,14 +396,37 @@ impl<'a> SetAttrRequest<'a> {
 }
 
 /// Represents file locking information for FUSE operations.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LockInfo {
     /// Starting offset of the lock range in bytes
     pub start: u64,
-    /// Ending offset of the lock range in bytes (exclusive)
+    /// Ending offset of the lock range in bytes (inclusive, `i64::MAX` for a lock up to the end of the file)
     pub end: u64,
     /// Type of lock (e.g., Read, Write, or Unlock)
     pub lock_type: LockType,
     /// Process ID of the lock owner
     pub pid: u32,
+    /// Whether the lock was requested with `fcntl` or `flock`
+    pub kind: LockKind,
+}
+
+/// Kind of a lock, see [`FuseHandler::remote_locks`](crate::prelude::FuseHandler::remote_locks)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockKind {
+    /// POSIX record lock (`fcntl`), owned by a process
+    #[default]
+    Posix,
+    /// BSD lock (`flock`), always covering the whole file and owned by the open file
+    Flock,
 }
+
+/// Directory entries produced lazily, as returned by `readdir_iter` and `readdirplus_iter`.
+///
+/// Each item is the name of the entry along with its metadata, which is `TId::MinimalMetadata`
+/// for `readdir_iter` and `TId::Metadata` for `readdirplus_iter`.
+/// The iterator is pulled by the driver only as far as the kernel buffer allows,
+/// and kept alive between successive reads of the same directory.
+///
+/// An error returned by the iterator aborts the current read with this error.
+pub type ReaddirIter<TMetadata> =
+    Box<dyn Iterator<Item = FuseResult<(OsString, TMetadata)>> + Send>;
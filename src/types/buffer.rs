@@ -0,0 +1,364 @@
+//! Buffers used to reply to reads without copying the data into a new allocation.
+//!
+//! - [`ReadData`]: Data returned by `FuseHandler::read_buf`, which can be owned, taken from a pool,
+//!   shared with the handler or static. The driver replies directly from it.
+//! - [`WriteData`]: Payload of a write request given to `FuseHandler::write_buf`, borrowed from the request
+//!   or copied into a pooled or owned buffer.
+//! - [`BufferPool`]: A pool of reusable buffers, saving one allocation per operation on hot paths.
+//!
+//! Note: fuser does not support splicing a reply from a file descriptor, so the content of a file
+//! still has to be read into a buffer of the filesystem. Reading it into a pooled buffer saves the
+//! allocation, and the reply is then written to the kernel from that same buffer.
+
+use std::fmt::{self, Debug};
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+/// Maximal number of buffers kept by the global pool
+pub const DEFAULT_POOL_BUFFERS: usize = 64;
+
+/// Maximal capacity of a buffer kept by the global pool, larger buffers are freed when dropped
+pub const DEFAULT_POOL_BUFFER_CAPACITY: usize = 1 << 20;
+
+struct PoolInner {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+    max_capacity: usize,
+}
+
+/// A pool of reusable byte buffers
+///
+/// `BufferPool` is a cheap handle to shared buffers. Buffers taken with `get` are given back to the pool
+/// when dropped, unless the pool is full or their capacity exceeds the maximal capacity of the pool.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+impl BufferPool {
+    /// Creates a pool keeping up to `max_buffers` buffers of at most `max_capacity` bytes
+    pub fn new(max_buffers: usize, max_capacity: usize) -> Self {
+        BufferPool {
+            inner: Arc::new(PoolInner {
+                buffers: Mutex::new(Vec::new()),
+                max_buffers,
+                max_capacity,
+            }),
+        }
+    }
+
+    /// Returns the pool shared by the templates of this crate
+    pub fn global() -> &'static BufferPool {
+        static GLOBAL: OnceLock<BufferPool> = OnceLock::new();
+        GLOBAL.get_or_init(|| BufferPool::new(DEFAULT_POOL_BUFFERS, DEFAULT_POOL_BUFFER_CAPACITY))
+    }
+
+    fn buffers(&self) -> MutexGuard<'_, Vec<Vec<u8>>> {
+        self.inner.buffers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes an empty buffer able to hold at least `capacity` bytes
+    pub fn get(&self, capacity: usize) -> PooledBuffer {
+        let mut buffer = self.buffers().pop().unwrap_or_default();
+        buffer.reserve(capacity);
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+
+    /// Number of buffers currently available in the pool
+    pub fn available(&self) -> usize {
+        self.buffers().len()
+    }
+}
+
+impl Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("available", &self.available())
+            .field("max_buffers", &self.inner.max_buffers)
+            .field("max_capacity", &self.inner.max_capacity)
+            .finish()
+    }
+}
+
+/// A buffer taken from a [`BufferPool`], given back to it when dropped
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl PooledBuffer {
+    /// Takes the buffer out of the pool for good
+    pub fn into_vec(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PooledBuffer({} bytes)", self.buffer.len())
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let capacity = self.buffer.capacity();
+        if capacity == 0 || capacity > self.pool.inner.max_capacity {
+            return;
+        }
+        let mut buffers = self.pool.buffers();
+        if buffers.len() < self.pool.inner.max_buffers {
+            let mut buffer = std::mem::take(&mut self.buffer);
+            buffer.clear();
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// Data of a read reply, as returned by `FuseHandler::read_buf`
+pub enum ReadData {
+    /// An owned buffer, as returned by `read`
+    Owned(Vec<u8>),
+    /// A buffer taken from a [`BufferPool`], given back to the pool once the reply is sent
+    Pooled(PooledBuffer),
+    /// A range of a buffer shared with the handler, eg: the cached content of a file
+    ///
+    /// The range is truncated to the length of the buffer.
+    Shared(Arc<[u8]>, Range<usize>),
+    /// Data living for the whole program, eg: the content of a file embedded in the binary
+    Static(&'static [u8]),
+}
+
+impl ReadData {
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            ReadData::Owned(data) => data,
+            ReadData::Pooled(data) => data,
+            ReadData::Shared(data, range) => {
+                let end = range.end.min(data.len());
+                data.get(range.start..end).unwrap_or_default()
+            }
+            ReadData::Static(data) => data,
+        }
+    }
+
+    /// Converts the data to an owned buffer, copying it unless it is already owned
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            ReadData::Owned(data) => data,
+            ReadData::Pooled(data) => data.into_vec(),
+            data => data.as_slice().to_vec(),
+        }
+    }
+}
+
+impl Deref for ReadData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl Debug for ReadData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let variant = match self {
+            ReadData::Owned(_) => "Owned",
+            ReadData::Pooled(_) => "Pooled",
+            ReadData::Shared(_, _) => "Shared",
+            ReadData::Static(_) => "Static",
+        };
+        write!(f, "{}({} bytes)", variant, self.len())
+    }
+}
+
+impl From<Vec<u8>> for ReadData {
+    fn from(data: Vec<u8>) -> Self {
+        ReadData::Owned(data)
+    }
+}
+
+impl From<PooledBuffer> for ReadData {
+    fn from(data: PooledBuffer) -> Self {
+        ReadData::Pooled(data)
+    }
+}
+
+impl From<Arc<[u8]>> for ReadData {
+    fn from(data: Arc<[u8]>) -> Self {
+        let len = data.len();
+        ReadData::Shared(data, 0..len)
+    }
+}
+
+impl From<&'static [u8]> for ReadData {
+    fn from(data: &'static [u8]) -> Self {
+        ReadData::Static(data)
+    }
+}
+
+/// Payload of a write request, as given to `FuseHandler::write_buf`
+pub enum WriteData<'a> {
+    /// The payload of the request, when the request is handled on the thread which received it
+    Borrowed(&'a [u8]),
+    /// A copy of the payload, when the request is handled by another thread and `FuseHandler::pooled_writes`
+    /// is true
+    Pooled(PooledBuffer),
+    /// A copy of the payload, when the request is handled by another thread and `FuseHandler::pooled_writes`
+    /// is false, handed as is to `write`
+    Owned(Vec<u8>),
+}
+
+impl WriteData<'_> {
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            WriteData::Borrowed(data) => data,
+            WriteData::Pooled(data) => data,
+            WriteData::Owned(data) => data,
+        }
+    }
+
+    /// Converts the payload to an owned buffer, copying it only if it is borrowed
+    ///
+    /// A pooled payload is taken out of its pool for good.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self {
+            WriteData::Borrowed(data) => data.to_vec(),
+            WriteData::Pooled(data) => data.into_vec(),
+            WriteData::Owned(data) => data,
+        }
+    }
+}
+
+impl Deref for WriteData<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl Debug for WriteData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let variant = match self {
+            WriteData::Borrowed(_) => "Borrowed",
+            WriteData::Pooled(_) => "Pooled",
+            WriteData::Owned(_) => "Owned",
+        };
+        write!(f, "{}({} bytes)", variant, self.len())
+    }
+}
+
+impl<'a> From<&'a [u8]> for WriteData<'a> {
+    fn from(data: &'a [u8]) -> Self {
+        WriteData::Borrowed(data)
+    }
+}
+
+impl From<PooledBuffer> for WriteData<'_> {
+    fn from(data: PooledBuffer) -> Self {
+        WriteData::Pooled(data)
+    }
+}
+
+impl From<Vec<u8>> for WriteData<'_> {
+    fn from(data: Vec<u8>) -> Self {
+        WriteData::Owned(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_pool() {
+        let pool = BufferPool::new(1, 1024);
+        let mut buffer = pool.get(100);
+        assert!(buffer.capacity() >= 100);
+        buffer.extend_from_slice(b"hello");
+        let pointer = buffer.as_ptr();
+        drop(buffer);
+        assert_eq!(pool.available(), 1);
+
+        // The buffer is reused, empty
+        let first = pool.get(10);
+        assert_eq!(first.as_ptr(), pointer);
+        assert!(first.is_empty());
+        let second = pool.get(10);
+        assert_eq!(pool.available(), 0);
+        drop(first);
+        // The pool is full
+        drop(second);
+        assert_eq!(pool.available(), 1);
+
+        let taken = pool.get(10).into_vec();
+        assert_eq!(taken.as_ptr(), pointer);
+        assert_eq!(pool.available(), 0);
+
+        // Buffers larger than the maximal capacity are freed
+        drop(pool.get(2048));
+        assert_eq!(pool.available(), 0);
+    }
+
+    #[test]
+    fn test_read_data() {
+        let shared: Arc<[u8]> = Arc::from(&b"hello world"[..]);
+        assert_eq!(&*ReadData::Shared(shared.clone(), 6..11), b"world");
+        assert_eq!(&*ReadData::Shared(shared.clone(), 6..100), b"world");
+        assert!(ReadData::Shared(shared.clone(), 20..30).is_empty());
+        assert_eq!(ReadData::from(shared).len(), 11);
+
+        let pool = BufferPool::new(4, 1024);
+        let mut buffer = pool.get(5);
+        buffer.extend_from_slice(b"hello");
+        let data = ReadData::from(buffer);
+        assert_eq!(format!("{:?}", data), "Pooled(5 bytes)");
+        assert_eq!(data.into_vec(), b"hello");
+        assert_eq!(pool.available(), 0);
+        assert_eq!(ReadData::from(&b"static"[..]).into_vec(), b"static");
+    }
+
+    #[test]
+    fn test_write_data() {
+        let pool = BufferPool::new(4, 1024);
+        let mut buffer = pool.get(5);
+        buffer.extend_from_slice(b"hello");
+        let pointer = buffer.as_ptr();
+        let data = WriteData::from(buffer);
+        assert_eq!(format!("{:?}", data), "Pooled(5 bytes)");
+        assert_eq!(&*data, b"hello");
+        // The pooled copy is handed over as is
+        let owned = data.into_vec();
+        assert_eq!(owned.as_ptr(), pointer);
+        assert_eq!(owned, b"hello");
+        assert_eq!(pool.available(), 0);
+
+        let payload = b"payload".to_vec();
+        let data = WriteData::from(&payload[..]);
+        assert_eq!(data.as_ptr(), payload.as_ptr());
+        assert_eq!(data.into_vec(), payload);
+
+        let pointer = payload.as_ptr();
+        let data = WriteData::from(payload);
+        assert_eq!(format!("{:?}", data), "Owned(7 bytes)");
+        let owned = data.into_vec();
+        assert_eq!(owned.as_ptr(), pointer);
+    }
+}
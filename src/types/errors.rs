@@ -14,12 +14,14 @@
 //! - [`PosixError::new`]: Creates a new PosixError with a given code and message.
 //! - [`PosixError::last_error`]: Creates a PosixError from the last system error.
 //! - [`ErrorKind::to_error`]: Converts an ErrorKind to a PosixError with a custom message.
+//! - [`PosixError::with_negative_ttl`]: Lets the kernel cache a missing entry returned by `lookup`.
 //!
 
 use crate::unix_fs::get_errno;
 use std::any::Any;
 
 use std::fmt::{Debug, Display};
+use std::time::Duration;
 
 pub type FuseResult<T> = Result<T, PosixError>;
 
@@ -28,6 +30,7 @@ pub type FuseResult<T> = Result<T, PosixError>;
 pub struct PosixError {
     code: i32,
     pub msg: String,
+    negative_ttl: Option<Duration>,
 }
 
 impl PosixError {
@@ -44,6 +47,7 @@ impl PosixError {
         Self {
             code: code.into(),
             msg: msg.to_string(),
+            negative_ttl: None,
         }
     }
 
@@ -62,6 +66,7 @@ impl PosixError {
         Self {
             code: get_errno(),
             msg: msg.to_string(),
+            negative_ttl: None,
         }
     }
 
@@ -72,6 +77,19 @@ impl PosixError {
     pub fn raw_error(&self) -> i32 {
         self.code
     }
+
+    /// Replies a negative entry cached by the kernel for `ttl`, when returned by `lookup` with `FileNotFound`
+    ///
+    /// Takes precedence over `FuseHandler::get_negative_ttl`, ignored for other errors and operations.
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = Some(ttl);
+        self
+    }
+
+    /// TTL given by `with_negative_ttl`
+    pub fn negative_ttl(&self) -> Option<Duration> {
+        self.negative_ttl
+    }
 }
 
 impl<E> From<E> for PosixError
@@ -6,6 +6,9 @@
 //! offers different trade-offs in terms of performance, ease of use, and memory usage.
 //! The module also includes associated types for full and minimal metadata, which
 //! are different possible return values in FUSE operations.
+//!
+//! Other identifiers can be used by implementing `FileIdType` along with [`InodeResolvable`]
+//! and a [`FileIdResolver`], see the documentation of [`FileIdType`].
 
 use std::{
     ffi::OsString,
@@ -15,7 +18,10 @@ use std::{
 
 use fuser::FileType as FileKind;
 
-use crate::core::InodeResolvable;
+pub use crate::core::{
+    ComponentsResolver, EvictionPolicy, FileIdResolver, InodeResolvable, InodeResolver,
+    PathResolver, ResolverStats, DEFAULT_MAX_RESOLVER_ENTRIES,
+};
 
 use super::arguments::FileAttribute;
 use super::inode::*;
@@ -38,8 +44,137 @@ use super::inode::*;
 ///    - Pros: Slightly lower overhead than PathBuf, allows path to be divided into parts.
 ///    - Cons: Path components are stored in reverse order, which may require additional handling.
 ///    - Root: Represented by an empty vector.
+///
+/// # Custom file ids
+///
+/// Any other type can identify files, for example content hashes, database row ids or `(bucket, key)` tuples.
+/// It must implement [`InodeResolvable`], which provides the [`FileIdResolver`] translating inode numbers to ids.
+/// The resolver defines how inode numbers are attributed and when they are released.
+///
+/// ```
+/// use easy_fuser::prelude::*;
+/// use std::collections::HashMap;
+/// use std::ffi::{OsStr, OsString};
+/// use std::fmt::Display;
+/// use std::sync::RwLock;
+///
+/// /// Files are rows of a database, the root directory being the row 0
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// struct RowId(u64);
+///
+/// impl FileIdType for RowId {
+///     // Handlers return the row id of the entries along with their attributes
+///     type _Id = RowId;
+///     type Metadata = (RowId, FileAttribute);
+///     type MinimalMetadata = (RowId, FileKind);
+///
+///     fn display(&self) -> impl Display {
+///         format!("row {}", self.0)
+///     }
+///
+///     fn is_filesystem_root(&self) -> bool {
+///         self.0 == 0
+///     }
+///
+///     fn extract_metadata(metadata: Self::Metadata) -> (RowId, FileAttribute) {
+///         metadata
+///     }
+///
+///     fn extract_minimal_metadata(metadata: Self::MinimalMetadata) -> (RowId, FileKind) {
+///         metadata
+///     }
+/// }
+///
+/// /// Attributes inode numbers to rows as they are looked up, and releases them when forgotten
+/// struct RowResolver {
+///     // inode -> (row, lookup count), and row -> inode
+///     inodes: RwLock<(HashMap<u64, (RowId, u64)>, HashMap<RowId, u64>, u64)>,
+/// }
+///
+/// impl FileIdResolver for RowResolver {
+///     type ResolvedType = RowId;
+///
+///     fn new() -> Self {
+///         let inodes = HashMap::from([(1, (RowId(0), 1))]);
+///         let rows = HashMap::from([(RowId(0), 1)]);
+///         RowResolver { inodes: RwLock::new((inodes, rows, 2)) }
+///     }
+///
+///     fn resolve_id(&self, ino: u64) -> FuseResult<RowId> {
+///         match self.inodes.read().unwrap().0.get(&ino) {
+///             Some((id, _)) => Ok(*id),
+///             None => Err(ErrorKind::StaleFileHandle.to_error(format!("Unknown inode {}", ino))),
+///         }
+///     }
+///
+///     fn find_ino(&self, id: &RowId) -> Option<u64> {
+///         self.inodes.read().unwrap().1.get(id).copied()
+///     }
+///
+///     fn lookup(&self, _parent: u64, _child: &OsStr, id: RowId, increment: bool) -> FuseResult<u64> {
+///         let mut guard = self.inodes.write().unwrap();
+///         let (inodes, rows, next_inode) = &mut *guard;
+///         let ino = *rows.entry(id).or_insert_with(|| {
+///             *next_inode += 1;
+///             *next_inode - 1
+///         });
+///         inodes.entry(ino).or_insert((id, 0)).1 += increment as u64;
+///         Ok(ino)
+///     }
+///
+///     fn add_children(
+///         &self,
+///         parent: u64,
+///         children: Vec<(OsString, RowId)>,
+///         increment: bool,
+///     ) -> FuseResult<Vec<(OsString, u64)>> {
+///         children
+///             .into_iter()
+///             .map(|(name, id)| {
+///                 let ino = self.lookup(parent, &name, id, increment)?;
+///                 Ok((name, ino))
+///             })
+///             .collect()
+///     }
+///
+///     fn forget(&self, ino: u64, nlookup: u64) -> FuseResult<()> {
+///         let mut guard = self.inodes.write().unwrap();
+///         let (inodes, rows, _) = &mut *guard;
+///         if let Some((id, count)) = inodes.get_mut(&ino) {
+///             *count = count.saturating_sub(nlookup);
+///             if *count == 0 && ino != 1 {
+///                 rows.remove(id);
+///                 inodes.remove(&ino);
+///             }
+///         }
+///         Ok(())
+///     }
+///
+///     // Row ids do not depend on names
+///     fn rename(&self, _parent: u64, _name: &OsStr, _newparent: u64, _newname: &OsStr) -> FuseResult<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// impl InodeResolvable for RowId {
+///     type Resolver = RowResolver;
+///
+///     fn create_resolver() -> RowResolver {
+///         RowResolver::new()
+///     }
+/// }
+///
+/// // RowId can now be used as the file id of a handler
+/// let _handler: Box<dyn FuseHandler<RowId>> = Box::new(easy_fuser::templates::DefaultFuseHandler::new());
+/// let resolver = RowId::create_resolver();
+/// let ino = resolver.lookup(1, OsStr::new("file"), RowId(42), true).unwrap();
+/// assert_eq!(resolver.resolve_id(ino).unwrap(), RowId(42));
+/// resolver.forget(ino, 1).unwrap();
+/// assert_eq!(resolver.find_ino(&RowId(42)), None);
+/// assert!(resolver.resolve_id(ino).is_err());
+/// ```
 pub trait FileIdType:
-    'static + Debug + Clone + PartialEq + Eq + std::hash::Hash + InodeResolvable
+    'static + Debug + Clone + PartialEq + Eq + std::hash::Hash + Send + Sync + InodeResolvable
 {
     /// Full metadata type for the file system.
     ///
@@ -48,7 +183,7 @@ pub trait FileIdType:
     ///
     /// For PathBuf-based: FileAttribute
     /// - User only needs to provide FileAttribute; Inode is managed internally.
-    type Metadata;
+    type Metadata: Send + 'static;
 
     /// Minimal metadata type for the file system.
     ///
@@ -57,9 +192,12 @@ pub trait FileIdType:
     ///
     /// For PathBuf-based: FileKind
     /// - User only needs to provide FileKind; Inode is managed internally.
-    type MinimalMetadata;
-    #[doc(hidden)]
-    type _Id;
+    type MinimalMetadata: Send + 'static;
+
+    /// Part of the metadata identifying the file, given to [`FileIdResolver::lookup`].
+    ///
+    /// For Inode-based: Inode. For PathBuf-based: `()`, the path being built from the names of the entries.
+    type _Id: Send;
 
     /// Returns a displayable representation of the file identifier.
     ///
@@ -73,9 +211,9 @@ pub trait FileIdType:
     /// topmost directory in the filesystem hierarchy.
     fn is_filesystem_root(&self) -> bool;
 
-    #[doc(hidden)]
+    /// Splits the full metadata returned by a handler into the id part and the attributes.
     fn extract_metadata(metadata: Self::Metadata) -> (Self::_Id, FileAttribute);
-    #[doc(hidden)]
+    /// Splits the minimal metadata returned by a handler into the id part and the kind of file.
     fn extract_minimal_metadata(minimal_metadata: Self::MinimalMetadata) -> (Self::_Id, FileKind);
 }
 
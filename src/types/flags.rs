@@ -58,6 +58,58 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Debug, Copy, Clone)]
+    /// Capabilities negotiated with the kernel during `init`, see `MountBuilder::capabilities`.
+    pub struct FUSECapabilities: u64 {
+        /// Asynchronous read requests
+        const ASYNC_READ = 1 << 0;
+        /// Remote POSIX record locks, prefer `FuseHandler::remote_locks`
+        const POSIX_LOCKS = 1 << 1;
+        /// Handling of `O_TRUNC` by `open`
+        const ATOMIC_O_TRUNC = 1 << 3;
+        /// Lookups of "." and ".."
+        const EXPORT_SUPPORT = 1 << 4;
+        /// Writes larger than 4 KiB
+        const BIG_WRITES = 1 << 5;
+        /// The umask is not applied by the kernel on creation
+        const DONT_MASK = 1 << 6;
+        /// Remote `flock` locks, prefer `FuseHandler::remote_locks`
+        const FLOCK_LOCKS = 1 << 10;
+        /// `ioctl` on directories
+        const HAS_IOCTL_DIR = 1 << 11;
+        /// Invalidation of the cached data when the modification time or the size changes
+        const AUTO_INVAL_DATA = 1 << 12;
+        /// `readdirplus` requests
+        const DO_READDIRPLUS = 1 << 13;
+        /// Adaptive choice between `readdir` and `readdirplus`
+        const READDIRPLUS_AUTO = 1 << 14;
+        /// Asynchronous direct I/O
+        const ASYNC_DIO = 1 << 15;
+        /// Writeback caching of the writes by the kernel
+        const WRITEBACK_CACHE = 1 << 16;
+        /// `open` may fail with `ENOSYS` to be skipped afterwards
+        const NO_OPEN_SUPPORT = 1 << 17;
+        /// Parallel lookups and readdirs in the same directory
+        const PARALLEL_DIROPS = 1 << 18;
+        /// The filesystem clears the setuid and setgid bits on write, truncate and chown
+        const HANDLE_KILLPRIV = 1 << 19;
+        /// POSIX ACLs, checked by the kernel
+        const POSIX_ACL = 1 << 20;
+        /// Reads and writes fail with `ECONNABORTED` once the connection is aborted
+        const ABORT_ERROR = 1 << 21;
+        /// Requests larger than 32 pages
+        const MAX_PAGES = 1 << 22;
+        /// Caching of the targets of symbolic links
+        const CACHE_SYMLINKS = 1 << 23;
+        /// `opendir` may fail with `ENOSYS` to be skipped afterwards
+        const NO_OPENDIR_SUPPORT = 1 << 24;
+        /// Cached data is only invalidated through the notifier
+        const EXPLICIT_INVAL_DATA = 1 << 25;
+        const _ = !0;
+    }
+}
+
 bitflags! {
     #[derive(Debug, Copy, Clone)]
     pub struct FUSEGetAttrFlags: i32 {
@@ -111,6 +163,16 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Debug, Copy, Clone)]
+    /// Flags used in poll requests.
+    pub struct FUSEPollFlags: u32 {
+        /// The kernel expects a notification through the poll handle once an event is ready.
+        const SCHEDULE_NOTIFY = 1 << 0;
+        const _ = !0;
+    }
+}
+
 bitflags! {
     #[derive(Debug, Copy, Clone)]
     pub struct FUSEReadFlags: i32 {
@@ -157,7 +219,17 @@ bitflags! {
 bitflags! {
     #[derive(Debug, Copy, Clone)]
     pub struct IOCtlFlags: u32 {
-        // Placeholder for future flags
+        /// 32-bit compat ioctl on a 64-bit machine
+        const COMPAT = 1 << 0;
+        /// Not restricted to well-formed ioctls (CUSE only)
+        const UNRESTRICTED = 1 << 1;
+        const RETRY = 1 << 2;
+        /// 32-bit ioctl
+        const BITS_32 = 1 << 3;
+        /// The ioctl targets a directory
+        const DIR = 1 << 4;
+        /// x32 compat ioctl on a 64-bit machine
+        const COMPAT_X32 = 1 << 5;
         const _ = !0;
     }
 }
@@ -219,6 +291,37 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Debug, Copy, Clone)]
+    /// Events requested and returned by poll operations.
+    pub struct PollEvents: u32 {
+        /// There is data to read.
+        const READABLE = libc::POLLIN as u32;
+        /// There is urgent data to read.
+        const PRIORITY = libc::POLLPRI as u32;
+        /// Writing is possible without blocking.
+        const WRITABLE = libc::POLLOUT as u32;
+        /// Error condition (only returned).
+        const ERROR = libc::POLLERR as u32;
+        /// Hang up (only returned).
+        const HANG_UP = libc::POLLHUP as u32;
+        /// Invalid request (only returned).
+        const INVALID = libc::POLLNVAL as u32;
+        /// Normal data may be read.
+        const READ_NORMAL = libc::POLLRDNORM as u32;
+        /// Priority band data may be read.
+        const READ_BAND = libc::POLLRDBAND as u32;
+        /// Normal data may be written.
+        const WRITE_NORMAL = libc::POLLWRNORM as u32;
+        /// Priority band data may be written.
+        const WRITE_BAND = libc::POLLWRBAND as u32;
+        /// The peer closed its end of the stream. (Linux only)
+        #[cfg(target_os = "linux")]
+        const READ_HANG_UP = libc::POLLRDHUP as u32;
+        const _ = !0;
+    }
+}
+
 bitflags! {
     #[derive(Debug, Copy, Clone)]
     /// Flags used in rename operations.
@@ -0,0 +1,129 @@
+//! Kernel cache invalidation for FUSE filesystems.
+//!
+//! The kernel caches entries, attributes and file content for the duration of their TTL.
+//! When the content of the filesystem changes behind its back (eg: a remote backend), the
+//! handler can use a [`Notifier`] to invalidate those caches without waiting for the TTL to expire.
+//!
+//! A `Notifier` is given to the handler in `FuseHandler::init`, and can be cloned and kept for
+//! the whole lifetime of the filesystem. It takes file ids, which are resolved to inodes through
+//! the same resolver used by the filesystem driver.
+
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::io;
+use std::sync::{Arc, OnceLock};
+
+use crate::core::{FileIdResolver, ResolverStats};
+
+use super::errors::{ErrorKind, FuseResult, PosixError};
+use super::file_id_type::FileIdType;
+
+/// A handle to send cache invalidation notifications to the kernel.
+///
+/// File ids which have never been returned to the kernel by a lookup (or already forgotten by it) can't
+/// be cached, so notifications targeting them are ignored and return `Ok(())`. This is also the case of
+/// the inodes the kernel reports as not cached, for resolvers which don't track its references
+/// (eg: with [`Inode`](crate::types::Inode) ids).
+///
+/// Notifications can only be sent once the filesystem is mounted. Before that, every method
+/// returns an error of kind `ErrorKind::NotConnected`.
+///
+/// Note: The kernel may wait on a pending request of the filesystem before processing an invalidation.
+/// Sending notifications from inside a handler method may thus deadlock, they should be sent from another thread.
+pub struct Notifier<TId: FileIdType> {
+    resolver: Arc<TId::Resolver>,
+    channel: Arc<OnceLock<fuser::Notifier>>,
+}
+
+impl<TId: FileIdType> Notifier<TId> {
+    pub(crate) fn new(resolver: Arc<TId::Resolver>) -> Self {
+        Notifier {
+            resolver,
+            channel: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Connects the notifier to the session of the mounted filesystem
+    pub(crate) fn attach(&self, notifier: fuser::Notifier) {
+        let _ = self.channel.set(notifier);
+    }
+
+    fn channel(&self) -> FuseResult<&fuser::Notifier> {
+        self.channel
+            .get()
+            .ok_or_else(|| ErrorKind::NotConnected.to_error("Filesystem is not mounted"))
+    }
+
+    /// Invalidate the cached attributes and the cached data of a file in the given range
+    ///
+    /// A `len` of zero or less invalidates the data up to the end of the file,
+    /// and a negative `offset` only invalidates the attributes.
+    pub fn inval_inode(&self, file_id: TId, offset: i64, len: i64) -> FuseResult<()> {
+        let Some(ino) = self.resolver.find_ino(&file_id) else {
+            return Ok(());
+        };
+        ignore_uncached(self.channel()?.inval_inode(ino, offset, len))
+    }
+
+    /// Invalidate the cached directory entry `name` inside `parent_id`
+    ///
+    /// The next access to this entry will trigger a new lookup.
+    pub fn inval_entry(&self, parent_id: TId, name: &OsStr) -> FuseResult<()> {
+        let Some(parent) = self.resolver.find_ino(&parent_id) else {
+            return Ok(());
+        };
+        ignore_uncached(self.channel()?.inval_entry(parent, name))
+    }
+
+    /// Invalidate the cached directory entry `name` inside `parent_id` and notify inotify watchers of its deletion
+    ///
+    /// If `child_id` is unknown to the kernel, this is equivalent to `inval_entry`.
+    pub fn delete(&self, parent_id: TId, child_id: TId, name: &OsStr) -> FuseResult<()> {
+        let Some(parent) = self.resolver.find_ino(&parent_id) else {
+            return Ok(());
+        };
+        let channel = self.channel()?;
+        ignore_uncached(match self.resolver.find_ino(&child_id) {
+            Some(child) => channel.delete(parent, child, name),
+            None => channel.inval_entry(parent, name),
+        })
+    }
+
+    /// Replace the cached data of a file starting at `offset` with `data`
+    pub fn store(&self, file_id: TId, offset: u64, data: &[u8]) -> FuseResult<()> {
+        let Some(ino) = self.resolver.find_ino(&file_id) else {
+            return Ok(());
+        };
+        ignore_uncached(self.channel()?.store(ino, offset, data))
+    }
+
+    /// Size of the resolver translating file ids to inode numbers, available before the filesystem is mounted
+    pub fn resolver_stats(&self) -> ResolverStats {
+        self.resolver.stats()
+    }
+}
+
+/// The kernel replies ENOENT to notifications targeting an inode or an entry it does not cache
+fn ignore_uncached(result: io::Result<()>) -> FuseResult<()> {
+    match result {
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+        result => result.map_err(PosixError::from),
+    }
+}
+
+impl<TId: FileIdType> Clone for Notifier<TId> {
+    fn clone(&self) -> Self {
+        Notifier {
+            resolver: self.resolver.clone(),
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<TId: FileIdType> Debug for Notifier<TId> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Notifier")
+            .field("connected", &self.channel.get().is_some())
+            .finish()
+    }
+}
@@ -425,6 +425,25 @@ pub fn rmdir(path: &Path) -> Result<(), PosixError> {
     Ok(())
 }
 
+/// Creates a hard link at `newpath` to the file at `path`, and returns the attributes of the linked file.
+///
+/// This function is equivalent to the FUSE `link` operation and uses the system's link call.
+pub fn link(path: &Path, newpath: &Path) -> Result<FileAttribute, PosixError> {
+    let c_path = cstring_from_path(path)?;
+    let c_newpath = cstring_from_path(newpath)?;
+
+    let result = unsafe { libc::link(c_path.as_ptr(), c_newpath.as_ptr()) };
+    if result == -1 {
+        return Err(PosixError::last_error(format!(
+            "{}: link failed (new path: {})",
+            path.display(),
+            newpath.display()
+        )));
+    }
+
+    lookup(newpath)
+}
+
 /// Creates a symbolic link at the specified path, pointing to the given target.
 ///
 /// This function is equivalent to the FUSE `symlink` operation and uses the system's symlink call.
@@ -499,7 +518,23 @@ pub fn open(path: &Path, flags: OpenFlags) -> Result<OwnedFd, PosixError> {
 /// then reads from there. In all cases, the file's position after the read operation
 /// remains where it was before the read, regardless of how much data was read.
 pub fn read(fd: BorrowedFd, seek: SeekFrom, size: usize) -> Result<Vec<u8>, PosixError> {
-    let mut buffer = vec![0; size as usize];
+    let mut buffer = Vec::new();
+    read_into(fd, seek, size, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Reads data from a file descriptor at a specified offset into an existing buffer.
+///
+/// This function behaves as `read`, but replaces the content of `buffer` instead of allocating a new one,
+/// which allows reusing buffers between reads (eg: with a `BufferPool`).
+pub fn read_into(
+    fd: BorrowedFd,
+    seek: SeekFrom,
+    size: usize,
+    buffer: &mut Vec<u8>,
+) -> Result<(), PosixError> {
+    buffer.clear();
+    buffer.reserve(size);
     let offset: libc::off_t = match seek {
         SeekFrom::Start(offset) => offset.try_into().map_err(|_| {
             PosixError::new(
@@ -537,8 +572,9 @@ pub fn read(fd: BorrowedFd, seek: SeekFrom, size: usize) -> Result<Vec<u8>, Posi
     if bytes_read == -1 {
         return Err(PosixError::last_error(format!("{:?}: read failed", fd)));
     }
-    buffer.truncate(bytes_read as usize);
-    Ok(buffer)
+    // pread initialized the first bytes_read bytes of the reserved capacity
+    unsafe { buffer.set_len(bytes_read as usize) };
+    Ok(())
 }
 
 /// Writes data to a file descriptor at a specified offset.
@@ -629,6 +665,137 @@ pub fn fsync(fd: BorrowedFd, datasync: bool) -> Result<(), PosixError> {
     Ok(())
 }
 
+/// Places, converts or removes a BSD lock on an open file.
+///
+/// This function is equivalent to the FUSE `setlk` operation for `flock` requests, the lock always
+/// covering the whole file. If `sleep` is false, a lock held through another open file makes the call
+/// fail with `EWOULDBLOCK` instead of waiting for its release.
+pub fn flock(fd: BorrowedFd, lock_type: LockType, sleep: bool) -> Result<(), PosixError> {
+    let fd = fd.as_raw_fd();
+    let mut operation = match lock_type.bits() {
+        bits if bits == LockType::READ_LOCK.bits() => libc::LOCK_SH,
+        bits if bits == LockType::WRITE_LOCK.bits() => libc::LOCK_EX,
+        bits if bits == LockType::UNLOCKED.bits() => libc::LOCK_UN,
+        bits => {
+            return Err(PosixError::new(
+                ErrorKind::InvalidArgument,
+                format!("{:?}: invalid lock type {}", fd, bits),
+            ))
+        }
+    };
+    if !sleep {
+        operation |= libc::LOCK_NB;
+    }
+
+    if unsafe { libc::flock(fd, operation) } == -1 {
+        return Err(PosixError::last_error(format!("{:?}: flock failed", fd)));
+    }
+
+    Ok(())
+}
+
+/// Performs an ioctl whose argument is a buffer, as forwarded by the FUSE `ioctl` operation.
+///
+/// The buffer is initialized with `in_data`, and is large enough for `in_data`, `out_size` and the
+/// argument size encoded in `cmd`. Its first `out_size` bytes are returned along with the result of the call.
+///
+/// Note: Commands taking their argument by value, or a pointer to other pointers, cannot be forwarded this way.
+pub fn ioctl(
+    fd: BorrowedFd,
+    cmd: u32,
+    in_data: &[u8],
+    out_size: u32,
+) -> Result<(i32, Vec<u8>), PosixError> {
+    let fd = fd.as_raw_fd();
+    let encoded_size = ((cmd >> 16) & 0x3fff) as usize;
+    let mut buffer = vec![
+        0u8;
+        in_data
+            .len()
+            .max(out_size as usize)
+            .max(encoded_size)
+            .max(std::mem::size_of::<libc::c_long>())
+    ];
+    buffer[..in_data.len()].copy_from_slice(in_data);
+
+    let result = unsafe { libc::ioctl(fd, cmd as _, buffer.as_mut_ptr()) };
+    if result == -1 {
+        return Err(PosixError::last_error(format!(
+            "{:?}: ioctl {:#x} failed",
+            fd, cmd
+        )));
+    }
+
+    buffer.truncate(out_size as usize);
+    Ok((result, buffer))
+}
+
+/// Maps a block of a file to a block of the device holding it.
+///
+/// This function is equivalent to the FUSE `bmap` operation, `idx` and the result being expressed in
+/// blocks of `blocksize` bytes. It relies on the `FIBMAP` ioctl, which is only available on Linux and
+/// requires the `CAP_SYS_RAWIO` capability. A hole in the file is mapped to block 0.
+pub fn bmap(path: &Path, blocksize: u32, idx: u64) -> Result<u64, PosixError> {
+    #[cfg(target_os = "linux")]
+    {
+        const FIBMAP: u32 = 1;
+        const FIGETBSZ: u32 = 2;
+
+        let fd = open(path, OpenFlags::READ_ONLY)?;
+        let raw_fd = fd.as_raw_fd();
+        let mut fs_blocksize: libc::c_int = 0;
+        if unsafe { libc::ioctl(raw_fd, FIGETBSZ as _, &mut fs_blocksize) } == -1 {
+            return Err(PosixError::last_error(format!(
+                "{}: FIGETBSZ failed",
+                path.display()
+            )));
+        }
+        if fs_blocksize <= 0 || blocksize == 0 {
+            return Err(PosixError::new(
+                ErrorKind::InvalidArgument,
+                format!(
+                    "{}: invalid block sizes {} and {}",
+                    path.display(),
+                    fs_blocksize,
+                    blocksize
+                ),
+            ));
+        }
+        let fs_blocksize = fs_blocksize as u64;
+        let offset = idx.checked_mul(blocksize as u64).ok_or_else(|| {
+            PosixError::new(
+                ErrorKind::InvalidArgument,
+                format!("{}: block {} out of range", path.display(), idx),
+            )
+        })?;
+        let mut block: libc::c_int = (offset / fs_blocksize).try_into().map_err(|_| {
+            PosixError::new(
+                ErrorKind::InvalidArgument,
+                format!("{}: block {} out of range", path.display(), idx),
+            )
+        })?;
+        if unsafe { libc::ioctl(raw_fd, FIBMAP as _, &mut block) } == -1 {
+            return Err(PosixError::last_error(format!(
+                "{}: FIBMAP failed",
+                path.display()
+            )));
+        }
+        if block == 0 {
+            return Ok(0);
+        }
+        Ok((block as u64 * fs_blocksize + offset % fs_blocksize) / blocksize as u64)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (blocksize, idx);
+        Err(PosixError::new(
+            ErrorKind::FunctionNotImplemented,
+            format!("{}: bmap is only supported on Linux", path.display()),
+        ))
+    }
+}
+
 /// Reads the contents of a directory.
 ///
 /// This function is equivalent to the FUSE `readdir` operation. It returns a vector of tuples,
@@ -687,6 +854,49 @@ pub fn readdir(path: &Path) -> Result<Vec<(OsString, FileKind)>, PosixError> {
     Ok(result)
 }
 
+/// Reads the contents of a directory lazily.
+///
+/// Lazy variant of [`readdir`]: entries are read from the directory as the returned iterator is consumed,
+/// which keeps the memory used by very large directories bounded. `.` and `..` are skipped.
+pub fn readdir_iter(
+    path: &Path,
+) -> Result<impl Iterator<Item = Result<(OsString, FileKind), PosixError>> + Send, PosixError> {
+    fn error(e: std::io::Error, path: &Path, operation: &str) -> PosixError {
+        PosixError::new(
+            e.raw_os_error().unwrap_or(libc::EIO),
+            format!("{}: {} failed", path.display(), operation),
+        )
+    }
+
+    let entries = fs::read_dir(path).map_err(|e| error(e, path, "opendir"))?;
+    let path = path.to_path_buf();
+    Ok(entries.map(move |entry| {
+        let entry = entry.map_err(|e| error(e, &path, "readdir"))?;
+        let file_type = entry
+            .file_type()
+            .map_err(|e| error(e, &entry.path(), "lstat"))?;
+        Ok((entry.file_name(), file_kind_from_file_type(file_type)))
+    }))
+}
+
+fn file_kind_from_file_type(file_type: fs::FileType) -> FileKind {
+    if file_type.is_dir() {
+        FileKind::Directory
+    } else if file_type.is_symlink() {
+        FileKind::Symlink
+    } else if file_type.is_fifo() {
+        FileKind::NamedPipe
+    } else if file_type.is_socket() {
+        FileKind::Socket
+    } else if file_type.is_char_device() {
+        FileKind::CharDevice
+    } else if file_type.is_block_device() {
+        FileKind::BlockDevice
+    } else {
+        FileKind::RegularFile
+    }
+}
+
 /// Releases a file descriptor, closing the associated file.
 ///
 /// This function is equivalent to the FUSE `release` operation. It closes the file descriptor
@@ -797,6 +1007,22 @@ pub fn getxattr(path: &Path, name: &OsStr, size: u32) -> Result<Vec<u8>, PosixEr
         )
     })?;
 
+    // A size of 0 requests the size of the value, which is retrieved entirely for the driver to reply its length
+    let size = if size == 0 {
+        let ret = unsafe {
+            unix_impl::getxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0)
+        };
+        if ret == -1 {
+            return Err(PosixError::last_error(format!(
+                "{}: getxattr failed. Name: {}, Size: 0",
+                path.display(),
+                Path::display(name.as_ref()),
+            )));
+        }
+        ret as u32
+    } else {
+        size
+    };
     let mut buf = vec![0u8; size as usize];
     let ret = unsafe {
         unix_impl::getxattr(
@@ -839,6 +1065,19 @@ pub fn getxattr(path: &Path, name: &OsStr, size: u32) -> Result<Vec<u8>, PosixEr
 ///   may return an empty list or an error.
 pub fn listxattr(path: &Path, size: u32) -> Result<Vec<u8>, PosixError> {
     let c_path = cstring_from_path(path)?;
+    // A size of 0 requests the size of the list, see getxattr
+    let size = if size == 0 {
+        let ret = unsafe { unix_impl::listxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+        if ret == -1 {
+            return Err(PosixError::last_error(format!(
+                "{}: listxattr failed",
+                path.display()
+            )));
+        }
+        ret as u32
+    } else {
+        size
+    };
     let mut buf = vec![0u8; size as usize];
     let ret =
         unsafe { unix_impl::listxattr(c_path.as_ptr(), buf.as_mut_ptr() as *mut _, buf.len()) };
@@ -939,13 +1178,7 @@ pub fn create(
     };
 
     // Open the file with O_CREAT (create if it does not exist)
-    let fd = unsafe {
-        libc::open(
-            c_path.as_ptr(),
-            open_flags | libc::O_CREAT,
-            final_mode,
-        )
-    };
+    let fd = unsafe { libc::open(c_path.as_ptr(), open_flags | libc::O_CREAT, final_mode) };
 
     if fd == -1 {
         return Err(PosixError::last_error(format!(
@@ -986,11 +1219,24 @@ pub fn fallocate(
 ///
 /// It changes the file offset for the given file descriptor, based on the provided
 /// offset and whence values. The new position is returned as a 64-bit integer.
-pub fn lseek(fd: BorrowedFd, seek: SeekFrom) -> Result<i64, PosixError> {
-    let (whence, offset) = match seek {
-        SeekFrom::Start(offset) => (libc::SEEK_SET, offset as libc::off_t),
-        SeekFrom::Current(offset) => (libc::SEEK_CUR, offset as libc::off_t),
-        SeekFrom::End(offset) => (libc::SEEK_END, offset as libc::off_t),
+///
+/// `seek` takes a `SeekFrom`, or a [`LseekFrom`] to look for the data and holes of sparse files.
+pub fn lseek(fd: BorrowedFd, seek: impl Into<LseekFrom>) -> Result<i64, PosixError> {
+    let (whence, offset) = match seek.into() {
+        LseekFrom::Seek(SeekFrom::Start(offset)) => (libc::SEEK_SET, offset as libc::off_t),
+        LseekFrom::Seek(SeekFrom::Current(offset)) => (libc::SEEK_CUR, offset as libc::off_t),
+        LseekFrom::Seek(SeekFrom::End(offset)) => (libc::SEEK_END, offset as libc::off_t),
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+        LseekFrom::Data(offset) => (libc::SEEK_DATA, offset as libc::off_t),
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+        LseekFrom::Hole(offset) => (libc::SEEK_HOLE, offset as libc::off_t),
+        #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+        seek => {
+            return Err(PosixError::new(
+                ErrorKind::InvalidArgument,
+                format!("{:?}: {:?} is not supported", fd, seek),
+            ))
+        }
     };
     let result = unsafe { libc::lseek(fd.as_raw_fd(), offset, whence) };
     if result == -1 {
@@ -1068,6 +1314,40 @@ mod tests {
         assert_eq!(c_string.to_str().unwrap(), path.to_str().unwrap());
     }
 
+    #[test]
+    fn test_flock() {
+        let tmpfile = NamedTempFile::new().unwrap();
+        let first = open(tmpfile.path(), OpenFlags::READ_ONLY).unwrap();
+        let second = open(tmpfile.path(), OpenFlags::READ_ONLY).unwrap();
+
+        flock(first.as_fd(), LockType::READ_LOCK, false).unwrap();
+        flock(second.as_fd(), LockType::READ_LOCK, false).unwrap();
+        let error = flock(second.as_fd(), LockType::WRITE_LOCK, false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ResourceUnavailableTryAgain);
+
+        flock(first.as_fd(), LockType::UNLOCKED, false).unwrap();
+        flock(second.as_fd(), LockType::WRITE_LOCK, false).unwrap();
+        drop(second);
+        // Closing the last descriptor of an open file releases its lock
+        flock(first.as_fd(), LockType::WRITE_LOCK, false).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_ioctl() {
+        let tmpfile = NamedTempFile::new().unwrap();
+        fs::write(tmpfile.path(), "hello").unwrap();
+        let fd = open(tmpfile.path(), OpenFlags::READ_ONLY).unwrap();
+        lseek(fd.as_fd(), SeekFrom::Start(1)).unwrap();
+
+        let (result, data) = ioctl(fd.as_fd(), libc::FIONREAD as u32, &[], 4).unwrap();
+        assert_eq!(result, 0);
+        assert_eq!(i32::from_ne_bytes(data.try_into().unwrap()), 4);
+
+        let error = ioctl(fd.as_fd(), libc::TIOCGWINSZ as u32, &[], 8).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InappropriateIoctlForDevice);
+    }
+
     #[test]
     fn test_get_attr() {
         let tmpfile = NamedTempFile::new().unwrap();
@@ -1277,6 +1557,15 @@ mod tests {
         let result = read(borrowed_fd, SeekFrom::Current(0), 5).unwrap();
         assert_eq!(result.len(), 0);
 
+        // Test SEEK_DATA and SEEK_HOLE, the end of file being a hole
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(lseek(borrowed_fd, LseekFrom::Data(3)).unwrap(), 3);
+            assert_eq!(lseek(borrowed_fd, LseekFrom::Hole(0)).unwrap(), 13);
+            let error = lseek(borrowed_fd, LseekFrom::Data(13)).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::NoSuchDeviceOrAddress);
+        }
+
         drop(tmpfile);
     }
 }
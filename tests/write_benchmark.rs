@@ -0,0 +1,118 @@
+//! Compares the write path of the driver before and after pooling the payload of write requests.
+//!
+//! The handler is called directly, without mounting, so that the cost of the FUSE round trip does not hide
+//! the cost of the payload. Run with:
+//!
+//! ```sh
+//! cargo test --release --features parallel --test write_benchmark -- --ignored --nocapture
+//! ```
+
+use easy_fuser::prelude::*;
+use easy_fuser::templates::{fd_handler_helper::FdHandlerHelper, DefaultFuseHandler};
+
+use std::fs::File;
+use std::io::SeekFrom;
+use std::os::fd::AsFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tempfile::TempDir;
+
+/// Default `max_write` of the kernel
+const CHUNK_SIZE: usize = 128 * 1024;
+/// Writes cycle over the start of the file, so that the page cache absorbs them
+const FILE_SIZE: u64 = 8 * 1024 * 1024;
+const WRITES: usize = 16 * 1024;
+
+fn run(name: &str, mut write: impl FnMut(SeekFrom, &[u8]) -> u32) -> Duration {
+    let payload = vec![0xa5u8; CHUNK_SIZE];
+    let start = Instant::now();
+    let mut written = 0u64;
+    for i in 0..WRITES {
+        let offset = (i * CHUNK_SIZE) as u64 % FILE_SIZE;
+        written += write(SeekFrom::Start(offset), &payload) as u64;
+    }
+    let elapsed = start.elapsed();
+    assert_eq!(written, (WRITES * CHUNK_SIZE) as u64);
+    println!(
+        "{:<32} {:>8.2?} {:>10.1} MiB/s",
+        name,
+        elapsed,
+        written as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+    );
+    elapsed
+}
+
+#[test]
+#[ignore]
+fn bench_write_payload() {
+    let dir = TempDir::new().unwrap();
+    let file = File::create(dir.path().join("bench")).unwrap();
+    let handler = FdHandlerHelper::<PathBuf>::new(DefaultFuseHandler::new());
+    let req = RequestInfo {
+        id: 0,
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    };
+    let file_handle = || BorrowedFileHandle::from_borrowed_fd(file.as_fd()).unwrap();
+
+    // Previous behavior, kept for handlers which do not set `pooled_writes`: the payload is copied into a new Vec
+    // for each request
+    let owned = run("write (owned copy)", |seek, data| {
+        let data = data.to_owned();
+        FuseHandler::write(
+            &handler,
+            &req,
+            PathBuf::new(),
+            file_handle(),
+            seek,
+            data,
+            FUSEWriteFlags::empty(),
+            OpenFlags::empty(),
+            None,
+        )
+        .unwrap()
+    });
+
+    // Threaded modes, as `FdHandlerHelper` sets `pooled_writes`: the payload is copied into a buffer reused
+    // across requests
+    let pooled = run("write_buf (pooled copy)", |seek, data| {
+        let mut buffer = BufferPool::global().get(data.len());
+        buffer.extend_from_slice(data);
+        FuseHandler::write_buf(
+            &handler,
+            &req,
+            PathBuf::new(),
+            file_handle(),
+            seek,
+            WriteData::Pooled(buffer),
+            FUSEWriteFlags::empty(),
+            OpenFlags::empty(),
+            None,
+        )
+        .unwrap()
+    });
+
+    // Serial mode: the payload is borrowed from the request
+    let borrowed = run("write_buf (borrowed)", |seek, data| {
+        FuseHandler::write_buf(
+            &handler,
+            &req,
+            PathBuf::new(),
+            file_handle(),
+            seek,
+            WriteData::Borrowed(data),
+            FUSEWriteFlags::empty(),
+            OpenFlags::empty(),
+            None,
+        )
+        .unwrap()
+    });
+
+    println!(
+        "speedup: pooled x{:.2}, borrowed x{:.2}",
+        owned.as_secs_f64() / pooled.as_secs_f64(),
+        owned.as_secs_f64() / borrowed.as_secs_f64()
+    );
+}